@@ -0,0 +1,165 @@
+use esp_idf_hal::delay::FreeRtos;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_sys::{self as sys, esp};
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use crate::config;
+
+// Mặc định: quang trở nối GPIO34 (ADC1 kênh 6), đọc 12-bit
+const DEFAULT_PIN: u8 = 34;
+const DEFAULT_DARK: u32 = 200;     // Giá trị ADC khi phòng tối → độ sáng tối thiểu
+const DEFAULT_BRIGHT: u32 = 3000;  // Giá trị ADC khi phòng sáng → độ sáng đầy đủ
+const DEFAULT_MIN_PERCENT: u8 = 10;
+
+const POLL_INTERVAL_MS: u32 = 100;
+const FILTER_ALPHA: f32 = 0.1;     // Lọc nhiễu ADC (~1s)
+const HYSTERESIS: u8 = 8;          // Chỉ đổi factor khi lệch ≥ 8/255 để tránh nhấp nháy ở ngưỡng
+
+/// Hệ số độ sáng theo ánh sáng môi trường, tách biệt với độ sáng của user.
+/// Controller nhân `brightness * factor / 255` khi xuất ra LED.
+pub struct AmbientLight {
+    enabled: AtomicBool,
+    factor: AtomicU8,
+    dark: AtomicU32,
+    bright: AtomicU32,
+    min_percent: AtomicU8,
+}
+
+impl AmbientLight {
+    /// Đọc cấu hình từ NVS. Factor luôn bắt đầu ở 255 (không ảnh hưởng)
+    pub fn from_nvs(partition: &EspDefaultNvsPartition) -> Self {
+        Self {
+            enabled: AtomicBool::new(config::get_u8(partition, config::KEY_AUTO_BRIGHTNESS, 0) != 0),
+            factor: AtomicU8::new(255),
+            dark: AtomicU32::new(config::get_u32(partition, config::KEY_AMBIENT_DARK, DEFAULT_DARK)),
+            bright: AtomicU32::new(config::get_u32(partition, config::KEY_AMBIENT_BRIGHT, DEFAULT_BRIGHT)),
+            min_percent: AtomicU8::new(config::get_u8(partition, config::KEY_AMBIENT_MIN, DEFAULT_MIN_PERCENT).min(100)),
+        }
+    }
+
+    /// Hệ số hiện tại (255 = không giảm)
+    pub fn factor(&self) -> u8 {
+        self.factor.load(Ordering::Relaxed)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.factor.store(255, Ordering::Relaxed);
+        }
+    }
+
+    /// Đường cong: tuyến tính từ `dark` (min%) tới `bright` (100%).
+    /// `dark > bright` cũng hợp lệ (quang trở nối ngược).
+    pub fn set_curve(&self, dark: u32, bright: u32, min_percent: u8) {
+        self.dark.store(dark, Ordering::Relaxed);
+        self.bright.store(bright, Ordering::Relaxed);
+        self.min_percent.store(min_percent.min(100), Ordering::Relaxed);
+    }
+
+    pub fn curve(&self) -> (u32, u32, u8) {
+        (
+            self.dark.load(Ordering::Relaxed),
+            self.bright.load(Ordering::Relaxed),
+            self.min_percent.load(Ordering::Relaxed),
+        )
+    }
+
+    fn target_factor(&self, reading: f32) -> u8 {
+        let (dark, bright, min_percent) = self.curve();
+        if dark == bright {
+            return 255;
+        }
+
+        let t = ((reading - dark as f32) / (bright as f32 - dark as f32)).clamp(0.0, 1.0);
+        let min = min_percent as f32 / 100.0;
+        ((min + (1.0 - min) * t) * 255.0).round() as u8
+    }
+}
+
+/// Chân chọn được cho cảm biến (xem `adc1_channel`)
+pub fn is_usable_pin(pin: u8) -> bool {
+    adc1_channel(pin).is_some()
+}
+
+/// GPIO → kênh ADC1 (ADC2 không dùng được khi WiFi bật).
+/// GPIO32/33 đã dùng cho micro nên không cho chọn.
+fn adc1_channel(pin: u8) -> Option<sys::adc_channel_t> {
+    let channel = match pin {
+        36 => 0,
+        37 => 1,
+        38 => 2,
+        39 => 3,
+        34 => 6,
+        35 => 7,
+        _ => return None,
+    };
+    Some(channel)
+}
+
+/// Đọc cảm biến ánh sáng và cập nhật `ambient.factor` tới khi `stop` bật.
+/// Lỗi khởi tạo ADC → trả Err, factor giữ 255 nên độ sáng không đổi.
+pub fn ambient_processing_blocking(
+    partition: &EspDefaultNvsPartition,
+    ambient: &AmbientLight,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    let pin = config::get_u8(partition, config::KEY_AMBIENT_PIN, DEFAULT_PIN);
+    let Some(channel) = adc1_channel(pin) else {
+        anyhow::bail!("GPIO{} is not a usable ADC1 input", pin);
+    };
+
+    let mut unit: sys::adc_oneshot_unit_handle_t = core::ptr::null_mut();
+    let unit_config = sys::adc_oneshot_unit_init_cfg_t {
+        unit_id: sys::adc_unit_t_ADC_UNIT_1,
+        ..Default::default()
+    };
+    esp!(unsafe { sys::adc_oneshot_new_unit(&unit_config, &mut unit) })?;
+
+    let chan_config = sys::adc_oneshot_chan_cfg_t {
+        atten: sys::adc_atten_t_ADC_ATTEN_DB_11,
+        bitwidth: sys::adc_bitwidth_t_ADC_BITWIDTH_12,
+    };
+    if let Err(e) = esp!(unsafe { sys::adc_oneshot_config_channel(unit, channel, &chan_config) }) {
+        unsafe { sys::adc_oneshot_del_unit(unit) };
+        return Err(e.into());
+    }
+
+    info!("Ambient light sensor on GPIO{} (ADC1 ch{})", pin, channel);
+
+    let mut filtered: Option<f32> = None;
+
+    while !stop.load(Ordering::Relaxed) {
+        FreeRtos::delay_ms(POLL_INTERVAL_MS);
+
+        if !ambient.is_enabled() {
+            continue;
+        }
+
+        let mut raw: i32 = 0;
+        if esp!(unsafe { sys::adc_oneshot_read(unit, channel, &mut raw) }).is_err() {
+            warn!("Ambient ADC read failed");
+            continue;
+        }
+
+        let reading = match filtered {
+            Some(f) => f + (raw as f32 - f) * FILTER_ALPHA,
+            None => raw as f32,
+        };
+        filtered = Some(reading);
+
+        let target = ambient.target_factor(reading);
+        let current = ambient.factor();
+        if target.abs_diff(current) >= HYSTERESIS || (target == 255 && current != 255) {
+            ambient.factor.store(target, Ordering::Relaxed);
+        }
+    }
+
+    unsafe { sys::adc_oneshot_del_unit(unit) };
+    Ok(())
+}
@@ -2,8 +2,8 @@ use esp_idf_hal::i2s::{self, I2sDriver, config};
 use esp_idf_hal::gpio::*;
 use esp_idf_hal::i2s::I2S0;
 use esp_idf_hal::delay::FreeRtos;
-use log::info;
-use std::sync::Arc;
+use log::{info, warn};
+use std::sync::atomic::{fence, AtomicBool, AtomicU32, Ordering};
 
 pub const SAMPLE_RATE: u32 = 16000;
 pub const BUFFER_SIZE: usize = 128;
@@ -18,9 +18,31 @@ const TREBLE_SCALE: f32 = 6.0;
 
 const PORT_MAX_DELAY: u32 = 0xFFFFFFFF;
 
+// Beat detection
+const BEAT_THRESHOLD: f32 = 0.3;      // beat_intensity tối thiểu để tính là beat
+const BEAT_MIN_INTERVAL_MS: u32 = 150; // Refractory - tối đa ~400 BPM
+
+// Kiểm tra micro: mic thật luôn có nhiễu, chân SD bỏ trống đọc ra hằng số
+const MIC_PROBE_BLOCKS: usize = 8;
+const MIC_MIN_SPREAD: i64 = 1 << 16;
+
 // Noise gate - lọc nhiễu nền
 const NOISE_FLOOR: f32 = 0.005;       // Dưới ngưỡng này = nhiễu
 
+// Soft clip: tuyến tính tới KNEE, phía trên nén dần về 1.0 thay vì cắt phẳng
+const SOFT_CLIP_KNEE: f32 = 0.7;
+
+// Bật mặc định; tắt = clamp cứng như trước. Đặt qua /config/soft-clip.
+static SOFT_CLIP: AtomicBool = AtomicBool::new(true);
+
+pub fn set_soft_clip(enabled: bool) {
+    SOFT_CLIP.store(enabled, Ordering::Relaxed);
+}
+
+pub fn soft_clip_enabled() -> bool {
+    SOFT_CLIP.load(Ordering::Relaxed)
+}
+
 /// AudioData - lightweight
 #[derive(Debug, Clone)]
 pub struct AudioData {
@@ -29,6 +51,12 @@ pub struct AudioData {
     pub mid: f32,
     pub treble: f32,
     pub bins: [f32; NUM_BINS],
+    /// true trong lúc đang có beat
+    pub beat: bool,
+    /// Tăng 1 mỗi beat mới - reader so sánh để không bỏ lỡ beat giữa 2 frame
+    pub beat_count: u32,
+    /// false nếu không phát hiện micro (dữ liệu I2S gần như hằng số)
+    pub audio_available: bool,
 }
 
 impl Default for AudioData {
@@ -39,10 +67,106 @@ impl Default for AudioData {
             mid: 0.0,
             treble: 0.0,
             bins: [0.0; NUM_BINS],
+            beat: false,
+            beat_count: 0,
+            audio_available: false,
+        }
+    }
+}
+
+/// Trao AudioData giữa các task không cần khóa (seqlock).
+///
+/// Chỉ audio task ghi: `seq` lẻ trong lúc đang ghi, chẵn khi frame đã hoàn chỉnh.
+/// Reader (LED task, HTTP) đọc lại nếu `seq` đổi giữa chừng, nên luôn nhận
+/// được frame mới nhất và đầy đủ; writer không bao giờ phải chờ hay bỏ frame.
+/// Các trường lưu dạng atomic (f32 theo bit) nên không có data race.
+pub struct SharedAudio {
+    seq: AtomicU32,
+    volume: AtomicU32,
+    bass: AtomicU32,
+    mid: AtomicU32,
+    treble: AtomicU32,
+    bins: [AtomicU32; NUM_BINS],
+    beat: AtomicBool,
+    beat_count: AtomicU32,
+    audio_available: AtomicBool,
+}
+
+impl SharedAudio {
+    pub fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            volume: AtomicU32::new(0),
+            bass: AtomicU32::new(0),
+            mid: AtomicU32::new(0),
+            treble: AtomicU32::new(0),
+            bins: core::array::from_fn(|_| AtomicU32::new(0)),
+            beat: AtomicBool::new(false),
+            beat_count: AtomicU32::new(0),
+            audio_available: AtomicBool::new(false),
+        }
+    }
+
+    /// Công bố frame mới. Chỉ được gọi từ một task (audio task).
+    fn publish(&self, data: &AudioData) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        self.volume.store(data.volume.to_bits(), Ordering::Relaxed);
+        self.bass.store(data.bass.to_bits(), Ordering::Relaxed);
+        self.mid.store(data.mid.to_bits(), Ordering::Relaxed);
+        self.treble.store(data.treble.to_bits(), Ordering::Relaxed);
+        for (slot, bin) in self.bins.iter().zip(data.bins.iter()) {
+            slot.store(bin.to_bits(), Ordering::Relaxed);
+        }
+        self.beat.store(data.beat, Ordering::Relaxed);
+        self.beat_count.store(data.beat_count, Ordering::Relaxed);
+        self.audio_available.store(data.audio_available, Ordering::Relaxed);
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Số frame audio đã công bố. Đang ghi dở thì tính luôn frame đó,
+    /// vì `latest()` sẽ chờ nó xong. LED task so sánh để biết có dữ liệu mới.
+    pub fn frame_count(&self) -> u32 {
+        self.seq.load(Ordering::Acquire).wrapping_add(1) / 2
+    }
+
+    /// Frame hoàn chỉnh mới nhất, không chặn (chỉ thử lại nếu trùng lúc đang ghi)
+    pub fn latest(&self) -> AudioData {
+        loop {
+            let before = self.seq.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            let data = AudioData {
+                volume: f32::from_bits(self.volume.load(Ordering::Relaxed)),
+                bass: f32::from_bits(self.bass.load(Ordering::Relaxed)),
+                mid: f32::from_bits(self.mid.load(Ordering::Relaxed)),
+                treble: f32::from_bits(self.treble.load(Ordering::Relaxed)),
+                bins: core::array::from_fn(|i| f32::from_bits(self.bins[i].load(Ordering::Relaxed))),
+                beat: self.beat.load(Ordering::Relaxed),
+                beat_count: self.beat_count.load(Ordering::Relaxed),
+                audio_available: self.audio_available.load(Ordering::Relaxed),
+            };
+
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == before {
+                return data;
+            }
         }
     }
 }
 
+impl Default for SharedAudio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Smooth value over time - faster response
 #[inline(always)]
 fn smooth(current: f32, target: f32, factor: f32) -> f32 {
@@ -55,6 +179,19 @@ fn clamp(v: f32) -> f32 {
     if v < 0.0 { 0.0 } else if v > 1.0 { 1.0 } else { v }
 }
 
+/// Giới hạn mức về 0.0-1.0. Soft clip (mặc định):
+/// `v` nếu `v <= KNEE`, ngược lại `KNEE + (1 - KNEE) * tanh((v - KNEE) / (1 - KNEE))`.
+/// Độ dốc liên tục tại KNEE và chỉ tiệm cận 1.0, nên nhạc to vẫn còn dao động
+/// thay vì dính ở max. Tắt soft clip → clamp cứng.
+#[inline(always)]
+fn limit(v: f32) -> f32 {
+    if !soft_clip_enabled() || v <= SOFT_CLIP_KNEE {
+        return clamp(v);
+    }
+    let range = 1.0 - SOFT_CLIP_KNEE;
+    SOFT_CLIP_KNEE + range * ((v - SOFT_CLIP_KNEE) / range).tanh()
+}
+
 /// Apply noise gate
 #[inline(always)]
 fn apply_noise_gate(value: f32, threshold: f32) -> f32 {
@@ -152,6 +289,32 @@ fn generate_simple_bins(samples: &[i32], bins: &mut [f32; NUM_BINS]) {
     }
 }
 
+/// Chênh lệch max - min của một block (dùng để phát hiện micro)
+#[inline]
+fn sample_spread(samples: &[i32]) -> i64 {
+    let mut min = i32::MAX;
+    let mut max = i32::MIN;
+    for &s in samples.iter() {
+        min = min.min(s);
+        max = max.max(s);
+    }
+    max as i64 - min as i64
+}
+
+/// Convert raw I2S bytes to i32 samples
+#[inline]
+fn bytes_to_samples(raw_bytes: &[u8], samples: &mut [i32]) {
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let idx = i * 4;
+        *sample = i32::from_le_bytes([
+            raw_bytes[idx],
+            raw_bytes[idx + 1],
+            raw_bytes[idx + 2],
+            raw_bytes[idx + 3],
+        ]);
+    }
+}
+
 /// Peak detection for beat/transient detection - more sensitive
 #[inline]
 fn detect_peak(current: f32, history: &[f32; 4]) -> f32 {
@@ -171,7 +334,8 @@ pub fn audio_processing_blocking(
     sck: Gpio33,
     ws: Gpio25,
     sd: Gpio32,
-    audio_data: Arc<std::sync::Mutex<AudioData>>,
+    audio_data: &SharedAudio,
+    stop: &AtomicBool,
 ) -> Result<(), anyhow::Error> {
     // I2S config
     let config = config::StdConfig::philips(
@@ -204,13 +368,38 @@ pub fn audio_processing_blocking(
     // Peak detection history
     let mut volume_history = [0.0f32; 4];
     let mut history_idx = 0;
+    let mut beat_count: u32 = 0;
+    let mut was_beat = false;
+    let mut ms_since_beat: u32 = BEAT_MIN_INTERVAL_MS;
     
+    // Đọc thử vài block để xem micro có được nối không
+    let mut mic_present = false;
+    for _ in 0..MIC_PROBE_BLOCKS {
+        if driver.read(&mut *raw_bytes, PORT_MAX_DELAY).is_ok() {
+            bytes_to_samples(&raw_bytes, &mut samples);
+            if sample_spread(&samples) >= MIC_MIN_SPREAD {
+                mic_present = true;
+                break;
+            }
+        }
+    }
+
+    if mic_present {
+        info!("Microphone detected");
+    } else {
+        warn!("No microphone detected - check INMP441 wiring (SCK=33, WS=25, SD=32)");
+    }
+
+    // Frame đang dựng, công bố nguyên khối sau mỗi block I2S
+    let mut frame = AudioData { audio_available: mic_present, ..Default::default() };
+    audio_data.publish(&frame);
+
     info!("Audio processing started - SENSITIVE MODE");
     info!("Sample rate: {}Hz, Buffer: {} samples", SAMPLE_RATE, BUFFER_SIZE);
     info!("Scales - Vol:{} Bass:{} Mid:{} Treble:{}", 
           VOL_SCALE, BASS_SCALE, MID_SCALE, TREBLE_SCALE);
 
-    loop {
+    while !stop.load(Ordering::Relaxed) {
         // Read I2S data
         if let Err(_) = driver.read(&mut *raw_bytes, PORT_MAX_DELAY) {
             FreeRtos::delay_ms(10);
@@ -218,14 +407,12 @@ pub fn audio_processing_blocking(
         }
 
         // Convert bytes to i32 samples
-        for i in 0..BUFFER_SIZE {
-            let idx = i * 4;
-            samples[i] = i32::from_le_bytes([
-                raw_bytes[idx],
-                raw_bytes[idx + 1],
-                raw_bytes[idx + 2],
-                raw_bytes[idx + 3],
-            ]);
+        bytes_to_samples(&raw_bytes, &mut samples);
+
+        // Micro được cắm lại sau khi khởi động
+        if !mic_present && sample_spread(&samples) >= MIC_MIN_SPREAD {
+            mic_present = true;
+            info!("Microphone detected");
         }
 
         // Calculate volume (RMS)
@@ -253,6 +440,14 @@ pub fn audio_processing_blocking(
         volume_history[history_idx] = volume;
         history_idx = (history_idx + 1) % volume_history.len();
         let beat_intensity = detect_peak(volume, &volume_history);
+
+        // Beat = sườn lên của beat_intensity, có refractory để tránh đếm trùng
+        let is_beat = beat_intensity > BEAT_THRESHOLD;
+        if is_beat && !was_beat && ms_since_beat >= BEAT_MIN_INTERVAL_MS {
+            beat_count = beat_count.wrapping_add(1);
+            ms_since_beat = 0;
+        }
+        was_beat = is_beat;
         
         // Apply smoothing (faster response than before)
         smooth_volume = smooth(smooth_volume, volume, SMOOTH_FACTOR);
@@ -268,18 +463,25 @@ pub fn audio_processing_blocking(
         let beat_boost = 1.0 + beat_intensity * 0.7;
 
         // Update shared data
-        if let Ok(mut data) = audio_data.lock() {
-            data.volume = clamp(smooth_volume * beat_boost);
-            data.bass = clamp(smooth_bass * beat_boost);
-            data.mid = clamp(smooth_mid);
-            data.treble = clamp(smooth_treble);
-            
-            for i in 0..NUM_BINS {
-                data.bins[i] = clamp(smooth_bins[i] * beat_boost);
-            }
+        frame.volume = limit(smooth_volume * beat_boost);
+        frame.bass = limit(smooth_bass * beat_boost);
+        frame.mid = limit(smooth_mid);
+        frame.treble = limit(smooth_treble);
+
+        for i in 0..NUM_BINS {
+            frame.bins[i] = limit(smooth_bins[i] * beat_boost);
         }
 
+        frame.beat = is_beat;
+        frame.beat_count = beat_count;
+        frame.audio_available = mic_present;
+        audio_data.publish(&frame);
+
         // Fast update rate
         FreeRtos::delay_ms(5);
+        ms_since_beat = ms_since_beat.saturating_add(5 + (BUFFER_SIZE as u32 * 1000) / SAMPLE_RATE);
     }
+
+    driver.rx_disable()?;
+    Ok(())
 }
\ No newline at end of file
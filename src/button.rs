@@ -0,0 +1,214 @@
+use esp_idf_hal::delay::FreeRtos;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_sys::{self as sys, esp};
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::config;
+use crate::effect::effect_from_name;
+use crate::http::{send_command_from, CommandProducer, CommandSource, LedCommand};
+
+/// Không cấu hình chân → không có nút (GPIO0 là nút BOOT trên nhiều board nên không dùng 0 làm "tắt")
+pub const PIN_DISABLED: u8 = 255;
+
+// Lấy mẫu mỗi 10ms, cần 3 mẫu liên tiếp giống nhau mới nhận trạng thái mới (~30ms)
+const SAMPLE_INTERVAL_MS: u32 = 10;
+const DEBOUNCE_SAMPLES: u8 = 3;
+const LONG_PRESS_MS: u32 = 800;
+const DOUBLE_PRESS_GAP_MS: u32 = 300;
+
+pub const DEFAULT_ACTIONS: &str = "power,next,favorite";
+pub const DEFAULT_FAVORITE: &str = "rainbow";
+/// Việc làm khi nhấn nút
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ButtonAction {
+    None,
+    Power,
+    Next,
+    Favorite,
+    NextColor,
+}
+
+impl ButtonAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(ButtonAction::None),
+            "power" => Some(ButtonAction::Power),
+            "next" => Some(ButtonAction::Next),
+            "favorite" => Some(ButtonAction::Favorite),
+            "color" => Some(ButtonAction::NextColor),
+            _ => None,
+        }
+    }
+}
+
+/// "power,next,favorite" → hành động cho nhấn ngắn, nhấn giữ, nhấn đúp
+pub fn parse_actions(s: &str) -> Option<[ButtonAction; 3]> {
+    let mut parts = s.split(',').map(str::trim);
+    let actions = [
+        ButtonAction::parse(parts.next()?)?,
+        ButtonAction::parse(parts.next()?)?,
+        ButtonAction::parse(parts.next()?)?,
+    ];
+    parts.next().is_none().then_some(actions)
+}
+
+/// Chân dùng được cho nút: bỏ chân flash (6-11), chân LED (18) và micro (25, 32, 33).
+/// GPIO34-39 không có pull-up nội, cần điện trở kéo lên bên ngoài.
+pub fn is_usable_pin(pin: u8) -> bool {
+    pin < 40 && !matches!(pin, 6..=11 | 18 | 25 | 32 | 33)
+}
+
+enum Press {
+    Short,
+    Long,
+    Double,
+}
+
+/// Nhận diện nhấn ngắn / giữ / đúp từ trạng thái nút đã debounce
+struct PressDetector {
+    raw: bool,
+    stable_count: u8,
+    pressed: bool,
+    held_ms: u32,
+    long_fired: bool,
+    // Đã nhả một lần nhấn ngắn, đang chờ xem có lần nhấn thứ hai không
+    waiting_ms: Option<u32>,
+}
+
+impl PressDetector {
+    fn new() -> Self {
+        Self { raw: false, stable_count: 0, pressed: false, held_ms: 0, long_fired: false, waiting_ms: None }
+    }
+
+    fn sample(&mut self, raw: bool) -> Option<Press> {
+        if raw == self.raw {
+            self.stable_count = self.stable_count.saturating_add(1);
+        } else {
+            self.raw = raw;
+            self.stable_count = 1;
+        }
+
+        let was_pressed = self.pressed;
+        if self.stable_count >= DEBOUNCE_SAMPLES {
+            self.pressed = raw;
+        }
+
+        match (was_pressed, self.pressed) {
+            (false, true) => {
+                self.held_ms = 0;
+                self.long_fired = false;
+                if self.waiting_ms.take().is_some() {
+                    // Lần nhấn thứ hai: không tính là giữ
+                    self.long_fired = true;
+                    return Some(Press::Double);
+                }
+                None
+            }
+            (true, true) => {
+                self.held_ms += SAMPLE_INTERVAL_MS;
+                // Nhấn giữ phát ngay khi đủ thời gian, không chờ nhả tay
+                if !self.long_fired && self.held_ms >= LONG_PRESS_MS {
+                    self.long_fired = true;
+                    return Some(Press::Long);
+                }
+                None
+            }
+            (true, false) => {
+                if !self.long_fired {
+                    self.waiting_ms = Some(0);
+                }
+                None
+            }
+            (false, false) => {
+                let waited = self.waiting_ms.as_mut()?;
+                *waited += SAMPLE_INTERVAL_MS;
+                if *waited >= DOUBLE_PRESS_GAP_MS {
+                    self.waiting_ms = None;
+                    return Some(Press::Short);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Đọc nút bấm và đẩy LedCommand vào cùng queue với HTTP tới khi `stop` bật.
+/// Không cấu hình chân → trả Ok ngay, task kết thúc.
+pub fn button_processing_blocking(
+    partition: &EspDefaultNvsPartition,
+    producer: &Mutex<CommandProducer>,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    let pin = config::get_u8(partition, config::KEY_BUTTON_PIN, PIN_DISABLED);
+    if pin == PIN_DISABLED {
+        return Ok(());
+    }
+    if !is_usable_pin(pin) {
+        anyhow::bail!("GPIO{} cannot be used for the button", pin);
+    }
+
+    let actions = config::get_str::<32>(partition, config::KEY_BUTTON_ACTIONS)
+        .and_then(|s| parse_actions(&s))
+        .or_else(|| parse_actions(DEFAULT_ACTIONS))
+        .unwrap();
+    let favorite = config::get_str::<16>(partition, config::KEY_BUTTON_FAVORITE)
+        .and_then(|s| effect_from_name(&s).map(|(e, _)| e))
+        .or_else(|| effect_from_name(DEFAULT_FAVORITE).map(|(e, _)| e))
+        .unwrap();
+
+    // Nút nối xuống GND, dùng pull-up nội → nhấn = mức 0
+    let io_config = sys::gpio_config_t {
+        pin_bit_mask: 1u64 << pin,
+        mode: sys::gpio_mode_t_GPIO_MODE_INPUT,
+        pull_up_en: sys::gpio_pullup_t_GPIO_PULLUP_ENABLE,
+        pull_down_en: sys::gpio_pulldown_t_GPIO_PULLDOWN_DISABLE,
+        intr_type: sys::gpio_int_type_t_GPIO_INTR_DISABLE,
+    };
+    esp!(unsafe { sys::gpio_config(&io_config) })?;
+
+    info!("Button on GPIO{}", pin);
+
+    let mut detector = PressDetector::new();
+    let mut effect_index: Option<usize> = None;
+
+    while !stop.load(Ordering::Relaxed) {
+        FreeRtos::delay_ms(SAMPLE_INTERVAL_MS);
+
+        let pressed = unsafe { sys::gpio_get_level(pin as i32) } == 0;
+        let action = match detector.sample(pressed) {
+            Some(Press::Short) => actions[0],
+            Some(Press::Long) => actions[1],
+            Some(Press::Double) => actions[2],
+            None => continue,
+        };
+
+        let command = match action {
+            ButtonAction::None => continue,
+            // Dùng chung mức nhớ với /led/toggle
+            ButtonAction::Power => crate::http::power_toggle_command().0,
+            ButtonAction::Next => {
+                // Đọc lại mỗi lần để thay đổi qua /config/rotation có hiệu lực ngay
+                let effects = crate::rotation::list();
+                if effects.is_empty() {
+                    continue;
+                }
+                let index = effect_index.map_or(0, |i| (i + 1) % effects.len());
+                effect_index = Some(index);
+                LedCommand::SetEffect(effects[index].clone())
+            }
+            ButtonAction::Favorite => LedCommand::SetEffect(favorite.clone()),
+            ButtonAction::NextColor => match crate::quick_colors::next() {
+                Some(c) => LedCommand::SetColor(c.r, c.g, c.b),
+                None => continue,
+            },
+        };
+
+        if !send_command_from(producer, CommandSource::Button, command) {
+            warn!("Button command dropped (queue busy)");
+        }
+    }
+
+    Ok(())
+}
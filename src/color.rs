@@ -0,0 +1,55 @@
+/// "FF8000", "#ff8000", "F80" (= FF8800). Chấp nhận khoảng trắng hai đầu và `#` đã
+/// mã hoá (`%23`) vì giá trị form không được url_decode trước khi gọi hàm này.
+pub fn parse_hex_color(s: &str) -> Result<(u8, u8, u8), ()> {
+    let s = s.trim();
+    let s = s.strip_prefix('#').or_else(|| s.strip_prefix("%23")).unwrap_or(s);
+
+    if !s.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(());
+    }
+
+    match s.len() {
+        6 => {
+            let r = u8::from_str_radix(&s[0..2], 16).map_err(|_| ())?;
+            let g = u8::from_str_radix(&s[2..4], 16).map_err(|_| ())?;
+            let b = u8::from_str_radix(&s[4..6], 16).map_err(|_| ())?;
+            Ok((r, g, b))
+        }
+        3 => {
+            // Mỗi chữ số nhân đôi: F → FF
+            let digit = |i: usize| u8::from_str_radix(&s[i..i + 1], 16).map(|v| v * 17).map_err(|_| ());
+            Ok((digit(0)?, digit(1)?, digit(2)?))
+        }
+        _ => Err(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_six_digit_forms() {
+        assert_eq!(parse_hex_color("FF8000"), Ok((255, 128, 0)));
+        assert_eq!(parse_hex_color("ff8000"), Ok((255, 128, 0)));
+        assert_eq!(parse_hex_color("Ff8000"), Ok((255, 128, 0)));
+        assert_eq!(parse_hex_color("#FF8000"), Ok((255, 128, 0)));
+        assert_eq!(parse_hex_color("%23FF8000"), Ok((255, 128, 0)));
+        assert_eq!(parse_hex_color("  #ff8000 "), Ok((255, 128, 0)));
+    }
+
+    #[test]
+    fn expands_three_digit_shorthand() {
+        assert_eq!(parse_hex_color("F80"), Ok((255, 136, 0)));
+        assert_eq!(parse_hex_color("#f80"), Ok((255, 136, 0)));
+        assert_eq!(parse_hex_color("000"), Ok((0, 0, 0)));
+        assert_eq!(parse_hex_color("fff"), Ok((255, 255, 255)));
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        for input in ["", "#", "F", "FF", "FFFF", "FFFFF", "FFFFFFF", "GG0000", "#FF 000", "+F0000", "##FF0000", "%2FF0000", "é00"] {
+            assert_eq!(parse_hex_color(input), Err(()), "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use core::fmt::Write as FmtWrite;
+use esp_idf_sys::esp_timer_get_time;
+use heapless::{Deque, String};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::effect::effect_name;
+use crate::http::{CommandSource, LedCommand};
+
+/// Số command gần nhất được giữ lại cho GET /log/commands
+pub const LOG_CAPACITY: usize = 50;
+
+#[derive(Clone)]
+pub struct LogEntry {
+    /// Thời điểm LED task áp dụng command (µs từ lúc boot)
+    pub time_us: u64,
+    pub source: CommandSource,
+    pub summary: String<48>,
+}
+
+// LED task ghi (try_lock, không chờ), HTTP chỉ giữ lock trong lúc sao chép
+static LOG: Mutex<Deque<LogEntry, LOG_CAPACITY>> = Mutex::new(Deque::new());
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Mô tả ngắn gọn của command, vd "effect rainbow", "color FF8000"
+fn write_summary(out: &mut impl FmtWrite, cmd: &LedCommand) -> core::fmt::Result {
+    match cmd {
+        LedCommand::SetEffect(effect) => write!(out, "effect {}", effect_name(effect)),
+        LedCommand::SetBrightness(b) => write!(out, "brightness {}%", (b * 100.0).round() as u32),
+        LedCommand::SetColor(r, g, b) => write!(out, "color {:02X}{:02X}{:02X}", r, g, b),
+        LedCommand::SetSpeed(s) => write!(out, "speed {}", s),
+        LedCommand::SetParam(k, v) => write!(out, "param {}:{}", k, v),
+        LedCommand::SetBeatSync(on) => write!(out, "beatsync {}", on),
+        LedCommand::Sync { restart, .. } => write!(out, "sync{}", if *restart { " restart" } else { "" }),
+        LedCommand::Identify { count } => write!(out, "identify x{}", count),
+        LedCommand::SetMinBrightness(v) => write!(out, "min brightness {}", v),
+        LedCommand::SetPowerSave(on) => write!(out, "power save {}", on),
+        LedCommand::SetPartyMode(settings) => write!(out, "party {}", if settings.is_some() { "on" } else { "off" }),
+        LedCommand::SetAudioMode(on) => write!(out, "audio mode {}", on),
+        LedCommand::SetColorCycle(s) => write!(out, "color cycle {}s", s),
+        LedCommand::SetSleepTimer { minutes, policy } => write!(out, "sleep timer {}min {}", minutes, policy.as_str()),
+        LedCommand::SetSkipPixels(list) => write!(out, "skip pixels ({})", list.len()),
+        LedCommand::SetOfflineBehavior { behavior, minutes } => write!(out, "offline {} after {}min", behavior.as_str(), minutes),
+        LedCommand::SetIdleTimeout(settings) => match settings {
+            Some(s) => write!(out, "idle {} {}min, off {}min", effect_name(&s.effect), s.ambient_minutes, s.off_minutes),
+            None => out.write_str("idle off"),
+        },
+        LedCommand::SetRange(range) => match range {
+            Some((start, end)) => write!(out, "range {}..{}", start, end),
+            None => out.write_str("range full"),
+        },
+        LedCommand::SetThermalProtection(minutes) => match minutes {
+            Some(m) => write!(out, "thermal {}min", m),
+            None => out.write_str("thermal off"),
+        },
+        LedCommand::SetColorCarryOver(mode) => write!(out, "color carry {}", mode.as_str()),
+        LedCommand::SetProgress(f) => write!(out, "progress {:.1}%", f * 100.0),
+        LedCommand::SetInterpolation(on) => write!(out, "interpolation {}", on),
+        LedCommand::SetHuePreserve(on) => write!(out, "hue preserve {}", on),
+        LedCommand::SetSaturation(v) => write!(out, "saturation {}%", v),
+        LedCommand::SetConnectFlash(color) => match color {
+            Some((r, g, b)) => write!(out, "connect flash {:02X}{:02X}{:02X}", r, g, b),
+            None => out.write_str("connect flash off"),
+        },
+    }
+}
+
+/// Ghi lại command vừa được áp dụng. Bỏ qua nếu tắt hoặc HTTP đang sao chép log.
+pub fn record(source: CommandSource, cmd: &LedCommand) {
+    if !is_enabled() {
+        return;
+    }
+
+    let mut summary = String::new();
+    // Mô tả dài hơn 48 ký tự bị cắt, không sao
+    let _ = write_summary(&mut summary, cmd);
+
+    let entry = LogEntry {
+        time_us: unsafe { esp_timer_get_time() } as u64,
+        source,
+        summary,
+    };
+
+    let Ok(mut log) = LOG.try_lock() else { return; };
+    if log.is_full() {
+        log.pop_front();
+    }
+    log.push_back(entry).ok();
+}
+
+/// JSON `{"now_us":..,"entries":[{"t_us":..,"source":..,"command":..}],"count":N,"max":50}`, cũ nhất trước
+pub fn write_json(out: &mut impl FmtWrite) -> core::fmt::Result {
+    let now_us = unsafe { esp_timer_get_time() } as u64;
+    write!(out, "{{\"enabled\":{},\"now_us\":{},\"entries\":", is_enabled(), now_us)?;
+
+    // Sao chép rồi nhả lock ngay: ghi ra socket chậm, giữ lock lâu làm LED task mất entry
+    let log: Vec<LogEntry> = match LOG.lock() {
+        Ok(log) => log.iter().cloned().collect(),
+        Err(_) => Vec::new(),
+    };
+    crate::http::write_json_list(out, log.iter(), |out, entry| {
+        write!(out, "{{\"t_us\":{},\"source\":\"{}\",\"command\":", entry.time_us, entry.source.as_str())?;
+        crate::config::write_json_str(out, &entry.summary)?;
+        out.write_char('}')
+    })?;
+    write!(out, ",\"count\":{},\"max\":{}}}", log.len(), LOG_CAPACITY)
+}
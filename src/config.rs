@@ -0,0 +1,775 @@
+use anyhow::{Context, Result};
+use core::fmt::Write as FmtWrite;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_sys::EspError;
+
+/// Namespace lưu cấu hình WiFi
+pub const WIFI_NAMESPACE: &str = "wifi_config";
+
+/// Số mạng WiFi lưu tối đa, thứ tự trong danh sách = độ ưu tiên
+pub const MAX_SAVED_NETWORKS: usize = 3;
+
+// Danh sách mạng: "ssid0".."ssid2" / "pass0".."pass2".
+// Layout cũ (1 mạng): "ssid" / "password" / "configured", chuyển lúc boot (config v0 → v1).
+const WIFI_SSID_KEYS: [&str; MAX_SAVED_NETWORKS] = ["ssid0", "ssid1", "ssid2"];
+const WIFI_PASS_KEYS: [&str; MAX_SAVED_NETWORKS] = ["pass0", "pass1", "pass2"];
+const WIFI_LEGACY_SSID: &str = "ssid";
+const WIFI_LEGACY_PASSWORD: &str = "password";
+const WIFI_LEGACY_CONFIGURED: &str = "configured";
+
+/// Namespace lưu cấu hình LED / hệ thống
+pub const CONFIG_NAMESPACE: &str = "led_config";
+
+// Key trong CONFIG_NAMESPACE
+pub const KEY_BOOT_ANIMATION: &str = "boot_anim";
+pub const KEY_DEVICE_NAME: &str = "device_name";
+pub const KEY_AUTO_BRIGHTNESS: &str = "auto_bri";
+pub const KEY_AMBIENT_PIN: &str = "amb_pin";
+pub const KEY_AMBIENT_DARK: &str = "amb_dark";
+pub const KEY_AMBIENT_BRIGHT: &str = "amb_bright";
+pub const KEY_AMBIENT_MIN: &str = "amb_min";
+pub const KEY_MIN_BRIGHTNESS: &str = "min_bri";
+pub const KEY_POWER_SAVE: &str = "power_save";
+pub const KEY_COLOR_CYCLE: &str = "color_cycle";
+pub const KEY_CHIP_TYPE: &str = "chip_type";
+pub const KEY_SKIP_PIXELS: &str = "skip_px";
+pub const KEY_THERMAL: &str = "thermal_min";
+pub const KEY_COLOR_CARRY: &str = "color_carry";
+pub const KEY_BUTTON_PIN: &str = "btn_pin";
+pub const KEY_BUTTON_ACTIONS: &str = "btn_actions";
+pub const KEY_ROTATION: &str = "rotation";
+pub const KEY_COMMAND_LOG: &str = "cmd_log";
+pub const KEY_SOFT_CLIP: &str = "soft_clip";
+pub const KEY_INTERPOLATION: &str = "interp";
+pub const KEY_HUE_PRESERVE: &str = "hue_preserve";
+pub const KEY_CONNECT_FLASH: &str = "conn_flash";
+pub const KEY_CONNECT_COLOR: &str = "conn_color";
+pub const KEY_OFFLINE_MODE: &str = "offline_mode";
+pub const KEY_OFFLINE_LEVEL: &str = "offline_lvl";
+pub const KEY_OFFLINE_MIN: &str = "offline_min";
+pub const KEY_IDLE_AMBIENT: &str = "idle_amb_min";
+pub const KEY_IDLE_OFF: &str = "idle_off_min";
+pub const KEY_IDLE_EFFECT: &str = "idle_effect";
+pub const KEY_IDLE_LEVEL: &str = "idle_level";
+pub const KEY_SATURATION: &str = "saturation";
+pub const KEY_QUICK_COLORS: &str = "quick_colors";
+pub const KEY_BASE_PATH: &str = "base_path";
+pub const KEY_BUTTON_FAVORITE: &str = "btn_fav";
+pub const KEY_BOOT_COUNT: &str = "boot_count";
+pub const KEY_ON_TIME_MIN: &str = "on_time_min";
+pub const KEY_CONFIG_VERSION: &str = "cfg_version";
+
+/// Phiên bản layout cấu hình trong NVS, tăng mỗi khi firmware đổi cách lưu.
+/// 0 = firmware chưa ghi version (có thể còn WiFi layout 1 mạng),
+/// 1 = WiFi dạng danh sách "ssid0".."ssid2".
+pub const CONFIG_VERSION: u8 = 1;
+
+/// Chu kỳ ghi tổng thời gian chạy vào flash (tránh ghi NVS quá thường xuyên)
+pub const ON_TIME_PERSIST_MIN: u32 = 10;
+
+/// Tên thiết bị mặc định khi chưa đặt qua /config/name
+pub const DEFAULT_DEVICE_NAME: &str = "WS2812 Controller";
+pub const MAX_DEVICE_NAME_LEN: usize = 32;
+
+/// Tiền tố đường dẫn khi đứng sau reverse proxy (vd "/lights"), rỗng = truy cập trực tiếp
+pub const MAX_BASE_PATH_LEN: usize = 32;
+
+/// Chuẩn hoá tiền tố: bắt đầu bằng `/`, bỏ `/` cuối, chỉ chữ/số/`/_-.`.
+/// "" hoặc "/" → "" (không có tiền tố). None nếu không hợp lệ.
+pub fn normalize_base_path(path: &str) -> Option<&str> {
+    let path = path.trim().trim_end_matches('/');
+    if path.is_empty() {
+        return Some("");
+    }
+    let valid = path.starts_with('/')
+        && path.len() <= MAX_BASE_PATH_LEN
+        && !path.contains("//")
+        && path.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '.'));
+    valid.then_some(path)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum KeyKind {
+    U8,
+    U32,
+    Str,
+}
+
+pub struct KnownKey {
+    pub namespace: &'static str,
+    pub key: &'static str,
+    pub kind: KeyKind,
+}
+
+/// Các key được phép xuất ra `/nvs/dump`.
+/// Password WiFi và token KHÔNG bao giờ được thêm vào đây.
+pub const DUMP_KEYS: &[KnownKey] = &[
+    KnownKey { namespace: WIFI_NAMESPACE, key: WIFI_SSID_KEYS[0], kind: KeyKind::Str },
+    KnownKey { namespace: WIFI_NAMESPACE, key: WIFI_SSID_KEYS[1], kind: KeyKind::Str },
+    KnownKey { namespace: WIFI_NAMESPACE, key: WIFI_SSID_KEYS[2], kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BOOT_ANIMATION, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_DEVICE_NAME, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_AUTO_BRIGHTNESS, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_AMBIENT_PIN, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_AMBIENT_DARK, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_AMBIENT_BRIGHT, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_AMBIENT_MIN, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_MIN_BRIGHTNESS, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_POWER_SAVE, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_COLOR_CYCLE, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_CHIP_TYPE, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_SKIP_PIXELS, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_THERMAL, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_COLOR_CARRY, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BUTTON_PIN, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BUTTON_ACTIONS, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_ROTATION, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_COMMAND_LOG, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_SOFT_CLIP, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_INTERPOLATION, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_HUE_PRESERVE, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_CONNECT_FLASH, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_CONNECT_COLOR, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_OFFLINE_MODE, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_OFFLINE_LEVEL, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_OFFLINE_MIN, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_IDLE_AMBIENT, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_IDLE_OFF, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_IDLE_EFFECT, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_IDLE_LEVEL, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_SATURATION, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_QUICK_COLORS, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BASE_PATH, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BUTTON_FAVORITE, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BOOT_COUNT, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_ON_TIME_MIN, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_CONFIG_VERSION, kind: KeyKind::U8 },
+];
+
+/// Phiên bản định dạng của /config/export
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Cấu hình được xuất/nhập qua /config/export và /config/import.
+/// Không gồm bộ đếm (boot_count, on_time_min) và không bao giờ gồm secret.
+const EXPORT_KEYS: &[KnownKey] = &[
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_DEVICE_NAME, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BOOT_ANIMATION, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_AUTO_BRIGHTNESS, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_AMBIENT_PIN, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_AMBIENT_DARK, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_AMBIENT_BRIGHT, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_AMBIENT_MIN, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_MIN_BRIGHTNESS, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_POWER_SAVE, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_COLOR_CYCLE, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_CHIP_TYPE, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_SKIP_PIXELS, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_THERMAL, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_COLOR_CARRY, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BUTTON_PIN, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BUTTON_ACTIONS, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_ROTATION, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_COMMAND_LOG, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_SOFT_CLIP, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_INTERPOLATION, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_HUE_PRESERVE, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_CONNECT_FLASH, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_CONNECT_COLOR, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_OFFLINE_MODE, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_OFFLINE_LEVEL, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_OFFLINE_MIN, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_IDLE_AMBIENT, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_IDLE_OFF, kind: KeyKind::U32 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_IDLE_EFFECT, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_IDLE_LEVEL, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_SATURATION, kind: KeyKind::U8 },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_QUICK_COLORS, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BASE_PATH, kind: KeyKind::Str },
+    KnownKey { namespace: CONFIG_NAMESPACE, key: KEY_BUTTON_FAVORITE, kind: KeyKind::Str },
+];
+
+// Palette của user được xuất thành "palette_0".."palette_7" = "name;RRGGBB,..."
+const EXPORT_PALETTE_PREFIX: &str = "palette_";
+
+/// Đọc u8 trong CONFIG_NAMESPACE, trả `default` nếu chưa có
+pub fn get_u8(partition: &EspDefaultNvsPartition, key: &str, default: u8) -> u8 {
+    EspNvs::new(partition.clone(), CONFIG_NAMESPACE, false)
+        .ok()
+        .and_then(|nvs| nvs.get_u8(key).ok().flatten())
+        .unwrap_or(default)
+}
+
+/// Ghi u8 vào CONFIG_NAMESPACE
+pub fn set_u8(partition: &EspDefaultNvsPartition, key: &str, value: u8) -> Result<()> {
+    let mut nvs = EspNvs::new(partition.clone(), CONFIG_NAMESPACE, true)
+        .context("Không thể mở NVS namespace để ghi")?;
+    nvs.set_u8(key, value)
+        .with_context(|| format!("Không thể lưu {}", key))?;
+    Ok(())
+}
+
+/// Đọc u32 trong CONFIG_NAMESPACE, trả `default` nếu chưa có
+pub fn get_u32(partition: &EspDefaultNvsPartition, key: &str, default: u32) -> u32 {
+    EspNvs::new(partition.clone(), CONFIG_NAMESPACE, false)
+        .ok()
+        .and_then(|nvs| nvs.get_u32(key).ok().flatten())
+        .unwrap_or(default)
+}
+
+/// Ghi u32 vào CONFIG_NAMESPACE
+pub fn set_u32(partition: &EspDefaultNvsPartition, key: &str, value: u32) -> Result<()> {
+    let mut nvs = EspNvs::new(partition.clone(), CONFIG_NAMESPACE, true)
+        .context("Không thể mở NVS namespace để ghi")?;
+    nvs.set_u32(key, value)
+        .with_context(|| format!("Không thể lưu {}", key))?;
+    Ok(())
+}
+
+/// Tăng bộ đếm số lần khởi động, trả về giá trị mới
+pub fn record_boot(partition: &EspDefaultNvsPartition) -> Result<u32> {
+    let count = get_u32(partition, KEY_BOOT_COUNT, 0).wrapping_add(1);
+    set_u32(partition, KEY_BOOT_COUNT, count)?;
+    Ok(count)
+}
+
+/// Cộng thêm `minutes` vào tổng thời gian đã chạy
+pub fn add_on_time(partition: &EspDefaultNvsPartition, minutes: u32) -> Result<()> {
+    let total = get_u32(partition, KEY_ON_TIME_MIN, 0).saturating_add(minutes);
+    set_u32(partition, KEY_ON_TIME_MIN, total)
+}
+
+/// Nâng cấu hình trong NVS lên CONFIG_VERSION, gọi 1 lần lúc boot trước khi đọc cấu hình.
+/// Version cao hơn firmware (đã hạ cấp firmware) thì không đọc được an toàn →
+/// xóa cấu hình LED về mặc định, giữ WiFi và bộ đếm. Trả version trước khi nâng.
+pub fn migrate(partition: &EspDefaultNvsPartition) -> Result<u8> {
+    let stored = get_u8(partition, KEY_CONFIG_VERSION, 0);
+
+    match plan_migration(stored, CONFIG_VERSION) {
+        MigrationPlan::UpToDate => return Ok(stored),
+        MigrationPlan::Reset => {
+            log::warn!("Config version {} is newer than firmware ({}), resetting LED settings", stored, CONFIG_VERSION);
+            reset_settings(partition)?;
+        }
+        MigrationPlan::Upgrade(steps) => {
+            for from in steps {
+                log::info!("Migrating config v{} -> v{}", from, from + 1);
+                migrate_step(partition, from)?;
+            }
+        }
+    }
+
+    set_u8(partition, KEY_CONFIG_VERSION, CONFIG_VERSION)?;
+    Ok(stored)
+}
+
+/// Việc `migrate` phải làm để đưa cấu hình từ `stored` lên `current`
+#[derive(Debug, Clone, PartialEq)]
+enum MigrationPlan {
+    UpToDate,
+    /// Chạy `migrate_step(from)` lần lượt cho từng `from` trong khoảng
+    Upgrade(core::ops::Range<u8>),
+    /// Cấu hình của firmware mới hơn, không đọc được an toàn
+    Reset,
+}
+
+fn plan_migration(stored: u8, current: u8) -> MigrationPlan {
+    match stored.cmp(&current) {
+        core::cmp::Ordering::Equal => MigrationPlan::UpToDate,
+        core::cmp::Ordering::Less => MigrationPlan::Upgrade(stored..current),
+        core::cmp::Ordering::Greater => MigrationPlan::Reset,
+    }
+}
+
+/// Một bước nâng cấp từ version `from` lên `from + 1`
+fn migrate_step(partition: &EspDefaultNvsPartition, from: u8) -> Result<()> {
+    match from {
+        0 => migrate_legacy_network(partition).context("Không thể chuyển WiFi sang dạng danh sách"),
+        _ => Ok(()),
+    }
+}
+
+/// Xóa các cấu hình xuất/nhập được (EXPORT_KEYS) về mặc định
+fn reset_settings(partition: &EspDefaultNvsPartition) -> Result<()> {
+    let mut nvs = EspNvs::new(partition.clone(), CONFIG_NAMESPACE, true)
+        .context("Không thể mở NVS namespace để ghi")?;
+    for entry in EXPORT_KEYS.iter().filter(|e| e.namespace == CONFIG_NAMESPACE) {
+        nvs.remove(entry.key)
+            .with_context(|| format!("Không thể xóa {}", entry.key))?;
+    }
+    Ok(())
+}
+
+/// Đọc chuỗi trong CONFIG_NAMESPACE, None nếu chưa có hoặc quá dài
+pub fn get_str<const N: usize>(partition: &EspDefaultNvsPartition, key: &str) -> Option<heapless::String<N>> {
+    let nvs = EspNvs::new(partition.clone(), CONFIG_NAMESPACE, false).ok()?;
+    let mut buf = [0u8; 256];
+    let value = nvs.get_str(key, &mut buf).ok()??;
+    heapless::String::try_from(value).ok()
+}
+
+/// Ghi chuỗi vào CONFIG_NAMESPACE
+pub fn set_str(partition: &EspDefaultNvsPartition, key: &str, value: &str) -> Result<()> {
+    let mut nvs = EspNvs::new(partition.clone(), CONFIG_NAMESPACE, true)
+        .context("Không thể mở NVS namespace để ghi")?;
+    nvs.set_str(key, value)
+        .with_context(|| format!("Không thể lưu {}", key))?;
+    Ok(())
+}
+
+/// Xóa key trong CONFIG_NAMESPACE để lần đọc sau dùng giá trị mặc định
+pub fn remove_key(partition: &EspDefaultNvsPartition, key: &str) -> Result<()> {
+    let mut nvs = EspNvs::new(partition.clone(), CONFIG_NAMESPACE, true)
+        .context("Không thể mở NVS namespace để ghi")?;
+    nvs.remove(key)
+        .with_context(|| format!("Không thể xóa {}", key))?;
+    Ok(())
+}
+
+/// Ghi toàn bộ key đã biết dưới dạng JSON: `{"namespace":{"key":value,...},...}`
+/// Key không tồn tại (hoặc namespace chưa được tạo) được ghi là `null`.
+pub fn dump_json(partition: &EspDefaultNvsPartition, out: &mut impl FmtWrite) -> core::fmt::Result {
+    let mut current_ns: Option<&str> = None;
+    let mut handle: Option<EspNvs<NvsDefault>> = None;
+
+    out.write_char('{')?;
+
+    for entry in DUMP_KEYS {
+        if current_ns != Some(entry.namespace) {
+            if current_ns.is_some() {
+                out.write_str("},")?;
+            }
+            write!(out, "\"{}\":{{", entry.namespace)?;
+            handle = EspNvs::new(partition.clone(), entry.namespace, false).ok();
+            current_ns = Some(entry.namespace);
+        } else {
+            out.write_char(',')?;
+        }
+
+        write!(out, "\"{}\":", entry.key)?;
+
+        let Some(nvs) = handle.as_ref() else {
+            out.write_str("null")?;
+            continue;
+        };
+
+        match entry.kind {
+            KeyKind::U8 => match nvs.get_u8(entry.key) {
+                Ok(Some(v)) => write!(out, "{}", v)?,
+                _ => out.write_str("null")?,
+            },
+            KeyKind::U32 => match nvs.get_u32(entry.key) {
+                Ok(Some(v)) => write!(out, "{}", v)?,
+                _ => out.write_str("null")?,
+            },
+            KeyKind::Str => {
+                let mut buf = [0u8; 256];
+                match nvs.get_str(entry.key, &mut buf) {
+                    Ok(Some(v)) => write_json_str(out, v)?,
+                    _ => out.write_str("null")?,
+                }
+            }
+        }
+    }
+
+    if current_ns.is_some() {
+        out.write_char('}')?;
+    }
+    out.write_char('}')
+}
+
+/// Ghi chuỗi JSON có escape `"` và `\`
+pub fn write_json_str(out: &mut impl FmtWrite, s: &str) -> core::fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// Một mạng WiFi đã lưu
+#[derive(Debug, Clone)]
+pub struct SavedNetwork {
+    pub ssid: heapless::String<32>,
+    pub password: heapless::String<64>,
+}
+
+pub type NetworkList = heapless::Vec<SavedNetwork, MAX_SAVED_NETWORKS>;
+
+fn read_network(nvs: &EspNvs<NvsDefault>, ssid_key: &str, pass_key: &str) -> Result<Option<SavedNetwork>, EspError> {
+    let mut ssid_buf = [0u8; 33];
+    let mut pass_buf = [0u8; 65];
+
+    let (Some(ssid), Some(password)) = (nvs.get_str(ssid_key, &mut ssid_buf)?, nvs.get_str(pass_key, &mut pass_buf)?) else {
+        return Ok(None);
+    };
+
+    Ok(match (heapless::String::try_from(ssid), heapless::String::try_from(password)) {
+        (Ok(ssid), Ok(password)) => Some(SavedNetwork { ssid, password }),
+        _ => None,
+    })
+}
+
+fn write_networks(nvs: &mut EspNvs<NvsDefault>, list: &NetworkList) -> Result<(), EspError> {
+    for i in 0..MAX_SAVED_NETWORKS {
+        match list.get(i) {
+            Some(n) => {
+                nvs.set_str(WIFI_SSID_KEYS[i], &n.ssid)?;
+                nvs.set_str(WIFI_PASS_KEYS[i], &n.password)?;
+            }
+            None => {
+                nvs.remove(WIFI_SSID_KEYS[i])?;
+                nvs.remove(WIFI_PASS_KEYS[i])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Danh sách mạng đã lưu theo thứ tự ưu tiên
+pub fn load_networks(partition: &EspDefaultNvsPartition) -> Result<NetworkList, EspError> {
+    let mut list = NetworkList::new();
+
+    // Chỉ đọc: namespace chưa tồn tại nghĩa là chưa lưu mạng nào
+    let nvs = match EspNvs::new(partition.clone(), WIFI_NAMESPACE, false) {
+        Ok(nvs) => nvs,
+        Err(e) if e.code() == esp_idf_sys::ESP_ERR_NVS_NOT_FOUND as esp_idf_sys::esp_err_t => return Ok(list),
+        Err(e) => return Err(e),
+    };
+
+    for i in 0..MAX_SAVED_NETWORKS {
+        if let Some(n) = read_network(&nvs, WIFI_SSID_KEYS[i], WIFI_PASS_KEYS[i])? {
+            list.push(n).ok();
+        }
+    }
+
+    Ok(list)
+}
+
+/// Config v0 → v1: layout 1 mạng cũ → mạng đầu tiên của danh sách.
+/// Danh sách đã có mạng thì giữ nguyên, chỉ xóa key cũ.
+fn migrate_legacy_network(partition: &EspDefaultNvsPartition) -> Result<(), EspError> {
+    let mut nvs = EspNvs::new(partition.clone(), WIFI_NAMESPACE, true)?;
+    if nvs.get_u8(WIFI_LEGACY_CONFIGURED)? != Some(1) {
+        return Ok(());
+    }
+
+    let has_list = read_network(&nvs, WIFI_SSID_KEYS[0], WIFI_PASS_KEYS[0])?.is_some();
+    if !has_list {
+        if let Some(n) = read_network(&nvs, WIFI_LEGACY_SSID, WIFI_LEGACY_PASSWORD)? {
+            log::info!("Migrating saved WiFi network to list format");
+            let mut list = NetworkList::new();
+            list.push(n).ok();
+            write_networks(&mut nvs, &list)?;
+        }
+    }
+    nvs.remove(WIFI_LEGACY_SSID)?;
+    nvs.remove(WIFI_LEGACY_PASSWORD)?;
+    nvs.remove(WIFI_LEGACY_CONFIGURED)?;
+    Ok(())
+}
+
+/// Thêm mạng mới vào cuối danh sách, hoặc cập nhật password nếu SSID đã có.
+/// Danh sách đầy → bỏ mạng có ưu tiên thấp nhất.
+pub fn save_network(partition: &EspDefaultNvsPartition, network: SavedNetwork) -> Result<(), EspError> {
+    let mut list = load_networks(partition)?;
+
+    if let Some(existing) = list.iter_mut().find(|n| n.ssid == network.ssid) {
+        existing.password = network.password;
+    } else {
+        if list.is_full() {
+            list.pop();
+        }
+        list.push(network).ok();
+    }
+
+    let mut nvs = EspNvs::new(partition.clone(), WIFI_NAMESPACE, true)?;
+    write_networks(&mut nvs, &list)
+}
+
+/// Xóa mạng theo SSID, false nếu không có
+pub fn remove_network(partition: &EspDefaultNvsPartition, ssid: &str) -> Result<bool, EspError> {
+    let mut list = load_networks(partition)?;
+    let before = list.len();
+    list.retain(|n| n.ssid != ssid);
+    if list.len() == before {
+        return Ok(false);
+    }
+
+    let mut nvs = EspNvs::new(partition.clone(), WIFI_NAMESPACE, true)?;
+    write_networks(&mut nvs, &list)?;
+    Ok(true)
+}
+
+/// Xuất cấu hình dưới dạng JSON phẳng: `{"version":1,"key":value,...}`.
+/// Key chưa có trong NVS thì bỏ qua.
+pub fn export_json(partition: &EspDefaultNvsPartition, out: &mut impl FmtWrite) -> core::fmt::Result {
+    write!(out, "{{\"version\":{}", EXPORT_VERSION)?;
+
+    if let Ok(nvs) = EspNvs::new(partition.clone(), CONFIG_NAMESPACE, false) {
+        for entry in EXPORT_KEYS {
+            match entry.kind {
+                KeyKind::U8 => {
+                    if let Ok(Some(v)) = nvs.get_u8(entry.key) {
+                        write!(out, ",\"{}\":{}", entry.key, v)?;
+                    }
+                }
+                KeyKind::U32 => {
+                    if let Ok(Some(v)) = nvs.get_u32(entry.key) {
+                        write!(out, ",\"{}\":{}", entry.key, v)?;
+                    }
+                }
+                KeyKind::Str => {
+                    let mut buf = [0u8; 256];
+                    if let Ok(Some(v)) = nvs.get_str(entry.key, &mut buf) {
+                        write!(out, ",\"{}\":", entry.key)?;
+                        write_json_str(out, v)?;
+                    }
+                }
+            }
+        }
+    }
+
+    for (i, palette) in crate::palettes::list().iter().enumerate() {
+        write!(out, ",\"{}{}\":", EXPORT_PALETTE_PREFIX, i)?;
+        write_json_str(out, &palette.to_record())?;
+    }
+
+    out.write_char('}')
+}
+
+/// Số key tối đa của một object JSON phẳng: "version" + mọi EXPORT_KEYS + mọi palette,
+/// để /config/import luôn nhận được kết quả của /config/export
+const MAX_JSON_KEYS: usize = 1 + EXPORT_KEYS.len() + crate::palettes::MAX_PALETTES;
+
+pub(crate) type JsonPairs<'a> = heapless::Vec<(&'a str, JsonValue<'a>), MAX_JSON_KEYS>;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum JsonValue<'a> {
+    Str(&'a str),
+    Int(u32),
+}
+
+/// Parse JSON phẳng `{"key":"str"|int,...}` (không hỗ trợ escape, object lồng nhau)
+pub(crate) fn parse_flat_json(s: &str) -> Result<JsonPairs<'_>, &'static str> {
+    let mut rest = s.trim()
+        .strip_prefix('{')
+        .and_then(|r| r.strip_suffix('}'))
+        .ok_or("Expected a JSON object")?
+        .trim();
+
+    let mut pairs = heapless::Vec::new();
+
+    while !rest.is_empty() {
+        let r = rest.strip_prefix('"').ok_or("Expected a key")?;
+        let (key, r) = r.split_once('"').ok_or("Unterminated key")?;
+        let r = r.trim_start().strip_prefix(':').ok_or("Expected ':'")?.trim_start();
+
+        let (value, r) = if let Some(r) = r.strip_prefix('"') {
+            let (v, r) = r.split_once('"').ok_or("Unterminated string")?;
+            if v.contains('\\') {
+                return Err("Escaped strings are not supported");
+            }
+            (JsonValue::Str(v), r)
+        } else {
+            let end = r.find(|c: char| !c.is_ascii_digit()).unwrap_or(r.len());
+            let n = r[..end].parse::<u32>().map_err(|_| "Expected a string or unsigned integer")?;
+            (JsonValue::Int(n), &r[end..])
+        };
+
+        pairs.push((key, value)).map_err(|_| "Too many keys")?;
+
+        let r = r.trim_start();
+        rest = match r.strip_prefix(',') {
+            Some(r) => r.trim_start(),
+            None if r.is_empty() => r,
+            None => return Err("Expected ',' or '}'"),
+        };
+    }
+
+    Ok(pairs)
+}
+
+/// Độ dài tối đa của giá trị chuỗi khi import
+fn max_str_len(key: &str) -> usize {
+    match key {
+        KEY_DEVICE_NAME => MAX_DEVICE_NAME_LEN,
+        KEY_BASE_PATH => MAX_BASE_PATH_LEN,
+        _ => 208,
+    }
+}
+
+/// Nhập cấu hình từ JSON của `export_json`. Mọi trường được kiểm tra trước,
+/// chỉ ghi NVS khi toàn bộ hợp lệ. Trả về số trường đã ghi.
+pub fn import_json(partition: &EspDefaultNvsPartition, body: &str) -> Result<usize, &'static str> {
+    let pairs = parse_flat_json(body)?;
+
+    match pairs.iter().find(|(k, _)| *k == "version") {
+        Some((_, JsonValue::Int(EXPORT_VERSION))) => {}
+        Some(_) => return Err("Unsupported config version"),
+        None => return Err("Missing version"),
+    }
+
+    let mut palettes: heapless::Vec<crate::palettes::Palette, { crate::palettes::MAX_PALETTES }> = heapless::Vec::new();
+
+    // Bước 1: kiểm tra
+    for (key, value) in pairs.iter() {
+        if *key == "version" {
+            continue;
+        }
+
+        if key.starts_with(EXPORT_PALETTE_PREFIX) {
+            let JsonValue::Str(record) = value else { return Err("Palette must be a string"); };
+            let palette = crate::palettes::Palette::from_record(record).ok_or("Invalid palette")?;
+            palettes.push(palette).map_err(|_| "Too many palettes")?;
+            continue;
+        }
+
+        let entry = EXPORT_KEYS.iter().find(|e| e.key == *key).ok_or("Unknown key")?;
+        match (entry.kind, value) {
+            (KeyKind::U8, JsonValue::Int(v)) if *v <= u8::MAX as u32 => import_int(entry.key, *v)?,
+            (KeyKind::U32, JsonValue::Int(v)) => import_int(entry.key, *v)?,
+            (KeyKind::Str, JsonValue::Str(v)) if !v.trim().is_empty() && v.len() <= max_str_len(entry.key) => {
+                import_str(entry.key, v)?;
+            }
+            _ => return Err("Invalid value type or range"),
+        }
+    }
+
+    // Bước 2: ghi
+    let mut written = 0;
+    for (key, value) in pairs.iter() {
+        let Some(entry) = EXPORT_KEYS.iter().find(|e| e.key == *key) else { continue; };
+        let result = match (entry.kind, value) {
+            (KeyKind::U8, JsonValue::Int(v)) => set_u8(partition, entry.key, *v as u8),
+            (KeyKind::U32, JsonValue::Int(v)) => set_u32(partition, entry.key, *v),
+            (KeyKind::Str, JsonValue::Str(v)) => set_str(partition, entry.key, import_str(entry.key, v)?),
+            _ => continue,
+        };
+        result.map_err(|_| "NVS write failed")?;
+        written += 1;
+    }
+
+    for palette in palettes {
+        crate::palettes::save(partition, palette).map_err(|_| "Palette save failed")?;
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+/// Kiểm tra số nhập cho `key` giống endpoint POST tương ứng
+fn import_int(key: &str, value: u32) -> Result<(), &'static str> {
+    let valid = match key {
+        KEY_BOOT_ANIMATION | KEY_AUTO_BRIGHTNESS | KEY_POWER_SAVE | KEY_COLOR_CARRY | KEY_COMMAND_LOG
+        | KEY_SOFT_CLIP | KEY_INTERPOLATION | KEY_HUE_PRESERVE | KEY_CONNECT_FLASH => value <= 1,
+        KEY_AMBIENT_PIN => crate::ambient::is_usable_pin(value as u8),
+        KEY_AMBIENT_DARK | KEY_AMBIENT_BRIGHT => value <= 4095,
+        KEY_AMBIENT_MIN | KEY_OFFLINE_LEVEL | KEY_SATURATION => value <= 100,
+        KEY_IDLE_LEVEL => (1..=100).contains(&value),
+        KEY_COLOR_CYCLE => {
+            value == 0 || (crate::http::COLOR_CYCLE_MIN_S..=crate::http::COLOR_CYCLE_MAX_S).contains(&value)
+        }
+        KEY_CHIP_TYPE => crate::output::ChipType::from_u8(value as u8).is_some(),
+        // 0 = tắt
+        KEY_THERMAL => value <= crate::http::THERMAL_MAX_MIN as u32,
+        KEY_BUTTON_PIN => {
+            value as u8 == crate::button::PIN_DISABLED || crate::button::is_usable_pin(value as u8)
+        }
+        KEY_OFFLINE_MIN => (1..=crate::http::SLEEP_TIMER_MAX_MIN as u32).contains(&value),
+        KEY_IDLE_AMBIENT | KEY_IDLE_OFF => value <= crate::http::SLEEP_TIMER_MAX_MIN as u32,
+        _ => true,
+    };
+    if valid { Ok(()) } else { Err("Value out of range") }
+}
+
+/// Chuỗi sẽ ghi cho `key`, kiểm tra giống endpoint POST tương ứng
+fn import_str<'a>(key: &str, value: &'a str) -> Result<&'a str, &'static str> {
+    let valid = match key {
+        KEY_BASE_PATH => {
+            return normalize_base_path(value)
+                .filter(|p| !p.is_empty())
+                .ok_or("Invalid base_path");
+        }
+        KEY_SKIP_PIXELS => crate::http::parse_skip_pixels(value, crate::NUM_LEDS).is_some(),
+        KEY_BUTTON_ACTIONS => crate::button::parse_actions(value).is_some(),
+        KEY_ROTATION => crate::rotation::parse(value).is_ok(),
+        KEY_QUICK_COLORS => crate::quick_colors::parse(value).is_ok(),
+        KEY_CONNECT_COLOR => crate::color::parse_hex_color(value).is_ok(),
+        KEY_OFFLINE_MODE => crate::controller::OfflineBehavior::parse(value, 0).is_some(),
+        KEY_IDLE_EFFECT | KEY_BUTTON_FAVORITE => crate::effect::effect_from_name(value).is_some(),
+        _ => true,
+    };
+    if valid { Ok(value) } else { Err("Invalid value") }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_device_runs_every_step_from_v0() {
+        assert_eq!(plan_migration(0, 1), MigrationPlan::Upgrade(0..1));
+        assert_eq!(plan_migration(0, 3), MigrationPlan::Upgrade(0..3));
+        assert_eq!(plan_migration(2, 3), MigrationPlan::Upgrade(2..3));
+    }
+
+    #[test]
+    fn current_version_is_left_alone() {
+        assert_eq!(plan_migration(CONFIG_VERSION, CONFIG_VERSION), MigrationPlan::UpToDate);
+    }
+
+    #[test]
+    fn future_version_falls_back_to_reset() {
+        assert_eq!(plan_migration(CONFIG_VERSION + 1, CONFIG_VERSION), MigrationPlan::Reset);
+        assert_eq!(plan_migration(u8::MAX, CONFIG_VERSION), MigrationPlan::Reset);
+    }
+
+    #[test]
+    fn full_export_parses_back() {
+        use core::fmt::Write;
+
+        let mut json = std::string::String::new();
+        write!(json, "{{\"version\":{}", EXPORT_VERSION).unwrap();
+        for entry in EXPORT_KEYS {
+            match entry.kind {
+                KeyKind::U8 => write!(json, ",\"{}\":{}", entry.key, u8::MAX).unwrap(),
+                KeyKind::U32 => write!(json, ",\"{}\":{}", entry.key, u32::MAX).unwrap(),
+                KeyKind::Str => write!(json, ",\"{}\":\"x\"", entry.key).unwrap(),
+            }
+        }
+        for i in 0..crate::palettes::MAX_PALETTES {
+            write!(json, ",\"{}{}\":\"p{};FF0000,00FF00,0000FF\"", EXPORT_PALETTE_PREFIX, i, i).unwrap();
+        }
+        json.push('}');
+
+        let pairs = parse_flat_json(&json).expect("full export must parse");
+        assert_eq!(pairs.len(), 1 + EXPORT_KEYS.len() + crate::palettes::MAX_PALETTES);
+        assert!(matches!(pairs[0], ("version", JsonValue::Int(EXPORT_VERSION))));
+        assert!(matches!(pairs.last(), Some((k, JsonValue::Str(_))) if k.starts_with(EXPORT_PALETTE_PREFIX)));
+    }
+
+    #[test]
+    fn one_key_past_the_limit_is_rejected() {
+        let mut json = std::string::String::from("{");
+        for i in 0..=MAX_JSON_KEYS {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str(&format!("\"k{}\":{}", i, i));
+        }
+        json.push('}');
+
+        assert_eq!(parse_flat_json(&json).err(), Some("Too many keys"));
+    }
+
+    #[test]
+    fn upgrade_steps_are_consecutive() {
+        let MigrationPlan::Upgrade(steps) = plan_migration(0, 4) else { panic!("expected upgrade") };
+        assert_eq!(steps.collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    }
+}
@@ -1,69 +1,1187 @@
 use std::sync::{Arc, Mutex}; 
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+#[cfg(not(test))]
 use esp_idf_sys::esp_timer_get_time;
 use log::{info, warn};
 use smart_leds::RGB8;
-use ws2812_esp32_rmt_driver::Ws2812Esp32RmtDriver;
+use crate::output::LedOutput;
 use palette::{FromColor, Hsv, RgbHue, Srgb};
-use crate::audio::AudioData;
+use crate::ambient::AmbientLight;
+use crate::audio::SharedAudio;
 use crate::effect::*;
 
 pub struct LedController<'a> {
-    driver: Ws2812Esp32RmtDriver<'a>,
+    driver: Box<dyn LedOutput + 'a>,
     num_leds: usize,
+    // LED vật lý bị bỏ qua (hỏng...), đã sắp xếp. `buffer` chỉ chứa LED logic
+    skip_pixels: Vec<u16>,
+    // Hiệu ứng chỉ chạy trong buffer[start..end], phần còn lại tắt (None = cả dải)
+    range: Option<(usize, usize)>,
     brightness: u8,
+    // Độ sáng trước lần tắt gần nhất (mode=off, brightness 0, sleep timer, toggle)
+    restore_brightness: u8,
+    // Kênh màu khác 0 không bao giờ xuống dưới mức này sau khi giảm sáng
+    min_brightness: u8,
+    // Giữ tỉ lệ kênh (hue) khi rất tối, xem dim_pixel
+    hue_preserve: bool,
     buffer: Vec<RGB8>,
     tx_buffer: Vec<u8>,
     last_update: u64,
     frame_interval: u64, 
     current_effect: Box<dyn Effect>,
     needs_update: bool,
+    // Màu user đặt gần nhất (không phải màu tạm của color cycle)
     last_set_color: RGB8,
+    color_carry_over: ColorCarryOver,
+    // Batch command hiện tại có SetColor → đổi hiệu ứng không reset về màu mặc định
+    batch_sets_color: bool,
     last_set_speed: u8,
-    audio_data: Option<Arc<Mutex<AudioData>>>
+    // `param=color2` gần nhất, kể cả khi gửi trước `mode=gradient` (hiệu ứng cũ bỏ qua nó)
+    last_color2: RGB8,
+    audio_data: Option<Arc<SharedAudio>>,
+
+    // Beat sync: tăng speed hiệu ứng theo beat nhạc
+    beat_sync: bool,
+    last_beat_count: u32,
+    beat_boost_until: Option<u64>,
+
+    last_rssi_poll: u64,
+
+    // Đồng bộ đồng hồ với thiết bị khác (xem apply_sync)
+    current_effect_type: EffectType,
+    clock_offset_us: i64,
+
+    // Hiệu ứng tạm thời (identify, thông báo...) chạy đè lên hiệu ứng hiện tại
+    override_effect: Option<Override>,
+
+    // Tự động giảm sáng theo cảm biến ánh sáng (nhân thêm vào brightness)
+    ambient: Option<Arc<AmbientLight>>,
+    ambient_factor: u8,
+
+    // Tiết kiệm điện: giảm FPS khi cảnh đứng yên hoặc rất tối
+    power_save: bool,
+    idle_frames: u32,
+
+    // Hiệu ứng audio / thường dùng gần nhất (xem set_audio_mode)
+    audio_slot: Option<ParkedEffect>,
+    visual_slot: Option<ParkedEffect>,
+
+    // Hẹn giờ tắt (đếm ngược, theo đồng hồ local - không bị /sync dịch)
+    sleep_timer: Option<SleepTimer>,
+
+    // Bảo vệ nhiệt: giảm sáng sau thời gian dài chạy tải cao
+    thermal: Option<Thermal>,
+    thermal_factor: u8,
+    output_load: f32,
+
+    // Demo khi chưa cấu hình WiFi: tự đổi hiệu ứng tới khi có command/kết nối
+    demo: Option<Demo>,
+
+    // Nháy màu khi có kết nối WiFi / mất kết nối
+    connect_flash: Option<ConnectFlash>,
+
+    // Tắt / giảm sáng khi mất WiFi quá lâu (nhân thêm vào brightness)
+    offline: Option<Offline>,
+    offline_factor: u8,
+
+    // Không có command lâu: chuyển sang hiệu ứng ambient tối, rồi tắt (nhân thêm vào brightness)
+    idle_timeout: Option<IdleTimeout>,
+    idle_factor: u8,
+
+    // Tự đổi màu theo vòng hue; user đặt màu thủ công thì tạm dừng
+    color_cycle: Option<ColorCycle>,
+
+    // Party mode: tự đổi hiệu ứng audio khi chuyển bài
+    party: Option<PartyMode>,
+
+    // Lỗi ghi RMT liên tiếp và lần log gần nhất (log có giới hạn tần suất)
+    write_failures: u32,
+    last_write_log_us: u64,
+
+    // Đo khoảng cách thực giữa 2 lần xuất frame (xem /diag/perf)
+    last_show_us: u64,
+    timing: FrameTiming,
+
+    // Frame audio đã render gần nhất: audio effect chỉ render khi có frame mới
+    last_audio_frame: u32,
+    // Lúc thấy frame audio mới gần nhất, để nhận ra audio task đã dừng
+    audio_frame_seen_us: u64,
+
+    // Nội suy giữa các frame hiệu ứng chậm hơn FPS của controller
+    interpolation: Option<Interpolation>,
+}
+
+/// Khoảng cách thực tế giữa các frame đã xuất, tính từ lúc đổi hiệu ứng.
+/// Static effect chỉ xuất khi có thay đổi (hoặc keep-alive mỗi 2s) nên max có thể rất lớn - đúng thực tế.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTiming {
+    pub effect: &'static str,
+    pub target_us: u64,
+    pub frames: u32,
+    pub min_us: u64,
+    pub max_us: u64,
+    /// Thời gian nội suy lâu nhất của một frame (0 nếu tắt nội suy)
+    pub blend_us: u64,
+    /// Số lần audio effect bỏ render vì audio task chưa có frame mới
+    pub audio_skipped: u32,
+    total_us: u64,
+}
+
+impl FrameTiming {
+    const fn new(effect: &'static str) -> Self {
+        Self { effect, target_us: 0, frames: 0, min_us: 0, max_us: 0, blend_us: 0, audio_skipped: 0, total_us: 0 }
+    }
+
+    fn record(&mut self, interval_us: u64, target_us: u64) {
+        self.target_us = target_us;
+        self.min_us = if self.frames == 0 { interval_us } else { self.min_us.min(interval_us) };
+        self.max_us = self.max_us.max(interval_us);
+        self.total_us = self.total_us.saturating_add(interval_us);
+        self.frames = self.frames.saturating_add(1);
+    }
+
+    pub fn avg_us(&self) -> u64 {
+        if self.frames == 0 { 0 } else { self.total_us / self.frames as u64 }
+    }
+}
+
+// Tổng số lần ghi ra LED thất bại từ lúc boot (xem /diag/perf)
+static WRITE_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+pub fn write_error_count() -> u32 {
+    WRITE_ERRORS.load(Ordering::Relaxed)
+}
+
+// Ghi lỗi: log tối đa 1 lần/giây; sau 10 lần liên tiếp thử ghi lại ngay một lần
+const WRITE_ERROR_LOG_INTERVAL_US: u64 = 1_000_000;
+const WRITE_RETRY_THRESHOLD: u32 = 10;
+
+// LED task ghi, HTTP đọc. LED task chỉ try_lock nên không bao giờ bị chặn.
+static FRAME_TIMING: Mutex<FrameTiming> = Mutex::new(FrameTiming::new("Static"));
+
+/// Số liệu frame của hiệu ứng hiện tại
+pub fn frame_timing() -> Option<FrameTiming> {
+    FRAME_TIMING.lock().ok().map(|t| *t)
+}
+
+/// Trạng thái user nhìn thấy, kèm số version tăng mỗi lần có command được áp dụng
+#[derive(Debug, Clone, Copy)]
+pub struct StateSnapshot {
+    pub version: u32,
+    pub mode: &'static str,
+    /// Phần trăm, giống tham số `brightness` của /led
+    pub brightness: u8,
+    /// Phần trăm, độ sáng trước lần tắt gần nhất để bật lại đúng mức cũ
+    pub restore_brightness: u8,
+    pub speed: u8,
+    pub color: RGB8,
+    /// Phần trăm, giống /config/saturation
+    pub saturation: u8,
+    /// None = cả dải
+    pub range: Option<(u16, u16)>,
+    pub beat_sync: bool,
+}
+
+static STATE: Mutex<StateSnapshot> = Mutex::new(StateSnapshot {
+    version: 0,
+    mode: "static",
+    brightness: 100,
+    restore_brightness: 100,
+    speed: 128,
+    color: RGB8 { r: 0, g: 0, b: 0 },
+    saturation: 100,
+    range: None,
+    beat_sync: false,
+});
+
+/// Trạng thái mới nhất do LED task công bố (xem /state/wait)
+pub fn state_snapshot() -> Option<StateSnapshot> {
+    STATE.lock().ok().map(|s| *s)
+}
+
+/// Sleep timer làm gì khi user gửi command thủ công
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepTimerPolicy {
+    Keep,
+    Reset,
+    Cancel,
+}
+
+impl SleepTimerPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "keep" => Some(SleepTimerPolicy::Keep),
+            "reset" => Some(SleepTimerPolicy::Reset),
+            "cancel" => Some(SleepTimerPolicy::Cancel),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SleepTimerPolicy::Keep => "keep",
+            SleepTimerPolicy::Reset => "reset",
+            SleepTimerPolicy::Cancel => "cancel",
+        }
+    }
+}
+
+/// Màu khi đổi hiệu ứng: giữ màu user đặt gần nhất (kể cả khi hiệu ứng trước
+/// không dùng màu, vd rainbow), hoặc về màu mặc định của hiệu ứng.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum ColorCarryOver {
+    Preserve = 0,
+    EffectDefault = 1,
+}
+
+impl ColorCarryOver {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ColorCarryOver::EffectDefault,
+            _ => ColorCarryOver::Preserve,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "preserve" => Some(ColorCarryOver::Preserve),
+            "default" => Some(ColorCarryOver::EffectDefault),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorCarryOver::Preserve => "preserve",
+            ColorCarryOver::EffectDefault => "default",
+        }
+    }
+}
+
+/// Màu khởi tạo của hiệu ứng khi không giữ màu user
+const EFFECT_DEFAULT_COLOR: RGB8 = RGB8 { r: 255, g: 255, b: 255 };
+
+struct SleepTimer {
+    duration_us: u64,
+    deadline_us: u64,
+    policy: SleepTimerPolicy,
+}
+
+// Mốc tắt của sleep timer tính bằng giây từ lúc boot, 0 = không hẹn.
+// LED task ghi, HTTP đọc để báo thời gian còn lại.
+static SLEEP_DEADLINE_S: AtomicU32 = AtomicU32::new(0);
+
+/// Số giây còn lại trước khi sleep timer tắt đèn
+pub fn sleep_remaining_s() -> Option<u32> {
+    let deadline = SLEEP_DEADLINE_S.load(Ordering::Relaxed);
+    if deadline == 0 {
+        return None;
+    }
+    let now_s = (timer_us() / 1_000_000) as u32;
+    Some(deadline.saturating_sub(now_s))
+}
+
+/// Tích phân thời gian tải cao. Tải = mức trung bình của các kênh đã xuất (0-1),
+/// ước lượng tương đối dòng tiêu thụ.
+struct Thermal {
+    engage_s: f32,
+    heat_s: f32,
+    engaged: bool,
+}
+
+// Tải > 80% tính là nóng; dưới ngưỡng thì nguội với tốc độ bằng 1/2.
+// Khi kích hoạt: giảm dần về 60%, chỉ trả lại khi đã nguội hẳn (heat = 0).
+const THERMAL_HIGH_LOAD: f32 = 0.8;
+const THERMAL_COOL_RATE: f32 = 0.5;
+const THERMAL_MIN_FACTOR: u8 = 153;
+const THERMAL_SLEW_PER_S: f32 = 20.0;
+
+// Trạng thái bảo vệ nhiệt cho /health
+static THERMAL_ENABLED: AtomicBool = AtomicBool::new(false);
+static THERMAL_HEAT_S: AtomicU32 = AtomicU32::new(0);
+static THERMAL_LIMIT: AtomicU8 = AtomicU8::new(255);
+
+/// (bật, số giây tải cao đã tích lũy, giới hạn độ sáng 0-255)
+pub fn thermal_status() -> (bool, u32, u8) {
+    (
+        THERMAL_ENABLED.load(Ordering::Relaxed),
+        THERMAL_HEAT_S.load(Ordering::Relaxed),
+        THERMAL_LIMIT.load(Ordering::Relaxed),
+    )
+}
+
+struct Demo {
+    step: usize,
+    next_step_us: u64,
+}
+
+// Demo: mỗi hiệu ứng chạy 8s, màu đổi theo từng bước
+const DEMO_STEP_US: u64 = 8_000_000;
+const DEMO_HUE_STEP: f32 = 67.0;
+
+/// Theo dõi trạng thái kết nối (`wifi::has_connection`) để nháy thông báo
+struct ConnectFlash {
+    color: RGB8,
+    connected: bool,
+    next_poll_us: u64,
 }
 
+// Kiểm tra kết nối mỗi giây; mất kết nối luôn nháy đỏ cam
+const CONNECT_POLL_INTERVAL_US: u64 = 1_000_000;
+const CONNECT_LOST_COLOR: RGB8 = RGB8 { r: 255, g: 40, b: 0 };
+
+/// Làm gì khi mất kết nối Station lâu hơn thời gian chờ. Có kết nối lại thì trả độ sáng.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OfflineBehavior {
+    KeepRunning,
+    TurnOff,
+    /// Giảm sáng về `level`% độ sáng hiện tại
+    DimTo(u8),
+}
+
+impl OfflineBehavior {
+    /// `mode` = keep|off|dim, `level` (0-100) chỉ dùng cho dim
+    pub fn parse(mode: &str, level: u8) -> Option<Self> {
+        match mode {
+            "keep" => Some(OfflineBehavior::KeepRunning),
+            "off" => Some(OfflineBehavior::TurnOff),
+            "dim" if level <= 100 => Some(OfflineBehavior::DimTo(level)),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OfflineBehavior::KeepRunning => "keep",
+            OfflineBehavior::TurnOff => "off",
+            OfflineBehavior::DimTo(_) => "dim",
+        }
+    }
+
+    /// Hệ số độ sáng khi đã mất kết nối quá lâu
+    fn factor(&self) -> u8 {
+        match self {
+            OfflineBehavior::KeepRunning => 255,
+            OfflineBehavior::TurnOff => 0,
+            OfflineBehavior::DimTo(level) => (*level as u16 * 255 / 100) as u8,
+        }
+    }
+}
+
+struct Offline {
+    behavior: OfflineBehavior,
+    timeout_us: u64,
+    // Lúc bắt đầu mất kết nối (None = đang có kết nối)
+    lost_since_us: Option<u64>,
+    engaged: bool,
+    next_poll_us: u64,
+}
+
+/// Cấu hình idle timeout (từ `/config/idle`). Phút = 0 là bỏ qua bước đó.
+pub struct IdleSettings {
+    pub ambient_minutes: u16,
+    pub off_minutes: u16,
+    pub effect: EffectType,
+    /// Độ sáng khi ambient, % độ sáng hiện tại
+    pub level: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IdleStage {
+    Active,
+    Ambient,
+    Off,
+}
+
+struct IdleTimeout {
+    settings: IdleSettings,
+    // Command thủ công gần nhất (đồng hồ local, không bị /sync dịch)
+    last_command_us: u64,
+    stage: IdleStage,
+}
+
+// Tốc độ đổi độ sáng khi vào/ra idle: từ 255 về 0 mất khoảng 2s
+const IDLE_SLEW_PER_S: f32 = 128.0;
+
+struct ColorCycle {
+    period_us: u64,
+    paused: bool,
+    last_hue: u16,
+}
+
+/// Hiệu ứng đã cất đi cùng màu/speed lúc đó, instance giữ nguyên params
+struct ParkedEffect {
+    effect_type: EffectType,
+    effect: Box<dyn Effect>,
+    color: RGB8,
+    speed: u8,
+}
+
+pub const PARTY_MAX_EFFECTS: usize = 8;
+
+/// Cấu hình party mode (từ `/partymode`)
+pub struct PartySettings {
+    pub effects: heapless::Vec<EffectType, PARTY_MAX_EFFECTS>,
+    /// Khoảng im lặng tối thiểu được coi là hết bài
+    pub gap_us: u64,
+}
+
+struct PartyMode {
+    settings: PartySettings,
+    silent_since: Option<u64>,
+}
+
+/// Hiệu ứng tạm thời. Hiệu ứng gốc được giữ nguyên (không update) và
+/// tiếp tục khi override hết hạn.
+struct Override {
+    effect: Box<dyn Effect>,
+    until_us: u64,
+}
+
+/// Frame hiệu ứng mới không hiện ngay mà được chuyển dần tới trong khoảng thời gian
+/// bằng khoảng cách giữa 2 frame hiệu ứng gần nhất (trễ tối đa 1 frame hiệu ứng).
+/// `buffer` vẫn chỉ chứa frame của hiệu ứng, kết quả nội suy nằm trong `shown`.
+struct Interpolation {
+    from: Vec<RGB8>,
+    shown: Vec<RGB8>,
+    last_frame_us: u64,
+    step_us: u64,
+    active: bool,
+}
+
+// Khoảng cách giữa 2 frame hiệu ứng lớn hơn mức này (static, đổi màu...) thì hiện ngay,
+// không làm mờ cảnh tĩnh
+const INTERPOLATION_MAX_STEP_US: u64 = 250_000;
+
+impl Interpolation {
+    fn new(len: usize) -> Self {
+        Self {
+            from: vec![RGB8::default(); len],
+            shown: vec![RGB8::default(); len],
+            last_frame_us: 0,
+            step_us: 0,
+            active: false,
+        }
+    }
+
+    /// Hiệu ứng vừa ra frame mới trong `target`
+    fn start(&mut self, target: &[RGB8], now: u64, frame_interval: u64) {
+        let step = now.saturating_sub(self.last_frame_us);
+        self.last_frame_us = now;
+
+        if self.shown.len() != target.len() {
+            self.from = vec![RGB8::default(); target.len()];
+            self.shown = target.to_vec();
+            self.active = false;
+            return;
+        }
+
+        // Hiệu ứng chạy đủ FPS hoặc đứng yên lâu → không cần nội suy
+        self.active = step > frame_interval && step <= INTERPOLATION_MAX_STEP_US;
+        if self.active {
+            self.from.copy_from_slice(&self.shown);
+            self.step_us = step;
+        }
+    }
+
+    /// Ghi frame tại `now` vào `shown`. Đi trước 1 tick để tick đầu đã có thay đổi,
+    /// tới đích ở tick ngay trước frame hiệu ứng kế tiếp.
+    fn blend(&mut self, target: &[RGB8], now: u64, frame_interval: u64) {
+        if !self.active {
+            self.shown.copy_from_slice(target);
+            return;
+        }
+
+        let elapsed = now.saturating_sub(self.last_frame_us) + frame_interval;
+        if elapsed >= self.step_us {
+            self.shown.copy_from_slice(target);
+            self.active = false;
+            return;
+        }
+
+        let t = ((elapsed * 256) / self.step_us) as i32;
+        let lerp = |a: u8, b: u8| -> u8 { (a as i32 + (((b as i32 - a as i32) * t) >> 8)) as u8 };
+        for ((out, from), to) in self.shown.iter_mut().zip(&self.from).zip(target) {
+            *out = RGB8 { r: lerp(from.r, to.r), g: lerp(from.g, to.g), b: lerp(from.b, to.b) };
+        }
+    }
+}
+
+// Sync: sai lệch lớn hơn ngưỡng này thì nhảy thẳng, nhỏ hơn thì chỉnh dần 1/8 mỗi gói
+const SYNC_STEP_THRESHOLD_US: i64 = 50_000;
+const SYNC_SLEW_DIVISOR: i64 = 8;
+
+// Chu kỳ đọc RSSI cho các hiệu ứng cần nó
+const RSSI_POLL_INTERVAL_US: u64 = 1_000_000;
+
+// Power save: sau 30 frame không đổi → 10 FPS; brightness < 10% → 20 FPS.
+// Audio effect, beat sync và override luôn chạy đủ FPS.
+const POWER_SAVE_IDLE_FRAMES: u32 = 30;
+const POWER_SAVE_IDLE_INTERVAL_US: u64 = 100_000;
+const POWER_SAVE_DIM_THRESHOLD: u8 = 26;
+const POWER_SAVE_DIM_INTERVAL_US: u64 = 50_000;
+
+// Party mode: volume dưới mức này là im lặng (cùng ngưỡng với beat sync)
+const PARTY_SILENCE_LEVEL: f32 = 0.02;
+
+// Beat sync: speed tăng thêm 60% trong 200ms sau mỗi beat
+const BEAT_SYNC_BOOST: f32 = 0.6;
+const BEAT_SYNC_HOLD_US: u64 = 200_000;
+
+// Frame đứng yên vẫn được xuất lại định kỳ: LED bị nhiễu / cắm nóng tự về đúng màu
+const KEEP_ALIVE_US: u64 = 2_000_000;
+
+/// Đồng hồ esp_timer (µs từ lúc boot). Test thay bằng đồng hồ giả để điều khiển frame.
+#[cfg(not(test))]
+fn timer_us() -> i64 {
+    unsafe { esp_timer_get_time() }
+}
+
+#[cfg(test)]
+fn timer_us() -> i64 {
+    tests::CLOCK_US.with(|c| c.get())
+}
+
+// Audio task không công bố frame mới quá lâu (task lỗi/dừng) → bỏ việc chờ frame,
+// audio effect render đủ FPS như trước (vd ripple vẫn tự sinh sóng)
+const AUDIO_STALL_US: u64 = 200_000;
+
 impl<'a> LedController<'a> {
-    pub fn new(driver: Ws2812Esp32RmtDriver<'a>, num_leds: usize) -> Self {
+    pub fn new(driver: Box<dyn LedOutput + 'a>, num_leds: usize) -> Self {
         let default_color = RGB8 { r: 0, g: 0, b: 0 };
         let default_speed = 128;
         
         Self {
             driver: driver,
             num_leds,
+            skip_pixels: Vec::new(),
+            range: None,
             brightness: 255,
+            restore_brightness: 255,
+            min_brightness: 0,
+            hue_preserve: false,
             buffer: vec![RGB8 { r: 0, g: 0, b: 0 }; num_leds],
             tx_buffer: Vec::with_capacity(num_leds * 3),
-            last_update: unsafe { esp_timer_get_time() } as u64,
+            last_update: timer_us().max(0) as u64,
             frame_interval: 33_333, // set fps
             current_effect: Box::new(StaticEffect::new(default_color)),
             needs_update: true,
             last_set_color: default_color,
+            color_carry_over: ColorCarryOver::Preserve,
+            batch_sets_color: false,
             last_set_speed: default_speed,
-            audio_data: None
+            last_color2: GradientEffect::DEFAULT_COLOR2,
+            audio_data: None,
+            beat_sync: false,
+            last_beat_count: 0,
+            beat_boost_until: None,
+            last_rssi_poll: 0,
+            current_effect_type: EffectType::Static,
+            clock_offset_us: 0,
+            override_effect: None,
+            ambient: None,
+            ambient_factor: 255,
+            power_save: false,
+            idle_frames: 0,
+            sleep_timer: None,
+            thermal: None,
+            thermal_factor: 255,
+            output_load: 0.0,
+            demo: None,
+            connect_flash: None,
+            offline: None,
+            offline_factor: 255,
+            idle_timeout: None,
+            idle_factor: 255,
+            color_cycle: None,
+            audio_slot: None,
+            visual_slot: None,
+            party: None,
+            write_failures: 0,
+            last_write_log_us: 0,
+            last_show_us: 0,
+            last_audio_frame: 0,
+            audio_frame_seen_us: 0,
+            timing: FrameTiming::new("Static"),
+            interpolation: None,
+        }
+    }
+
+    pub fn set_audio_data(&mut self, audio_data: Arc<SharedAudio>) {
+        self.audio_data = Some(audio_data);
+        info!("Audio data source connected to LED controller");
+    }
+
+    pub fn set_ambient_light(&mut self, ambient: Arc<AmbientLight>) {
+        self.ambient = Some(ambient);
+    }
+
+    pub fn set_brightness(&mut self, level: f32) {
+        let new_level = (level.clamp(0.0, 1.0) * 255.0).round() as u8;
+        
+        if self.brightness != new_level {
+            if new_level == 0 {
+                self.restore_brightness = self.brightness;
+            }
+            self.brightness = new_level;
+            self.needs_update = true; // Brightness là toàn cục
+        }
+    }
+
+    /// Đặt danh sách LED vật lý bị bỏ qua. Hiệu ứng render vào buffer logic
+    /// `num_leds - skipped` LED, được trải lên các LED còn lại khi xuất.
+    pub fn set_skip_pixels(&mut self, pixels: &[u16]) {
+        let mut skip: Vec<u16> = pixels.iter().copied().filter(|&i| (i as usize) < self.num_leds).collect();
+        skip.sort_unstable();
+        skip.dedup();
+
+        if skip.len() >= self.num_leds {
+            warn!("Refusing to skip every LED");
+            return;
+        }
+        if skip == self.skip_pixels {
+            return;
+        }
+
+        info!("Skipping {} LEDs, {} logical LEDs", skip.len(), self.num_leds - skip.len());
+        self.buffer = vec![RGB8::default(); self.num_leds - skip.len()];
+        self.skip_pixels = skip;
+
+        // Vài hiệu ứng giữ vị trí theo độ dài cũ → tạo lại
+        self.set_effect(self.current_effect_type.clone());
+    }
+
+    /// Giới hạn hiệu ứng trong LED `start..end` (theo buffer logic, sau skip pixels),
+    /// các LED ngoài vùng tắt. None = cả dải.
+    pub fn set_range(&mut self, range: Option<(u16, u16)>) {
+        let range = range.map(|(start, end)| (start as usize, end as usize));
+        if range == self.range {
+            return;
+        }
+
+        match range {
+            Some((start, end)) => info!("Effect range {}..{}", start, end),
+            None => info!("Effect range cleared"),
+        }
+        self.range = range;
+
+        // Hiệu ứng giữ vị trí theo độ dài cũ → tạo lại cho độ dài vùng mới
+        self.set_effect(self.current_effect_type.clone());
+    }
+
+    /// Vùng buffer hiệu ứng được vẽ, đã kẹp theo độ dài buffer hiện tại
+    fn effect_range(&self) -> core::ops::Range<usize> {
+        let len = self.buffer.len();
+        match self.range {
+            Some((start, end)) => start.min(len)..end.min(len).max(start.min(len)),
+            None => 0..len,
+        }
+    }
+
+    pub fn set_min_brightness(&mut self, level: u8) {
+        if self.min_brightness != level {
+            self.min_brightness = level;
+            self.needs_update = true;
+        }
+    }
+
+    pub fn set_hue_preserve(&mut self, enabled: bool) {
+        if self.hue_preserve != enabled {
+            info!("Hue-preserving dimming {}", if enabled { "enabled" } else { "disabled" });
+            self.hue_preserve = enabled;
+            self.needs_update = true;
+        }
+    }
+
+    /// Áp độ sáng `scale` (256 = giữ nguyên) cho một pixel. Kênh tắt giữ nguyên 0,
+    /// kênh đang sáng không xuống dưới `floor`. Khi giữ hue, nếu kênh lớn nhất vẫn
+    /// sáng thì các kênh khác bị làm tròn về 0 được giữ ở 1 (cam tối không thành đỏ)
+    /// - độ sáng tổng gần như không đổi.
+    fn dim_pixel(pixel: &RGB8, scale: u16, floor: u8, hue_preserve: bool) -> RGB8 {
+        let max = pixel.r.max(pixel.g).max(pixel.b) as u16;
+        let floor = if hue_preserve && (max * scale) >> 8 > 0 { floor.max(1) } else { floor };
+        let channel = |v: u8| -> u8 {
+            if v == 0 || scale == 0 { 0 } else { (((v as u16 * scale) >> 8) as u8).max(floor) }
+        };
+        RGB8 { r: channel(pixel.r), g: channel(pixel.g), b: channel(pixel.b) }
+    }
+
+    pub fn set_power_save(&mut self, enabled: bool) {
+        self.power_save = enabled;
+        self.idle_frames = 0;
+        info!("Power save {}", if enabled { "enabled" } else { "disabled" });
+    }
+
+    pub fn set_interpolation(&mut self, enabled: bool) {
+        if enabled == self.interpolation.is_some() {
+            return;
+        }
+        self.interpolation = enabled.then(|| {
+            let mut interp = Interpolation::new(self.buffer.len());
+            interp.shown.copy_from_slice(&self.buffer);
+            interp
+        });
+        self.needs_update = true;
+        info!("Frame interpolation {}", if enabled { "enabled" } else { "disabled" });
+    }
+
+    /// Tăng version và công bố trạng thái hiện tại, gọi sau mỗi command
+    pub fn publish_state(&self) {
+        let mode = effect_name(&self.current_effect_type);
+
+        if let Ok(mut state) = STATE.lock() {
+            *state = StateSnapshot {
+                version: state.version.wrapping_add(1),
+                mode,
+                brightness: ((self.brightness as u16 * 100 + 127) / 255) as u8,
+                restore_brightness: ((self.restore_brightness as u16 * 100 + 127) / 255) as u8,
+                speed: self.last_set_speed,
+                color: self.last_set_color,
+                saturation: saturation_percent(),
+                range: self.range.map(|(start, end)| (start as u16, end as u16)),
+                beat_sync: self.beat_sync,
+            };
+        }
+    }
+
+    /// Cập nhật thanh tiến trình, chuyển sang hiệu ứng progress nếu đang ở hiệu ứng khác
+    pub fn set_progress(&mut self, fraction: f32) {
+        if self.current_effect_type != EffectType::Progress {
+            self.set_effect(EffectType::Progress);
+        }
+        if self.current_effect.set_progress(fraction) {
+            self.needs_update = true;
+        }
+    }
+
+    /// Bật bảo vệ nhiệt với ngưỡng `engage_minutes` phút tải cao (None = tắt)
+    pub fn set_thermal_protection(&mut self, engage_minutes: Option<u16>) {
+        match engage_minutes {
+            Some(minutes) => {
+                info!("Thermal protection enabled ({} min at high load)", minutes);
+                let heat_s = self.thermal.as_ref().map_or(0.0, |t| t.heat_s);
+                self.thermal = Some(Thermal { engage_s: minutes as f32 * 60.0, heat_s, engaged: false });
+            }
+            None => {
+                if self.thermal.take().is_some() {
+                    info!("Thermal protection disabled");
+                }
+                if self.thermal_factor != 255 {
+                    self.thermal_factor = 255;
+                    self.needs_update = true;
+                }
+            }
+        }
+        THERMAL_ENABLED.store(self.thermal.is_some(), Ordering::Relaxed);
+        THERMAL_LIMIT.store(self.thermal_factor, Ordering::Relaxed);
+    }
+
+    fn update_thermal(&mut self, delta_us: u64) {
+        let Some(ref mut thermal) = self.thermal else { return; };
+        let dt = delta_us.min(1_000_000) as f32 / 1_000_000.0;
+
+        if self.output_load > THERMAL_HIGH_LOAD {
+            thermal.heat_s += dt;
+        } else {
+            thermal.heat_s = (thermal.heat_s - dt * THERMAL_COOL_RATE).max(0.0);
+        }
+
+        if !thermal.engaged && thermal.heat_s >= thermal.engage_s {
+            thermal.engaged = true;
+            warn!("Thermal protection engaged after {:.0}s at high load, dimming", thermal.heat_s);
+        } else if thermal.engaged && thermal.heat_s <= 0.0 {
+            thermal.engaged = false;
+            info!("Thermal protection released after cool-down");
+        }
+
+        // Đổi dần để không thấy bước nhảy độ sáng
+        let target = if thermal.engaged { THERMAL_MIN_FACTOR } else { 255 };
+        let step = (THERMAL_SLEW_PER_S * dt).max(1.0) as u8;
+        let factor = if self.thermal_factor > target {
+            self.thermal_factor.saturating_sub(step).max(target)
+        } else {
+            self.thermal_factor.saturating_add(step).min(target)
+        };
+
+        THERMAL_HEAT_S.store(thermal.heat_s as u32, Ordering::Relaxed);
+        if factor != self.thermal_factor {
+            self.thermal_factor = factor;
+            self.needs_update = true;
+            THERMAL_LIMIT.store(factor, Ordering::Relaxed);
+        }
+    }
+
+    /// Bắt đầu demo (thiết bị chưa được cấu hình)
+    pub fn start_demo(&mut self) {
+        info!("Demo mode started");
+        self.demo = Some(Demo { step: 0, next_step_us: 0 });
+    }
+
+    /// Dừng đổi hiệu ứng, giữ nguyên hiệu ứng đang hiển thị
+    pub fn stop_demo(&mut self) {
+        if self.demo.take().is_some() {
+            info!("Demo mode stopped");
+        }
+    }
+
+    fn update_demo(&mut self, now: u64) {
+        let Some(ref mut demo) = self.demo else { return; };
+        if now < demo.next_step_us {
+            return;
+        }
+
+        let step = demo.step;
+        demo.step += 1;
+        demo.next_step_us = now + DEMO_STEP_US;
+
+        if crate::wifi::has_connection() {
+            self.stop_demo();
+            return;
+        }
+
+        // Demo lấy các hiệu ứng không cần micro trong rotation
+        let rotation = crate::rotation::list();
+        let effects: heapless::Vec<&EffectType, { crate::rotation::MAX_ROTATION }> =
+            rotation.iter().filter(|e| !e.is_audio_reactive()).collect();
+        let Some(effect) = effects.get(step % effects.len().max(1)) else { return; };
+
+        // Màu demo chỉ áp cho hiệu ứng, không ghi đè màu của user
+        self.set_effect((*effect).clone());
+        self.current_effect.set_color(hsv_to_rgb(step as f32 * DEMO_HUE_STEP % 360.0, 1.0, 1.0));
+    }
+
+    /// Nháy `color` khi có kết nối và nháy đỏ khi mất kết nối (None = tắt)
+    pub fn set_connect_flash(&mut self, color: Option<RGB8>) {
+        match color {
+            Some(color) => {
+                let connected = self.connect_flash.as_ref().map_or(false, |f| f.connected);
+                self.connect_flash = Some(ConnectFlash { color, connected, next_poll_us: 0 });
+            }
+            None => self.connect_flash = None,
+        }
+    }
+
+    fn update_connect_flash(&mut self, now: u64) {
+        let Some(ref mut flash) = self.connect_flash else { return; };
+        if now < flash.next_poll_us {
+            return;
+        }
+        flash.next_poll_us = now + CONNECT_POLL_INTERVAL_US;
+
+        let connected = crate::wifi::has_connection();
+        if connected == flash.connected {
+            return;
+        }
+        flash.connected = connected;
+
+        let color = if connected { flash.color } else { CONNECT_LOST_COLOR };
+        info!("WiFi {}", if connected { "connected" } else { "connection lost" });
+        let effect = IdentifyEffect::notify(color);
+        let duration_us = effect.duration_us();
+        self.start_override(Box::new(effect), duration_us);
+    }
+
+    /// Tắt / giảm sáng khi mất WiFi liên tục `minutes` phút, trả lại khi có kết nối
+    pub fn set_offline_behavior(&mut self, behavior: OfflineBehavior, minutes: u16) {
+        if behavior == OfflineBehavior::KeepRunning {
+            self.offline = None;
+            if self.offline_factor != 255 {
+                self.offline_factor = 255;
+                self.needs_update = true;
+            }
+            return;
+        }
+
+        info!("Offline behavior: {} after {} min without WiFi", behavior.as_str(), minutes);
+        // Giữ mốc mất kết nối hiện tại để đổi cấu hình không đếm lại từ đầu
+        let lost_since_us = self.offline.as_ref().and_then(|o| o.lost_since_us);
+        self.offline = Some(Offline {
+            behavior,
+            timeout_us: minutes as u64 * 60_000_000,
+            lost_since_us,
+            engaged: false,
+            next_poll_us: 0,
+        });
+        if self.offline_factor != 255 {
+            self.offline_factor = 255;
+            self.needs_update = true;
+        }
+    }
+
+    fn update_offline(&mut self, now: u64) {
+        let Some(ref mut offline) = self.offline else { return; };
+        if now < offline.next_poll_us {
+            return;
+        }
+        offline.next_poll_us = now + CONNECT_POLL_INTERVAL_US;
+
+        // Chỉ tính kết nối Station tới router; điện thoại vào AP fallback không tính
+        let factor = if crate::wifi::sta_connected() {
+            offline.lost_since_us = None;
+            if offline.engaged {
+                offline.engaged = false;
+                info!("WiFi back, restoring brightness");
+            }
+            255
+        } else {
+            let lost_since = *offline.lost_since_us.get_or_insert(now);
+            if !offline.engaged && now - lost_since >= offline.timeout_us {
+                offline.engaged = true;
+                warn!("No WiFi for {} s, offline behavior: {}", (now - lost_since) / 1_000_000, offline.behavior.as_str());
+            }
+            if offline.engaged { offline.behavior.factor() } else { 255 }
+        };
+
+        if factor != self.offline_factor {
+            self.offline_factor = factor;
+            self.needs_update = true;
+        }
+    }
+
+    /// Hẹn tắt đèn sau `minutes` phút (0 = hủy)
+    pub fn set_sleep_timer(&mut self, minutes: u16, policy: SleepTimerPolicy) {
+        if minutes == 0 {
+            if self.sleep_timer.take().is_some() {
+                info!("Sleep timer cancelled");
+            }
+            SLEEP_DEADLINE_S.store(0, Ordering::Relaxed);
+            return;
+        }
+
+        let duration_us = minutes as u64 * 60_000_000;
+        info!("Sleep timer: off in {} min (on command: {})", minutes, policy.as_str());
+        self.sleep_timer = Some(SleepTimer { duration_us, deadline_us: 0, policy });
+        self.arm_sleep_timer();
+    }
+
+    fn arm_sleep_timer(&mut self) {
+        let Some(ref mut timer) = self.sleep_timer else { return; };
+        let now = timer_us().max(0) as u64;
+        timer.deadline_us = now + timer.duration_us;
+        SLEEP_DEADLINE_S.store(timer.deadline_us.div_ceil(1_000_000) as u32, Ordering::Relaxed);
+    }
+
+    /// Gọi khi user gửi command thủ công: đếm lại idle timeout; giữ, đếm lại hoặc hủy sleep timer
+    pub fn on_manual_command(&mut self) {
+        self.reset_idle_timeout();
+
+        let Some(policy) = self.sleep_timer.as_ref().map(|t| t.policy) else { return; };
+        match policy {
+            SleepTimerPolicy::Keep => {}
+            SleepTimerPolicy::Reset => self.arm_sleep_timer(),
+            SleepTimerPolicy::Cancel => self.set_sleep_timer(0, policy),
+        }
+    }
+
+    /// Bật (Some) hoặc tắt (None) idle timeout, đếm từ bây giờ
+    pub fn set_idle_timeout(&mut self, settings: Option<IdleSettings>) {
+        self.leave_idle();
+        self.idle_timeout = settings.map(|settings| {
+            info!(
+                "Idle timeout: {} after {} min, off after {} min",
+                effect_name(&settings.effect), settings.ambient_minutes, settings.off_minutes
+            );
+            IdleTimeout {
+                settings,
+                last_command_us: timer_us().max(0) as u64,
+                stage: IdleStage::Active,
+            }
+        });
+        if self.idle_timeout.is_none() && self.idle_factor != 255 {
+            self.idle_factor = 255;
+            self.needs_update = true;
+        }
+    }
+
+    fn reset_idle_timeout(&mut self) {
+        let Some(ref mut idle) = self.idle_timeout else { return; };
+        idle.last_command_us = timer_us().max(0) as u64;
+        self.leave_idle();
+    }
+
+    /// Về trạng thái bình thường: bỏ hiệu ứng ambient, độ sáng tăng dần lại
+    fn leave_idle(&mut self) {
+        let Some(ref mut idle) = self.idle_timeout else { return; };
+        if idle.stage == IdleStage::Active {
+            return;
+        }
+        info!("Idle timeout: activity, restoring {}", self.current_effect.name());
+        idle.stage = IdleStage::Active;
+        if self.override_effect.take().is_some() {
+            self.needs_update = true;
+        }
+    }
+
+    fn update_idle_timeout(&mut self, delta_us: u64) {
+        let Some(ref mut idle) = self.idle_timeout else { return; };
+        let now = timer_us().max(0) as u64;
+        let idle_us = now.saturating_sub(idle.last_command_us);
+        let settings = &idle.settings;
+
+        let stage = if settings.off_minutes > 0 && idle_us >= settings.off_minutes as u64 * 60_000_000 {
+            IdleStage::Off
+        } else if settings.ambient_minutes > 0 && idle_us >= settings.ambient_minutes as u64 * 60_000_000 {
+            IdleStage::Ambient
+        } else {
+            IdleStage::Active
+        };
+        let target = match stage {
+            IdleStage::Active => 255,
+            IdleStage::Ambient => (settings.level as u16 * 255 / 100) as u8,
+            IdleStage::Off => 0,
+        };
+        let effect_type = settings.effect.clone();
+
+        let entered_ambient = stage == IdleStage::Ambient && idle.stage != IdleStage::Ambient;
+        if stage != idle.stage {
+            info!("Idle for {} s, stage {:?}", idle_us / 1_000_000, stage);
+            idle.stage = stage;
+        }
+
+        // Override khác (identify, nháy kết nối) thay chỗ ambient → bật lại khi nó xong
+        if stage == IdleStage::Ambient && (entered_ambient || self.override_effect.is_none()) {
+            let effect = self.build_effect(effect_type);
+            self.override_effect = Some(Override { effect, until_us: u64::MAX });
+            self.needs_update = true;
+        }
+
+        // Đổi dần để chuyển sang ambient / tắt không bị giật
+        let dt = delta_us.min(1_000_000) as f32 / 1_000_000.0;
+        let step = (IDLE_SLEW_PER_S * dt).max(1.0) as u8;
+        let factor = if self.idle_factor > target {
+            self.idle_factor.saturating_sub(step).max(target)
+        } else {
+            self.idle_factor.saturating_add(step).min(target)
+        };
+        if factor != self.idle_factor {
+            self.idle_factor = factor;
+            self.needs_update = true;
+        }
+    }
+
+    fn check_sleep_timer(&mut self) {
+        let Some(ref timer) = self.sleep_timer else { return; };
+        let now = timer_us().max(0) as u64;
+        if now < timer.deadline_us {
+            return;
+        }
+
+        info!("Sleep timer expired, turning off");
+        self.sleep_timer = None;
+        SLEEP_DEADLINE_S.store(0, Ordering::Relaxed);
+        self.set_brightness(0.0);
+        self.publish_state();
+    }
+
+    /// Đổi màu hiệu ứng qua một vòng hue mỗi `period_us` (0 = tắt).
+    /// Gọi lại (kể cả cùng chu kỳ) sẽ bỏ trạng thái tạm dừng.
+    pub fn set_color_cycle(&mut self, period_us: u64) {
+        if period_us == 0 {
+            if self.color_cycle.take().is_some() {
+                info!("Color cycle disabled");
+                // Trả lại màu user
+                if self.current_effect.set_color(self.last_set_color) {
+                    self.needs_update = true;
+                }
+            }
+            return;
+        }
+
+        info!("Color cycle every {}s", period_us / 1_000_000);
+        self.color_cycle = Some(ColorCycle { period_us, paused: false, last_hue: u16::MAX });
+    }
+
+    fn update_color_cycle(&mut self, now: u64) {
+        let Some(ref mut cycle) = self.color_cycle else { return; };
+        if cycle.paused {
+            return;
+        }
+
+        // Bước 1° là đủ mượt, tránh gọi set_color mỗi frame
+        let hue = ((now % cycle.period_us) * 360 / cycle.period_us) as u16;
+        if hue == cycle.last_hue {
+            return;
+        }
+        cycle.last_hue = hue;
+
+        if self.current_effect.set_color(hsv_to_rgb(hue as f32, 1.0, 1.0)) {
+            self.needs_update = true;
         }
     }
 
-    pub fn set_audio_data(&mut self, audio_data: Arc<Mutex<AudioData>>) {
-        self.audio_data = Some(audio_data);
-        info!("Audio data source connected to LED controller");
+    /// Bật (Some) hoặc tắt (None) party mode. Nếu hiệu ứng hiện tại không
+    /// nằm trong danh sách thì chuyển ngay sang một hiệu ứng của danh sách.
+    pub fn set_party_mode(&mut self, settings: Option<PartySettings>) {
+        let Some(settings) = settings else {
+            if self.party.take().is_some() {
+                info!("Party mode disabled");
+            }
+            return;
+        };
+
+        if settings.effects.is_empty() {
+            warn!("Party mode needs at least one effect");
+            return;
+        }
+
+        info!("Party mode enabled ({} effects, gap {}ms)", settings.effects.len(), settings.gap_us / 1000);
+        let switch = !settings.effects.contains(&self.current_effect_type);
+        self.party = Some(PartyMode { settings, silent_since: None });
+
+        if switch {
+            if let Some(next) = self.pick_party_effect() {
+                self.set_effect(next);
+            }
+        }
     }
 
-    pub fn set_brightness(&mut self, level: f32) {
-        let new_level = (level.clamp(0.0, 1.0) * 255.0).round() as u8;
-        
-        if self.brightness != new_level {
-            self.brightness = new_level;
-            self.needs_update = true; // Brightness là toàn cục
+    /// Chu kỳ frame thực tế khi không có thay đổi đang chờ
+    fn effective_frame_interval(&self) -> u64 {
+        if !self.power_save
+            || self.beat_sync
+            || self.override_effect.is_some()
+            || self.current_effect.is_audio_reactive()
+        {
+            return self.frame_interval;
+        }
+
+        if self.idle_frames >= POWER_SAVE_IDLE_FRAMES {
+            POWER_SAVE_IDLE_INTERVAL_US
+        } else if self.brightness < POWER_SAVE_DIM_THRESHOLD {
+            POWER_SAVE_DIM_INTERVAL_US
+        } else {
+            self.frame_interval
         }
     }
 
     pub fn set_color(&mut self, color: RGB8) {   
         self.last_set_color = color;
+        if let Some(ref mut cycle) = self.color_cycle {
+            if !cycle.paused {
+                info!("Color cycle paused by manual color");
+                cycle.paused = true;
+            }
+        }
         if self.current_effect.set_color(color) {
             self.needs_update = true;
         }
     }
 
+    pub fn set_beat_sync(&mut self, enabled: bool) {
+        if self.beat_sync == enabled {
+            return;
+        }
+
+        self.beat_sync = enabled;
+        info!("Beat sync {}", if enabled { "enabled" } else { "disabled" });
+
+        if !enabled && self.beat_boost_until.take().is_some() {
+            // Trả lại speed gốc của user
+            self.current_effect.set_speed(self.last_set_speed);
+        }
+    }
+
     pub fn set_speed(&mut self, speed: u8) {
         self.last_set_speed = speed;
         if self.current_effect.set_speed(speed) {
@@ -71,106 +1189,710 @@ impl<'a> LedController<'a> {
         }
     }
 
-    pub fn set_effect(&mut self, effect: EffectType) {
-        let new_effect: Box<dyn Effect> = match effect {
-            EffectType::Static => {
-                Box::new(StaticEffect::new(self.last_set_color))
-            }
-            EffectType::Rainbow => {
-                Box::new(RainbowEffect::new(self.num_leds, self.last_set_speed))
-            }
-            EffectType::Breathe => {
-                Box::new(BreatheEffect::new(self.last_set_color, self.last_set_speed))
+    pub fn set_param(&mut self, key: &str, value: &str) {
+        // `sat` áp cho mọi hiệu ứng, không riêng hiệu ứng hiện tại (không lưu NVS)
+        if key == "sat" {
+            match value.parse::<u8>() {
+                Ok(v) if v <= 100 => self.set_saturation(v),
+                _ => warn!("Invalid sat value: {}", value),
             }
-            EffectType::ColorWipe => {
-                Box::new(ColorWipeEffect::new(self.last_set_color, self.last_set_speed, self.num_leds))
-            }
-            EffectType::Comet => {
-                Box::new(CometEffect::new(self.last_set_color, self.last_set_speed, self.num_leds))
-            }
-            EffectType::Scanner => {
-                Box::new(ScannerEffect::new(self.last_set_color, self.last_set_speed, self.num_leds))
-            }
-             EffectType::TheaterChase => {
-                Box::new(TheaterChaseEffect::new(self.last_set_color, self.last_set_speed, self.num_leds))
+            return;
+        }
+
+        if key == "color2" {
+            if let Ok((r, g, b)) = crate::color::parse_hex_color(value) {
+                self.last_color2 = RGB8 { r, g, b };
             }
-             EffectType::Bounce => {
-                Box::new(BounceEffect::new(self.last_set_speed, self.num_leds))
+        }
+
+        if self.current_effect.set_param(key, value) {
+            self.needs_update = true;
+        }
+    }
+
+    /// Độ bão hoà (0-100) cho các hiệu ứng tự sinh màu theo hue
+    pub fn set_saturation(&mut self, percent: u8) {
+        if percent != saturation_percent() {
+            info!("Saturation {}%", percent);
+            set_saturation(percent);
+            self.needs_update = true;
+        }
+    }
+
+    /// Chạy `effect` đè lên hiệu ứng hiện tại trong `duration_us`, hoặc tới khi
+    /// hiệu ứng báo `is_complete` (chạy đủ số lần) nếu sớm hơn
+    pub fn start_override(&mut self, effect: Box<dyn Effect>, duration_us: u64) {
+        info!("Override started: {} for {}ms", effect.name(), duration_us / 1000);
+        self.override_effect = Some(Override {
+            effect,
+            until_us: self.now_us() + duration_us,
+        });
+        self.needs_update = true;
+    }
+
+    /// Nhấp nháy để nhận diện thiết bị, sau đó trả về trạng thái cũ
+    pub fn identify(&mut self, count: u8) {
+        let effect = IdentifyEffect::repeat(RGB8 { r: 255, g: 255, b: 255 }, count);
+        let duration_us = effect.duration_us();
+        self.start_override(Box::new(effect), duration_us);
+    }
+
+    /// Seed cho PRNG của hiệu ứng (bit thấp của đồng hồ)
+    fn seed(&self) -> u32 {
+        (timer_us() & 0xFFFF_FFFF) as u32
+    }
+
+    /// Thời gian đã đồng bộ (local + offset nhận từ `/sync`)
+    fn now_us(&self) -> u64 {
+        (timer_us() + self.clock_offset_us).max(0) as u64
+    }
+
+    /// Nhận mốc thời gian tham chiếu từ thiết bị "leader".
+    ///
+    /// Jitter mạng tới ±50ms được hấp thụ bằng cách chỉnh offset dần (1/8 mỗi
+    /// gói); sai lệch lớn hơn thì nhảy thẳng. Gửi gói mỗi 1-5s là đủ.
+    /// `restart` khởi động lại hiệu ứng hiện tại để mọi thiết bị cùng pha.
+    pub fn apply_sync(&mut self, reference_us: u64, restart: bool) {
+        let error = reference_us as i64 - self.now_us() as i64;
+
+        if error.abs() > SYNC_STEP_THRESHOLD_US {
+            self.clock_offset_us += error;
+            info!("Clock sync: stepped by {}us", error);
+        } else {
+            self.clock_offset_us += error / SYNC_SLEW_DIVISOR;
+        }
+
+        if restart {
+            self.set_effect(self.current_effect_type.clone());
+            // Frame đầu tiên bắt đầu ở tick chung kế tiếp
+            let now = self.now_us();
+            self.last_update = now - now % self.frame_interval;
+        }
+    }
+
+    pub fn set_color_carry_over(&mut self, mode: ColorCarryOver) {
+        info!("Color on effect switch: {}", mode.as_str());
+        self.color_carry_over = mode;
+    }
+
+    /// LED task báo trước khi áp một batch command: có màu gửi kèm
+    /// (vd `/led color=..&mode=..`) thì màu đó thắng màu mặc định của hiệu ứng
+    pub fn begin_batch(&mut self, sets_color: bool) {
+        self.batch_sets_color = sets_color;
+    }
+
+    pub fn set_effect(&mut self, effect: EffectType) {
+        // Khởi động lại cùng hiệu ứng (sync) thì luôn giữ màu
+        if self.color_carry_over == ColorCarryOver::EffectDefault
+            && !self.batch_sets_color
+            && effect != self.current_effect_type
+        {
+            self.last_set_color = EFFECT_DEFAULT_COLOR;
+        }
+
+        let new_effect = self.build_effect(effect.clone());
+
+        // Đổi giữa audio ↔ thường: cất hiệu ứng cũ để /audio-mode lấy lại được
+        if effect.is_audio_reactive() != self.current_effect_type.is_audio_reactive() {
+            self.park_current();
+        }
+
+        self.install_effect(effect, new_effect);
+    }
+
+    /// Chuyển sang hiệu ứng audio (true) hoặc hiệu ứng thường (false) đã dùng
+    /// gần nhất, giữ nguyên màu/speed/params của nó. Chưa có thì dùng mặc định.
+    pub fn set_audio_mode(&mut self, enabled: bool) {
+        if self.current_effect_type.is_audio_reactive() == enabled {
+            return;
+        }
+
+        let slot = if enabled { self.audio_slot.take() } else { self.visual_slot.take() };
+        self.park_current();
+
+        match slot {
+            Some(slot) => {
+                info!("Audio mode {}: restoring {}", if enabled { "on" } else { "off" }, slot.effect.name());
+                self.last_set_color = slot.color;
+                self.last_set_speed = slot.speed;
+                self.install_effect(slot.effect_type, slot.effect);
             }
-            EffectType::AudioVolumeBar => {
-                Box::new(AudioVolumeBarEffect::new(self.last_set_color, self.num_leds))
+            None => {
+                let effect = if enabled { EffectType::AudioVolumeBar } else { EffectType::Static };
+                let new_effect = self.build_effect(effect.clone());
+                self.install_effect(effect, new_effect);
             }
+        }
+    }
 
+    /// Cất hiệu ứng hiện tại vào slot tương ứng (audio hoặc thường)
+    fn park_current(&mut self) {
+        let slot = ParkedEffect {
+            effect_type: self.current_effect_type.clone(),
+            effect: core::mem::replace(&mut self.current_effect, Box::new(StaticEffect::new(RGB8::default()))),
+            color: self.last_set_color,
+            speed: self.last_set_speed,
         };
-        
+
+        if slot.effect_type.is_audio_reactive() {
+            self.audio_slot = Some(slot);
+        } else {
+            self.visual_slot = Some(slot);
+        }
+    }
+
+    fn install_effect(&mut self, effect_type: EffectType, new_effect: Box<dyn Effect>) {
+        self.current_effect_type = effect_type;
         info!("Effect changed to: {}", new_effect.name());
+        self.timing = FrameTiming::new(new_effect.name());
+        self.last_show_us = 0;
+        self.publish_timing();
         self.current_effect = new_effect;
         self.needs_update = true; 
+        self.last_rssi_poll = 0; // Hiệu ứng RSSI nhận dữ liệu ngay frame đầu
+    }
+
+    fn build_effect(&self, effect: EffectType) -> Box<dyn Effect> {
+        let params = EffectParams {
+            color: self.last_set_color,
+            color2: self.last_color2,
+            speed: self.last_set_speed,
+            num_leds: self.effect_range().len(),
+            seed: self.seed(),
+        };
+        crate::effect::build_effect(&effect, &params)
     }
 
     pub fn update(&mut self) {
-        let now = unsafe { esp_timer_get_time() } as u64;
+        let now = self.now_us();
+
+        // Đồng hồ bị chỉnh lùi bởi sync
+        if now < self.last_update {
+            self.last_update = now;
+            return;
+        }
         
-        if now - self.last_update < self.frame_interval { return; }
-        let delta_us = now.saturating_sub(self.last_update);
-        self.last_update = now;
+        // Có thay đổi đang chờ (command mới...) → không chờ chu kỳ power save
+        let interval = if self.needs_update { self.frame_interval } else { self.effective_frame_interval() };
+        if now - self.last_update < interval { return; }
 
-        if self.current_effect.update(delta_us) {
-            self.needs_update = true;
+        // Căn frame theo tick chung để các thiết bị đã sync render cùng lúc
+        let frame_start = now - now % self.frame_interval;
+        let delta_us = frame_start.saturating_sub(self.last_update);
+        self.last_update = frame_start;
+
+        self.update_thermal(delta_us);
+
+        if self.beat_sync {
+            self.update_beat_sync(now);
+        }
+
+        if self.party.is_some() {
+            self.update_party(now);
+        }
+
+        self.check_sleep_timer();
+        self.update_demo(now);
+        self.update_connect_flash(now);
+        self.update_offline(now);
+        self.update_idle_timeout(delta_us);
+
+        self.update_color_cycle(now);
+
+        if let Some(ref ambient) = self.ambient {
+            let factor = ambient.factor();
+            if factor != self.ambient_factor {
+                self.ambient_factor = factor;
+                self.needs_update = true;
+            }
+        }
+
+        if self.current_effect.uses_rssi() && now.saturating_sub(self.last_rssi_poll) >= RSSI_POLL_INTERVAL_US {
+            self.last_rssi_poll = now;
+            if self.current_effect.set_rssi(crate::wifi::current_rssi()) {
+                self.needs_update = true;
+            }
+        }
+
+        // Override hết hạn hoặc đã chạy xong → quay lại hiệu ứng gốc
+        if let Some(ref o) = self.override_effect {
+            if now >= o.until_us || o.effect.is_complete() {
+                info!("Override ended, restoring {}", self.current_effect.name());
+                self.override_effect = None;
+                self.needs_update = true;
+            }
+        }
+
+        let range = self.effect_range();
+        let effect = match self.override_effect.as_mut() {
+            Some(o) => &mut o.effect,
+            None => &mut self.current_effect,
+        };
+
+        // Audio effect luôn báo cần render, nhưng audio task chưa có frame mới
+        // thì render lại chỉ vẽ đúng dữ liệu cũ → bỏ qua, tiết kiệm CPU core LED
+        let audio_frame = match self.audio_data {
+            Some(ref audio_data) if effect.is_audio_reactive() => Some(audio_data.frame_count()),
+            _ => None,
+        };
+        if audio_frame.is_some_and(|f| f != self.last_audio_frame) {
+            self.audio_frame_seen_us = now;
+        }
+        let audio_stale = audio_frame == Some(self.last_audio_frame)
+            && now.saturating_sub(self.audio_frame_seen_us) < AUDIO_STALL_US;
+
+        if effect.update(delta_us) {
+            if !audio_stale {
+                self.needs_update = true;
+            } else if !self.needs_update {
+                self.timing.audio_skipped = self.timing.audio_skipped.saturating_add(1);
+            }
+        }
+
+        // Tick giữa 2 frame hiệu ứng: vẫn xuất frame nội suy
+        let interpolating = self.interpolation.as_ref().is_some_and(|i| i.active);
+
+        if self.needs_update || interpolating {
+            self.idle_frames = 0;
+        } else {
+            self.idle_frames = self.idle_frames.saturating_add(1);
         }
 
         // Chỉ render nếu cần
         if self.needs_update {
-            if self.current_effect.is_audio_reactive() {
+            // Hiệu ứng chỉ thấy vùng của nó, ngoài vùng luôn tắt
+            self.buffer[..range.start].fill(RGB8::default());
+            self.buffer[range.end..].fill(RGB8::default());
+            let region = &mut self.buffer[range];
+
+            if effect.is_audio_reactive() {
                 // Audio reactive effect - cần audio data
                 if let Some(ref audio_data) = self.audio_data {
-                    if let Ok(audio) = audio_data.lock() {
-                        self.current_effect.render_audio(&mut self.buffer, &audio, now);
+                    let audio = audio_data.latest();
+                    if audio.audio_available {
+                        effect.render_audio(region, &audio, now);
                     } else {
-                        // Fallback nếu không lock được
-                        self.current_effect.render(&mut self.buffer);
+                        // Không có micro - hiển thị trạng thái chờ
+                        effect.render(region);
                     }
                 } else {
                     // Không có audio data - render bình thường
                     warn!("Audio effect active but no audio data source!");
-                    self.current_effect.render(&mut self.buffer);
+                    effect.render(region);
                 }
             } else {
                 // Normal effect
-                self.current_effect.render(&mut self.buffer);
+                effect.render(region);
             }
             
-            self.update_display();
             self.needs_update = false;
+            if let Some(frame) = audio_frame {
+                self.last_audio_frame = frame;
+            }
+            if let Some(ref mut interp) = self.interpolation {
+                interp.start(&self.buffer, now, self.frame_interval);
+            }
+            self.show_frame(now);
+        } else if interpolating || now.saturating_sub(self.last_show_us) >= KEEP_ALIVE_US {
+            // Không render lại hiệu ứng, chỉ gửi lại frame hiện tại
+            self.show_frame(now);
+        }
+    }
+
+    /// Xuất `buffer` ra LED, qua bước nội suy nếu đang bật
+    fn show_frame(&mut self, now: u64) {
+        let Some(interp) = self.interpolation.as_mut() else {
+            self.update_display();
+            self.record_frame(now);
+            return;
+        };
+
+        let started = timer_us();
+        interp.blend(&self.buffer, now, self.frame_interval);
+        let cost = (timer_us() - started).max(0) as u64;
+        self.timing.blend_us = self.timing.blend_us.max(cost);
+
+        // Đổi chỗ tạm để update_display xuất frame nội suy, buffer hiệu ứng giữ nguyên
+        self.swap_shown();
+        self.update_display();
+        self.swap_shown();
+        self.record_frame(now);
+    }
+
+    fn swap_shown(&mut self) {
+        if let Some(interp) = self.interpolation.as_mut() {
+            std::mem::swap(&mut self.buffer, &mut interp.shown);
+        }
+    }
+
+    fn record_frame(&mut self, now: u64) {
+        if self.last_show_us != 0 && now > self.last_show_us {
+            let target = self.effective_frame_interval();
+            self.timing.record(now - self.last_show_us, target);
+            self.publish_timing();
+        }
+        self.last_show_us = now;
+    }
+
+    fn handle_write_error(&mut self, e: anyhow::Error) {
+        self.write_failures = self.write_failures.saturating_add(1);
+        WRITE_ERRORS.fetch_add(1, Ordering::Relaxed);
+
+        let now = timer_us().max(0) as u64;
+        if self.write_failures == 1 || now.saturating_sub(self.last_write_log_us) >= WRITE_ERROR_LOG_INTERVAL_US {
+            self.last_write_log_us = now;
+            warn!("LED write error ({} in a row): {:?}", self.write_failures, e);
+        }
+
+        if self.write_failures == WRITE_RETRY_THRESHOLD {
+            log::error!("LED output keeps failing - check data wiring and power");
+            if self.driver.write(&self.tx_buffer).is_ok() {
+                info!("LED output recovered on retry");
+                self.write_failures = 0;
+            }
+        }
+
+        // Frame sau render lại toàn bộ thay vì chờ hiệu ứng đổi
+        self.needs_update = true;
+    }
+
+    fn publish_timing(&self) {
+        if let Ok(mut shared) = FRAME_TIMING.try_lock() {
+            *shared = self.timing;
+        }
+    }
+
+    /// Chỉ gọi set_speed 2 lần mỗi beat (tăng rồi trả về) để hiệu ứng
+    /// tự làm mượt bằng cơ chế riêng của nó, không bị giành quyền mỗi frame
+    fn update_beat_sync(&mut self, now: u64) {
+        let Some(ref audio_data) = self.audio_data else { return; };
+
+        let audio = audio_data.latest();
+        let (beat_count, has_audio) = (audio.beat_count, audio.volume > 0.02);
+
+        if beat_count != self.last_beat_count {
+            self.last_beat_count = beat_count;
+
+            if has_audio {
+                let boosted = (self.last_set_speed as f32 * (1.0 + BEAT_SYNC_BOOST)).min(255.0) as u8;
+                self.current_effect.set_speed(boosted);
+                self.beat_boost_until = Some(now + BEAT_SYNC_HOLD_US);
+                return;
+            }
+        }
+
+        if let Some(until) = self.beat_boost_until {
+            if now >= until {
+                self.current_effect.set_speed(self.last_set_speed);
+                self.beat_boost_until = None;
+            }
+        }
+    }
+
+    /// Hết bài = im lặng ít nhất `gap_us` rồi có tiếng trở lại
+    fn update_party(&mut self, now: u64) {
+        let Some(ref audio_data) = self.audio_data else { return; };
+
+        let audio = audio_data.latest();
+        if !audio.audio_available {
+            return;
+        }
+        let volume = audio.volume;
+
+        let Some(party) = self.party.as_mut() else { return; };
+
+        if volume < PARTY_SILENCE_LEVEL {
+            party.silent_since.get_or_insert(now);
+            return;
+        }
+
+        let Some(since) = party.silent_since.take() else { return; };
+        if now.saturating_sub(since) < party.settings.gap_us {
+            return; // Đoạn lặng trong bài
         }
+
+        if let Some(next) = self.pick_party_effect() {
+            info!("Party mode: new song, switching to {:?}", next);
+            self.set_effect(next);
+            self.publish_state();
+        }
+    }
+
+    /// Chọn ngẫu nhiên trong danh sách, tránh lặp lại hiệu ứng hiện tại
+    fn pick_party_effect(&self) -> Option<EffectType> {
+        let effects = &self.party.as_ref()?.settings.effects;
+
+        let candidates: heapless::Vec<&EffectType, PARTY_MAX_EFFECTS> = effects
+            .iter()
+            .filter(|e| **e != self.current_effect_type)
+            .collect();
+
+        if candidates.is_empty() {
+            return effects.first().cloned();
+        }
+
+        let index = self.seed() as usize % candidates.len();
+        Some(candidates[index].clone())
+    }
+
+    /// Tắt toàn bộ LED ngay lập tức (dùng khi shutdown)
+    pub fn blank(&mut self) {
+        self.buffer.fill(RGB8::default());
+        self.update_display();
     }
 
     fn update_display(&mut self) {
         self.tx_buffer.clear();
-        let brightness = self.brightness;
+        let brightness = ((self.brightness as u16 * self.ambient_factor as u16) / 255) as u8;
+        let brightness = ((brightness as u16 * self.thermal_factor as u16) / 255) as u8;
+        let brightness = ((brightness as u16 * self.offline_factor as u16) / 255) as u8;
+        let brightness = ((brightness as u16 * self.idle_factor as u16) / 255) as u8;
+        let floor = self.min_brightness;
 
-        if brightness == 255 { 
+        if brightness == 255 && floor == 0 { 
             for pixel in &self.buffer { 
                 self.tx_buffer.extend_from_slice(&[pixel.g, pixel.r, pixel.b]);
             }
         } else {
             
-            let scale = brightness as u16;
-            
+            let scale = if brightness == 255 { 256 } else { brightness as u16 }; // 255 = giữ nguyên màu gốc
+
             for pixel in &self.buffer {
-                let scaled = RGB8 {
-                    r: ((pixel.r as u16 * scale) >> 8) as u8,
-                    g: ((pixel.g as u16 * scale) >> 8) as u8,
-                    b: ((pixel.b as u16 * scale) >> 8) as u8,
-                };
+                let scaled = Self::dim_pixel(pixel, scale, floor, self.hue_preserve);
                 self.tx_buffer.extend_from_slice(&[scaled.g, scaled.r, scaled.b]);
             }
         }
 
+        // Chèn LED tắt vào vị trí bị bỏ qua (danh sách đã sắp xếp tăng dần)
+        for &index in &self.skip_pixels {
+            let at = index as usize * 3;
+            if at <= self.tx_buffer.len() {
+                self.tx_buffer.splice(at..at, [0u8; 3]);
+            }
+        }
+
        
-        if let Err(e) = self.driver.write_blocking(self.tx_buffer.iter().cloned()) {
-            warn!("LED write error: {:?}", e);
+        if self.thermal.is_some() {
+            let total: u32 = self.tx_buffer.iter().map(|&v| v as u32).sum();
+            self.output_load = total as f32 / (self.tx_buffer.len().max(1) as f32 * 255.0);
+        }
+
+        match self.driver.write(&self.tx_buffer) {
+            Ok(()) => {
+                if self.write_failures > 0 {
+                    info!("LED output recovered after {} failed writes", self.write_failures);
+                    self.write_failures = 0;
+                }
+            }
+            Err(e) => self.handle_write_error(e),
+        }
+
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    thread_local! {
+        // Đồng hồ giả thay cho esp_timer_get_time, xem timer_us
+        pub(super) static CLOCK_US: Cell<i64> = const { Cell::new(1_000_000) };
+    }
+
+    fn advance(us: i64) {
+        CLOCK_US.with(|c| c.set(c.get() + us));
+    }
+
+    fn rgb(r: u8, g: u8, b: u8) -> RGB8 {
+        RGB8 { r, g, b }
+    }
+
+    /// Ghi lại mọi frame controller xuất ra
+    struct RecordingOutput(Rc<RefCell<Vec<Vec<u8>>>>);
+
+    impl LedOutput for RecordingOutput {
+        fn write(&mut self, grb: &[u8]) -> anyhow::Result<()> {
+            self.0.borrow_mut().push(grb.to_vec());
+            Ok(())
+        }
+    }
+
+    /// Hiệu ứng một màu, đếm số lần render; `changed` là giá trị update() trả về
+    struct StubEffect {
+        color: RGB8,
+        changed: Rc<Cell<bool>>,
+        renders: Rc<Cell<u32>>,
+    }
+
+    impl Effect for StubEffect {
+        fn update(&mut self, _delta_us: u64) -> bool {
+            self.changed.get()
+        }
+
+        fn render(&self, buffer: &mut [RGB8]) {
+            self.renders.set(self.renders.get() + 1);
+            buffer.fill(self.color);
+        }
+
+        fn name(&self) -> &'static str {
+            "Stub"
+        }
+    }
+
+    struct Harness {
+        controller: LedController<'static>,
+        frames: Rc<RefCell<Vec<Vec<u8>>>>,
+        changed: Rc<Cell<bool>>,
+        renders: Rc<Cell<u32>>,
+    }
+
+    impl Harness {
+        fn new(num_leds: usize, color: RGB8) -> Self {
+            let frames = Rc::new(RefCell::new(Vec::new()));
+            let changed = Rc::new(Cell::new(false));
+            let renders = Rc::new(Cell::new(0));
+
+            let mut controller = LedController::new(Box::new(RecordingOutput(frames.clone())), num_leds);
+            controller.current_effect = Box::new(StubEffect { color, changed: changed.clone(), renders: renders.clone() });
+
+            Self { controller, frames, changed, renders }
+        }
+
+        /// Chạy một tick sau `us` micro giây
+        fn tick(&mut self, us: i64) {
+            advance(us);
+            self.controller.update();
+        }
+
+        fn frame_count(&self) -> usize {
+            self.frames.borrow().len()
+        }
+
+        fn last_frame(&self) -> Vec<u8> {
+            self.frames.borrow().last().cloned().expect("no frame written")
+        }
+    }
+
+    #[test]
+    fn pending_change_renders_on_next_tick() {
+        let mut h = Harness::new(3, rgb(10, 20, 30));
+
+        // Chưa tới chu kỳ frame → chưa xuất
+        h.tick(1_000);
+        assert_eq!(h.frame_count(), 0);
+
+        h.tick(40_000);
+        assert_eq!(h.frame_count(), 1);
+        assert_eq!(h.renders.get(), 1);
+
+        // Hiệu ứng đứng yên, không có thay đổi → không render lại
+        h.tick(40_000);
+        assert_eq!(h.frame_count(), 1);
+
+        // Command đổi độ sáng buộc render dù hiệu ứng không đổi
+        h.controller.set_brightness(0.5);
+        h.tick(40_000);
+        assert_eq!(h.frame_count(), 2);
+        assert_eq!(h.renders.get(), 2);
+    }
+
+    #[test]
+    fn changing_effect_renders_every_frame() {
+        let mut h = Harness::new(2, rgb(1, 2, 3));
+        h.changed.set(true);
+
+        for _ in 0..5 {
+            h.tick(40_000);
+        }
+        assert_eq!(h.frame_count(), 5);
+        assert_eq!(h.renders.get(), 5);
+    }
+
+    #[test]
+    fn static_frame_is_resent_as_keep_alive() {
+        let mut h = Harness::new(2, rgb(10, 20, 30));
+        h.tick(40_000);
+        assert_eq!(h.frame_count(), 1);
+
+        // 19 x 100ms: chưa đủ 2s kể từ frame trước
+        for _ in 0..19 {
+            h.tick(100_000);
+        }
+        assert_eq!(h.frame_count(), 1);
+
+        h.tick(100_000);
+        assert_eq!(h.frame_count(), 2);
+        // Gửi lại đúng frame cũ, không render lại hiệu ứng
+        assert_eq!(h.renders.get(), 1);
+        assert_eq!(h.frames.borrow()[0], h.frames.borrow()[1]);
+    }
+
+    #[test]
+    fn full_brightness_writes_grb_bytes() {
+        let mut h = Harness::new(2, rgb(10, 20, 30));
+        h.tick(40_000);
+        assert_eq!(h.last_frame(), vec![20, 10, 30, 20, 10, 30]);
+    }
+
+    #[test]
+    fn brightness_scales_every_channel() {
+        let mut h = Harness::new(2, rgb(200, 100, 50));
+        h.controller.set_brightness(0.5); // 128/256
+        h.tick(40_000);
+        assert_eq!(h.last_frame(), vec![50, 100, 25, 50, 100, 25]);
+    }
+
+    #[test]
+    fn zero_brightness_writes_black() {
+        let mut h = Harness::new(3, rgb(255, 255, 255));
+        h.controller.set_brightness(0.0);
+        h.tick(40_000);
+        assert_eq!(h.last_frame(), vec![0; 9]);
+    }
+
+    #[test]
+    fn skipped_pixels_are_written_dark() {
+        let mut h = Harness::new(3, rgb(10, 20, 30));
+        h.controller.set_skip_pixels(&[1]);
+        h.controller.current_effect = Box::new(StubEffect {
+            color: rgb(10, 20, 30),
+            changed: h.changed.clone(),
+            renders: h.renders.clone(),
+        });
+        h.tick(40_000);
+        assert_eq!(h.last_frame(), vec![20, 10, 30, 0, 0, 0, 20, 10, 30]);
+    }
+
+    #[test]
+    fn dim_orange_keeps_its_green_channel() {
+        assert_eq!(LedController::dim_pixel(&rgb(255, 1, 0), 13, 0, true), rgb(12, 1, 0));
+        assert_eq!(LedController::dim_pixel(&rgb(255, 3, 0), 13, 0, true), rgb(12, 1, 0));
+    }
+
+    #[test]
+    fn hue_preserve_does_not_boost_brightness() {
+        for pixel in [rgb(255, 1, 0), rgb(255, 3, 0), rgb(200, 2, 40)] {
+            let plain = LedController::dim_pixel(&pixel, 13, 0, false);
+            let kept = LedController::dim_pixel(&pixel, 13, 0, true);
+            assert_eq!(kept.r.max(kept.g).max(kept.b), plain.r.max(plain.g).max(plain.b));
         }
+    }
+
+    #[test]
+    fn without_hue_preserve_small_channels_round_to_zero() {
+        assert_eq!(LedController::dim_pixel(&rgb(255, 1, 0), 13, 0, false), rgb(12, 0, 0));
+    }
+
+    #[test]
+    fn pixel_too_dim_to_light_stays_off() {
+        assert_eq!(LedController::dim_pixel(&rgb(10, 1, 0), 13, 0, true), rgb(0, 0, 0));
+        assert_eq!(LedController::dim_pixel(&rgb(255, 1, 0), 0, 0, true), rgb(0, 0, 0));
+    }
 
+    #[test]
+    fn full_scale_is_unchanged() {
+        assert_eq!(LedController::dim_pixel(&rgb(255, 1, 7), 256, 0, true), rgb(255, 1, 7));
     }
-}
\ No newline at end of file
+}
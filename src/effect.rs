@@ -1,7 +1,9 @@
 use smart_leds::RGB8;
 use palette::{FromColor, Hsv, RgbHue, Srgb};
-use crate::audio::AudioData;
+use crate::audio::{AudioData, NUM_BINS};
+use crate::palettes::{self, Palette};
 use std::cell::RefCell;
+use std::sync::atomic::{AtomicU8, Ordering};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum EffectType {
@@ -13,15 +15,296 @@ pub enum EffectType {
     Scanner,
     TheaterChase,
     Bounce,  
-    AudioVolumeBar   
+    AudioVolumeBar,
+    VuCenter,
+    PulseSolid,
+    Wander,
+    Stripes,
+    Gradient,
+    Rssi,
+    Ripple,
+    BeatColor,
+    Progress,
+    TriBand,
+    Mood,
+    Pattern,
+    Sunrise,
+    Runs,
 }
 
-/// Trait chung cho tất cả các hiệu ứng
+/// Tên hiệu ứng dùng trong API (`mode=...`) ↔ EffectType
+pub const EFFECT_REGISTRY: &[(&str, EffectType)] = &[
+    ("static", EffectType::Static),
+    ("rainbow", EffectType::Rainbow),
+    ("breathe", EffectType::Breathe),
+    ("colorwipe", EffectType::ColorWipe),
+    ("comet", EffectType::Comet),
+    ("scanner", EffectType::Scanner),
+    ("theaterchase", EffectType::TheaterChase),
+    ("bounce", EffectType::Bounce),
+    ("volumebar", EffectType::AudioVolumeBar),
+    ("vucenter", EffectType::VuCenter),
+    ("pulsesolid", EffectType::PulseSolid),
+    ("wander", EffectType::Wander),
+    ("stripes", EffectType::Stripes),
+    ("gradient", EffectType::Gradient),
+    ("rssi", EffectType::Rssi),
+    ("ripple", EffectType::Ripple),
+    ("beatcolor", EffectType::BeatColor),
+    ("progress", EffectType::Progress),
+    ("triband", EffectType::TriBand),
+    ("mood", EffectType::Mood),
+    ("pattern", EffectType::Pattern),
+    ("sunrise", EffectType::Sunrise),
+    ("runs", EffectType::Runs),
+];
+
+impl EffectType {
+    /// Hiệu ứng cần dữ liệu audio (dùng khi chưa tạo instance, vd party mode)
+    pub fn is_audio_reactive(&self) -> bool {
+        matches!(self, EffectType::AudioVolumeBar | EffectType::VuCenter | EffectType::PulseSolid | EffectType::BeatColor | EffectType::TriBand)
+    }
+
+    /// Hiệu ứng dùng PRNG, nhận `param=seed:N` (xem `/led/reseed`)
+    pub fn is_randomized(&self) -> bool {
+        matches!(self, EffectType::Bounce | EffectType::Wander | EffectType::Ripple | EffectType::BeatColor)
+    }
+
+    /// Các `param=key:value` mà hiệu ứng nhận (GET /effects). Match đầy đủ nên
+    /// thêm EffectType mới mà quên khai báo thì không build được.
+    pub fn params(&self) -> &'static [EffectParam] {
+        match self {
+            EffectType::Static
+            | EffectType::Breathe
+            | EffectType::ColorWipe
+            | EffectType::TheaterChase
+            | EffectType::Rssi
+            | EffectType::Progress
+            | EffectType::Pattern
+            | EffectType::Runs => &[],
+            EffectType::Rainbow => RAINBOW_PARAMS,
+            EffectType::Comet | EffectType::Scanner => FADE_PARAMS,
+            EffectType::Bounce | EffectType::Wander => SEED_PARAMS,
+            EffectType::AudioVolumeBar => VOLUME_BAR_PARAMS,
+            EffectType::VuCenter => VU_CENTER_PARAMS,
+            EffectType::PulseSolid => PULSE_SOLID_PARAMS,
+            EffectType::Stripes => STRIPES_PARAMS,
+            EffectType::Gradient => GRADIENT_PARAMS,
+            EffectType::Ripple => RIPPLE_PARAMS,
+            EffectType::BeatColor => BEAT_COLOR_PARAMS,
+            EffectType::TriBand => INTENSITY_PARAMS,
+            EffectType::Mood => PALETTE_PARAMS,
+            EffectType::Sunrise => SUNRISE_PARAMS,
+        }
+    }
+}
+
+/// Mô tả một tham số riêng của hiệu ứng
+pub struct EffectParam {
+    pub name: &'static str,
+    /// int | bool | color | palette | enum
+    pub kind: &'static str,
+    pub range: Option<(u32, u32)>,
+    /// Giá trị hợp lệ khi `kind` là enum
+    pub values: &'static [&'static str],
+    pub description: &'static str,
+}
+
+const BAND_PARAM: EffectParam = EffectParam {
+    name: "band", kind: "enum", range: None,
+    values: &["all", "bass", "mid", "treble", "0", "1", "2", "3", "4", "5", "6", "7"],
+    description: "Frequency band to follow: all, bass, mid, treble or a single bin 0-7",
+};
+const INVERT_PARAM: EffectParam = EffectParam {
+    name: "invert", kind: "bool", range: None, values: &[],
+    description: "Louder = dimmer: silence is brightest and sound darkens the effect",
+};
+const INTENSITY_PARAM: EffectParam = EffectParam {
+    name: "intensity", kind: "int", range: Some((1, 100)), values: &[],
+    description: "Gain applied to the audio level",
+};
+const SEED_PARAM: EffectParam = EffectParam {
+    name: "seed", kind: "int", range: Some((0, u32::MAX)), values: &[],
+    description: "Restart the random pattern from this seed (same seed, same pattern)",
+};
+const PALETTE_PARAM: EffectParam = EffectParam {
+    name: "palette", kind: "palette", range: None, values: &[],
+    description: "Saved or built-in palette name",
+};
+
+const RAINBOW_PARAMS: &[EffectParam] = &[
+    EffectParam { name: "uniform", kind: "bool", range: None, values: &[], description: "Whole strip shows one hue instead of a spread rainbow" },
+    EffectParam { name: "palette", kind: "palette", range: None, values: &[], description: "Scroll through a palette instead of the hue spectrum, none = spectrum" },
+];
+const FADE_PARAMS: &[EffectParam] = &[
+    EffectParam { name: "fade", kind: "enum", range: None, values: &["linear", "exp"], description: "Tail fade curve" },
+    EffectParam { name: "fade_rate", kind: "int", range: Some((1, 255)), values: &[], description: "Brightness kept per tail step with exp fade, out of 256" },
+];
+const SEED_PARAMS: &[EffectParam] = &[SEED_PARAM];
+const INTENSITY_PARAMS: &[EffectParam] = &[INTENSITY_PARAM];
+const PALETTE_PARAMS: &[EffectParam] = &[PALETTE_PARAM];
+const VOLUME_BAR_PARAMS: &[EffectParam] = &[
+    INTENSITY_PARAM,
+    EffectParam { name: "responsiveness", kind: "int", range: Some((1, 100)), values: &[], description: "How fast the bar follows the level" },
+    BAND_PARAM,
+    INVERT_PARAM,
+];
+const VU_CENTER_PARAMS: &[EffectParam] = &[
+    INTENSITY_PARAM,
+    BAND_PARAM,
+    INVERT_PARAM,
+    EffectParam { name: "origin", kind: "enum", range: None, values: &["center", "start", "end"], description: "Where the bar grows from" },
+];
+const PULSE_SOLID_PARAMS: &[EffectParam] = &[
+    EffectParam { name: "attack", kind: "int", range: Some((1, 100)), values: &[], description: "How fast the level rises" },
+    EffectParam { name: "release", kind: "int", range: Some((1, 100)), values: &[], description: "How fast the level falls" },
+    EffectParam { name: "floor", kind: "int", range: Some((0, 255)), values: &[], description: "Minimum brightness when quiet" },
+    BAND_PARAM,
+    INVERT_PARAM,
+];
+const STRIPES_PARAMS: &[EffectParam] = &[
+    EffectParam { name: "width", kind: "int", range: Some((1, StripesEffect::MAX_WIDTH as u32)), values: &[], description: "LEDs per stripe" },
+    EffectParam { name: "color2", kind: "color", range: None, values: &[], description: "Second stripe color (RRGGBB)" },
+    EffectParam { name: "color3", kind: "color", range: None, values: &[], description: "Third stripe color (RRGGBB), none = two stripes" },
+];
+const GRADIENT_PARAMS: &[EffectParam] = &[
+    EffectParam { name: "color2", kind: "color", range: None, values: &[], description: "End color (RRGGBB), the start is the effect color" },
+    PALETTE_PARAM,
+];
+const RIPPLE_PARAMS: &[EffectParam] = &[
+    BAND_PARAM,
+    INVERT_PARAM,
+    EffectParam { name: "audio", kind: "bool", range: None, values: &[], description: "Spawn ripples on beats (0 = on a timer only)" },
+    EffectParam { name: "random", kind: "bool", range: None, values: &[], description: "Random color per ripple instead of the effect color" },
+    EffectParam { name: "flush", kind: "bool", range: None, values: &[], description: "1 = clear ripples in flight so a new color or speed shows at once" },
+    SEED_PARAM,
+];
+const BEAT_COLOR_PARAMS: &[EffectParam] = &[
+    EffectParam { name: "palette", kind: "palette", range: None, values: &[], description: "Step through a palette instead of random hues, none = random" },
+    EffectParam { name: "refractory", kind: "int", range: Some((50, 1000)), values: &[], description: "Ignore beats closer than this many ms" },
+    SEED_PARAM,
+];
+const SUNRISE_PARAMS: &[EffectParam] = &[
+    EffectParam {
+        name: "duration", kind: "int",
+        range: Some((SunriseEffect::MIN_DURATION_S, SunriseEffect::MAX_DURATION_S)), values: &[],
+        description: "Seconds from dim red to full white",
+    },
+];
+
+/// Tên API của hiệu ứng (`mode=...`)
+pub fn effect_name(effect: &EffectType) -> &'static str {
+    EFFECT_REGISTRY
+        .iter()
+        .find(|(_, e)| e == effect)
+        .map(|(name, _)| *name)
+        .unwrap_or("unknown")
+}
+
+/// Tìm EffectType theo tên trong registry
+pub fn effect_from_name(name: &str) -> Option<(EffectType, &'static str)> {
+    EFFECT_REGISTRY
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(n, effect)| (effect.clone(), *n))
+}
+
+/// Trạng thái user đặt gần nhất, dùng để tạo hiệu ứng mới
+pub struct EffectParams {
+    pub color: RGB8,
+    pub color2: RGB8,
+    pub speed: u8,
+    /// Số LED của vùng hiệu ứng
+    pub num_leds: usize,
+    /// Seed cho PRNG của hiệu ứng
+    pub seed: u32,
+}
+
+/// Tạo hiệu ứng từ EffectType. Pattern/Runs lấy dữ liệu đã lưu trong `crate::pattern`.
+pub fn build_effect(effect: &EffectType, params: &EffectParams) -> Box<dyn Effect> {
+    match effect {
+        EffectType::Static => {
+            Box::new(StaticEffect::new(params.color))
+        }
+        EffectType::Rainbow => {
+            Box::new(RainbowEffect::new(params.speed))
+        }
+        EffectType::Breathe => {
+            Box::new(BreatheEffect::new(params.color, params.speed))
+        }
+        EffectType::ColorWipe => {
+            Box::new(ColorWipeEffect::new(params.color, params.speed, params.num_leds))
+        }
+        EffectType::Comet => {
+            Box::new(CometEffect::new(params.color, params.speed, params.num_leds))
+        }
+        EffectType::Scanner => {
+            Box::new(ScannerEffect::new(params.color, params.speed, params.num_leds))
+        }
+        EffectType::TheaterChase => {
+            Box::new(TheaterChaseEffect::new(params.color, params.speed, params.num_leds))
+        }
+        EffectType::Bounce => {
+            Box::new(BounceEffect::new(params.speed, params.num_leds, params.seed))
+        }
+        EffectType::AudioVolumeBar => {
+            Box::new(AudioVolumeBarEffect::new(params.color, params.num_leds))
+        }
+        EffectType::VuCenter => {
+            Box::new(VuCenterEffect::new())
+        }
+        EffectType::PulseSolid => {
+            Box::new(PulseSolidEffect::new(params.color))
+        }
+        EffectType::Wander => {
+            Box::new(WanderEffect::new(params.speed, params.seed))
+        }
+        EffectType::Stripes => {
+            Box::new(StripesEffect::new(params.color, params.speed))
+        }
+        EffectType::Gradient => {
+            Box::new(GradientEffect::new(params.color, params.color2))
+        }
+        EffectType::Rssi => {
+            Box::new(RssiEffect::new())
+        }
+        EffectType::Ripple => {
+            Box::new(RippleEffect::new(params.color, params.speed, params.seed))
+        }
+        EffectType::BeatColor => {
+            Box::new(BeatColorEffect::new(params.color, params.speed, params.seed))
+        }
+        EffectType::Progress => {
+            Box::new(ProgressEffect::new(params.color))
+        }
+        EffectType::TriBand => {
+            Box::new(TriBandEffect::new())
+        }
+        EffectType::Mood => {
+            Box::new(MoodEffect::new(params.speed))
+        }
+        EffectType::Sunrise => {
+            Box::new(SunriseEffect::new())
+        }
+        EffectType::Pattern => {
+            let pattern = crate::pattern::get();
+            Box::new(PatternEffect::new(pattern.colors, pattern.tile))
+        }
+        EffectType::Runs => {
+            Box::new(RunLengthEffect::new(crate::pattern::get_runs()))
+        }
+    }
+}
+
+/// Trait chung cho tất cả các hiệu ứng.
+/// Hiệu ứng không tự đọc đồng hồ phần cứng: thời gian đến qua `delta_us` /
+/// `now_us`, seed ngẫu nhiên qua constructor, nên có thể chạy với đồng hồ giả.
 pub trait Effect {
 
     fn update(&mut self, delta_us: u64) -> bool;
     
-
+    /// Mọi tính toán hình học (vị trí, tâm, độ dài) phải dựa trên
+    /// `buffer.len()`, không dựa trên `num_leds` lúc tạo hiệu ứng.
     fn render(&self, buffer: &mut [RGB8]);
 
     fn render_audio(&mut self, buffer: &mut [RGB8], audio: &AudioData, now_us: u64) {
@@ -38,9 +321,31 @@ pub trait Effect {
     fn set_speed(&mut self, speed: u8) -> bool {
         false 
     }
+
+    /// Tham số riêng của từng hiệu ứng (`param=key:value`)
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        false
+    }
+    
+    /// Nhận RSSI (dBm) mới do controller đẩy vào, chỉ khi `uses_rssi()`
+    fn set_rssi(&mut self, rssi_dbm: Option<i8>) -> bool {
+        false
+    }
+
+    /// Giá trị 0.0-1.0 cho hiệu ứng progress, true nếu cần render lại
+    fn set_progress(&mut self, fraction: f32) -> bool {
+        false
+    }
+
+    /// Hiệu ứng chạy một số lần rồi dừng (nhấp nháy N nhịp, quét N lượt) trả true
+    /// khi đã xong, để controller kết thúc override ngay thay vì chờ hết giờ
+    fn is_complete(&self) -> bool {
+        false
+    }
     
     fn name(&self) -> &'static str;
     fn is_audio_reactive(&self) -> bool { false }
+    fn uses_rssi(&self) -> bool { false }
 }
 
 
@@ -75,34 +380,216 @@ impl Effect for StaticEffect {
 }
 
 
+/// Gradient tĩnh từ `color_a` (LED đầu) tới `color_b` (LED cuối),
+/// hoặc trải một palette (`param=palette:name`) dọc dải LED
+pub struct GradientEffect {
+    color_a: RGB8,
+    color_b: RGB8,
+    palette: Option<Palette>,
+}
+
+impl GradientEffect {
+    /// Màu cuối khi chưa từng gửi `color2`
+    pub const DEFAULT_COLOR2: RGB8 = RGB8 { r: 0, g: 0, b: 255 };
+
+    pub fn new(color_a: RGB8, color_b: RGB8) -> Self {
+        Self { color_a, color_b, palette: None }
+    }
+}
+
+impl Effect for GradientEffect {
+    fn name(&self) -> &'static str { "Gradient" }
+
+    fn update(&mut self, _delta_us: u64) -> bool {
+        false  // Giống Static: chỉ render khi màu đổi
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        let last = buffer.len().saturating_sub(1).max(1) as u32;
+
+        for (i, pixel) in buffer.iter_mut().enumerate() {
+            let t = ((i as u32 * 255) / last) as u8;
+            *pixel = match &self.palette {
+                Some(palette) => palette.sample(t),
+                None => blend_color(self.color_a, self.color_b, t),
+            };
+        }
+    }
+
+    fn set_color(&mut self, color: RGB8) -> bool {
+        // Chọn màu → quay về gradient 2 màu
+        if self.color_a != color || self.palette.is_some() {
+            self.color_a = color;
+            self.palette = None;
+            return true;
+        }
+        false
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "color2" => match crate::color::parse_hex_color(value) {
+                Ok((r, g, b)) => {
+                    let color = RGB8 { r, g, b };
+                    if self.color_b != color || self.palette.is_some() {
+                        self.color_b = color;
+                        self.palette = None;
+                        return true;
+                    }
+                    false
+                }
+                Err(_) => false,
+            },
+            "palette" => match palettes::find(value) {
+                Some(palette) => {
+                    self.palette = Some(palette);
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+
+/// Hiển thị cường độ WiFi dạng thanh: đỏ = yếu, xanh lá = mạnh
+pub struct RssiEffect {
+    rssi_dbm: Option<i8>,
+}
+
+impl RssiEffect {
+    const RSSI_MIN: i32 = -90;
+    const RSSI_MAX: i32 = -30;
+
+    pub fn new() -> Self {
+        Self { rssi_dbm: None }
+    }
+}
+
+impl Effect for RssiEffect {
+    fn name(&self) -> &'static str { "RSSI" }
+
+    fn update(&mut self, _delta_us: u64) -> bool {
+        false  // Chỉ render khi controller đẩy RSSI mới
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        buffer.fill(RGB8::default());
+
+        let Some(rssi) = self.rssi_dbm else {
+            // Chưa có dữ liệu: 1 pixel đỏ mờ
+            if let Some(first) = buffer.first_mut() {
+                *first = RGB8 { r: 32, g: 0, b: 0 };
+            }
+            return;
+        };
+
+        let clamped = (rssi as i32).clamp(Self::RSSI_MIN, Self::RSSI_MAX);
+        let strength = ((clamped - Self::RSSI_MIN) * 255 / (Self::RSSI_MAX - Self::RSSI_MIN)) as u8;
+
+        let lit = (buffer.len() * strength as usize / 255).max(1).min(buffer.len());
+        let color = blend_color(RGB8 { r: 255, g: 0, b: 0 }, RGB8 { r: 0, g: 255, b: 0 }, strength);
+        buffer[..lit].fill(color);
+    }
+
+    fn set_rssi(&mut self, rssi_dbm: Option<i8>) -> bool {
+        if self.rssi_dbm != rssi_dbm {
+            self.rssi_dbm = rssi_dbm;
+            return true;
+        }
+        false
+    }
+
+    fn uses_rssi(&self) -> bool {
+        true
+    }
+}
+
+
+/// N nhịp sáng rồi tắt, báo `is_complete` sau nhịp cuối (dùng qua override,
+/// không có trong registry). Mặc định 3 nhịp trắng để tìm thiết bị;
+/// `notify` là 2 nhịp màu cho thông báo ngắn.
+pub struct IdentifyEffect {
+    color: RGB8,
+    pulses: u64,
+    elapsed_us: u64,
+    level: u8,
+}
+
+impl IdentifyEffect {
+    const PULSE_US: u64 = 600_000;
+    pub const DEFAULT_PULSES: u8 = 3;
+    pub const MAX_PULSES: u8 = 10;
+    const NOTIFY_PULSES: u8 = 2;
+
+    pub fn notify(color: RGB8) -> Self {
+        Self::repeat(color, Self::NOTIFY_PULSES)
+    }
+
+    /// `count` nhịp màu `color` (1-10)
+    pub fn repeat(color: RGB8, count: u8) -> Self {
+        let pulses = count.clamp(1, Self::MAX_PULSES) as u64;
+        Self { color, pulses, elapsed_us: 0, level: 0 }
+    }
+
+    /// Giới hạn thời gian cho override, phòng khi không nhận được đủ frame
+    pub fn duration_us(&self) -> u64 {
+        Self::PULSE_US * self.pulses + 200_000
+    }
+}
+
+impl Effect for IdentifyEffect {
+    fn name(&self) -> &'static str { "Identify" }
+
+    fn update(&mut self, delta_us: u64) -> bool {
+        self.elapsed_us += delta_us;
+
+        let level = if self.elapsed_us >= Self::PULSE_US * self.pulses {
+            0
+        } else {
+            // Tam giác 0 → 255 → 0 trong mỗi nhịp
+            let phase = (self.elapsed_us % Self::PULSE_US) * 510 / Self::PULSE_US;
+            if phase < 255 { phase as u8 } else { (510 - phase) as u8 }
+        };
+
+        if level != self.level {
+            self.level = level;
+            return true;
+        }
+        false
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        buffer.fill(dim_color(self.color, self.level));
+    }
+
+    fn is_complete(&self) -> bool {
+        self.elapsed_us >= Self::PULSE_US * self.pulses
+    }
+}
+
+
+/// Cầu vồng cuộn dọc dải. `param=palette:name` cuộn qua màu của palette thay cho
+/// vòng hue (`palette:none` = trở lại cầu vồng), `param=uniform:1` cả dải cùng màu.
 pub struct RainbowEffect {
     phase16: u16,
     speed: u8,
-    phase_spacing: u16,
+    uniform: bool, // Cả dải cùng một màu, đổi theo thời gian
     lut: Vec<RGB8>,
+    lut_saturation: u8, // Độ bão hoà lúc tạo LUT, khác giá trị chung → tạo lại
+    palette: Option<Palette>,
 }
 
 impl RainbowEffect {
-    pub fn new(num_leds: usize, speed: u8) -> Self {
-        let mut lut = Vec::with_capacity(256);
-        
-        for i in 0..=255 {
-            let hue = (i as f32 * 360.0) / 256.0;
-            let color = Hsv::new(RgbHue::from_degrees(hue), 1.0, 1.0);
-            let srgb: Srgb = Srgb::from_color(color);
-
-            lut.push(RGB8 {
-                r: (srgb.red * 255.0).round() as u8,
-                g: (srgb.green * 255.0).round() as u8,
-                b: (srgb.blue * 255.0).round() as u8,
-            });
-        }
-
+    pub fn new(speed: u8) -> Self {
         Self {
             phase16: 0,
             speed: speed.clamp(1, 255),
-            phase_spacing: (65536_u32 / num_leds.max(1) as u32) as u16,
-            lut,
+            uniform: false,
+            lut: rainbow_lut(),
+            lut_saturation: saturation_percent(),
+            palette: None,
         }
     }
 }
@@ -111,11 +598,19 @@ impl Effect for RainbowEffect {
     fn name(&self) -> &'static str { "Rainbow" }
 
     fn update(&mut self, delta_us: u64) -> bool {
+        // Màu palette do user chọn, không phụ thuộc độ bão hoà chung
+        let saturation = saturation_percent();
+        let lut_changed = self.palette.is_none() && saturation != self.lut_saturation;
+        if lut_changed {
+            self.lut = rainbow_lut();
+            self.lut_saturation = saturation;
+        }
+
         // Tính phase increment với overflow protection
         let increment = ((self.speed as u64).saturating_mul(delta_us)) / 10000;
         
         // Chỉ update nếu có thay đổi
-        if increment > 0 {
+        if increment > 0 || lut_changed {
             self.phase16 = self.phase16.wrapping_add(increment as u16);
             return true;  // Phase thay đổi → cần render
         }
@@ -124,9 +619,16 @@ impl Effect for RainbowEffect {
     }
 
     fn render(&self, buffer: &mut [RGB8]) {
+        // Một vòng cầu vồng trải hết chiều dài buffer (uniform: mọi LED cùng hue)
+        let phase_spacing = if self.uniform {
+            0
+        } else {
+            (65536_u32 / buffer.len().max(1) as u32) as u16
+        };
+
         for (i, pixel) in buffer.iter_mut().enumerate() {
             let pixel_phase = self.phase16
-                .wrapping_add((i as u16).wrapping_mul(self.phase_spacing));
+                .wrapping_add((i as u16).wrapping_mul(phase_spacing));
             let hue_index = (pixel_phase >> 8) as u8;
             *pixel = self.lut[hue_index as usize];
         }
@@ -136,6 +638,36 @@ impl Effect for RainbowEffect {
         self.speed = speed.clamp(1, 255);
         false  // Speed không cần render ngay
     }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "uniform" => match crate::http::parse_bool(value) {
+                Some(uniform) if uniform != self.uniform => {
+                    // Giữ nguyên phase16 → hue hiện tại không nhảy khi chuyển chế độ
+                    self.uniform = uniform;
+                    true
+                }
+                _ => false,
+            },
+            "palette" if value == "none" => {
+                if self.palette.take().is_none() {
+                    return false;
+                }
+                self.lut = rainbow_lut();
+                self.lut_saturation = saturation_percent();
+                true
+            }
+            "palette" => match palettes::find(value) {
+                Some(palette) => {
+                    self.lut = palette_loop_lut(&palette);
+                    self.palette = Some(palette);
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
 }
 
 
@@ -237,9 +769,7 @@ impl ColorWipeEffect {
     }
 
     fn map_speed_to_interval(speed: u8) -> u64 {
-        let inverted_speed = 256 - speed.max(1) as u64;
-        let interval_ms = (inverted_speed * 148) / 254 + 2;
-        interval_ms * 1000
+        speed_to_interval_us(speed, 2, 150)
     }
 }
 
@@ -301,6 +831,7 @@ pub struct CometEffect {
     num_leds: usize,
     position: usize, 
     tail_len: usize,
+    fade: FadeCurve,
     time_accumulator: u64,
     pixel_interval_us: u64,
 }
@@ -311,7 +842,9 @@ impl CometEffect {
             color,
             num_leds,
             position: 0,
-            tail_len: (num_leds / 5).max(3), // Đuôi dài 20% strip, tối thiểu 3
+            // Đuôi dài 20% strip, tối thiểu 3 nhưng không dài quá dải (dải ngắn chỉ còn đầu)
+            tail_len: (num_leds / 5).max(3).min(num_leds.saturating_sub(1)),
+            fade: FadeCurve::Linear,
             time_accumulator: 0,
             pixel_interval_us: Self::map_speed_to_interval(speed),
         }
@@ -319,9 +852,7 @@ impl CometEffect {
 
     // Tốc độ nhanh hơn ColorWipe (max 100ms)
     fn map_speed_to_interval(speed: u8) -> u64 {
-        let inverted_speed = 256 - speed.max(1) as u64;
-        let interval_ms = (inverted_speed * 100) / 254 + 2; // 2ms - 102ms
-        interval_ms * 1000
+        speed_to_interval_us(speed, 2, 102)
     }
 }
 
@@ -335,7 +866,7 @@ impl Effect for CometEffect {
             self.time_accumulator -= self.pixel_interval_us;
             
             // Di chuyển vị trí, lặp lại khi đến cuối
-            self.position = (self.position + 1) % self.num_leds;
+            self.position = (self.position + 1) % self.num_leds.max(1);
             return true;
         }
         false
@@ -345,18 +876,18 @@ impl Effect for CometEffect {
         // 1. Xóa toàn bộ buffer (hoặc làm mờ nếu muốn hiệu ứng mượt hơn)
         buffer.fill(RGB8::default());
 
+        let len = buffer.len();
+        if len == 0 {
+            return;
+        }
+
         // 2. Vẽ "đầu" sao chổi
-        buffer[self.position] = self.color;
+        let head = self.position % len;
+        buffer[head] = self.color;
 
-        // 3. Vẽ "đuôi"
-        for i in 1..=self.tail_len {
-            // Tính vị trí pixel của đuôi (vòng lặp lại)
-            let pos = (self.position + self.num_leds - i) % self.num_leds;
-            
-            // Tính độ mờ (giảm dần)
-            let fade_factor = 255 - (i * (255 / self.tail_len.max(1))) as u8;
-            buffer[pos] = dim_color(self.color, fade_factor);
-        }
+        // 3. Vẽ "đuôi" (vòng lặp lại)
+        let tail_len = self.tail_len.min(len - 1);
+        draw_tail(buffer, head, -1, true, tail_len, self.color, self.fade);
     }
 
     fn set_color(&mut self, color: RGB8) -> bool {
@@ -368,6 +899,10 @@ impl Effect for CometEffect {
         self.pixel_interval_us = Self::map_speed_to_interval(speed);
         false
     }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        self.fade.set_param(key, value)
+    }
 }
 
 pub struct ScannerEffect {
@@ -375,6 +910,7 @@ pub struct ScannerEffect {
     num_leds: usize,
     position: usize, // Vị trí "mắt"
     direction: i8, // 1 = sang phải, -1 = sang trái
+    fade: FadeCurve,
     time_accumulator: u64,
     pixel_interval_us: u64,
 }
@@ -386,16 +922,17 @@ impl ScannerEffect {
             num_leds,
             position: 0,
             direction: 1,
+            fade: FadeCurve::Exponential(128), // 50%, 25%
             time_accumulator: 0,
             pixel_interval_us: Self::map_speed_to_interval(speed),
         }
     }
     
+    const GLOW_LEN: usize = 2;
+
     // Tốc độ tương tự Comet
     fn map_speed_to_interval(speed: u8) -> u64 {
-        let inverted_speed = 256 - speed.max(1) as u64;
-        let interval_ms = (inverted_speed * 100) / 254 + 2;
-        interval_ms * 1000
+        speed_to_interval_us(speed, 2, 102)
     }
 }
 
@@ -407,11 +944,18 @@ impl Effect for ScannerEffect {
 
         if self.time_accumulator >= self.pixel_interval_us {
             self.time_accumulator -= self.pixel_interval_us;
+
+            // Dải 0-1 LED: mắt đứng yên, không có chỗ để quét
+            let last = self.num_leds.saturating_sub(1);
+            if last == 0 {
+                self.position = 0;
+                return false;
+            }
             
             // Logic đổi hướng khi chạm 2 đầu
             if self.direction > 0 {
                 // Đang đi sang phải
-                if self.position >= self.num_leds - 1 {
+                if self.position >= last {
                     self.direction = -1; // Đổi hướng
                 }
             } else {
@@ -432,20 +976,17 @@ impl Effect for ScannerEffect {
         // Xóa buffer
         buffer.fill(RGB8::default());
 
-        
-        if self.position < self.num_leds {
-            buffer[self.position] = self.color;
+        let len = buffer.len();
+        if len == 0 {
+            return;
         }
-        
-        
-        let inner_dim = dim_color(self.color, 128); // 50%
-        if self.position >= 1 { buffer[self.position - 1] = inner_dim; }
-        if self.position + 1 < self.num_leds { buffer[self.position + 1] = inner_dim; }
-        
-    
-        let outer_dim = dim_color(self.color, 64); // 25%
-        if self.position >= 2 { buffer[self.position - 2] = outer_dim; }
-        if self.position + 2 < self.num_leds { buffer[self.position + 2] = outer_dim; }
+        let position = self.position.min(len - 1);
+
+        buffer[position] = self.color;
+
+        // Quầng mờ 2 pixel mỗi bên
+        draw_tail(buffer, position, -1, false, Self::GLOW_LEN, self.color, self.fade);
+        draw_tail(buffer, position, 1, false, Self::GLOW_LEN, self.color, self.fade);
     }
 
     fn set_color(&mut self, color: RGB8) -> bool {
@@ -457,6 +998,10 @@ impl Effect for ScannerEffect {
         self.pixel_interval_us = Self::map_speed_to_interval(speed);
         false
     }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        self.fade.set_param(key, value)
+    }
 }
 
 
@@ -468,6 +1013,7 @@ pub struct TheaterChaseEffect {
     position_offset: usize,
     time_accumulator: u64,
     pixel_interval_us: u64,
+    speed: SpeedRamp,
 }
 
 impl TheaterChaseEffect {
@@ -480,14 +1026,13 @@ impl TheaterChaseEffect {
             position_offset: 0,
             time_accumulator: 0,
             pixel_interval_us: Self::map_speed_to_interval(speed),
+            speed: SpeedRamp::new(speed),
         }
     }
 
     // Tốc độ tương tự Comet
     fn map_speed_to_interval(speed: u8) -> u64 {
-        let inverted_speed = 256 - speed.max(1) as u64;
-        let interval_ms = (inverted_speed * 100) / 254 + 2;
-        interval_ms * 1000
+        speed_to_interval_us(speed, 2, 102)
     }
 }
 
@@ -495,6 +1040,11 @@ impl Effect for TheaterChaseEffect {
     fn name(&self) -> &'static str { "Theater Chase" }
 
     fn update(&mut self, delta_us: u64) -> bool {
+        if self.speed.is_ramping() {
+            let speed = self.speed.step(delta_us);
+            self.pixel_interval_us = Self::map_speed_to_interval(speed);
+        }
+
         self.time_accumulator += delta_us;
 
         if self.time_accumulator >= self.pixel_interval_us {
@@ -524,13 +1074,60 @@ impl Effect for TheaterChaseEffect {
     }
 
     fn set_speed(&mut self, speed: u8) -> bool {
-        self.pixel_interval_us = Self::map_speed_to_interval(speed);
+        self.speed.set_target(speed);
         false
     }
 }
 
 
 
+/// Speed chung cho mọi hiệu ứng: 1 = chậm nhất, 255 = nhanh nhất.
+/// Ánh xạ tuyến tính sang chu kỳ bước `max_ms` → `min_ms`, nên speed=128
+/// luôn nằm giữa dải của từng hiệu ứng.
+pub(crate) fn speed_to_interval_us(speed: u8, min_ms: u64, max_ms: u64) -> u64 {
+    let inverted = 255 - speed.max(1) as u64; // 0..254
+    (min_ms + inverted * (max_ms - min_ms) / 254) * 1000
+}
+
+/// Làm mượt thay đổi speed: tiến dần từ speed hiện tại tới target
+/// (dùng cho các hiệu ứng tích phân vị trí theo từng frame)
+struct SpeedRamp {
+    current: f32,
+    target: f32,
+}
+
+impl SpeedRamp {
+    // ~250ms để đi được 63% quãng đường tới target
+    const TIME_CONSTANT_US: f32 = 250_000.0;
+
+    fn new(speed: u8) -> Self {
+        Self { current: speed as f32, target: speed as f32 }
+    }
+
+    fn set_target(&mut self, speed: u8) {
+        self.target = speed as f32;
+    }
+
+    fn is_ramping(&self) -> bool {
+        self.current != self.target
+    }
+
+    /// Tiến một bước theo thời gian, trả về speed hiện tại
+    fn step(&mut self, delta_us: u64) -> u8 {
+        let diff = self.target - self.current;
+
+        if diff.abs() < 0.5 {
+            self.current = self.target;
+        } else {
+            let k = (delta_us as f32 / Self::TIME_CONSTANT_US).min(1.0);
+            self.current += diff * k;
+        }
+
+        self.current.round() as u8
+    }
+}
+
+
 /// Bộ tạo số giả ngẫu nhiên (PRNG) đơn giản
 struct FastRand {
     seed: u32,
@@ -557,10 +1154,21 @@ impl FastRand {
 
     /// Trả về một số trong [0, max)
     fn rand_max(&mut self, max: usize) -> usize {
-        (self.rand_u32() as u64 * max as u64 / u32::MAX as u64) as usize
+        scale_rand(self.rand_u32(), max)
     }
 }
 
+/// Đưa `rand` (0..=u32::MAX) về [0, max). Chia cho 2^32 chứ không phải u32::MAX,
+/// nếu không rand = u32::MAX cho ra đúng `max` (vị trí ngoài dải).
+fn scale_rand(rand: u32, max: usize) -> usize {
+    ((rand as u64 * max as u64) >> 32) as usize
+}
+
+/// Giá trị của `param=seed:N`. Cùng seed → cùng chuỗi ngẫu nhiên để tái hiện một cảnh.
+fn parse_seed(value: &str) -> Option<u32> {
+    value.parse::<u32>().ok()
+}
+
 pub struct TwinkleEffect {
     base_color: RGB8,    // Màu nền
     sparkle_color: RGB8, // Màu lấp lánh
@@ -573,10 +1181,8 @@ pub struct TwinkleEffect {
 }
 
 impl TwinkleEffect {
-    pub fn new(color: RGB8, speed: u8, num_leds: usize) -> Self {
-        // Lấy seed ngẫu nhiên từ thời gian
-        let seed = (unsafe { esp_idf_sys::esp_timer_get_time() } & 0xFFFFFFFF) as u32;
-
+    /// `seed` do controller cấp (thường lấy từ đồng hồ) để hiệu ứng không phụ thuộc esp-idf
+    pub fn new(color: RGB8, speed: u8, num_leds: usize, seed: u32) -> Self {
         Self {
             base_color: RGB8::default(), // Nền đen
             sparkle_color: color,
@@ -591,9 +1197,7 @@ impl TwinkleEffect {
 
     // Tốc độ ở đây là tốc độ "tick" của hiệu ứng
     fn map_speed_to_interval(speed: u8) -> u64 {
-        let inverted_speed = 256 - speed.max(1) as u64;
-        let interval_ms = (inverted_speed * 50) / 254 + 5; // 5ms - 55ms
-        interval_ms * 1000
+        speed_to_interval_us(speed, 5, 55)
     }
 }
 
@@ -619,9 +1223,9 @@ impl Effect for TwinkleEffect {
 
         // 2. Thêm các pixel lấp lánh mới
         let mut rand_mut = self.rand.borrow_mut();
-        if rand_mut.rand_u8() < self.density {
+        if !buffer.is_empty() && rand_mut.rand_u8() < self.density {
             // Chọn một vị trí ngẫu nhiên
-            let pos = rand_mut.rand_max(self.num_leds);
+            let pos = rand_mut.rand_max(buffer.len());
             
             // Chỉ đặt nếu nó gần như đã tắt (tránh ghi đè pixel đang sáng)
             if buffer[pos].r < 10 && buffer[pos].g < 10 && buffer[pos].b < 10 {
@@ -651,95 +1255,318 @@ fn fade_color(color: RGB8, fade_by: u8) -> RGB8 {
     }
 }
 
-fn dim_color(color: RGB8, scale: u8) -> RGB8 {
-    RGB8 {
-        r: ((color.r as u16 * scale as u16) >> 8) as u8,
-        g: ((color.g as u16 * scale as u16) >> 8) as u8,
-        b: ((color.b as u16 * scale as u16) >> 8) as u8,
-    }
+// Độ bão hoà (0-100) dùng chung cho các hiệu ứng tự sinh màu theo hue
+// (rainbow, wander, bounce, màu ngẫu nhiên của ripple/beatcolor). Đặt qua controller.
+static SATURATION: AtomicU8 = AtomicU8::new(100);
+
+pub fn set_saturation(percent: u8) {
+    SATURATION.store(percent.min(100), Ordering::Relaxed);
 }
 
-#[derive(Clone, Copy)]
-struct Particle {
-    position: f32, // Vị trí (float)
-    velocity: f32, // Vận tốc (pixel / giây)
-    color: RGB8,
+pub fn saturation_percent() -> u8 {
+    SATURATION.load(Ordering::Relaxed)
 }
 
-pub struct BounceEffect {
-    num_leds: usize,
-    particles: Vec<Particle>,
-    lut: Vec<RGB8>, // Bảng màu
-    rand: RefCell<FastRand>,
+/// Màu thuần của `hue` (độ) theo độ bão hoà chung
+pub fn hue_to_rgb(hue: f32) -> RGB8 {
+    hsv_to_rgb(hue, saturation_percent() as f32 / 100.0, 1.0)
 }
 
-impl BounceEffect {
-    pub fn new(speed: u8, num_leds: usize) -> Self {
-        let seed = (unsafe { esp_idf_sys::esp_timer_get_time() } & 0xFFFFFFFF) as u32;
-        let mut rand = FastRand::new(seed);
-        
-        // Tạo LUT cầu vồng
-        let mut lut = Vec::with_capacity(256);
-        for i in 0..=255 {
-            let hue = (i as f32 * 360.0) / 256.0;
-            let color = Hsv::new(RgbHue::from_degrees(hue), 1.0, 1.0);
-            let srgb: Srgb = Srgb::from_color(color);
-            lut.push(RGB8 {
-                r: (srgb.red * 255.0).round() as u8,
-                g: (srgb.green * 255.0).round() as u8,
-                b: (srgb.blue * 255.0).round() as u8,
-            });
-        }
+/// Bảng 256 màu một vòng hue theo độ bão hoà chung
+fn rainbow_lut() -> Vec<RGB8> {
+    (0..256).map(|i| hue_to_rgb(i as f32 * 360.0 / 256.0)).collect()
+}
 
-        // Tạo các hạt
-        let num_particles = (num_leds / 20).max(3); // 5% dải LED, tối thiểu 3
-        let mut particles = Vec::with_capacity(num_particles);
-        
-        // Ánh xạ speed (1-255) sang vận tốc (10-60 pixels/sec)
-        let max_vel = (speed as f32 / 255.0) * 50.0 + 10.0;
+/// Bảng 256 màu đi qua các điểm màu của palette rồi quay về điểm đầu,
+/// để cuộn liên tục không có chỗ nối
+fn palette_loop_lut(palette: &Palette) -> Vec<RGB8> {
+    let stops = &palette.stops;
+    let n = stops.len().max(1);
+    (0..256)
+        .map(|i| {
+            let scaled = i * n;
+            let (idx, frac) = (scaled / 256, (scaled % 256) as u8);
+            blend_color(stops[idx % n], stops[(idx + 1) % n], frac)
+        })
+        .collect()
+}
 
-        for _ in 0..num_particles {
-            // Vận tốc ngẫu nhiên (có thể âm hoặc dương)
-            let vel = (rand.rand_u32() as f32 / u32::MAX as f32 - 0.5) * 2.0 * max_vel;
-            
-            particles.push(Particle {
-                position: rand.rand_max(num_leds) as f32,
-                velocity: vel.clamp(-max_vel, max_vel),
-                color: lut[rand.rand_u8() as usize],
-            });
-        }
+/// HSV → RGB8. `hue` theo độ (0-360), `saturation`/`value` trong 0.0-1.0
+pub fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> RGB8 {
+    let color = Hsv::new(RgbHue::from_degrees(hue), saturation.clamp(0.0, 1.0), value.clamp(0.0, 1.0));
+    let srgb: Srgb = Srgb::from_color(color);
 
-        Self {
-            num_leds,
-            particles,
-            lut,
-            rand: RefCell::new(rand),
-        }
+    RGB8 {
+        r: (srgb.red * 255.0).round() as u8,
+        g: (srgb.green * 255.0).round() as u8,
+        b: (srgb.blue * 255.0).round() as u8,
     }
-    
-    // Hàm này sẽ được dùng trong set_speed
-    fn update_speeds(&mut self, speed: u8) {
-        let max_vel = (speed as f32 / 255.0) * 50.0 + 10.0;
-        let mut rand = self.rand.borrow_mut();
-        
-        for p in self.particles.iter_mut() {
-            let vel = (rand.rand_u32() as f32 / u32::MAX as f32 - 0.5) * 2.0 * max_vel;
-            p.velocity = vel.clamp(-max_vel, max_vel);
-        }
+}
+
+/// Nội suy tuyến tính giữa 2 màu, `t` = 0 → `a`, `t` = 255 → `b`
+pub(crate) fn blend_color(a: RGB8, b: RGB8, t: u8) -> RGB8 {
+    let t = t as u16;
+    let inv = 255 - t;
+    RGB8 {
+        r: ((a.r as u16 * inv + b.r as u16 * t) / 255) as u8,
+        g: ((a.g as u16 * inv + b.g as u16 * t) / 255) as u8,
+        b: ((a.b as u16 * inv + b.b as u16 * t) / 255) as u8,
     }
 }
 
-impl Effect for BounceEffect {
-    fn name(&self) -> &'static str { "Bounce" }
+/// Dạng suy giảm độ sáng dọc đuôi/quầng của các hiệu ứng chuyển động.
+/// Chọn qua `param=fade:linear|exp` và `param=fade_rate:1-255`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FadeCurve {
+    /// Giảm đều về 0 ở cuối đuôi
+    Linear,
+    /// Mỗi pixel còn `rate/256` độ sáng của pixel trước
+    Exponential(u8),
+}
+
+impl FadeCurve {
+    const DEFAULT_EXP_RATE: u8 = 200;
+
+    /// Xử lý `fade` / `fade_rate`, trả true nếu đường cong thay đổi
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        let new = match (key, value) {
+            ("fade", "linear") => FadeCurve::Linear,
+            ("fade", "exp") => match *self {
+                FadeCurve::Exponential(_) => return false,
+                FadeCurve::Linear => FadeCurve::Exponential(Self::DEFAULT_EXP_RATE),
+            },
+            ("fade_rate", v) => match v.parse::<u8>() {
+                Ok(rate) => FadeCurve::Exponential(rate.max(1)),
+                Err(_) => return false,
+            },
+            _ => return false,
+        };
+
+        if *self != new {
+            *self = new;
+            return true;
+        }
+        false
+    }
+
+    /// Độ sáng (0-255) của pixel thứ `i` tính từ đầu (1 = sát đầu)
+    fn level(&self, i: usize, len: usize) -> u8 {
+        match *self {
+            FadeCurve::Linear => 255u8.saturating_sub((i * (255 / len.max(1))).min(255) as u8),
+            FadeCurve::Exponential(rate) => {
+                let mut level: u16 = 256;
+                for _ in 0..i {
+                    level = (level * rate as u16) >> 8;
+                }
+                level.min(255) as u8
+            }
+        }
+    }
+}
+
+/// Vẽ đuôi `len` pixel từ `head` theo hướng `dir` (-1 / +1), độ sáng theo `curve`.
+/// `wrap` = vòng lại đầu kia của buffer, nếu không thì dừng ở mép.
+fn draw_tail(buffer: &mut [RGB8], head: usize, dir: isize, wrap: bool, len: usize, color: RGB8, curve: FadeCurve) {
+    let n = buffer.len() as isize;
+    if n == 0 {
+        return;
+    }
+
+    for i in 1..=len {
+        let mut pos = head as isize + dir * i as isize;
+        if wrap {
+            pos = pos.rem_euclid(n);
+        } else if pos < 0 || pos >= n {
+            break;
+        }
+
+        let level = curve.level(i, len);
+        if level == 0 {
+            break;
+        }
+        buffer[pos as usize] = dim_color(color, level);
+    }
+}
+
+fn dim_color(color: RGB8, scale: u8) -> RGB8 {
+    RGB8 {
+        r: ((color.r as u16 * scale as u16) >> 8) as u8,
+        g: ((color.g as u16 * scale as u16) >> 8) as u8,
+        b: ((color.b as u16 * scale as u16) >> 8) as u8,
+    }
+}
+
+/// Một quầng sáng mềm trôi ngẫu nhiên dọc dải LED, màu đổi dần.
+/// Vị trí tính bằng tỉ lệ (0.0-1.0) của buffer, render sub-pixel.
+pub struct WanderEffect {
+    position: f32,
+    velocity: f32, // tỉ lệ dải LED / giây
+    hue: f32,
+    speed: u8,
+    retarget_accumulator: u64,
+    rand: FastRand,
+}
+
+impl WanderEffect {
+    const RETARGET_US: u64 = 1_500_000; // Đổi hướng ngẫu nhiên mỗi 1.5s
+    const MAX_VELOCITY: f32 = 0.04;     // Ở speed=128: ~25s để đi hết dải
+    const HUE_DRIFT_DEG: f32 = 4.0;     // Độ/giây ở speed=128
+    const GLOW_WIDTH: f32 = 0.12;       // Sigma của quầng, theo tỉ lệ dải LED
+
+    pub fn new(speed: u8, seed: u32) -> Self {
+        let mut rand = FastRand::new(seed);
+        let hue = rand.rand_max(360) as f32;
+
+        Self {
+            position: 0.5,
+            velocity: 0.0,
+            hue,
+            speed: speed.max(1),
+            retarget_accumulator: Self::RETARGET_US,
+            rand,
+        }
+    }
+
+    /// speed 1-255 → hệ số 0.25-2.0 (128 ≈ 1.0)
+    fn rate(&self) -> f32 {
+        0.25 + self.speed as f32 / 255.0 * 1.75
+    }
+
+    fn random_unit(&mut self) -> f32 {
+        self.rand.rand_u32() as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+impl Effect for WanderEffect {
+    fn name(&self) -> &'static str { "Wander" }
+
+    fn update(&mut self, delta_us: u64) -> bool {
+        let dt = delta_us as f32 / 1_000_000.0;
+        let rate = self.rate();
+        let max_vel = Self::MAX_VELOCITY * rate;
+
+        // Random walk: thay đổi vận tốc từng chút, không giật hướng
+        self.retarget_accumulator += delta_us;
+        if self.retarget_accumulator >= Self::RETARGET_US {
+            self.retarget_accumulator = 0;
+            let nudge = self.random_unit() * max_vel * 0.5;
+            self.velocity = (self.velocity + nudge).clamp(-max_vel, max_vel);
+        }
+
+        self.position += self.velocity * dt;
+        if self.position < 0.0 {
+            self.position = 0.0;
+            self.velocity = self.velocity.abs();
+        } else if self.position > 1.0 {
+            self.position = 1.0;
+            self.velocity = -self.velocity.abs();
+        }
+
+        self.hue = (self.hue + Self::HUE_DRIFT_DEG * rate * dt) % 360.0;
+        true
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        let len = buffer.len();
+        if len == 0 {
+            return;
+        }
+
+        let base = hue_to_rgb(self.hue);
+        let center = self.position * (len - 1) as f32;
+        let sigma = (len as f32 * Self::GLOW_WIDTH).max(1.5);
+        let inv_two_sigma_sq = 1.0 / (2.0 * sigma * sigma);
+
+        for (i, pixel) in buffer.iter_mut().enumerate() {
+            let d = i as f32 - center;
+            let level = (-(d * d) * inv_two_sigma_sq).exp();
+            *pixel = dim_color(base, (level * 255.0) as u8);
+        }
+    }
+
+    fn set_speed(&mut self, speed: u8) -> bool {
+        self.speed = speed.max(1);
+        false
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match (key, parse_seed(value)) {
+            ("seed", Some(seed)) => {
+                *self = Self::new(self.speed, seed);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Particle {
+    position: f32, // Vị trí (float)
+    velocity: f32, // Hướng + độ lớn tương đối (-1.0 .. 1.0), nhân với max_vel
+    color: RGB8,
+}
+
+pub struct BounceEffect {
+    num_leds: usize,
+    particles: Vec<Particle>,
+    lut: Vec<RGB8>, // Bảng màu
+    rand: RefCell<FastRand>,
+    speed: SpeedRamp,
+}
+
+impl BounceEffect {
+    pub fn new(speed: u8, num_leds: usize, seed: u32) -> Self {
+        let mut rand = FastRand::new(seed);
+        
+        // Tạo LUT cầu vồng (màu hạt chọn lúc tạo hiệu ứng)
+        let lut = rainbow_lut();
+
+        // Tạo các hạt
+        // 5% dải LED, tối thiểu 3, không nhiều hơn số LED
+        let num_particles = (num_leds / 20).max(3).min(num_leds.max(1));
+        let mut particles = Vec::with_capacity(num_particles);
+
+        for _ in 0..num_particles {
+            // Vận tốc tương đối ngẫu nhiên (có thể âm hoặc dương)
+            let vel = (rand.rand_u32() as f32 / u32::MAX as f32 - 0.5) * 2.0;
+            
+            particles.push(Particle {
+                position: rand.rand_max(num_leds) as f32,
+                velocity: vel.clamp(-1.0, 1.0),
+                color: lut[rand.rand_u8() as usize],
+            });
+        }
+
+        Self {
+            num_leds,
+            particles,
+            lut,
+            rand: RefCell::new(rand),
+            speed: SpeedRamp::new(speed),
+        }
+    }
+
+    // Ánh xạ speed (1-255) sang vận tốc tối đa (10-60 pixels/sec)
+    fn max_velocity(speed: u8) -> f32 {
+        (speed as f32 / 255.0) * 50.0 + 10.0
+    }
+}
+
+impl Effect for BounceEffect {
+    fn name(&self) -> &'static str { "Bounce" }
 
     fn update(&mut self, delta_us: u64) -> bool {
         // Chuyển delta_us sang giây (dưới dạng f32)
         let delta_sec = (delta_us as f32) / 1_000_000.0;
-        let max_pos = (self.num_leds - 1) as f32;
+        let max_pos = self.num_leds.saturating_sub(1) as f32;
+
+        // Speed được làm mượt → vận tốc đổi dần, không giật
+        let max_vel = Self::max_velocity(self.speed.step(delta_us));
 
         for p in self.particles.iter_mut() {
             // Tính vị trí mới
-            let mut new_pos = p.position + p.velocity * delta_sec;
+            let mut new_pos = p.position + p.velocity * max_vel * delta_sec;
 
             // Kiểm tra va chạm
             if new_pos < 0.0 {
@@ -778,18 +1605,99 @@ impl Effect for BounceEffect {
     }
 
     fn set_speed(&mut self, speed: u8) -> bool {
-        // Tính toán lại tất cả vận tốc
-        self.update_speeds(speed);
+        // Giữ nguyên hướng các hạt, chỉ đổi dần độ lớn vận tốc
+        self.speed.set_target(speed);
         false
     }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match (key, parse_seed(value)) {
+            // Sinh lại toàn bộ hạt từ seed mới
+            ("seed", Some(seed)) => {
+                *self = Self::new(self.speed.target as u8, self.num_leds, seed);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Dải tần (`param=band:all|bass|mid|treble|0-7`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Band {
+    All,
+    Bass,
+    Mid,
+    Treble,
+    Bin(usize),
+}
+
+impl Band {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "all" | "volume" => Some(Band::All),
+            "bass" => Some(Band::Bass),
+            "mid" => Some(Band::Mid),
+            "treble" => Some(Band::Treble),
+            v => v.parse::<usize>().ok().filter(|i| *i < NUM_BINS).map(Band::Bin),
+        }
+    }
+}
+
+/// Đầu vào audio của hiệu ứng: dải tần bám theo và `param=invert:1`
+/// (đảo ngược, càng to càng tối - im lặng thì sáng nhất)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandSelect {
+    pub band: Band,
+    pub invert: bool,
+}
+
+impl BandSelect {
+    pub const ALL: Self = Self { band: Band::All, invert: false };
+
+    /// Giá trị 0.0-1.0 của dải đã chọn, thay cho `audio.volume`
+    pub fn level(&self, audio: &AudioData) -> f32 {
+        let level = match self.band {
+            Band::All => audio.volume,
+            Band::Bass => audio.bass,
+            Band::Mid => audio.mid,
+            Band::Treble => audio.treble,
+            Band::Bin(i) => audio.bins.get(i).copied().unwrap_or(0.0),
+        };
+        if self.invert {
+            1.0 - level.clamp(0.0, 1.0)
+        } else {
+            level
+        }
+    }
+
+    /// set_param dùng chung cho các hiệu ứng audio (`band`, `invert`), true nếu đã đổi
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "band" => match Band::parse(value) {
+                Some(band) if band != self.band => {
+                    self.band = band;
+                    true
+                }
+                _ => false,
+            },
+            "invert" => match crate::http::parse_bool(value) {
+                Some(invert) if invert != self.invert => {
+                    self.invert = invert;
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
 }
 
 pub struct AudioVolumeBarEffect {
     color: RGB8,
-    num_leds: usize,
-    center: usize,
+    band: BandSelect,
     
-    // Peak hold system (for both sides)
+    // Peak hold system (for both sides), vị trí tuyệt đối trong buffer
     peak_hold_left: usize,
     peak_hold_right: usize,
     peak_hold_time: u64,
@@ -798,6 +1706,7 @@ pub struct AudioVolumeBarEffect {
     // Smoothing for natural movement
     current_level: f32,
     smooth_factor: f32,
+    idle_decay: f32,
     
     // Idle animation
     idle_phase: f32,
@@ -806,24 +1715,53 @@ pub struct AudioVolumeBarEffect {
     
     // Background brightness
     bg_brightness: u8,
+
+    // Intensity 1-100 (50 = mặc định): ngưỡng kích hoạt và độ khuếch đại
+    intensity: u8,
 }
 
 impl AudioVolumeBarEffect {
+    const DEFAULT_INTENSITY: u8 = 50;
+    const BASE_THRESHOLD: f32 = 0.02;
+
+    const DEFAULT_RESPONSIVENESS: u8 = 50;
+    const BASE_SMOOTH_FACTOR: f32 = 0.2;
+    const BASE_IDLE_DECAY: f32 = 0.95;
+
+    /// Responsiveness 1-100 → hằng số smoothing.
+    /// Cao hơn = ít smoothing = nhanh nhưng giật hơn. 50 giữ nguyên tuning gốc
+    /// (smooth 0.2, decay 0.95); 100 gấp đôi tốc độ bám, 1 gần như đứng yên.
+    fn apply_responsiveness(&mut self, responsiveness: u8) {
+        let scale = responsiveness.clamp(1, 100) as f32 / Self::DEFAULT_RESPONSIVENESS as f32;
+        self.smooth_factor = (Self::BASE_SMOOTH_FACTOR * scale).clamp(0.01, 1.0);
+        self.idle_decay = (1.0 - (1.0 - Self::BASE_IDLE_DECAY) * scale).clamp(0.0, 0.999);
+    }
+
+    /// intensity cao → ngưỡng thấp hơn, thanh dài hơn; 50 giữ nguyên tuning gốc
+    fn gain(&self) -> f32 {
+        self.intensity as f32 / Self::DEFAULT_INTENSITY as f32
+    }
+
+    fn threshold(&self) -> f32 {
+        Self::BASE_THRESHOLD * (2.0 - self.gain()).max(0.25)
+    }
+
     pub fn new(color: RGB8, num_leds: usize) -> Self {
         Self {
             color,
-            num_leds,
-            center: num_leds / 2,
+            band: BandSelect::ALL,
             peak_hold_left: num_leds / 2,
             peak_hold_right: num_leds / 2,
             peak_hold_time: 500_000, // 500ms
             last_peak_update: 0,
             current_level: 0.0,
-            smooth_factor: 0.2,
+            smooth_factor: Self::BASE_SMOOTH_FACTOR,
+            idle_decay: Self::BASE_IDLE_DECAY,
             idle_phase: 0.0,
             idle_speed: 2.0,
             idle_amplitude: 0.15, // 15% breathing when idle
             bg_brightness: 20, // White background at 20/255 brightness
+            intensity: Self::DEFAULT_INTENSITY,
         }
     }
 }
@@ -836,7 +1774,15 @@ impl Effect for AudioVolumeBarEffect {
     }
 
     fn render(&self, buffer: &mut [RGB8]) {
-        buffer.fill(RGB8::default());
+        // Trạng thái chờ (không có micro): nền mờ + LED giữa màu user
+        buffer.fill(RGB8 {
+            r: self.bg_brightness,
+            g: self.bg_brightness,
+            b: self.bg_brightness,
+        });
+        if let Some(center) = buffer.get_mut(buffer.len() / 2) {
+            *center = self.color;
+        }
     }
     
     fn render_audio(&mut self, buffer: &mut [RGB8], audio: &AudioData, now_us: u64) {
@@ -847,6 +1793,12 @@ impl Effect for AudioVolumeBarEffect {
             b: self.bg_brightness,
         };
         buffer.fill(bg_color);
+
+        let len = buffer.len();
+        if len == 0 {
+            return;
+        }
+        let center = len / 2;
         
         // Step 2: Update breathing phase for idle animation
         let delta_sec = 0.033; // ~30 FPS
@@ -858,48 +1810,55 @@ impl Effect for AudioVolumeBarEffect {
         let breath = self.idle_phase.sin() * 0.5 + 0.5; // 0.0 to 1.0
         
         // Step 3: Calculate spread level
-        let has_audio = audio.volume > 0.02;
+        let input = self.band.level(audio);
+        let has_audio = input > self.threshold();
         
         let spread: f32 = if has_audio {
             // Smooth audio response with subtle breathing
-            let target = audio.volume;
+            let target = (input * self.gain()).min(1.0);
             self.current_level += (target - self.current_level) * self.smooth_factor;
             self.current_level * 0.9 + breath * 0.1
         } else {
             // Idle breathing animation
-            self.current_level *= 0.95; // Decay
+            self.current_level *= self.idle_decay; // Decay
             self.idle_amplitude * breath
         };
         
         // Step 4: Calculate LEDs to light from center
-        let half_spread = ((spread * (self.num_leds / 2) as f32) as usize).min(self.num_leds / 2);
+        let half_spread = ((spread * (len / 2) as f32) as usize).min(len / 2);
         
         // Step 5: Render user color from center spreading out
         // Left side
         for i in 0..half_spread {
-            let pos = self.center.saturating_sub(i + 1);
-            if pos < self.num_leds {
+            let pos = center.saturating_sub(i + 1);
+            if pos < len {
                 buffer[pos] = self.color;
             }
         }
         
         // Right side
         for i in 0..half_spread {
-            let pos = self.center + i + 1;
-            if pos < self.num_leds {
+            let pos = center + i + 1;
+            if pos < len {
                 buffer[pos] = self.color;
             }
         }
         
         // Center LED (always user color when active)
         if spread > 0.01 {
-            buffer[self.center] = self.color;
+            buffer[center] = self.color;
         }
         
         // Step 6: Peak hold system (only when audio active)
         if has_audio {
-            let left_peak_pos = self.center.saturating_sub(half_spread);
-            let right_peak_pos = (self.center + half_spread).min(self.num_leds - 1);
+            // Buffer đổi độ dài → peak cũ có thể nằm ngoài, kéo về tâm
+            if self.peak_hold_left > center || self.peak_hold_right >= len {
+                self.peak_hold_left = center;
+                self.peak_hold_right = center;
+            }
+
+            let left_peak_pos = center.saturating_sub(half_spread);
+            let right_peak_pos = (center + half_spread).min(len - 1);
             
             // Update peaks
             if left_peak_pos < self.peak_hold_left {
@@ -912,25 +1871,25 @@ impl Effect for AudioVolumeBarEffect {
             }
             
             // Peak decay
-            if now_us - self.last_peak_update > self.peak_hold_time {
-                if self.peak_hold_left < self.center {
+            if now_us.saturating_sub(self.last_peak_update) > self.peak_hold_time {
+                if self.peak_hold_left < center {
                     self.peak_hold_left += 1;
                 }
-                if self.peak_hold_right > self.center {
+                if self.peak_hold_right > center {
                     self.peak_hold_right = self.peak_hold_right.saturating_sub(1);
                 }
                 self.last_peak_update = now_us;
             }
             
             // Render peak markers (brighter version of user color)
-            if self.peak_hold_left < self.center && self.peak_hold_left < self.num_leds {
+            if self.peak_hold_left < center && self.peak_hold_left < len {
                 buffer[self.peak_hold_left] = RGB8 {
                     r: self.color.r.saturating_add(50).min(255),
                     g: self.color.g.saturating_add(50).min(255),
                     b: self.color.b.saturating_add(50).min(255),
                 };
             }
-            if self.peak_hold_right > self.center && self.peak_hold_right < self.num_leds {
+            if self.peak_hold_right > center && self.peak_hold_right < len {
                 buffer[self.peak_hold_right] = RGB8 {
                     r: self.color.r.saturating_add(50).min(255),
                     g: self.color.g.saturating_add(50).min(255),
@@ -939,8 +1898,8 @@ impl Effect for AudioVolumeBarEffect {
             }
         } else {
             // Reset peaks when idle
-            self.peak_hold_left = self.center;
-            self.peak_hold_right = self.center;
+            self.peak_hold_left = center;
+            self.peak_hold_right = center;
         }
     }
 
@@ -949,7 +1908,1323 @@ impl Effect for AudioVolumeBarEffect {
         true // Need re-render with new color
     }
     
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "intensity" => match value.parse::<u8>() {
+                Ok(v) => {
+                    self.intensity = v.clamp(1, 100);
+                    false
+                }
+                Err(_) => false,
+            },
+            "responsiveness" => match value.parse::<u8>() {
+                Ok(v) => {
+                    self.apply_responsiveness(v);
+                    false
+                }
+                Err(_) => false,
+            },
+            "band" | "invert" => self.band.set_param(key, value),
+            _ => false,
+        }
+    }
+    
     fn is_audio_reactive(&self) -> bool { 
         true 
     }
-}
\ No newline at end of file
+}
+
+
+/// Cả dải một màu, độ sáng bám theo volume.
+/// Tham số: `attack` / `release` (1-100, % khoảng cách tới target mỗi frame)
+/// và `floor` (0-255, độ sáng tối thiểu khi im lặng), `band`.
+pub struct PulseSolidEffect {
+    color: RGB8,
+    band: BandSelect,
+    level: f32,
+    attack: f32,
+    release: f32,
+    floor: u8,
+}
+
+impl PulseSolidEffect {
+    const DEFAULT_ATTACK: u8 = 60;
+    const DEFAULT_RELEASE: u8 = 15;
+
+    pub fn new(color: RGB8) -> Self {
+        Self {
+            color,
+            band: BandSelect::ALL,
+            level: 0.0,
+            attack: Self::DEFAULT_ATTACK as f32 / 100.0,
+            release: Self::DEFAULT_RELEASE as f32 / 100.0,
+            floor: 0,
+        }
+    }
+
+    fn fill(&self, buffer: &mut [RGB8], level: f32) {
+        let floor = self.floor as f32 / 255.0;
+        let scale = (floor + (1.0 - floor) * level.clamp(0.0, 1.0)) * 255.0;
+        buffer.fill(dim_color(self.color, scale.round() as u8));
+    }
+}
+
+impl Effect for PulseSolidEffect {
+    fn name(&self) -> &'static str { "Pulse Solid" }
+
+    fn update(&mut self, _delta_us: u64) -> bool {
+        true
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        // Không có micro: chỉ hiển thị mức nền
+        self.fill(buffer, 0.0);
+    }
+
+    fn render_audio(&mut self, buffer: &mut [RGB8], audio: &AudioData, _now_us: u64) {
+        let target = self.band.level(audio).clamp(0.0, 1.0);
+        let k = if target > self.level { self.attack } else { self.release };
+        self.level += (target - self.level) * k;
+        self.fill(buffer, self.level);
+    }
+
+    fn set_color(&mut self, color: RGB8) -> bool {
+        self.color = color;
+        true
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        if key == "band" || key == "invert" {
+            return self.band.set_param(key, value);
+        }
+
+        let Ok(v) = value.parse::<u8>() else {
+            return false;
+        };
+
+        match key {
+            "attack" => self.attack = v.clamp(1, 100) as f32 / 100.0,
+            "release" => self.release = v.clamp(1, 100) as f32 / 100.0,
+            "floor" => {
+                self.floor = v;
+                return true;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    fn is_audio_reactive(&self) -> bool {
+        true
+    }
+}
+
+/// Điểm gốc của hiệu ứng mọc ra (thanh VU, peak rơi về phía gốc)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Origin {
+    Center,
+    Start,
+    End,
+}
+
+impl Origin {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "center" => Some(Origin::Center),
+            "start" => Some(Origin::Start),
+            "end" => Some(Origin::End),
+            _ => None,
+        }
+    }
+}
+
+/// Mức VU đã làm mượt (lên nhanh, xuống chậm) kèm chấm peak giữ 400ms
+/// rồi rơi có gia tốc. Dùng chung cho vucenter và triband.
+struct LevelMeter {
+    level: f32,
+    peak: f32,
+    peak_velocity: f32,
+    peak_hold_until: u64,
+    last_us: u64,
+}
+
+impl LevelMeter {
+    const ATTACK: f32 = 0.6;
+    const RELEASE: f32 = 0.15;
+    const PEAK_HOLD_US: u64 = 400_000;
+    const PEAK_GRAVITY: f32 = 2.5; // tỉ lệ cả thanh / s²
+
+    fn new() -> Self {
+        Self { level: 0.0, peak: 0.0, peak_velocity: 0.0, peak_hold_until: 0, last_us: 0 }
+    }
+
+    /// Đưa mức hiện tại về phía `target` (0.0-1.0) và cập nhật peak
+    fn update(&mut self, target: f32, now_us: u64) {
+        let rate = if target > self.level { Self::ATTACK } else { Self::RELEASE };
+        self.level += (target - self.level) * rate;
+
+        let dt = if self.last_us == 0 {
+            0.0
+        } else {
+            (now_us.saturating_sub(self.last_us).min(100_000)) as f32 / 1_000_000.0
+        };
+        self.last_us = now_us;
+
+        if self.level >= self.peak {
+            self.peak = self.level;
+            self.peak_velocity = 0.0;
+            self.peak_hold_until = now_us + Self::PEAK_HOLD_US;
+        } else if now_us >= self.peak_hold_until {
+            self.peak_velocity += Self::PEAK_GRAVITY * dt;
+            self.peak = (self.peak - self.peak_velocity * dt).max(self.level);
+        }
+    }
+}
+
+/// VU đối xứng: thanh mọc từ tâm ra hai đầu, gradient xanh → vàng → đỏ
+/// theo khoảng cách tới tâm, mỗi bên một chấm peak rơi có gia tốc.
+/// Dải lẻ: LED giữa là điểm 0; dải chẵn: hai LED giữa là điểm 0.
+/// Tham số: `intensity` (1-100, giống volumebar), `band`,
+/// `origin` (center | start | end: mọc từ một đầu thay vì từ tâm).
+pub struct VuCenterEffect {
+    band: BandSelect,
+    origin: Origin,
+    meter: LevelMeter,
+    intensity: u8,
+}
+
+impl VuCenterEffect {
+    const DEFAULT_INTENSITY: u8 = 50;
+    const IDLE_BRIGHTNESS: u8 = 20;
+
+    pub fn new() -> Self {
+        Self {
+            band: BandSelect::ALL,
+            origin: Origin::Center,
+            meter: LevelMeter::new(),
+            intensity: Self::DEFAULT_INTENSITY,
+        }
+    }
+
+    fn gain(&self) -> f32 {
+        self.intensity as f32 / Self::DEFAULT_INTENSITY as f32
+    }
+
+    /// Số bước từ gốc tới đầu xa nhất
+    fn reach(&self, len: usize) -> usize {
+        match self.origin {
+            Origin::Center => (len + 1) / 2,
+            Origin::Start | Origin::End => len,
+        }
+    }
+
+    /// Vị trí thứ `i` tính từ gốc → (trái, phải). Từ tâm: hai bên đối xứng,
+    /// bằng nhau ở tâm dải lẻ. Từ một đầu: cả hai là cùng một LED.
+    fn side_positions(&self, len: usize, i: usize) -> (usize, usize) {
+        match self.origin {
+            Origin::Center if len % 2 == 1 => (len / 2 - i, len / 2 + i),
+            Origin::Center => (len / 2 - 1 - i, len / 2 + i),
+            Origin::Start => (i, i),
+            Origin::End => (len - 1 - i, len - 1 - i),
+        }
+    }
+}
+
+/// 0.0 → xanh lá, 0.5 → vàng, 1.0 → đỏ
+fn level_to_color(t: f32) -> RGB8 {
+    let t = t.clamp(0.0, 1.0);
+    if t < 0.5 {
+        RGB8 { r: (t * 2.0 * 255.0) as u8, g: 255, b: 0 }
+    } else {
+        RGB8 { r: 255, g: ((1.0 - t) * 2.0 * 255.0) as u8, b: 0 }
+    }
+}
+
+impl Effect for VuCenterEffect {
+    fn name(&self) -> &'static str { "VU Center" }
+
+    fn update(&mut self, _delta_us: u64) -> bool {
+        true
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        // Không có micro: chỉ điểm 0 sáng mờ
+        buffer.fill(RGB8::default());
+        if buffer.is_empty() {
+            return;
+        }
+        let (left, right) = self.side_positions(buffer.len(), 0);
+        let idle = dim_color(level_to_color(0.0), Self::IDLE_BRIGHTNESS);
+        buffer[left] = idle;
+        buffer[right] = idle;
+    }
+
+    fn render_audio(&mut self, buffer: &mut [RGB8], audio: &AudioData, now_us: u64) {
+        buffer.fill(RGB8::default());
+        let len = buffer.len();
+        if len == 0 {
+            return;
+        }
+
+        let target = (self.band.level(audio) * self.gain()).min(1.0);
+        self.meter.update(target, now_us);
+
+        // Số LED mỗi bên (hoặc cả dải nếu gốc ở một đầu), tính cả điểm 0
+        let half = self.reach(len);
+        let lit = (self.meter.level * half as f32).round() as usize;
+
+        for i in 0..lit.min(half) {
+            let color = level_to_color(i as f32 / (half - 1).max(1) as f32);
+            let (left, right) = self.side_positions(len, i);
+            buffer[left] = color;
+            buffer[right] = color;
+        }
+
+        if self.meter.peak > 0.01 {
+            let i = ((self.meter.peak * half as f32).ceil() as usize).clamp(1, half) - 1;
+            let (left, right) = self.side_positions(len, i);
+            let white = RGB8 { r: 255, g: 255, b: 255 };
+            buffer[left] = white;
+            buffer[right] = white;
+        }
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match (key, value.parse::<u8>()) {
+            ("intensity", Ok(v)) => {
+                self.intensity = v.clamp(1, 100);
+                false
+            }
+            ("band" | "invert", _) => self.band.set_param(key, value),
+            ("origin", _) => match Origin::parse(value) {
+                Some(origin) if origin != self.origin => {
+                    self.origin = origin;
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn is_audio_reactive(&self) -> bool {
+        true
+    }
+}
+
+/// Ba thanh VU độc lập trên một dải: bass ở 1/3 đầu, mid ở giữa, treble ở cuối.
+/// Dải không chia hết cho 3 thì phần dư thuộc vùng giữa. Mỗi thanh mọc từ
+/// đầu vùng, sáng dần về phía đỉnh, chấm peak trắng rơi như vucenter.
+/// Tham số: `intensity` (1-100, giống volumebar).
+pub struct TriBandEffect {
+    meters: [LevelMeter; 3],
+    intensity: u8,
+}
+
+impl TriBandEffect {
+    const DEFAULT_INTENSITY: u8 = 50;
+    const IDLE_BRIGHTNESS: u8 = 20;
+    // Màu vùng bass, mid, treble
+    const COLORS: [RGB8; 3] = [
+        RGB8 { r: 255, g: 40, b: 0 },
+        RGB8 { r: 40, g: 255, b: 0 },
+        RGB8 { r: 0, g: 80, b: 255 },
+    ];
+
+    pub fn new() -> Self {
+        Self {
+            meters: [LevelMeter::new(), LevelMeter::new(), LevelMeter::new()],
+            intensity: Self::DEFAULT_INTENSITY,
+        }
+    }
+
+    /// (vị trí bắt đầu, độ dài) của 3 vùng
+    fn zones(len: usize) -> [(usize, usize); 3] {
+        let base = len / 3;
+        let middle = base + len % 3;
+        [(0, base), (base, middle), (base + middle, base)]
+    }
+}
+
+impl Effect for TriBandEffect {
+    fn name(&self) -> &'static str { "Tri-band" }
+
+    fn update(&mut self, _delta_us: u64) -> bool {
+        true
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        // Không có micro: LED đầu mỗi vùng sáng mờ
+        buffer.fill(RGB8::default());
+        for (&(start, size), &color) in Self::zones(buffer.len()).iter().zip(&Self::COLORS) {
+            if size > 0 {
+                buffer[start] = dim_color(color, Self::IDLE_BRIGHTNESS);
+            }
+        }
+    }
+
+    fn render_audio(&mut self, buffer: &mut [RGB8], audio: &AudioData, now_us: u64) {
+        buffer.fill(RGB8::default());
+        let gain = self.intensity as f32 / Self::DEFAULT_INTENSITY as f32;
+        let levels = [audio.bass, audio.mid, audio.treble];
+
+        for (i, &(start, size)) in Self::zones(buffer.len()).iter().enumerate() {
+            let meter = &mut self.meters[i];
+            meter.update((levels[i] * gain).min(1.0), now_us);
+            if size == 0 {
+                continue;
+            }
+
+            let zone = &mut buffer[start..start + size];
+            let lit = ((meter.level * size as f32).round() as usize).min(size);
+            for (j, pixel) in zone[..lit].iter_mut().enumerate() {
+                // Gốc thanh 40%, đỉnh sáng hết
+                let scale = 102 + (153 * (j + 1) / size) as u8;
+                *pixel = dim_color(Self::COLORS[i], scale);
+            }
+
+            if meter.peak > 0.01 {
+                let j = ((meter.peak * size as f32).ceil() as usize).clamp(1, size) - 1;
+                zone[j] = RGB8 { r: 255, g: 255, b: 255 };
+            }
+        }
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match (key, value.parse::<u8>()) {
+            ("intensity", Ok(v)) => {
+                self.intensity = v.clamp(1, 100);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn is_audio_reactive(&self) -> bool {
+        true
+    }
+}
+
+/// Các dải màu đặc rộng `width` LED, 2-3 màu xen kẽ, trôi dọc dải LED.
+/// Vị trí tính sub-pixel: biên giữa 2 dải được trộn màu theo phần lẻ.
+/// Tham số: `width` (1-50), `color2`, `color3` (RRGGBB hoặc `none` = 2 màu).
+pub struct StripesEffect {
+    colors: [RGB8; 3],
+    count: usize,
+    width: u8,
+    speed: u8,
+    offset: f32, // Số LED đã trôi, trong [0, width * count)
+}
+
+impl StripesEffect {
+    const DEFAULT_WIDTH: u8 = 5;
+    const MAX_WIDTH: u8 = 50;
+    const MAX_PX_PER_S: f32 = 40.0;
+
+    pub fn new(color: RGB8, speed: u8) -> Self {
+        Self {
+            colors: [color, RGB8 { r: 255, g: 255, b: 255 }, RGB8::default()],
+            count: 2,
+            width: Self::DEFAULT_WIDTH,
+            speed: speed.clamp(1, 255),
+            offset: 0.0,
+        }
+    }
+
+    fn period(&self) -> f32 {
+        self.width as f32 * self.count as f32
+    }
+
+    fn set_slot(&mut self, slot: usize, color: RGB8) -> bool {
+        if self.colors[slot] == color {
+            return false;
+        }
+        self.colors[slot] = color;
+        true
+    }
+}
+
+impl Effect for StripesEffect {
+    fn name(&self) -> &'static str { "Stripes" }
+
+    fn update(&mut self, delta_us: u64) -> bool {
+        // speed 1-255 → ~0.16-40 LED/s
+        let px_per_s = self.speed as f32 / 255.0 * Self::MAX_PX_PER_S;
+        self.offset = (self.offset + px_per_s * delta_us as f32 / 1_000_000.0) % self.period();
+        true
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        let width = self.width as f32;
+        let period = self.period();
+
+        for (i, pixel) in buffer.iter_mut().enumerate() {
+            // Pattern lặp theo chu kỳ riêng nên không phụ thuộc độ dài dải
+            let pos = (i as f32 + period - self.offset) % period;
+            let band = (pos / width) as usize % self.count;
+            let within = pos - band as f32 * width;
+
+            // LED cuối của dải trộn với dải kế tiếp theo phần lẻ → trôi mượt
+            let edge = within - (width - 1.0);
+            *pixel = if edge > 0.0 {
+                let next = self.colors[(band + 1) % self.count];
+                blend_color(self.colors[band], next, (edge * 255.0) as u8)
+            } else {
+                self.colors[band]
+            };
+        }
+    }
+
+    fn set_color(&mut self, color: RGB8) -> bool {
+        self.set_slot(0, color)
+    }
+
+    fn set_speed(&mut self, speed: u8) -> bool {
+        self.speed = speed.clamp(1, 255);
+        false
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "width" => match value.parse::<u8>() {
+                Ok(w) => {
+                    let w = w.clamp(1, Self::MAX_WIDTH);
+                    if w == self.width {
+                        return false;
+                    }
+                    self.width = w;
+                    self.offset %= self.period();
+                    true
+                }
+                Err(_) => false,
+            },
+            "color2" => match crate::color::parse_hex_color(value) {
+                Ok((r, g, b)) => self.set_slot(1, RGB8 { r, g, b }),
+                Err(_) => false,
+            },
+            "color3" if value == "none" => {
+                self.count = 2;
+                self.offset %= self.period();
+                true
+            }
+            "color3" => match crate::color::parse_hex_color(value) {
+                Ok((r, g, b)) => {
+                    self.count = 3;
+                    self.set_slot(2, RGB8 { r, g, b });
+                    true
+                }
+                Err(_) => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+const MAX_RIPPLES: usize = 6;
+
+struct Ripple {
+    // Vị trí gốc theo tỉ lệ 0.0-1.0 của dải, quy ra LED lúc render
+    origin: f32,
+    radius: f32,
+    intensity: f32,
+    color: RGB8,
+    // LED/giây lúc sinh ra: đổi speed chỉ áp cho vòng mới, vòng đang lan giữ tốc độ cũ
+    speed: f32,
+}
+
+/// Giọt mưa trên mặt nước: vòng sóng sinh ở vị trí ngẫu nhiên, lan ra hai phía và mờ dần.
+/// Có micro thì sinh vòng mới theo beat (độ mạnh theo `band`), không có thì sinh định kỳ.
+/// Nền mờ dần thay vì xóa nên vòng sóng để lại vệt.
+/// Tham số: `audio` (0|1, 0 = luôn sinh định kỳ), `band`, `random` (0|1: mỗi vòng một màu ngẫu nhiên),
+/// `flush` (1 = xóa các vòng đang lan, để màu/speed mới thấy ngay).
+pub struct RippleEffect {
+    ripples: heapless::Vec<Ripple, MAX_RIPPLES>,
+    color: RGB8,
+    random_colors: bool,
+    audio: bool,
+    band: BandSelect,
+    // LED/giây cho vòng sinh sau này
+    expand_speed: f32,
+    spawn_interval_us: u64,
+    spawn_timer: u64,
+    // Số frame chưa nhận audio: quá ngưỡng thì chuyển sang sinh định kỳ
+    frames_without_audio: u32,
+    last_beat_count: u32,
+    rand: FastRand,
+}
+
+impl RippleEffect {
+    const RING_SIGMA: f32 = 1.2;      // Độ rộng vòng sóng (LED)
+    const DECAY_PER_S: f32 = 1.5;     // Cường độ giảm theo e^(-k*t)
+    const BACKGROUND_FADE: u8 = 40;
+    const AUDIO_TIMEOUT_FRAMES: u32 = 30;
+
+    pub fn new(color: RGB8, speed: u8, seed: u32) -> Self {
+        let mut effect = Self {
+            ripples: heapless::Vec::new(),
+            color,
+            random_colors: false,
+            audio: true,
+            band: BandSelect::ALL,
+            expand_speed: 0.0,
+            spawn_interval_us: 0,
+            spawn_timer: 0,
+            frames_without_audio: Self::AUDIO_TIMEOUT_FRAMES,
+            last_beat_count: 0,
+            rand: FastRand::new(seed),
+        };
+        effect.set_speed(speed);
+        effect
+    }
+
+    fn spawn(&mut self, intensity: f32) {
+        let color = if self.random_colors {
+            hue_to_rgb(self.rand.rand_max(360) as f32)
+        } else {
+            self.color
+        };
+        let ripple = Ripple {
+            origin: self.rand.rand_u32() as f32 / u32::MAX as f32,
+            radius: 0.0,
+            intensity: intensity.clamp(0.0, 1.0),
+            color,
+            speed: self.expand_speed,
+        };
+
+        // Đầy thì thay vòng mờ nhất
+        if let Err(ripple) = self.ripples.push(ripple) {
+            if let Some(weakest) = self.ripples.iter_mut().min_by(|a, b| a.intensity.total_cmp(&b.intensity)) {
+                *weakest = ripple;
+            }
+        }
+    }
+
+    fn draw(&self, buffer: &mut [RGB8]) {
+        for pixel in buffer.iter_mut() {
+            *pixel = fade_color(*pixel, Self::BACKGROUND_FADE);
+        }
+
+        let len = buffer.len();
+        if len == 0 {
+            return;
+        }
+
+        let reach = (Self::RING_SIGMA * 3.0).ceil();
+        for ripple in &self.ripples {
+            let center = ripple.origin * (len - 1) as f32;
+            // Hai mặt sóng trùng nhau lúc radius = 0, chỉ vẽ một lần
+            let fronts: &[f32] = if ripple.radius < 0.5 {
+                &[0.0]
+            } else {
+                &[-1.0, 1.0]
+            };
+
+            for dir in fronts {
+                let pos = center + dir * ripple.radius;
+                // Cắt phần vòng sóng nằm ngoài dải
+                let lo = (pos - reach).ceil().max(0.0);
+                let hi = (pos + reach).floor().min((len - 1) as f32);
+                if hi < lo {
+                    continue;
+                }
+                for i in lo as usize..=hi as usize {
+                    let d = i as f32 - pos;
+                    let falloff = (-(d * d) / (2.0 * Self::RING_SIGMA * Self::RING_SIGMA)).exp();
+                    let c = dim_color(ripple.color, (ripple.intensity * falloff * 255.0) as u8);
+                    let p = &mut buffer[i];
+                    *p = RGB8 { r: p.r.saturating_add(c.r), g: p.g.saturating_add(c.g), b: p.b.saturating_add(c.b) };
+                }
+            }
+        }
+    }
+}
+
+impl Effect for RippleEffect {
+    fn name(&self) -> &'static str { "Ripple" }
+
+    fn update(&mut self, delta_us: u64) -> bool {
+        let dt = delta_us as f32 / 1_000_000.0;
+        let decay = (-Self::DECAY_PER_S * dt).exp();
+        for ripple in self.ripples.iter_mut() {
+            ripple.radius += ripple.speed * dt;
+            ripple.intensity *= decay;
+        }
+        self.ripples.retain(|r| r.intensity > 0.02);
+
+        // Không có audio (hoặc tắt audio) → sinh vòng sóng định kỳ
+        self.frames_without_audio = self.frames_without_audio.saturating_add(1);
+        if !self.audio || self.frames_without_audio > Self::AUDIO_TIMEOUT_FRAMES {
+            self.spawn_timer += delta_us;
+            if self.spawn_timer >= self.spawn_interval_us {
+                self.spawn_timer = 0;
+                let intensity = 0.6 + self.rand.rand_u8() as f32 / 255.0 * 0.4;
+                self.spawn(intensity);
+            }
+        }
+        true
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        self.draw(buffer);
+    }
+
+    fn render_audio(&mut self, buffer: &mut [RGB8], audio: &AudioData, _now_us: u64) {
+        if self.audio {
+            self.frames_without_audio = 0;
+            if audio.beat_count != self.last_beat_count {
+                self.last_beat_count = audio.beat_count;
+                let level = self.band.level(audio);
+                self.spawn(0.4 + level.clamp(0.0, 1.0) * 0.6);
+            }
+        }
+        self.draw(buffer);
+    }
+
+    // Vòng mới dùng màu mới, vòng đang lan giữ màu cũ tới khi tắt (xem `flush`)
+    fn set_color(&mut self, color: RGB8) -> bool {
+        self.color = color;
+        false
+    }
+
+    fn set_speed(&mut self, speed: u8) -> bool {
+        // speed 0 → 5 LED/s, 255 → 60 LED/s; sinh định kỳ nhanh hơn khi speed cao
+        self.expand_speed = 5.0 + speed as f32 * 55.0 / 255.0;
+        self.spawn_interval_us = speed_to_interval_us(speed, 300, 1500);
+        false
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "band" | "invert" => self.band.set_param(key, value),
+            "audio" => match value {
+                "0" => { self.audio = false; false }
+                "1" => { self.audio = true; false }
+                _ => false,
+            },
+            "random" => match value {
+                "0" => { self.random_colors = false; false }
+                "1" => { self.random_colors = true; false }
+                _ => false,
+            },
+            "seed" => match parse_seed(value) {
+                Some(seed) => {
+                    self.rand = FastRand::new(seed);
+                    self.ripples.clear();
+                    self.spawn_timer = 0;
+                    true
+                }
+                None => false,
+            },
+            // Vòng kế tiếp sinh ngay (hoặc ở beat tới) với màu/speed hiện tại
+            "flush" if value == "1" => {
+                self.ripples.clear();
+                self.spawn_timer = self.spawn_interval_us;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn is_audio_reactive(&self) -> bool {
+        true
+    }
+}
+
+/// Đổi sang màu mới mỗi beat rồi giữ nguyên tới beat sau (không tắt dần như flash).
+/// Màu mới: hue ngẫu nhiên cách màu cũ ít nhất 60°, hoặc điểm màu kế tiếp của palette.
+/// `speed` quyết định độ "nháy" sáng lên ở mỗi beat (0 = không nháy).
+/// Tham số: `palette` (tên hoặc `none`), `refractory` (50-1000ms, bỏ qua beat đến quá sát).
+pub struct BeatColorEffect {
+    color: RGB8,
+    hue: u16,
+    palette: Option<Palette>,
+    stop_index: usize,
+    last_beat_count: u32,
+    last_change_us: u64,
+    last_us: u64,
+    refractory_us: u64,
+    pulse: f32,
+    pulse_depth: f32,
+    rand: FastRand,
+}
+
+impl BeatColorEffect {
+    const DEFAULT_REFRACTORY_MS: u64 = 150;
+    const MAX_PULSE_DEPTH: f32 = 0.35;
+    const PULSE_DECAY_PER_S: f32 = 8.0;
+
+    pub fn new(color: RGB8, speed: u8, seed: u32) -> Self {
+        let mut effect = Self {
+            color,
+            hue: 0,
+            palette: None,
+            stop_index: 0,
+            last_beat_count: 0,
+            last_change_us: 0,
+            last_us: 0,
+            refractory_us: Self::DEFAULT_REFRACTORY_MS * 1000,
+            pulse: 0.0,
+            pulse_depth: 0.0,
+            rand: FastRand::new(seed),
+        };
+        effect.set_speed(speed);
+        effect
+    }
+
+    fn next_color(&mut self) {
+        self.color = match &self.palette {
+            Some(palette) => {
+                self.stop_index = (self.stop_index + 1) % palette.stops.len();
+                palette.stops[self.stop_index]
+            }
+            None => {
+                self.hue = (self.hue + 60 + self.rand.rand_max(241) as u16) % 360;
+                hue_to_rgb(self.hue as f32)
+            }
+        };
+    }
+}
+
+impl Effect for BeatColorEffect {
+    fn name(&self) -> &'static str { "Beat Color" }
+
+    fn update(&mut self, _delta_us: u64) -> bool {
+        true
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        // Không có micro: giữ màu hiện tại
+        buffer.fill(self.color);
+    }
+
+    fn render_audio(&mut self, buffer: &mut [RGB8], audio: &AudioData, now_us: u64) {
+        let dt = now_us.saturating_sub(self.last_us) as f32 / 1_000_000.0;
+        self.last_us = now_us;
+        self.pulse *= (-Self::PULSE_DECAY_PER_S * dt.min(1.0)).exp();
+
+        if audio.beat_count != self.last_beat_count {
+            self.last_beat_count = audio.beat_count;
+            if now_us.saturating_sub(self.last_change_us) >= self.refractory_us {
+                self.last_change_us = now_us;
+                self.next_color();
+                self.pulse = 1.0;
+            }
+        }
+
+        // Nền hơi tối hơn màu đầy đủ, beat đẩy lên 100% rồi lắng xuống
+        let level = 1.0 - self.pulse_depth * (1.0 - self.pulse);
+        buffer.fill(dim_color(self.color, (level * 255.0).round() as u8));
+    }
+
+    fn set_color(&mut self, color: RGB8) -> bool {
+        self.color = color;
+        true
+    }
+
+    fn set_speed(&mut self, speed: u8) -> bool {
+        self.pulse_depth = speed as f32 / 255.0 * Self::MAX_PULSE_DEPTH;
+        false
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "palette" if value == "none" => {
+                self.palette = None;
+                false
+            }
+            "palette" => match palettes::find(value) {
+                Some(palette) => {
+                    self.stop_index = 0;
+                    self.color = palette.stops[0];
+                    self.palette = Some(palette);
+                    true
+                }
+                None => false,
+            },
+            "refractory" => match value.parse::<u64>() {
+                Ok(ms) => {
+                    self.refractory_us = ms.clamp(50, 1000) * 1000;
+                    false
+                }
+                Err(_) => false,
+            },
+            // Chỉ ảnh hưởng hue ngẫu nhiên (không dùng palette)
+            "seed" => match parse_seed(value) {
+                Some(seed) => {
+                    self.rand = FastRand::new(seed);
+                    self.hue = 0;
+                    false
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn is_audio_reactive(&self) -> bool {
+        true
+    }
+}
+
+/// Thanh tiến trình: `fraction` × số LED sáng bằng màu đã chọn, LED ở biên sáng
+/// một phần theo phần lẻ. Giá trị đến qua `set_progress` (POST /led/progress).
+/// Giống Static: chỉ render lại khi giá trị hoặc màu đổi.
+pub struct ProgressEffect {
+    color: RGB8,
+    fraction: f32,
+    dirty: bool,
+}
+
+impl ProgressEffect {
+    pub fn new(color: RGB8) -> Self {
+        Self { color, fraction: 0.0, dirty: true }
+    }
+}
+
+impl Effect for ProgressEffect {
+    fn name(&self) -> &'static str { "Progress" }
+
+    fn update(&mut self, _delta_us: u64) -> bool {
+        core::mem::take(&mut self.dirty)
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        buffer.fill(RGB8::default());
+
+        let lit = self.fraction * buffer.len() as f32;
+        let full = (lit as usize).min(buffer.len());
+        buffer[..full].fill(self.color);
+
+        let partial = lit - full as f32;
+        if let Some(edge) = buffer.get_mut(full) {
+            *edge = dim_color(self.color, (partial * 255.0).round() as u8);
+        }
+    }
+
+    fn set_color(&mut self, color: RGB8) -> bool {
+        self.color = color;
+        true
+    }
+
+    fn set_progress(&mut self, fraction: f32) -> bool {
+        let fraction = fraction.clamp(0.0, 1.0);
+        if fraction == self.fraction {
+            return false;
+        }
+        self.fraction = fraction;
+        true
+    }
+}
+
+/// Đèn mood: cả dải một màu, lần lượt qua các điểm màu của palette rồi quay lại.
+/// Mỗi bước giữ nguyên màu nửa thời gian, nửa còn lại chuyển dần sang màu kế tiếp.
+/// `speed` quyết định thời gian mỗi bước: 255 = 5s, 1 = 5 phút.
+/// Tham số: `palette` (tên palette, mặc định `mood`: đỏ → cam → tím → xanh dương).
+pub struct MoodEffect {
+    stops: heapless::Vec<RGB8, { palettes::MAX_STOPS }>,
+    index: usize,
+    elapsed_us: u64,
+    step_us: u64,
+    color: RGB8,
+}
+
+impl MoodEffect {
+    const DEFAULT_PALETTE: &'static str = "mood";
+    const FALLBACK: [RGB8; 2] = [RGB8 { r: 255, g: 0, b: 0 }, RGB8 { r: 0, g: 48, b: 255 }];
+
+    pub fn new(speed: u8) -> Self {
+        let mut effect = Self {
+            stops: Self::FALLBACK.iter().copied().collect(),
+            index: 0,
+            elapsed_us: 0,
+            step_us: 0,
+            color: Self::FALLBACK[0],
+        };
+        if let Some(palette) = palettes::find(Self::DEFAULT_PALETTE) {
+            effect.use_palette(palette);
+        }
+        effect.set_speed(speed);
+        effect
+    }
+
+    fn use_palette(&mut self, palette: Palette) {
+        self.stops = palette.stops;
+        self.index = 0;
+        self.elapsed_us = 0;
+        self.color = self.stops[0];
+    }
+
+    /// Màu tại thời điểm hiện tại của bước `index`
+    fn current_color(&self) -> RGB8 {
+        let from = self.stops[self.index];
+        let to = self.stops[(self.index + 1) % self.stops.len()];
+
+        let hold_us = self.step_us / 2;
+        if self.elapsed_us <= hold_us {
+            return from;
+        }
+        let t = (self.elapsed_us - hold_us) * 255 / (self.step_us - hold_us).max(1);
+        blend_color(from, to, t.min(255) as u8)
+    }
+}
+
+impl Effect for MoodEffect {
+    fn name(&self) -> &'static str { "Mood" }
+
+    fn update(&mut self, delta_us: u64) -> bool {
+        self.elapsed_us += delta_us;
+        while self.elapsed_us >= self.step_us {
+            self.elapsed_us -= self.step_us;
+            self.index = (self.index + 1) % self.stops.len();
+        }
+
+        // Chuyển màu rất chậm: phần lớn frame không đổi màu → không render
+        let color = self.current_color();
+        if color != self.color {
+            self.color = color;
+            return true;
+        }
+        false
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        buffer.fill(self.color);
+    }
+
+    fn set_speed(&mut self, speed: u8) -> bool {
+        // Giữ tiến độ tương đối của bước hiện tại khi đổi speed
+        let new_step = speed_to_interval_us(speed, 5_000, 300_000);
+        if self.step_us > 0 {
+            self.elapsed_us = self.elapsed_us * new_step / self.step_us;
+        }
+        self.step_us = new_step;
+        false
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "palette" => match palettes::find(value) {
+                Some(palette) => {
+                    self.use_palette(palette);
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Ảnh tĩnh upload qua `/led/pattern`: mỗi LED một màu cố định.
+/// Ít màu hơn số LED thì phần còn lại tắt, hoặc lặp lại nếu `tile`.
+/// Chỉ render khi mới tạo (giống Static).
+pub struct PatternEffect {
+    colors: Vec<RGB8>,
+    tile: bool,
+    dirty: bool,
+}
+
+impl PatternEffect {
+    pub fn new(colors: Vec<RGB8>, tile: bool) -> Self {
+        Self { colors, tile, dirty: true }
+    }
+}
+
+impl Effect for PatternEffect {
+    fn name(&self) -> &'static str { "Pattern" }
+
+    fn update(&mut self, _delta_us: u64) -> bool {
+        core::mem::take(&mut self.dirty)
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        let len = self.colors.len();
+        for (i, pixel) in buffer.iter_mut().enumerate() {
+            *pixel = match self.colors.get(i) {
+                Some(&color) => color,
+                None if self.tile && len > 0 => self.colors[i % len],
+                None => RGB8::default(),
+            };
+        }
+    }
+}
+
+/// Dải màu ghép từ các đoạn (`/led/runs`): `len` LED liên tiếp cùng một màu.
+/// Tổng dài hơn dải thì bị cắt, ngắn hơn thì phần còn lại tắt.
+/// Chỉ render khi mới tạo (giống Pattern).
+pub struct RunLengthEffect {
+    runs: Vec<(u16, RGB8)>,
+    dirty: bool,
+}
+
+impl RunLengthEffect {
+    pub fn new(runs: Vec<(u16, RGB8)>) -> Self {
+        Self { runs, dirty: true }
+    }
+}
+
+impl Effect for RunLengthEffect {
+    fn name(&self) -> &'static str { "Runs" }
+
+    fn update(&mut self, _delta_us: u64) -> bool {
+        core::mem::take(&mut self.dirty)
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        let mut colors = self.runs.iter()
+            .flat_map(|&(len, color)| core::iter::repeat(color).take(len as usize));
+        for pixel in buffer.iter_mut() {
+            *pixel = colors.next().unwrap_or_default();
+        }
+    }
+}
+
+/// Đèn báo thức bình minh: trong `duration` giây chuyển từ đỏ thẫm rất tối
+/// qua cam, vàng tới trắng ấm sáng hết, rồi giữ nguyên.
+/// Độ sáng riêng của hiệu ứng tăng theo t² (mắt nhạy ở mức tối) và vẫn
+/// bị giới hạn bởi brightness chung. Thời gian tính theo delta thực của mỗi frame.
+/// Tham số: `duration` (60-7200 giây, mặc định 1800).
+pub struct SunriseEffect {
+    duration_us: u64,
+    elapsed_us: u64,
+    color: RGB8,
+}
+
+impl SunriseEffect {
+    pub const DEFAULT_DURATION_S: u32 = 1800;
+    pub const MIN_DURATION_S: u32 = 60;
+    pub const MAX_DURATION_S: u32 = 7200;
+    // Đỏ → cam → vàng → trắng ấm, cách đều nhau theo thời gian
+    const STOPS: [RGB8; 5] = [
+        RGB8 { r: 255, g: 0, b: 0 },
+        RGB8 { r: 255, g: 60, b: 0 },
+        RGB8 { r: 255, g: 150, b: 0 },
+        RGB8 { r: 255, g: 210, b: 100 },
+        RGB8 { r: 255, g: 240, b: 200 },
+    ];
+    const MIN_LEVEL: f32 = 2.0;
+
+    pub fn new() -> Self {
+        let mut effect = Self {
+            duration_us: Self::DEFAULT_DURATION_S as u64 * 1_000_000,
+            elapsed_us: 0,
+            color: RGB8::default(),
+        };
+        effect.color = effect.current_color();
+        effect
+    }
+
+    fn current_color(&self) -> RGB8 {
+        let t = (self.elapsed_us as f32 / self.duration_us as f32).min(1.0);
+
+        let pos = t * (Self::STOPS.len() - 1) as f32;
+        let index = (pos as usize).min(Self::STOPS.len() - 2);
+        let frac = ((pos - index as f32) * 255.0) as u8;
+        let hue = blend_color(Self::STOPS[index], Self::STOPS[index + 1], frac);
+
+        let level = Self::MIN_LEVEL + (255.0 - Self::MIN_LEVEL) * t * t;
+        dim_color(hue, level.round() as u8)
+    }
+}
+
+impl Effect for SunriseEffect {
+    fn name(&self) -> &'static str { "Sunrise" }
+
+    fn update(&mut self, delta_us: u64) -> bool {
+        if self.elapsed_us >= self.duration_us {
+            return false; // Đã sáng hết, giữ nguyên
+        }
+        self.elapsed_us = (self.elapsed_us + delta_us).min(self.duration_us);
+
+        let color = self.current_color();
+        if color != self.color {
+            self.color = color;
+            return true;
+        }
+        false
+    }
+
+    fn render(&self, buffer: &mut [RGB8]) {
+        buffer.fill(self.color);
+    }
+
+    fn set_param(&mut self, key: &str, value: &str) -> bool {
+        match (key, value.parse::<u32>()) {
+            ("duration", Ok(s)) => {
+                // Giữ thời gian đã chạy, chỉ đổi điểm kết thúc
+                let s = s.clamp(Self::MIN_DURATION_S, Self::MAX_DURATION_S);
+                self.duration_us = s as u64 * 1_000_000;
+                self.elapsed_us = self.elapsed_us.min(self.duration_us);
+                self.color = self.current_color();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rainbow_hue_is_continuous_across_speed_change() {
+        let mut rainbow = RainbowEffect::new(100);
+        let mut before = [RGB8::default(); 8];
+        let mut after = [RGB8::default(); 8];
+
+        rainbow.update(500_000);
+        rainbow.render(&mut before);
+        let phase = rainbow.phase16;
+
+        // Đổi speed không được làm hue nhảy
+        assert!(!rainbow.set_speed(250));
+        rainbow.render(&mut after);
+        assert_eq!(before, after);
+
+        // Frame tiếp theo tiến đúng theo speed mới, tính từ phase cũ
+        rainbow.update(10_000);
+        assert_eq!(rainbow.phase16, phase.wrapping_add(250));
+    }
+
+    #[test]
+    fn rainbow_phase_wraps_without_panicking() {
+        let mut rainbow = RainbowEffect::new(255);
+        let mut buffer = [RGB8::default(); 4];
+        for _ in 0..100 {
+            rainbow.update(u32::MAX as u64);
+            rainbow.render(&mut buffer);
+        }
+    }
+
+    #[test]
+    fn color_wipe_fills_then_wraps_to_dark() {
+        let color = RGB8 { r: 10, g: 20, b: 30 };
+        let mut wipe = ColorWipeEffect::new(color, 255, 3);
+        let step = wipe.pixel_interval_us;
+        let mut buffer = [RGB8::default(); 3];
+
+        for lit in 1..=3 {
+            assert!(wipe.update(step));
+            wipe.render(&mut buffer);
+            assert!(buffer[..lit].iter().all(|p| *p == color));
+            assert!(buffer[lit..].iter().all(|p| *p == RGB8::default()));
+        }
+
+        // Qua hết dải → về đầu, tắt tất cả
+        assert!(wipe.update(step));
+        wipe.render(&mut buffer);
+        assert!(buffer.iter().all(|p| *p == RGB8::default()));
+    }
+
+    #[test]
+    fn color_wipe_keeps_remainder_between_frames() {
+        let mut wipe = ColorWipeEffect::new(RGB8 { r: 1, g: 1, b: 1 }, 255, 10);
+        let step = wipe.pixel_interval_us;
+
+        assert!(!wipe.update(step / 2));
+        assert!(wipe.update(step / 2 + step / 4));
+        assert_eq!(wipe.current_pixel, 1);
+        assert_eq!(wipe.time_accumulator, step / 4);
+    }
+
+    #[test]
+    fn scale_rand_never_reaches_max() {
+        for max in 1..=300 {
+            assert_eq!(scale_rand(0, max), 0);
+            assert_eq!(scale_rand(u32::MAX, max), max - 1);
+        }
+    }
+
+    #[test]
+    fn rand_max_covers_the_whole_range() {
+        let mut rand = FastRand::new(12345);
+        let mut seen = [0u32; 3];
+        for _ in 0..10_000 {
+            let v = rand.rand_max(3);
+            assert!(v < 3);
+            seen[v] += 1;
+        }
+        assert!(seen.iter().all(|&n| n > 0), "{:?}", seen);
+    }
+
+    fn build(effect: &EffectType, num_leds: usize) -> Box<dyn Effect> {
+        let params = EffectParams {
+            color: RGB8 { r: 255, g: 64, b: 0 },
+            color2: GradientEffect::DEFAULT_COLOR2,
+            speed: 200,
+            num_leds,
+            seed: 1,
+        };
+        build_effect(effect, &params)
+    }
+
+    // Pattern/Runs hiển thị pattern đã lưu, test không lưu gì nên luôn tắt
+    fn shows_stored_pattern(effect: &EffectType) -> bool {
+        matches!(effect, EffectType::Pattern | EffectType::Runs)
+    }
+
+    /// Chạy `frames` frame 20ms với audio lớn, beat mỗi 10 frame; gọi `check` sau mỗi lần render
+    fn run(effect: &mut dyn Effect, buffer: &mut [RGB8], frames: u32, mut check: impl FnMut(&[RGB8])) {
+        let mut loud = AudioData { volume: 1.0, bass: 1.0, mid: 1.0, treble: 1.0, audio_available: true, ..Default::default() };
+        loud.bins = [1.0; NUM_BINS];
+
+        effect.set_progress(1.0);
+        effect.set_rssi(Some(-60));
+
+        for frame in 0..frames {
+            loud.beat = frame % 10 == 0;
+            loud.beat_count = frame / 10;
+            effect.update(20_000);
+            if effect.is_audio_reactive() {
+                effect.render_audio(buffer, &loud, (frame as u64 + 1) * 20_000);
+            } else {
+                effect.render(buffer);
+            }
+            check(buffer);
+        }
+    }
+
+    #[test]
+    fn every_effect_lights_tiny_strips() {
+        for (name, effect_type) in EFFECT_REGISTRY {
+            for num_leds in 1..=3 {
+                let mut effect = build(effect_type, num_leds);
+                let mut buffer = vec![RGB8::default(); num_leds];
+                let mut lit_frames = 0;
+
+                // Đủ lâu để đi qua vài vòng của mọi hiệu ứng
+                run(effect.as_mut(), &mut buffer, 500, |frame| {
+                    if frame.iter().any(|p| *p != RGB8::default()) {
+                        lit_frames += 1;
+                    }
+                });
+
+                if shows_stored_pattern(effect_type) {
+                    assert_eq!(lit_frames, 0, "{} on {} LEDs", name, num_leds);
+                } else {
+                    assert!(lit_frames > 0, "{} stayed dark on {} LEDs", name, num_leds);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn long_runs_stay_inside_tiny_strips() {
+        // Vị trí (bounce, ripple, comet...) tính sai ra ngoài dải sẽ panic khi ghi buffer
+        for (_, effect_type) in EFFECT_REGISTRY {
+            for num_leds in 1..=3 {
+                let mut effect = build(effect_type, num_leds);
+                let mut buffer = vec![RGB8::default(); num_leds];
+                run(effect.as_mut(), &mut buffer, 5_000, |_| {});
+            }
+        }
+    }
+
+    #[test]
+    fn single_led_static_shows_user_color() {
+        let mut buffer = [RGB8::default(); 1];
+        build(&EffectType::Static, 1).render(&mut buffer);
+        assert_eq!(buffer[0], RGB8 { r: 255, g: 64, b: 0 });
+    }
+}
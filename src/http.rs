@@ -1,100 +1,610 @@
 use anyhow::Result;
 use embedded_svc::http::Headers;
-use esp_idf_svc::http::server::{EspHttpServer, Configuration};
+use esp_idf_svc::http::server::{EspHttpServer, EspHttpConnection, Configuration, Request};
 use esp_idf_svc::io::{Read, Write};
-use crate::effect::EffectType;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use crate::ambient::AmbientLight;
+use crate::audio::SharedAudio;
+use crate::color::parse_hex_color;
+use crate::effect::{EffectType, IdentifyEffect, SunriseEffect, EFFECT_REGISTRY, effect_from_name, effect_name};
 use log::{info, warn};
+use esp_idf_hal::delay::FreeRtos;
 use heapless::spsc::Producer;
 use heapless::Vec as HeaplessVec;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
 use core::fmt::Write as FmtWrite;
+use crate::output::ChipType;
+use crate::controller::{ColorCarryOver, IdleSettings, OfflineBehavior, PartySettings, SleepTimerPolicy, PARTY_MAX_EFFECTS};
 
 pub enum LedCommand {
     SetEffect(EffectType),
     SetBrightness(f32),
     SetColor(u8, u8, u8),
     SetSpeed(u8),
+    SetParam(ParamKey, ParamValue),
+    SetBeatSync(bool),
+    Sync { reference_us: u64, restart: bool },
+    /// Nhấp nháy trắng `count` lần
+    Identify { count: u8 },
+    SetMinBrightness(u8),
+    SetPowerSave(bool),
+    SetPartyMode(Option<PartySettings>),
+    SetAudioMode(bool),
+    SetColorCycle(u32),
+    SetSleepTimer { minutes: u16, policy: SleepTimerPolicy },
+    SetSkipPixels(SkipList),
+    SetThermalProtection(Option<u16>),
+    SetColorCarryOver(ColorCarryOver),
+    SetProgress(f32),
+    SetInterpolation(bool),
+    SetHuePreserve(bool),
+    SetConnectFlash(Option<(u8, u8, u8)>),
+    SetSaturation(u8),
+    /// Hiệu ứng chỉ chạy trong LED `start..end`, None = cả dải
+    SetRange(Option<(u16, u16)>),
+    SetOfflineBehavior { behavior: OfflineBehavior, minutes: u16 },
+    /// None = tắt idle timeout
+    SetIdleTimeout(Option<IdleSettings>),
 }
 
-pub fn start_http_server(producer: Arc<Mutex<Producer<'static, LedCommand>>>) -> Result<EspHttpServer<'static>> {
-    let config = Configuration::default();
+/// Số thay đổi tối đa trong một request `/led` (mode, brightness, speed, color, param...)
+pub const LED_MAX_COMMANDS: usize = 6;
+const TOO_MANY_CHANGES: &str = "Too many changes in one request (max 6)";
+
+pub const MAX_SKIP_PIXELS: usize = 16;
+pub type SkipList = HeaplessVec<u16, MAX_SKIP_PIXELS>;
+
+/// "3,17,42" → danh sách index. None nếu có phần tử không hợp lệ,
+/// ngoài dải `0..num_leds` hoặc quá nhiều phần tử. Chuỗi rỗng = không bỏ LED nào.
+pub fn parse_skip_pixels(s: &str, num_leds: usize) -> Option<SkipList> {
+    let mut list = SkipList::new();
+    for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let index = part.parse::<u16>().ok().filter(|&i| (i as usize) < num_leds)?;
+        if !list.contains(&index) {
+            list.push(index).ok()?;
+        }
+    }
+    Some(list)
+}
+
+impl LedCommand {
+    /// Command chỉ mang trạng thái mới nhất: nhiều cái cùng loại trong một lượt
+    /// đọc queue thì chỉ cái cuối có hiệu lực
+    fn is_last_write_wins(&self) -> bool {
+        matches!(
+            self,
+            LedCommand::SetEffect(_)
+                | LedCommand::SetBrightness(_)
+                | LedCommand::SetColor(..)
+                | LedCommand::SetSpeed(_)
+                | LedCommand::SetBeatSync(_)
+                | LedCommand::SetMinBrightness(_)
+                | LedCommand::SetPowerSave(_)
+                | LedCommand::SetPartyMode(_)
+                | LedCommand::SetAudioMode(_)
+                | LedCommand::SetColorCycle(_)
+                | LedCommand::SetSleepTimer { .. }
+                | LedCommand::SetSkipPixels(_)
+                | LedCommand::SetThermalProtection(_)
+                | LedCommand::SetColorCarryOver(_)
+                | LedCommand::SetProgress(_)
+                | LedCommand::SetInterpolation(_)
+                | LedCommand::SetHuePreserve(_)
+                | LedCommand::SetConnectFlash(_)
+                | LedCommand::SetSaturation(_)
+                | LedCommand::SetRange(_)
+                | LedCommand::SetOfflineBehavior { .. }
+                | LedCommand::SetIdleTimeout(_)
+        )
+    }
+}
+
+/// Command kèm nơi phát ra, là phần tử của queue HTTP → LED task
+pub struct QueuedCommand {
+    pub source: CommandSource,
+    pub cmd: LedCommand,
+}
+
+pub type CommandProducer = Producer<'static, QueuedCommand>;
+
+/// Kích thước queue command (heapless giữ được N-1 phần tử)
+pub const COMMAND_QUEUE_SIZE: usize = 16;
+
+/// Bỏ các command đã bị command cùng loại phía sau ghi đè, giữ nguyên thứ tự
+/// phần còn lại. Hai client gửi xen kẽ sẽ ra kết quả của command cuối cùng
+/// thay vì nhấp nháy qua từng giá trị.
+pub fn coalesce_commands<const N: usize>(commands: &mut HeaplessVec<QueuedCommand, N>) {
+    let mut keep = HeaplessVec::<bool, N>::new();
+    for (i, queued) in commands.iter().enumerate() {
+        let cmd = &queued.cmd;
+        let superseded = cmd.is_last_write_wins()
+            && commands[i + 1..]
+                .iter()
+                .any(|later| core::mem::discriminant(&later.cmd) == core::mem::discriminant(cmd));
+        keep.push(!superseded).ok();
+    }
+
+    let mut index = 0;
+    commands.retain(|_| {
+        let k = keep[index];
+        index += 1;
+        k
+    });
+}
+
+/// Nơi phát ra command gần nhất (để debug "ai đã đổi đèn")
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum CommandSource {
+    None = 0,
+    Boot = 1,
+    Http = 2,
+    Sync = 3,
+    Button = 4,
+}
+
+impl CommandSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandSource::None => "none",
+            CommandSource::Boot => "boot",
+            CommandSource::Http => "http",
+            CommandSource::Sync => "sync",
+            CommandSource::Button => "button",
+        }
+    }
+
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => CommandSource::Boot,
+            2 => CommandSource::Http,
+            3 => CommandSource::Sync,
+            4 => CommandSource::Button,
+            _ => CommandSource::None,
+        }
+    }
+}
+
+// Ghi lại khi command được đưa vào queue thành công
+static LAST_COMMAND_SOURCE: AtomicU8 = AtomicU8::new(CommandSource::None as u8);
+
+pub fn last_command_source() -> CommandSource {
+    CommandSource::from_u8(LAST_COMMAND_SOURCE.load(Ordering::Relaxed))
+}
+
+fn record_source(source: CommandSource) {
+    LAST_COMMAND_SOURCE.store(source as u8, Ordering::Relaxed);
+}
+
+// Màu live từ /led/color (0xRRGGBB | LIVE_COLOR_PENDING): chỉ giữ màu mới nhất,
+// không chiếm chỗ trong queue nên kéo color wheel liên tục không bị 503
+static LIVE_COLOR: AtomicU32 = AtomicU32::new(0);
+const LIVE_COLOR_PENDING: u32 = 1 << 24;
+
+fn set_live_color(r: u8, g: u8, b: u8) {
+    let packed = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+    LIVE_COLOR.store(packed | LIVE_COLOR_PENDING, Ordering::Release);
+    record_source(CommandSource::Http);
+}
+
+/// Lấy màu live đang chờ (nếu có), gọi từ LED task mỗi lượt đọc queue
+pub fn take_live_color() -> Option<(u8, u8, u8)> {
+    let v = LIVE_COLOR.swap(0, Ordering::Acquire);
+    (v & LIVE_COLOR_PENDING != 0).then(|| ((v >> 16) as u8, (v >> 8) as u8, v as u8))
+}
+
+// Cấu hình HTTP server
+// Số kết nối đồng thời tối đa. LWIP mặc định 10 socket, httpd tự dùng 3 → tối đa 7.
+const HTTP_MAX_OPEN_SOCKETS: usize = 7;
+const HTTP_STACK_SIZE: usize = 10240;
+// Mặc định của httpd chỉ 32 handler: đăng ký route thứ 33 sẽ lỗi và server không chạy.
+// Mỗi route trong ROUTES là một handler, cộng thêm OPTIONS /led và chỗ dự phòng.
+const HTTP_MAX_HANDLERS: usize = ROUTES.len() + 4;
+// Timeout nhận/gửi của socket: client gửi Content-Length rồi ngừng không giữ handler mãi
+const HTTP_SOCKET_TIMEOUT: Duration = Duration::from_secs(5);
+// Tổng thời gian tối đa để nhận một body (chặn cả client nhỏ giọt từng byte)
+const BODY_READ_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Kích thước body tối đa cho mọi handler POST
+pub const MAX_BODY_SIZE: usize = 1024;
+
+// Giới hạn tham số của /led (dùng chung cho parser và /api)
+pub const BRIGHTNESS_MAX: u8 = 100;
+pub const SPEED_MIN: u8 = 1;
+pub const SPEED_MAX: u8 = 255;
+
+/// Mô tả một tham số cho /api
+struct ParamInfo {
+    name: &'static str,
+    kind: &'static str,
+    range: Option<(u32, u32)>,
+    description: &'static str,
+}
+
+/// Mô tả một endpoint cho /api
+struct RouteInfo {
+    path: &'static str,
+    method: &'static str,
+    description: &'static str,
+    params: &'static [ParamInfo],
+}
+
+const LED_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "mode", kind: "effect", range: None, description: "Effect name from the effects list, or off to turn the strip off (brightness 0)" },
+    ParamInfo { name: "brightness", kind: "int", range: Some((0, BRIGHTNESS_MAX as u32)), description: "Brightness in percent" },
+    ParamInfo { name: "bri255", kind: "int", range: Some((0, 255)), description: "Brightness on the 0-255 scale (response still reports percent)" },
+    ParamInfo { name: "speed", kind: "int", range: Some((SPEED_MIN as u32, SPEED_MAX as u32)), description: "Effect speed, 1 = slowest, 255 = fastest (out-of-range values are clamped)" },
+    ParamInfo { name: "color", kind: "hex", range: None, description: "Color as RRGGBB or RGB, optional leading #" },
+    ParamInfo { name: "hsv", kind: "string", range: None, description: "Color as H,S,V (H 0-360, S/V 0-100); ignored if color is set" },
+    ParamInfo { name: "param", kind: "string", range: None, description: "Effect specific key:value" },
+];
+
+const SOLID_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "color", kind: "hex", range: None, description: "Color as RRGGBB or RGB, optional leading #" },
+    ParamInfo { name: "brightness", kind: "int", range: Some((0, BRIGHTNESS_MAX as u32)), description: "Brightness in percent (optional)" },
+];
+
+const BRIGHTNESS_VALUE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "value", kind: "int", range: Some((0, BRIGHTNESS_MAX as u32)), description: "Brightness in percent (or a bare number as body)" },
+];
+
+const PROGRESS_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "value", kind: "float", range: Some((0, 100)), description: "Percent of the strip to fill (or a bare number as body)" },
+];
+
+// Body /led/pattern: tối đa 9 ký tự mỗi màu ("RRGGBB%2C", form encode dấu phẩy) + tile
+const PATTERN_BODY_MAX: usize = crate::pattern::MAX_PATTERN_LEDS * 9 + 32;
+
+const PATTERN_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "colors", kind: "string", range: Some((1, crate::pattern::MAX_PATTERN_LEDS as u32)), description: "Comma-separated RRGGBB per LED from LED 0; or send raw RGB bytes as application/octet-stream" },
+    ParamInfo { name: "tile", kind: "bool", range: Some((0, 1)), description: "1 = repeat the pattern along the strip, 0 = leave the rest off (query string for binary bodies)" },
+];
+
+// Danh sách đoạn sau khi decode: tối đa "65535:RRGGBB," mỗi đoạn
+const RUNS_LIST_MAX: usize = crate::pattern::MAX_RUNS * 13;
+
+const RUNS_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "runs", kind: "string", range: Some((1, crate::pattern::MAX_RUNS as u32)), description: "Comma-separated length:RRGGBB segments from LED 0, e.g. 5:FF0000,3:000000,10:00FF00" },
+    ParamInfo { name: "save", kind: "bool", range: Some((0, 1)), description: "1 = keep the runs across reboots (default 0)" },
+];
+
+const SUNRISE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "duration", kind: "int", range: Some((SunriseEffect::MIN_DURATION_S, SunriseEffect::MAX_DURATION_S)), description: "Seconds from dim red to full warm white (default 1800)" },
+];
+
+const RANGE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "start", kind: "int", range: Some((0, crate::NUM_LEDS as u32 - 1)), description: "First LED the effect uses" },
+    ParamInfo { name: "end", kind: "int", range: Some((1, crate::NUM_LEDS as u32)), description: "LED after the last one the effect uses; start=0 and end=LED count = whole strip" },
+];
+
+const LIVE_COLOR_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "color", kind: "hex", range: None, description: "Color as RRGGBB or RGB (or a bare color as body); only the latest pending color is applied" },
+];
+
+const SPEED_VALUE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "value", kind: "int", range: Some((SPEED_MIN as u32, SPEED_MAX as u32)), description: "Effect speed, clamped (or a bare number as body)" },
+];
+
+const SATURATION_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "value", kind: "int", range: Some((0, 100)), description: "Saturation in percent for rainbow, wander, bounce and random-color effects (default 100)" },
+];
+
+const ENABLED_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "enabled", kind: "bool", range: Some((0, 1)), description: "1 = on, 0 = off" },
+];
+
+const IDENTIFY_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "count", kind: "int", range: Some((1, crate::effect::IdentifyEffect::MAX_PULSES as u32)), description: "Number of flashes (optional, default 3)" },
+];
+
+const SYNC_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "t", kind: "int", range: None, description: "Leader clock in microseconds" },
+    ParamInfo { name: "restart", kind: "bool", range: Some((0, 1)), description: "Restart the current effect on the shared tick" },
+];
+
+const PALETTE_SAVE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "name", kind: "string", range: Some((1, crate::palettes::MAX_NAME_LEN as u32)), description: "Palette name (a-z, 0-9, _ and -)" },
+    ParamInfo { name: "stops", kind: "string", range: Some((2, crate::palettes::MAX_STOPS as u32)), description: "Comma-separated RRGGBB colors" },
+];
+
+const SCENE_SAVE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "name", kind: "string", range: Some((1, crate::scenes::MAX_NAME_LEN as u32)), description: "Scene name (a-z, 0-9, _ and -)" },
+    ParamInfo { name: "mode", kind: "effect", range: None, description: "Effect (default: current)" },
+    ParamInfo { name: "brightness", kind: "int", range: Some((0, BRIGHTNESS_MAX as u32)), description: "Percent (default: current)" },
+    ParamInfo { name: "speed", kind: "int", range: Some((SPEED_MIN as u32, SPEED_MAX as u32)), description: "Effect speed (default: current)" },
+    ParamInfo { name: "color", kind: "color", range: None, description: "RRGGBB (default: current)" },
+    ParamInfo { name: "range", kind: "string", range: None, description: "start-end LEDs the effect runs on, or full (default: current)" },
+    ParamInfo { name: "sat", kind: "int", range: Some((0, 100)), description: "Saturation of hue-generated colors (default: current)" },
+    ParamInfo { name: "beatsync", kind: "bool", range: Some((0, 1)), description: "Modulate speed with beats (default: current)" },
+    ParamInfo { name: "params", kind: "string", range: Some((0, crate::scenes::MAX_SCENE_PARAMS as u32)), description: "Effect params as key:value,key:value, e.g. band:bass,invert:1" },
+];
+
+const SCENE_NAME_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "name", kind: "string", range: None, description: "Scene name" },
+];
+
+const SCENE_DELETE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "name", kind: "string", range: None, description: "Scene name (query string)" },
+];
+
+const PALETTE_DELETE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "name", kind: "string", range: None, description: "Palette name (query string)" },
+];
+
+const BASE_PATH_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "path", kind: "string", range: Some((0, crate::config::MAX_BASE_PATH_LEN as u32)), description: "Prefix the device is served under, e.g. /lights; empty = direct access" },
+];
+
+const NAME_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "name", kind: "string", range: Some((1, crate::config::MAX_DEVICE_NAME_LEN as u32)), description: "Device name shown in /status" },
+];
+
+const AUTO_BRIGHTNESS_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "enabled", kind: "bool", range: Some((0, 1)), description: "1 = on, 0 = off" },
+    ParamInfo { name: "dark", kind: "int", range: Some((0, 4095)), description: "ADC reading mapped to the minimum brightness" },
+    ParamInfo { name: "bright", kind: "int", range: Some((0, 4095)), description: "ADC reading mapped to full brightness" },
+    ParamInfo { name: "min", kind: "int", range: Some((0, 100)), description: "Minimum brightness in percent" },
+    ParamInfo { name: "pin", kind: "int", range: Some((34, 39)), description: "Sensor GPIO (ADC1), applied after restart" },
+];
+
+const MIN_BRIGHTNESS_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "value", kind: "int", range: Some((0, 255)), description: "Lowest output level of a lit color channel, 0 = off" },
+];
+
+const WIFI_SAVE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "ssid", kind: "string", range: Some((1, 32)), description: "Network name" },
+    ParamInfo { name: "password", kind: "string", range: Some((8, 64)), description: "WPA2 passphrase" },
+];
+
+const WIFI_FORGET_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "ssid", kind: "string", range: Some((1, 32)), description: "Saved network to forget (query string)" },
+];
+
+const PARTY_GAP_MS_DEFAULT: u32 = 3000;
+
+const PARTY_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "enabled", kind: "bool", range: Some((0, 1)), description: "1 = on, 0 = off" },
+    ParamInfo { name: "gap", kind: "int", range: Some((500, 60000)), description: "Silence in ms that ends a song (default 3000)" },
+    ParamInfo { name: "effects", kind: "string", range: None, description: "Comma-separated audio effects to rotate (default: audio effects in /config/rotation)" },
+];
+
+pub const COLOR_CYCLE_MIN_S: u32 = 5;
+pub const COLOR_CYCLE_MAX_S: u32 = 3600;
+
+const COLOR_CYCLE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "period", kind: "int", range: Some((0, COLOR_CYCLE_MAX_S)), description: "Seconds per trip around the color wheel, 0 = off (min 5)" },
+];
+
+pub const SLEEP_TIMER_MAX_MIN: u16 = 24 * 60;
+
+const SLEEP_TIMER_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "minutes", kind: "int", range: Some((0, SLEEP_TIMER_MAX_MIN as u32)), description: "Turn the strip off after this many minutes, 0 = cancel" },
+    ParamInfo { name: "on_command", kind: "string", range: None, description: "What a manual command does to the timer: keep (default), reset or cancel" },
+];
+
+const CHIP_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "type", kind: "string", range: None, description: "ws2812, ws2811 or sk6812 (applied after restart)" },
+];
+
+const BUTTON_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "pin", kind: "int", range: Some((0, 255)), description: "Button GPIO (to GND), 255 = no button (applied after restart)" },
+    ParamInfo { name: "actions", kind: "string", range: None, description: "short,long,double actions: power, next, favorite, color or none" },
+    ParamInfo { name: "favorite", kind: "string", range: None, description: "Effect selected by 'favorite'" },
+];
+
+const COLOR_CARRY_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "mode", kind: "string", range: None, description: "preserve = keep the last color you set when switching effects, default = start each effect in white" },
+];
+
+/// Màu nháy mặc định khi có kết nối
+pub const CONNECT_FLASH_DEFAULT_COLOR: (u8, u8, u8) = (0, 255, 0);
+
+const CONNECT_FLASH_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "enabled", kind: "bool", range: Some((0, 1)), description: "Flash when WiFi connects and when the connection is lost (default on)" },
+    ParamInfo { name: "color", kind: "hex", range: None, description: "Connect flash color as RRGGBB (default 00FF00); connection loss always flashes red" },
+];
+
+const QUICK_COLORS_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "colors", kind: "string", range: Some((1, crate::quick_colors::MAX_QUICK_COLORS as u32)), description: "Comma-separated RRGGBB colors in order" },
+];
+
+const ROTATION_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "effects", kind: "string", range: Some((1, crate::rotation::MAX_ROTATION as u32)), description: "Comma-separated effect names in order" },
+];
+
+const SKIP_PIXELS_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "pixels", kind: "string", range: Some((0, MAX_SKIP_PIXELS as u32)), description: "Comma-separated physical LED indices to leave dark, empty = none" },
+];
+
+// Long-poll: esp_http_server xử lý mọi request trên một task, request đang chờ
+// chặn các endpoint khác → chỉ cho 1 waiter và chờ ngắn
+const STATE_WAIT_MAX_S: u32 = 3;
+const STATE_WAIT_POLL_MS: u32 = 50;
+static STATE_WAITERS: AtomicU8 = AtomicU8::new(0);
+
+const STATE_WAIT_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "since", kind: "int", range: None, description: "Last version seen; returns as soon as the state version differs (query string)" },
+    ParamInfo { name: "timeout", kind: "int", range: Some((1, STATE_WAIT_MAX_S)), description: "Seconds to wait before returning 304 Not Modified (default 3)" },
+];
+
+pub const THERMAL_DEFAULT_MIN: u8 = 10;
+pub const THERMAL_MAX_MIN: u8 = 120;
+
+const THERMAL_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "enabled", kind: "bool", range: Some((0, 1)), description: "1 = on, 0 = off" },
+    ParamInfo { name: "minutes", kind: "int", range: Some((1, THERMAL_MAX_MIN as u32)), description: "Minutes above 80% load before dimming to 60% (default 10)" },
+];
+
+const EFFECTS_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "name", kind: "effect", range: None, description: "Query string: only this effect" },
+];
+
+const RESEED_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "seed", kind: "int", range: Some((0, u32::MAX)), description: "Seed to use instead of a random one" },
+];
+
+pub const IDLE_DEFAULT_EFFECT: &str = "breathe";
+pub const IDLE_DEFAULT_LEVEL: u8 = 30;
+
+const IDLE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "ambient", kind: "int", range: Some((0, SLEEP_TIMER_MAX_MIN as u32)), description: "Minutes without commands before switching to the ambient effect, 0 = skip" },
+    ParamInfo { name: "off", kind: "int", range: Some((0, SLEEP_TIMER_MAX_MIN as u32)), description: "Minutes without commands before turning off, 0 = never" },
+    ParamInfo { name: "effect", kind: "string", range: None, description: "Ambient effect name (default breathe)" },
+    ParamInfo { name: "level", kind: "int", range: Some((1, 100)), description: "Ambient brightness in percent of the current brightness (default 30)" },
+];
+
+pub const OFFLINE_DEFAULT_MIN: u16 = 10;
+pub const OFFLINE_DEFAULT_LEVEL: u8 = 20;
+
+const OFFLINE_PARAMS: &[ParamInfo] = &[
+    ParamInfo { name: "mode", kind: "string", range: None, description: "keep = keep running (default), off = turn off, dim = dim to level" },
+    ParamInfo { name: "minutes", kind: "int", range: Some((1, SLEEP_TIMER_MAX_MIN as u32)), description: "Minutes without WiFi before acting (default 10)" },
+    ParamInfo { name: "level", kind: "int", range: Some((0, 100)), description: "Percent of the current brightness for dim (default 20)" },
+];
+
+const ROUTES: &[RouteInfo] = &[
+    RouteInfo { path: "/led", method: "POST", description: "Control effect, brightness, speed and color (form-urlencoded)", params: LED_PARAMS },
+    RouteInfo { path: "/led/brightness", method: "GET", description: "Current brightness in percent", params: &[] },
+    RouteInfo { path: "/led/brightness", method: "POST", description: "Set only the brightness", params: BRIGHTNESS_VALUE_PARAMS },
+    RouteInfo { path: "/led/toggle", method: "POST", description: "Turn the lights off if on, or back on at the brightness they had before", params: &[] },
+    RouteInfo { path: "/led/color", method: "POST", description: "Set only the color, for live color pickers (never queues more than one pending color)", params: LIVE_COLOR_PARAMS },
+    RouteInfo { path: "/led/speed", method: "GET", description: "Current effect speed", params: &[] },
+    RouteInfo { path: "/led/speed", method: "POST", description: "Set only the effect speed", params: SPEED_VALUE_PARAMS },
+    RouteInfo { path: "/led/progress", method: "POST", description: "Show a progress bar in the current color (switches to the progress effect)", params: PROGRESS_PARAMS },
+    RouteInfo { path: "/led/pattern", method: "POST", description: "Show a fixed per-LED pattern (pattern effect), saved across reboots", params: PATTERN_PARAMS },
+    RouteInfo { path: "/led/runs", method: "POST", description: "Show run-length color segments (runs effect); extra LEDs are cut off, missing ones stay off", params: RUNS_PARAMS },
+    RouteInfo { path: "/led/sunrise", method: "POST", description: "Start a wake-up light: dim red slowly warming to bright white, then hold", params: SUNRISE_PARAMS },
+    RouteInfo { path: "/led/range", method: "POST", description: "Run the effect only on LEDs start..end, the rest stay off", params: RANGE_PARAMS },
+    RouteInfo { path: "/led/solid", method: "POST", description: "Fill the strip with one color (static effect)", params: SOLID_PARAMS },
+    RouteInfo { path: "/config/beatsync", method: "POST", description: "Modulate effect speed with detected beats", params: ENABLED_PARAMS },
+    RouteInfo { path: "/config/boot-animation", method: "POST", description: "Enable the power-on LED sweep", params: ENABLED_PARAMS },
+    RouteInfo { path: "/config/chip", method: "POST", description: "Select the LED chip bit timing", params: CHIP_PARAMS },
+    RouteInfo { path: "/led/reseed", method: "POST", description: "Give a random effect (bounce, wander, ripple, beatcolor) a new random pattern; returns the seed to reproduce it with param=seed:N", params: RESEED_PARAMS },
+    RouteInfo { path: "/led/next-color", method: "POST", description: "Apply the next color from /config/quick-colors to the current effect", params: &[] },
+    RouteInfo { path: "/config/quick-colors", method: "GET", description: "Colors cycled by /led/next-color and the button", params: &[] },
+    RouteInfo { path: "/config/quick-colors", method: "POST", description: "Set the colors cycled by /led/next-color and the button", params: QUICK_COLORS_PARAMS },
+    RouteInfo { path: "/config/rotation", method: "GET", description: "Effects used by demo, party mode and the button", params: &[] },
+    RouteInfo { path: "/config/rotation", method: "POST", description: "Set the effects used by demo, party mode and the button", params: ROTATION_PARAMS },
+    RouteInfo { path: "/config/button", method: "POST", description: "Physical button: pin and press actions", params: BUTTON_PARAMS },
+    RouteInfo { path: "/config/skip-pixels", method: "POST", description: "Skip dead LEDs; effects are mapped onto the remaining ones", params: SKIP_PIXELS_PARAMS },
+    RouteInfo { path: "/config/thermal", method: "POST", description: "Dim after a long time at very high load, restore after cool-down", params: THERMAL_PARAMS },
+    RouteInfo { path: "/health", method: "GET", description: "Thermal protection state, LED write errors and command queue usage", params: &[] },
+    RouteInfo { path: "/config/color-carry", method: "POST", description: "Which color a newly selected effect starts with", params: COLOR_CARRY_PARAMS },
+    RouteInfo { path: "/config/power-save", method: "POST", description: "Lower the frame rate when the scene is static or very dim", params: ENABLED_PARAMS },
+    RouteInfo { path: "/config/idle", method: "POST", description: "After a while without commands fade to a dim ambient effect, then turn off; any command restores", params: IDLE_PARAMS },
+    RouteInfo { path: "/config/offline-behavior", method: "POST", description: "Turn off or dim after the station has been off the saved WiFi for a long time, restore when it reconnects (needs a saved network)", params: OFFLINE_PARAMS },
+    RouteInfo { path: "/config/connect-flash", method: "POST", description: "Flash the strip when WiFi connects or the connection drops", params: CONNECT_FLASH_PARAMS },
+    RouteInfo { path: "/config/saturation", method: "POST", description: "Saturation of hue-generated effect colors (param=sat:N changes it until restart)", params: SATURATION_PARAMS },
+    RouteInfo { path: "/config/hue-preserve", method: "POST", description: "At very low brightness scale each pixel so no lit channel rounds to zero, keeping dim colors from shifting hue", params: ENABLED_PARAMS },
+    RouteInfo { path: "/config/interpolation", method: "POST", description: "Blend between frames of effects that update slower than the output frame rate", params: ENABLED_PARAMS },
+    RouteInfo { path: "/config/color-cycle", method: "POST", description: "Slowly cycle the effect color through all hues; a manual color pauses it", params: COLOR_CYCLE_PARAMS },
+    RouteInfo { path: "/config/min-brightness", method: "POST", description: "Keep lit channels above a floor when dimmed", params: MIN_BRIGHTNESS_PARAMS },
+    RouteInfo { path: "/config/auto-brightness", method: "POST", description: "Dim with an ambient light sensor", params: AUTO_BRIGHTNESS_PARAMS },
+    RouteInfo { path: "/audio-mode", method: "POST", description: "Switch between the last audio effect and the last regular effect, keeping their settings", params: ENABLED_PARAMS },
+    RouteInfo { path: "/partymode", method: "POST", description: "Switch to another audio effect whenever a new song starts", params: PARTY_PARAMS },
+    RouteInfo { path: "/timer/sleep", method: "POST", description: "Turn the strip off after a countdown", params: SLEEP_TIMER_PARAMS },
+    RouteInfo { path: "/sync", method: "POST", description: "Align the effect clock with another device", params: SYNC_PARAMS },
+    RouteInfo { path: "/scene/save", method: "POST", description: "Save a named scene: effect, color, brightness, speed, range, saturation, beat sync and effect params", params: SCENE_SAVE_PARAMS },
+    RouteInfo { path: "/scene/list", method: "GET", description: "Saved scenes", params: &[] },
+    RouteInfo { path: "/scene/apply", method: "POST", description: "Switch to a saved scene in one step", params: SCENE_NAME_PARAMS },
+    RouteInfo { path: "/scene", method: "DELETE", description: "Delete a saved scene", params: SCENE_DELETE_PARAMS },
+    RouteInfo { path: "/palette/save", method: "POST", description: "Save a named color palette", params: PALETTE_SAVE_PARAMS },
+    RouteInfo { path: "/palette/list", method: "GET", description: "Saved and built-in palettes", params: &[] },
+    RouteInfo { path: "/palette", method: "DELETE", description: "Delete a saved palette", params: PALETTE_DELETE_PARAMS },
+    RouteInfo { path: "/restart", method: "POST", description: "Stop tasks cleanly, blank the strip and reboot", params: &[] },
+    RouteInfo { path: "/audio", method: "GET", description: "Current audio levels and microphone status", params: &[] },
+    RouteInfo { path: "/config/soft-clip", method: "POST", description: "Compress audio levels smoothly near full scale instead of hard-clamping at 1.0", params: ENABLED_PARAMS },
+    RouteInfo { path: "/status", method: "GET", description: "Device info", params: &[] },
+    RouteInfo { path: "/state/wait", method: "GET", description: "Long-poll: wait briefly until the LED state changes, then return it (304 if unchanged)", params: STATE_WAIT_PARAMS },
+    RouteInfo { path: "/log/commands", method: "GET", description: "Last 50 applied commands with time and source, oldest first", params: &[] },
+    RouteInfo { path: "/config/command-log", method: "POST", description: "Record applied commands for /log/commands", params: ENABLED_PARAMS },
+    RouteInfo { path: "/diag/perf", method: "GET", description: "Achieved frame interval of the current effect (min/avg/max) and audio-effect renders skipped because no new audio frame had arrived", params: &[] },
+    RouteInfo { path: "/identify", method: "POST", description: "Flash the strip white (three times by default), then restore", params: IDENTIFY_PARAMS },
+    RouteInfo { path: "/config/base-path", method: "POST", description: "Path prefix for URLs in /api when behind a reverse proxy (X-Forwarded-Prefix takes precedence)", params: BASE_PATH_PARAMS },
+    RouteInfo { path: "/config/name", method: "POST", description: "Set the device name", params: NAME_PARAMS },
+    RouteInfo { path: "/rpc", method: "POST", description: "JSON array of up to 8 {method, params, id} calls (at most 15 queued changes in total), one result or error each. Methods: set_led, set_effect, set_brightness, set_color, set_speed, set_param, set_sleep_timer, set_audio_mode, identify, get_state", params: &[] },
+    RouteInfo { path: "/config/export", method: "GET", description: "Device configuration as JSON (no secrets)", params: &[] },
+    RouteInfo { path: "/config/import", method: "POST", description: "Apply a /config/export JSON body, restart to take effect", params: &[] },
+    RouteInfo { path: "/wifi/saved", method: "GET", description: "Saved WiFi networks in priority order (SSIDs only)", params: &[] },
+    RouteInfo { path: "/wifi/saved", method: "POST", description: "Save a WiFi network (added last in priority, updated in place if the SSID exists), tried on next restart", params: WIFI_SAVE_PARAMS },
+    RouteInfo { path: "/wifi/saved", method: "DELETE", description: "Forget a saved WiFi network", params: WIFI_FORGET_PARAMS },
+    RouteInfo { path: "/nvs/dump", method: "GET", description: "Stored configuration (no secrets)", params: &[] },
+    RouteInfo { path: "/effects", method: "GET", description: "Effects and the param=key:value settings each one accepts, with types and ranges", params: EFFECTS_PARAMS },
+    RouteInfo { path: "/api", method: "GET", description: "This description", params: &[] },
+];
+
+pub type ParamKey = heapless::String<16>;
+pub type ParamValue = heapless::String<32>;
+
+pub fn start_http_server(
+    producer: Arc<Mutex<CommandProducer>>,
+    nvs: EspDefaultNvsPartition,
+    audio_data: Arc<SharedAudio>,
+    ambient: Arc<AmbientLight>,
+    stop: Arc<AtomicBool>,
+) -> Result<EspHttpServer<'static>> {
+    let config = Configuration {
+        max_open_sockets: HTTP_MAX_OPEN_SOCKETS,
+        stack_size: HTTP_STACK_SIZE,
+        max_uri_handlers: HTTP_MAX_HANDLERS,
+        session_timeout: HTTP_SOCKET_TIMEOUT,
+        ..Default::default()
+    };
     let mut server = EspHttpServer::new(&config)?;
     
     info!("HTTP Server starting on port 80");
 
-    const MAX_BODY_SIZE: usize = 512;
-
+    let led_producer = producer.clone();
     server.fn_handler::<anyhow::Error, _>("/led", esp_idf_svc::http::Method::Post, move |mut req| {
         
         // Read body into buffer
         let mut buf = [0u8; MAX_BODY_SIZE];
-        let len = req.content_len().unwrap_or(0) as usize;
-
-        if len == 0 || len > MAX_BODY_SIZE {
-            let mut response = req.into_status_response(400)?;
-            response.write_all(b"{\"status\":\"error\",\"message\":\"Invalid body length\"}")?;
-            return Ok(());
-        }
-
-        req.read_exact(&mut buf[..len])?;
-        
-        let body_str = match std::str::from_utf8(&buf[..len]) {
+        let body_str = match read_body(&mut req, &mut buf) {
             Ok(s) => s,
-            Err(_) => {
-                let mut response = req.into_status_response(400)?;
-                response.write_all(b"{\"status\":\"error\",\"message\":\"Invalid UTF-8\"}")?;
-                return Ok(());
-            }
+            Err(e) => return send_body_error(req, e),
         };
         
         info!("Received: '{}'", body_str);
         
-        // Parse commands (support up to 4 commands per request)
-        let mut commands_to_send: HeaplessVec<LedCommand, 4> = HeaplessVec::new();
+        // Parse commands (tối đa LED_MAX_COMMANDS thay đổi mỗi request)
+        let mut commands_to_send: HeaplessVec<LedCommand, LED_MAX_COMMANDS> = HeaplessVec::new();
         
         // Response tracking
         let mut resp_mode: Option<&str> = None;
         let mut resp_brightness: Option<u8> = None;
         let mut resp_speed: Option<u8> = None;
-        let mut resp_color: Option<&str> = None;
+        let mut resp_color: Option<(u8, u8, u8)> = None;
+        let mut hsv_color: Option<(u8, u8, u8)> = None;
 
         // Parse form-urlencoded body: key=value&key=value
         for pair in body_str.split('&') {
             if let Some((key, value)) = pair.split_once('=') {
                 match key {
                     "mode" => {
-                        let (effect, mode_str) = match value {
-                            "static" => (EffectType::Static, "static"),
-                            "rainbow" => (EffectType::Rainbow, "rainbow"),
-                            "breathe" => (EffectType::Breathe, "breathe"),
-                            "colorwipe" => (EffectType::ColorWipe, "colorwipe"),
-                            "comet" => (EffectType::Comet, "comet"),
-                            "scanner" => (EffectType::Scanner, "scanner"),
-                            "theaterchase" => (EffectType::TheaterChase, "theaterchase"),
-                            "bounce" => (EffectType::Bounce, "bounce"),
-                            "volumebar" => (EffectType::AudioVolumeBar, "volumebar"),
-                            _ => {
-                                warn!("Unknown mode: {}", value);
-                                continue;
-                            }
+                        let Some((command, mode_str)) = mode_command(value) else {
+                            warn!("Unknown mode: {}", value);
+                            continue;
                         };
                         
                         // Prevent buffer overflow
-                        if commands_to_send.push(LedCommand::SetEffect(effect)).is_err() {
-                            warn!("Command buffer full, ignoring mode");
-                            continue;
+                        if commands_to_send.push(command).is_err() {
+                            return send_error(req, 400, TOO_MANY_CHANGES);
                         }
                         resp_mode = Some(mode_str);
+                        if mode_str == "off" {
+                            resp_brightness = Some(0);
+                        }
                     }
                     
                     "brightness" => {
                         if let Ok(val) = value.parse::<u8>() {
-                            let clamped = val.min(100);
-                            let brightness_val = (clamped as f32) / 100.0;
+                            let clamped = val.min(BRIGHTNESS_MAX);
+                            let brightness_val = (clamped as f32) / BRIGHTNESS_MAX as f32;
                             
                             if commands_to_send.push(LedCommand::SetBrightness(brightness_val)).is_err() {
-                                warn!("Command buffer full, ignoring brightness");
-                                continue;
+                                return send_error(req, 400, TOO_MANY_CHANGES);
                             }
                             resp_brightness = Some(clamped);
                         } else {
@@ -102,34 +612,72 @@ pub fn start_http_server(producer: Arc<Mutex<Producer<'static, LedCommand>>>) ->
                         }
                     }
                     
-                    "speed" => {
+                    // Thang 0-255 (WLED và nhiều app tích hợp), response vẫn trả %
+                    "bri255" => {
                         if let Ok(val) = value.parse::<u8>() {
-                            if commands_to_send.push(LedCommand::SetSpeed(val)).is_err() {
-                                warn!("Command buffer full, ignoring speed");
-                                continue;
+                            if commands_to_send.push(LedCommand::SetBrightness(val as f32 / 255.0)).is_err() {
+                                return send_error(req, 400, TOO_MANY_CHANGES);
                             }
-                            resp_speed = Some(val);
+                            resp_brightness = Some(((val as u16 * BRIGHTNESS_MAX as u16 + 127) / 255) as u8);
                         } else {
+                            warn!("Invalid bri255 value: {}", value);
+                        }
+                    }
+
+                    "speed" => {
+                        let Ok(val) = value.parse::<u32>() else {
                             warn!("Invalid speed value: {}", value);
+                            return send_error(req, 400, "speed must be a number (1-255)");
+                        };
+
+                        // Giá trị ngoài dải được kẹp lại, response trả speed thực tế
+                        let effective = val.clamp(SPEED_MIN as u32, SPEED_MAX as u32) as u8;
+                        if commands_to_send.push(LedCommand::SetSpeed(effective)).is_err() {
+                            return send_error(req, 400, TOO_MANY_CHANGES);
                         }
+                        resp_speed = Some(effective);
                     }
                     
                     "color" => {
                         match parse_hex_color(value) {
                             Ok((r, g, b)) => {
                                 if commands_to_send.push(LedCommand::SetColor(r, g, b)).is_err() {
-                                    warn!("Command buffer full, ignoring color");
-                                    continue;
+                                    return send_error(req, 400, TOO_MANY_CHANGES);
                                 }
-                                resp_color = Some(value);
+                                resp_color = Some((r, g, b));
                                 info!("Color parsed: #{:02X}{:02X}{:02X}", r, g, b);
                             }
                             Err(_) => {
-                                warn!("Invalid color format: {} (expected: RRGGBB)", value);
+                                warn!("Invalid color format: {} (expected: RRGGBB or RGB)", value);
                             }
                         }
                     }
                     
+                    "hsv" => {
+                        // hsv=H,S,V (H 0-360, S/V 0-100); `color` được ưu tiên nếu có cả hai
+                        match parse_hsv(value) {
+                            Some(rgb) => hsv_color = Some(rgb),
+                            None => warn!("Invalid hsv format: {} (expected: H,S,V)", value),
+                        }
+                    }
+                    
+                    "param" => {
+                        // param=key:value, ví dụ param=color2:8000FF
+                        let Some((param_key, param_value)) = value.split_once(':') else {
+                            warn!("Invalid param format: {} (expected: key:value)", value);
+                            continue;
+                        };
+
+                        let (Ok(k), Ok(v)) = (ParamKey::try_from(param_key), ParamValue::try_from(param_value)) else {
+                            warn!("Param too long: {}", value);
+                            continue;
+                        };
+
+                        if commands_to_send.push(LedCommand::SetParam(k, v)).is_err() {
+                            return send_error(req, 400, TOO_MANY_CHANGES);
+                        }
+                    }
+                    
                     _ => {
                         warn!("Unknown parameter: {}", key);
                     }
@@ -137,28 +685,23 @@ pub fn start_http_server(producer: Arc<Mutex<Producer<'static, LedCommand>>>) ->
             }
         }
         
-        // Send commands to LED task
-        let mut send_success = true;
-        
-        if !commands_to_send.is_empty() {
-            match producer.try_lock() {
-                Ok(mut producer_guard) => {
-                    for cmd in commands_to_send {
-                        if producer_guard.enqueue(cmd).is_err() {
-                            warn!("⚠️ Command queue is FULL!");
-                            send_success = false;
-                            break;
-                        }
-                    }
-                }
-                Err(_) => {
-                    warn!("⚠️ Mutex lock failed - concurrent access!");
-                    send_success = false;
-                }
+        if let (None, Some((r, g, b))) = (resp_color, hsv_color) {
+            if commands_to_send.push(LedCommand::SetColor(r, g, b)).is_err() {
+                return send_error(req, 400, TOO_MANY_CHANGES);
             }
-        } else {
+            resp_color = Some((r, g, b));
+            info!("HSV parsed: #{:02X}{:02X}{:02X}", r, g, b);
+        }
+
+        if commands_to_send.is_empty() {
             warn!("No valid commands parsed from body");
-            send_success = false;
+            return send_error(req, 400, "No valid parameters");
+        }
+
+        // Send commands to LED task: vào queue hết hoặc không cái nào
+        let send_success = send_commands_from(&led_producer, CommandSource::Http, commands_to_send);
+        if !send_success {
+            warn!("Command queue full or busy");
         }
         
         // Build response
@@ -179,8 +722,8 @@ pub fn start_http_server(producer: Arc<Mutex<Producer<'static, LedCommand>>>) ->
             if let Some(speed) = resp_speed {
                 write!(resp_str, ",\"speed\":{}", speed).unwrap();
             }
-            if let Some(color) = resp_color {
-                write!(resp_str, ",\"color\":\"{}\"", color).unwrap();
+            if let Some((r, g, b)) = resp_color {
+                write!(resp_str, ",\"color\":\"{:02X}{:02X}{:02X}\"", r, g, b).unwrap();
             }
             
             write!(resp_str, "}}").unwrap();
@@ -190,39 +733,2403 @@ pub fn start_http_server(producer: Arc<Mutex<Producer<'static, LedCommand>>>) ->
 
         } else {
             let mut response = req.into_status_response(503)?;
-            response.write_all(b"{\"status\":\"error\",\"message\":\"Device busy or invalid params\"}")?;
+            response.write_all(b"{\"status\":\"error\",\"message\":\"Device busy (command queue full)\"}")?;
         }
 
         Ok(())
     })?;
 
-    server.fn_handler::<anyhow::Error, _>("/status", esp_idf_svc::http::Method::Get, |req| {
-        info!("Status requested");
+    server.fn_handler::<anyhow::Error, _>("/led/brightness", esp_idf_svc::http::Method::Get, |req| {
+        let Some(state) = crate::controller::state_snapshot() else {
+            return send_error(req, 503, "State unavailable");
+        };
+        send_single_value(req, "brightness", state.brightness as u32)
+    })?;
+
+    let brightness_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/brightness", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 32];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let value = match single_value(body_str).and_then(|v| v.parse::<u8>().ok()) {
+            Some(v) if v <= BRIGHTNESS_MAX => v,
+            _ => return send_error(req, 400, "Expected value=0-100"),
+        };
+
+        if !send_command(&brightness_producer, LedCommand::SetBrightness(value as f32 / BRIGHTNESS_MAX as f32)) {
+            return send_error(req, 503, "Device busy");
+        }
+        send_single_value(req, "brightness", value as u32)
+    })?;
+
+    let toggle_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/toggle", esp_idf_svc::http::Method::Post, move |req| {
+        let (cmd, brightness) = power_toggle_command();
+        if !send_command(&toggle_producer, cmd) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(
+            resp_str,
+            "{{\"status\":\"ok\",\"power\":\"{}\",\"brightness\":{}}}",
+            if brightness > 0 { "on" } else { "off" }, brightness
+        ).unwrap();
+
         let mut response = req.into_ok_response()?;
-        response.write_all(
-            b"{\"status\":\"ok\",\"device\":\"WS2812 Controller\",\"version\":\"3.3\",\"firmware\":\"esp32-rust\"}"
-        )?;
+        response.write_all(resp_str.as_bytes())?;
         Ok(())
     })?;
 
-    server.fn_handler::<anyhow::Error, _>("/led", esp_idf_svc::http::Method::Options, |req| {
+    server.fn_handler::<anyhow::Error, _>("/led/color", esp_idf_svc::http::Method::Post, |mut req| {
+        let mut buf = [0u8; 32];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let value = form_value(body_str, "color").or_else(|| single_value(body_str));
+        let Some((r, g, b)) = value.and_then(|v| parse_hex_color(v).ok()) else {
+            return send_error(req, 400, "Expected color=RRGGBB");
+        };
+
+        set_live_color(r, g, b);
+
+        let mut resp_str = heapless::String::<24>::new();
+        write!(resp_str, "{{\"color\":\"{:02X}{:02X}{:02X}\"}}", r, g, b).ok();
+
         let mut response = req.into_ok_response()?;
-        response.write_all(b"")?;
+        response.write_all(resp_str.as_bytes())?;
         Ok(())
     })?;
 
-    info!("✅ HTTP server configured successfully");
-    Ok(server)
-}
+    server.fn_handler::<anyhow::Error, _>("/led/speed", esp_idf_svc::http::Method::Get, |req| {
+        let Some(state) = crate::controller::state_snapshot() else {
+            return send_error(req, 503, "State unavailable");
+        };
+        send_single_value(req, "speed", state.speed as u32)
+    })?;
 
-fn parse_hex_color(s: &str) -> Result<(u8, u8, u8), ()> {
-    if s.len() != 6 {
-        return Err(());
-    }
-    
-    let r = u8::from_str_radix(&s[0..2], 16).map_err(|_| ())?;
-    let g = u8::from_str_radix(&s[2..4], 16).map_err(|_| ())?;
-    let b = u8::from_str_radix(&s[4..6], 16).map_err(|_| ())?;
-    
-    Ok((r, g, b))
-}
\ No newline at end of file
+    let speed_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/speed", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 32];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(value) = single_value(body_str).and_then(|v| v.parse::<u32>().ok()) else {
+            return send_error(req, 400, "Expected value=1-255");
+        };
+        let effective = value.clamp(SPEED_MIN as u32, SPEED_MAX as u32) as u8;
+
+        if !send_command(&speed_producer, LedCommand::SetSpeed(effective)) {
+            return send_error(req, 503, "Device busy");
+        }
+        send_single_value(req, "speed", effective as u32)
+    })?;
+
+    let progress_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/progress", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 32];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(percent) = single_value(body_str)
+            .and_then(|v| v.parse::<f32>().ok())
+            .filter(|p| (0.0..=100.0).contains(p))
+        else {
+            return send_error(req, 400, "Expected value=0-100");
+        };
+
+        if !send_command(&progress_producer, LedCommand::SetProgress(percent / 100.0)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<32>::new();
+        write!(resp_str, "{{\"progress\":{:.1}}}", percent).ok();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let sunrise_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/sunrise", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(BodyError::Empty) => "",
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let duration = match form_value(body_str, "duration") {
+            None => SunriseEffect::DEFAULT_DURATION_S,
+            Some(v) => match v.parse::<u32>() {
+                Ok(s) if (SunriseEffect::MIN_DURATION_S..=SunriseEffect::MAX_DURATION_S).contains(&s) => s,
+                _ => return send_error(req, 400, "duration must be 60-7200"),
+            },
+        };
+
+        let mut value = ParamValue::new();
+        write!(value, "{}", duration).ok();
+        let mut cmds = HeaplessVec::<LedCommand, 2>::new();
+        cmds.push(LedCommand::SetEffect(EffectType::Sunrise)).ok();
+        cmds.push(LedCommand::SetParam(ParamKey::try_from("duration").unwrap(), value)).ok();
+
+        if !send_commands_from(&sunrise_producer, CommandSource::Http, cmds) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"mode\":\"sunrise\",\"duration\":{}}}", duration).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let range_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/range", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let start = form_value(body_str, "start").and_then(|v| v.parse::<u16>().ok());
+        let end = form_value(body_str, "end").and_then(|v| v.parse::<u16>().ok());
+        let (Some(start), Some(end)) = (start, end) else {
+            return send_error(req, 400, "Expected start=N&end=N");
+        };
+        if start >= end || end as usize > crate::NUM_LEDS {
+            return send_error(req, 400, "Range must satisfy start < end <= LED count");
+        }
+
+        // Cả dải = bỏ giới hạn
+        let range = (start > 0 || (end as usize) < crate::NUM_LEDS).then_some((start, end));
+        if !send_command(&range_producer, LedCommand::SetRange(range)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"start\":{},\"end\":{}}}", start, end).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let pattern_producer = producer.clone();
+    let pattern_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/pattern", esp_idf_svc::http::Method::Post, move |mut req| {
+        let len = req.content_len().unwrap_or(0) as usize;
+        if len == 0 {
+            return send_body_error(req, BodyError::Empty);
+        }
+        if len > PATTERN_BODY_MAX {
+            return send_body_error(req, BodyError::TooLarge { max: PATTERN_BODY_MAX });
+        }
+        let binary = req.content_type().is_some_and(|t| t.starts_with("application/octet-stream"));
+
+        // Body lớn hơn các endpoint khác → đọc vào heap
+        let mut body = vec![0u8; len];
+        if let Err(e) = read_exact_body(&mut req, &mut body) {
+            return send_body_error(req, e);
+        }
+
+        let (colors, tile) = if binary {
+            (crate::pattern::from_bytes(&body), query_value(req.uri(), "tile"))
+        } else {
+            let Ok(body_str) = std::str::from_utf8(&body) else {
+                return send_body_error(req, BodyError::InvalidUtf8);
+            };
+            let Some(list) = form_value(body_str, "colors") else {
+                return send_error(req, 400, "Expected colors=RRGGBB,RRGGBB,...");
+            };
+            (crate::pattern::parse_hex(list), form_value(body_str, "tile"))
+        };
+
+        let colors = match colors {
+            Ok(c) => c,
+            Err(msg) => return send_error(req, 400, msg),
+        };
+        let tile = match tile {
+            None => false,
+            Some(v) => match parse_bool(v) {
+                Some(t) => t,
+                None => return send_error(req, 400, "tile must be 0|1"),
+            },
+        };
+
+        let count = colors.len();
+        if let Err(e) = crate::pattern::save(&pattern_nvs, crate::pattern::Pattern { colors, tile }) {
+            warn!("Failed to save pattern: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        // Tạo lại hiệu ứng (kể cả khi đang ở pattern) để nạp pattern mới
+        if !send_command(&pattern_producer, LedCommand::SetEffect(EffectType::Pattern)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<80>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"mode\":\"pattern\",\"leds\":{},\"tile\":{}}}", count, tile).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let runs_producer = producer.clone();
+    let runs_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/runs", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 640];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(list) = form_value(body_str, "runs").and_then(url_decode::<RUNS_LIST_MAX>) else {
+            return send_error(req, 400, "Expected runs=LEN:RRGGBB,LEN:RRGGBB,...");
+        };
+        let runs = match crate::pattern::parse_runs(&list) {
+            Ok(r) => r,
+            Err(msg) => return send_error(req, 400, msg),
+        };
+        let save = match form_value(body_str, "save") {
+            None => false,
+            Some(v) => match parse_bool(v) {
+                Some(s) => s,
+                None => return send_error(req, 400, "save must be 0|1"),
+            },
+        };
+
+        let count = runs.len();
+        let leds: u32 = runs.iter().map(|(len, _)| *len as u32).sum();
+        if let Err(e) = crate::pattern::set_runs(&runs_nvs, runs, save) {
+            warn!("Failed to save runs: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        // Tạo lại hiệu ứng (kể cả khi đang ở runs) để nạp các đoạn mới
+        if !send_command(&runs_producer, LedCommand::SetEffect(EffectType::Runs)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<96>::new();
+        write!(
+            resp_str,
+            "{{\"status\":\"ok\",\"mode\":\"runs\",\"runs\":{},\"leds\":{},\"saved\":{}}}",
+            count, leds.min(crate::NUM_LEDS as u32), save
+        ).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let solid_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/solid", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some((r, g, b)) = form_value(body_str, "color").and_then(|v| parse_hex_color(v).ok()) else {
+            return send_error(req, 400, "Expected color=RRGGBB");
+        };
+
+        let brightness = match form_value(body_str, "brightness") {
+            None => None,
+            Some(v) => match v.parse::<u8>() {
+                Ok(b) if b <= BRIGHTNESS_MAX => Some(b),
+                _ => return send_error(req, 400, "brightness must be 0-100"),
+            },
+        };
+
+        // Màu trước để Static được tạo với đúng màu này
+        let mut cmds = HeaplessVec::<LedCommand, 3>::new();
+        cmds.push(LedCommand::SetColor(r, g, b)).ok();
+        cmds.push(LedCommand::SetEffect(EffectType::Static)).ok();
+        if let Some(level) = brightness {
+            cmds.push(LedCommand::SetBrightness(level as f32 / BRIGHTNESS_MAX as f32)).ok();
+        }
+
+        if !send_commands_from(&solid_producer, CommandSource::Http, cmds) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<96>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"mode\":\"static\",\"color\":\"{:02X}{:02X}{:02X}\"", r, g, b).unwrap();
+        if let Some(level) = brightness {
+            write!(resp_str, ",\"brightness\":{}", level).unwrap();
+        }
+        resp_str.push('}').ok();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/state/wait", esp_idf_svc::http::Method::Get, |req| {
+        let since = query_value(req.uri(), "since").and_then(|v| v.parse::<u32>().ok());
+        let timeout_s = query_value(req.uri(), "timeout")
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(STATE_WAIT_MAX_S)
+            .clamp(1, STATE_WAIT_MAX_S);
+
+        let mut state = crate::controller::state_snapshot();
+
+        // Không có since → trả ngay trạng thái hiện tại
+        if let Some(since) = since {
+            if STATE_WAITERS.compare_exchange(0, 1, Ordering::AcqRel, Ordering::Relaxed).is_err() {
+                return send_error(req, 429, "Another client is already waiting");
+            }
+
+            let mut waited_ms = 0;
+            while state.is_some_and(|s| s.version == since) && waited_ms < timeout_s * 1000 {
+                FreeRtos::delay_ms(STATE_WAIT_POLL_MS);
+                waited_ms += STATE_WAIT_POLL_MS;
+                state = crate::controller::state_snapshot();
+            }
+            STATE_WAITERS.store(0, Ordering::Release);
+
+            if state.is_some_and(|s| s.version == since) {
+                req.into_status_response(304)?;
+                return Ok(());
+            }
+        }
+
+        let Some(state) = state else {
+            return send_error(req, 503, "State unavailable");
+        };
+
+        let mut resp_str = heapless::String::<192>::new();
+        write!(
+            resp_str,
+            "{{\"version\":{},\"mode\":\"{}\",\"brightness\":{},\"speed\":{},\"color\":\"{:02X}{:02X}{:02X}\"}}",
+            state.version,
+            state.mode,
+            state.brightness,
+            state.speed,
+            state.color.r, state.color.g, state.color.b
+        ).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/log/commands", esp_idf_svc::http::Method::Get, |req| {
+        let mut response = req.into_ok_response()?;
+        let mut out = ChunkWriter::new(&mut response);
+        let _ = crate::command_log::write_json(&mut out);
+        out.finish()
+    })?;
+
+    let command_log_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/command-log", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(enabled) = form_value(body_str, "enabled").and_then(parse_bool) else {
+            return send_error(req, 400, "Expected enabled=0|1");
+        };
+
+        if let Err(e) = crate::config::set_u8(&command_log_nvs, crate::config::KEY_COMMAND_LOG, enabled as u8) {
+            warn!("Failed to save command log setting: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+        crate::command_log::set_enabled(enabled);
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"command_log\":{}}}", enabled).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let rpc_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/rpc", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; MAX_BODY_SIZE];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let mut resp_str = heapless::String::<1536>::new();
+        if let Err(msg) = crate::rpc::run_batch(body_str, &rpc_producer, &mut resp_str) {
+            return send_error(req, 400, msg);
+        }
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let status_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/status", esp_idf_svc::http::Method::Get, move |req| {
+        info!("Status requested");
+
+        let name = crate::config::get_str::<{ crate::config::MAX_DEVICE_NAME_LEN }>(&status_nvs, crate::config::KEY_DEVICE_NAME);
+        let name = name.as_deref().unwrap_or(crate::config::DEFAULT_DEVICE_NAME);
+
+        let boot_count = crate::config::get_u32(&status_nvs, crate::config::KEY_BOOT_COUNT, 0);
+        let on_minutes = crate::config::get_u32(&status_nvs, crate::config::KEY_ON_TIME_MIN, 0);
+
+        let mut resp_str = heapless::String::<384>::new();
+        resp_str.push_str("{\"status\":\"ok\",\"device\":\"WS2812 Controller\",\"name\":").ok();
+        crate::config::write_json_str(&mut resp_str, name).ok();
+        write!(
+            resp_str,
+            ",\"version\":\"3.3\",\"firmware\":\"esp32-rust\",\"boot_count\":{},\"total_on_hours\":{:.1},\"last_command_source\":\"{}\"",
+            boot_count,
+            on_minutes as f32 / 60.0,
+            last_command_source().as_str()
+        ).ok();
+        match crate::controller::sleep_remaining_s() {
+            Some(remaining) => write!(resp_str, ",\"sleep_remaining_s\":{}}}", remaining).ok(),
+            None => resp_str.push_str(",\"sleep_remaining_s\":null}").ok(),
+        };
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let sleep_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/timer/sleep", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let minutes = match form_value(body_str, "minutes").and_then(|v| v.parse::<u16>().ok()) {
+            Some(m) if m <= SLEEP_TIMER_MAX_MIN => m,
+            _ => return send_error(req, 400, "Expected minutes=0-1440"),
+        };
+
+        let policy = match form_value(body_str, "on_command") {
+            None => SleepTimerPolicy::Keep,
+            Some(v) => match SleepTimerPolicy::parse(v) {
+                Some(p) => p,
+                None => return send_error(req, 400, "on_command must be keep, reset or cancel"),
+            },
+        };
+
+        if !send_command(&sleep_producer, LedCommand::SetSleepTimer { minutes, policy }) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<96>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"minutes\":{},\"on_command\":\"{}\"}}", minutes, policy.as_str()).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let identify_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/identify", esp_idf_svc::http::Method::Post, move |mut req| {
+        // Body không bắt buộc: POST rỗng = 3 nhịp như trước
+        let mut buf = [0u8; 32];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(BodyError::Empty) => "",
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let count = match form_value(body_str, "count") {
+            None => IdentifyEffect::DEFAULT_PULSES,
+            Some(v) => match v.parse::<u8>() {
+                Ok(n) if (1..=IdentifyEffect::MAX_PULSES).contains(&n) => n,
+                _ => return send_error(req, 400, "count must be 1-10"),
+            },
+        };
+
+        info!("Identify requested ({} flashes)", count);
+        if !send_command(&identify_producer, LedCommand::Identify { count }) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<48>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"count\":{}}}", count).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let name_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/name", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 128];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(name) = form_value(body_str, "name").and_then(url_decode::<{ crate::config::MAX_DEVICE_NAME_LEN }>) else {
+            return send_error(req, 400, "Expected name=... (max 32 chars)");
+        };
+        if name.trim().is_empty() {
+            return send_error(req, 400, "Name must not be empty");
+        }
+
+        if let Err(e) = crate::config::set_str(&name_nvs, crate::config::KEY_DEVICE_NAME, &name) {
+            warn!("Failed to save device name: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        info!("Device name set to: {}", name);
+        let mut response = req.into_ok_response()?;
+        response.write_all(b"{\"status\":\"ok\"}")?;
+        Ok(())
+    })?;
+
+    let base_path_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/base-path", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 128];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(BodyError::Empty) => "",
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(raw) = url_decode::<96>(form_value(body_str, "path").unwrap_or("")) else {
+            return send_error(req, 400, "Invalid path encoding");
+        };
+        let Some(path) = crate::config::normalize_base_path(&raw) else {
+            return send_error(req, 400, "path must start with / and use a-z, 0-9, /, _, -, . (max 32)");
+        };
+
+        // Rỗng = bỏ tiền tố: xóa key thay vì lưu "" (import/export coi "" là không hợp lệ)
+        let saved = if path.is_empty() {
+            crate::config::remove_key(&base_path_nvs, crate::config::KEY_BASE_PATH)
+        } else {
+            crate::config::set_str(&base_path_nvs, crate::config::KEY_BASE_PATH, path)
+        };
+        if let Err(e) = saved {
+            warn!("Failed to save base path: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        info!("Base path set to: '{}'", path);
+        let mut resp_str = heapless::String::<96>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"base_path\":\"{}\"}}", path).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let beatsync_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/beatsync", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(enabled) = form_value(body_str, "enabled").and_then(parse_bool) else {
+            return send_error(req, 400, "Expected enabled=0|1");
+        };
+
+        if !send_command(&beatsync_producer, LedCommand::SetBeatSync(enabled)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"beatsync\":{}}}", enabled).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let sync_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/sync", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(reference_us) = form_value(body_str, "t").and_then(|v| v.parse::<u64>().ok()) else {
+            return send_error(req, 400, "Expected t=<microseconds>");
+        };
+        let restart = form_value(body_str, "restart").and_then(parse_bool).unwrap_or(false);
+
+        if !send_command_from(&sync_producer, CommandSource::Sync, LedCommand::Sync { reference_us, restart }) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(b"{\"status\":\"ok\"}")?;
+        Ok(())
+    })?;
+
+    let boot_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/boot-animation", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(enabled) = form_value(body_str, "enabled").and_then(parse_bool) else {
+            return send_error(req, 400, "Expected enabled=0|1");
+        };
+
+        if let Err(e) = crate::config::set_u8(&boot_nvs, crate::config::KEY_BOOT_ANIMATION, enabled as u8) {
+            warn!("Failed to save boot animation setting: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"boot_animation\":{}}}", enabled).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let chip_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/chip", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(chip) = form_value(body_str, "type").and_then(ChipType::parse) else {
+            return send_error(req, 400, "Expected type=ws2812|ws2811|sk6812");
+        };
+
+        if let Err(e) = crate::config::set_u8(&chip_nvs, crate::config::KEY_CHIP_TYPE, chip as u8) {
+            warn!("Failed to save chip type: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        info!("LED chip set to {} (applies after restart)", chip.as_str());
+        let mut resp_str = heapless::String::<96>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"chip\":\"{}\",\"restart_required\":true}}", chip.as_str()).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let skip_producer = producer.clone();
+    let skip_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/skip-pixels", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 256];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(raw) = form_value(body_str, "pixels").and_then(url_decode::<128>) else {
+            return send_error(req, 400, "Expected pixels=i,j,...");
+        };
+        let Some(mut pixels) = parse_skip_pixels(&raw, crate::NUM_LEDS) else {
+            return send_error(req, 400, "Indices must be 0..LED count-1, at most 16");
+        };
+        pixels.sort_unstable();
+
+        // Lưu dạng chuẩn hóa "3,17,42"
+        let mut stored = heapless::String::<96>::new();
+        for (i, index) in pixels.iter().enumerate() {
+            if i > 0 {
+                stored.push(',').ok();
+            }
+            write!(stored, "{}", index).ok();
+        }
+
+        if let Err(e) = crate::config::set_str(&skip_nvs, crate::config::KEY_SKIP_PIXELS, &stored) {
+            warn!("Failed to save skip pixels: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        let count = pixels.len();
+        if !send_command(&skip_producer, LedCommand::SetSkipPixels(pixels)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<160>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"pixels\":[{}],\"active_leds\":{}}}", stored, crate::NUM_LEDS - count).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let thermal_producer = producer.clone();
+    let thermal_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/thermal", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(enabled) = form_value(body_str, "enabled").and_then(parse_bool) else {
+            return send_error(req, 400, "Expected enabled=0|1");
+        };
+
+        let minutes = match form_value(body_str, "minutes") {
+            None => THERMAL_DEFAULT_MIN,
+            Some(v) => match v.parse::<u8>() {
+                Ok(m) if (1..=THERMAL_MAX_MIN).contains(&m) => m,
+                _ => return send_error(req, 400, "minutes must be 1-120"),
+            },
+        };
+
+        // 0 = tắt, khác 0 = số phút
+        let stored = if enabled { minutes } else { 0 };
+        if let Err(e) = crate::config::set_u8(&thermal_nvs, crate::config::KEY_THERMAL, stored) {
+            warn!("Failed to save thermal setting: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        let setting = enabled.then_some(minutes as u16);
+        if !send_command(&thermal_producer, LedCommand::SetThermalProtection(setting)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"thermal\":{},\"minutes\":{}}}", enabled, minutes).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let offline_producer = producer.clone();
+    let offline_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/offline-behavior", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let minutes = match form_value(body_str, "minutes") {
+            None => OFFLINE_DEFAULT_MIN,
+            Some(v) => match v.parse::<u16>() {
+                Ok(m) if (1..=SLEEP_TIMER_MAX_MIN).contains(&m) => m,
+                _ => return send_error(req, 400, "minutes must be 1-1440"),
+            },
+        };
+        let level = match form_value(body_str, "level") {
+            None => OFFLINE_DEFAULT_LEVEL,
+            Some(v) => match v.parse::<u8>() {
+                Ok(l) if l <= 100 => l,
+                _ => return send_error(req, 400, "level must be 0-100"),
+            },
+        };
+        let Some(behavior) = form_value(body_str, "mode").and_then(|m| OfflineBehavior::parse(m, level)) else {
+            return send_error(req, 400, "Expected mode=keep|off|dim");
+        };
+
+        // Không có mạng đã lưu thì Station không bao giờ kết nối → off/dim sẽ luôn bật
+        if behavior != OfflineBehavior::KeepRunning
+            && crate::config::load_networks(&offline_nvs).map(|n| n.is_empty()).unwrap_or(true)
+        {
+            return send_error(req, 409, "Save a WiFi network first (POST /wifi/saved)");
+        }
+
+        let saved = crate::config::set_str(&offline_nvs, crate::config::KEY_OFFLINE_MODE, behavior.as_str())
+            .and_then(|_| crate::config::set_u8(&offline_nvs, crate::config::KEY_OFFLINE_LEVEL, level))
+            .and_then(|_| crate::config::set_u32(&offline_nvs, crate::config::KEY_OFFLINE_MIN, minutes as u32));
+        if let Err(e) = saved {
+            warn!("Failed to save offline behavior: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        if !send_command(&offline_producer, LedCommand::SetOfflineBehavior { behavior, minutes }) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<96>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"mode\":\"{}\",\"minutes\":{},\"level\":{}}}", behavior.as_str(), minutes, level).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let idle_producer = producer.clone();
+    let idle_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/idle", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 96];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let mut minutes = [0u16; 2];
+        for (slot, key) in minutes.iter_mut().zip(["ambient", "off"]) {
+            *slot = match form_value(body_str, key) {
+                None => 0,
+                Some(v) => match v.parse::<u16>() {
+                    Ok(m) if m <= SLEEP_TIMER_MAX_MIN => m,
+                    _ => return send_error(req, 400, "ambient and off must be 0-1440 minutes"),
+                },
+            };
+        }
+        let [ambient_minutes, off_minutes] = minutes;
+        let level = match form_value(body_str, "level") {
+            None => IDLE_DEFAULT_LEVEL,
+            Some(v) => match v.parse::<u8>() {
+                Ok(l) if (1..=100).contains(&l) => l,
+                _ => return send_error(req, 400, "level must be 1-100"),
+            },
+        };
+        let Some((effect, effect_str)) = effect_from_name(form_value(body_str, "effect").unwrap_or(IDLE_DEFAULT_EFFECT)) else {
+            return send_error(req, 400, "Unknown effect");
+        };
+
+        let saved = crate::config::set_u32(&idle_nvs, crate::config::KEY_IDLE_AMBIENT, ambient_minutes as u32)
+            .and_then(|_| crate::config::set_u32(&idle_nvs, crate::config::KEY_IDLE_OFF, off_minutes as u32))
+            .and_then(|_| crate::config::set_str(&idle_nvs, crate::config::KEY_IDLE_EFFECT, effect_str))
+            .and_then(|_| crate::config::set_u8(&idle_nvs, crate::config::KEY_IDLE_LEVEL, level));
+        if let Err(e) = saved {
+            warn!("Failed to save idle timeout: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        let enabled = ambient_minutes > 0 || off_minutes > 0;
+        let settings = enabled.then_some(IdleSettings { ambient_minutes, off_minutes, effect, level });
+        if !send_command(&idle_producer, LedCommand::SetIdleTimeout(settings)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<128>::new();
+        write!(
+            resp_str,
+            "{{\"status\":\"ok\",\"enabled\":{},\"ambient\":{},\"off\":{},\"effect\":\"{}\",\"level\":{}}}",
+            enabled, ambient_minutes, off_minutes, effect_str, level
+        ).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let health_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/health", esp_idf_svc::http::Method::Get, move |req| {
+        let (enabled, heat_s, limit) = crate::controller::thermal_status();
+        // Số command đang chờ LED task xử lý / sức chứa của queue
+        let (queued, queue_max) = health_producer.lock()
+            .map(|p| (p.len(), p.capacity()))
+            .unwrap_or((0, 0));
+
+        let mut resp_str = heapless::String::<200>::new();
+        write!(
+            resp_str,
+            "{{\"thermal\":{{\"enabled\":{},\"high_load_s\":{},\"limit_percent\":{},\"engaged\":{}}},\"write_errors\":{},\"queue\":{{\"count\":{},\"max\":{}}}}}",
+            enabled,
+            heat_s,
+            (limit as u16 * 100 + 127) / 255,
+            limit < 255,
+            crate::controller::write_error_count(),
+            queued,
+            queue_max
+        ).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let power_save_producer = producer.clone();
+    let power_save_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/power-save", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(enabled) = form_value(body_str, "enabled").and_then(parse_bool) else {
+            return send_error(req, 400, "Expected enabled=0|1");
+        };
+
+        if let Err(e) = crate::config::set_u8(&power_save_nvs, crate::config::KEY_POWER_SAVE, enabled as u8) {
+            warn!("Failed to save power save setting: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        if !send_command(&power_save_producer, LedCommand::SetPowerSave(enabled)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"power_save\":{}}}", enabled).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let connect_producer = producer.clone();
+    let connect_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/connect-flash", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let enabled = match form_value(body_str, "enabled") {
+            None => None,
+            Some(v) => match parse_bool(v) {
+                Some(b) => Some(b),
+                None => return send_error(req, 400, "enabled must be 0|1"),
+            },
+        };
+        let color = match form_value(body_str, "color") {
+            None => None,
+            Some(v) => match parse_hex_color(v) {
+                Ok(c) => Some(c),
+                Err(_) => return send_error(req, 400, "color must be RRGGBB"),
+            },
+        };
+        if enabled.is_none() && color.is_none() {
+            return send_error(req, 400, "Expected enabled=0|1 and/or color=RRGGBB");
+        }
+
+        // Trường không gửi giữ giá trị đã lưu
+        let enabled = enabled.unwrap_or_else(|| crate::config::get_u8(&connect_nvs, crate::config::KEY_CONNECT_FLASH, 1) != 0);
+        let (r, g, b) = color.unwrap_or_else(|| {
+            crate::config::get_str::<8>(&connect_nvs, crate::config::KEY_CONNECT_COLOR)
+                .and_then(|s| parse_hex_color(&s).ok())
+                .unwrap_or(CONNECT_FLASH_DEFAULT_COLOR)
+        });
+
+        let mut hex = heapless::String::<8>::new();
+        write!(hex, "{:02X}{:02X}{:02X}", r, g, b).unwrap();
+
+        let saved = crate::config::set_u8(&connect_nvs, crate::config::KEY_CONNECT_FLASH, enabled as u8)
+            .and_then(|_| crate::config::set_str(&connect_nvs, crate::config::KEY_CONNECT_COLOR, &hex));
+        if let Err(e) = saved {
+            warn!("Failed to save connect flash setting: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        if !send_command(&connect_producer, LedCommand::SetConnectFlash(enabled.then_some((r, g, b)))) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<96>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"enabled\":{},\"color\":\"{}\"}}", enabled, hex).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let saturation_producer = producer.clone();
+    let saturation_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/saturation", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 32];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(value) = single_value(body_str).and_then(|v| v.parse::<u8>().ok()).filter(|v| *v <= 100) else {
+            return send_error(req, 400, "Expected value=0-100");
+        };
+
+        if let Err(e) = crate::config::set_u8(&saturation_nvs, crate::config::KEY_SATURATION, value) {
+            warn!("Failed to save saturation: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        if !send_command(&saturation_producer, LedCommand::SetSaturation(value)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"saturation\":{}}}", value).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let interp_producer = producer.clone();
+    let interp_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/interpolation", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(enabled) = form_value(body_str, "enabled").and_then(parse_bool) else {
+            return send_error(req, 400, "Expected enabled=0|1");
+        };
+
+        if let Err(e) = crate::config::set_u8(&interp_nvs, crate::config::KEY_INTERPOLATION, enabled as u8) {
+            warn!("Failed to save interpolation setting: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        if !send_command(&interp_producer, LedCommand::SetInterpolation(enabled)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"interpolation\":{}}}", enabled).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let hue_producer = producer.clone();
+    let hue_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/hue-preserve", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(enabled) = form_value(body_str, "enabled").and_then(parse_bool) else {
+            return send_error(req, 400, "Expected enabled=0|1");
+        };
+
+        if let Err(e) = crate::config::set_u8(&hue_nvs, crate::config::KEY_HUE_PRESERVE, enabled as u8) {
+            warn!("Failed to save hue-preserve setting: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        if !send_command(&hue_producer, LedCommand::SetHuePreserve(enabled)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"hue_preserve\":{}}}", enabled).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let carry_producer = producer.clone();
+    let carry_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/color-carry", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(mode) = form_value(body_str, "mode").and_then(ColorCarryOver::parse) else {
+            return send_error(req, 400, "Expected mode=preserve|default");
+        };
+
+        if let Err(e) = crate::config::set_u8(&carry_nvs, crate::config::KEY_COLOR_CARRY, mode as u8) {
+            warn!("Failed to save color carry-over setting: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        if !send_command(&carry_producer, LedCommand::SetColorCarryOver(mode)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"mode\":\"{}\"}}", mode.as_str()).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let audio_mode_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/audio-mode", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(enabled) = form_value(body_str, "enabled").and_then(parse_bool) else {
+            return send_error(req, 400, "Expected enabled=0|1");
+        };
+
+        if !send_command(&audio_mode_producer, LedCommand::SetAudioMode(enabled)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"audio_mode\":{}}}", enabled).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let party_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/partymode", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 256];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(enabled) = form_value(body_str, "enabled").and_then(parse_bool) else {
+            return send_error(req, 400, "Expected enabled=0|1");
+        };
+
+        if !enabled {
+            if !send_command(&party_producer, LedCommand::SetPartyMode(None)) {
+                return send_error(req, 503, "Device busy");
+            }
+            let mut response = req.into_ok_response()?;
+            response.write_all(b"{\"status\":\"ok\",\"party\":false}")?;
+            return Ok(());
+        }
+
+        let gap_ms = match form_value(body_str, "gap") {
+            None => PARTY_GAP_MS_DEFAULT,
+            Some(v) => match v.parse::<u32>() {
+                Ok(ms) if (500..=60_000).contains(&ms) => ms,
+                _ => return send_error(req, 400, "gap must be 500-60000 ms"),
+            },
+        };
+
+        let mut effects = HeaplessVec::<EffectType, PARTY_MAX_EFFECTS>::new();
+        let mut names = HeaplessVec::<&'static str, PARTY_MAX_EFFECTS>::new();
+        match form_value(body_str, "effects") {
+            Some(list) => {
+                let Some(list) = url_decode::<128>(list) else {
+                    return send_error(req, 400, "Invalid effects list");
+                };
+                for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                    match effect_from_name(name) {
+                        Some((effect, name)) if effect.is_audio_reactive() => {
+                            if effects.contains(&effect) {
+                                continue;
+                            }
+                            if effects.push(effect).is_err() {
+                                return send_error(req, 400, "Too many effects (max 8)");
+                            }
+                            names.push(name).ok();
+                        }
+                        Some(_) => return send_error(req, 400, "Party mode only rotates audio effects"),
+                        None => return send_error(req, 400, "Unknown effect"),
+                    }
+                }
+            }
+            None => {
+                for effect in crate::rotation::list().into_iter().filter(|e| e.is_audio_reactive()) {
+                    names.push(effect_name(&effect)).ok();
+                    if effects.push(effect).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if effects.is_empty() {
+            return send_error(req, 400, "Expected at least one effect");
+        }
+
+        let settings = PartySettings { effects, gap_us: gap_ms as u64 * 1000 };
+        if !send_command(&party_producer, LedCommand::SetPartyMode(Some(settings))) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        info!("Party mode enabled: {:?}, gap {}ms", names, gap_ms);
+        let mut resp_str = heapless::String::<256>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"party\":true,\"gap_ms\":{},\"effects\":", gap_ms).unwrap();
+        write_json_list(&mut resp_str, names.iter(), |out, name| write!(out, "\"{}\"", name)).unwrap();
+        resp_str.push('}').ok();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let color_cycle_producer = producer.clone();
+    let color_cycle_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/color-cycle", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let period = match form_value(body_str, "period").and_then(|v| v.parse::<u32>().ok()) {
+            Some(p) if p == 0 || (COLOR_CYCLE_MIN_S..=COLOR_CYCLE_MAX_S).contains(&p) => p,
+            _ => return send_error(req, 400, "Expected period=0 or 5-3600"),
+        };
+
+        if let Err(e) = crate::config::set_u32(&color_cycle_nvs, crate::config::KEY_COLOR_CYCLE, period) {
+            warn!("Failed to save color cycle: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        if !send_command(&color_cycle_producer, LedCommand::SetColorCycle(period)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"period\":{}}}", period).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let min_bri_producer = producer.clone();
+    let min_bri_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/min-brightness", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(value) = form_value(body_str, "value").and_then(|v| v.parse::<u8>().ok()) else {
+            return send_error(req, 400, "Expected value=0-255");
+        };
+
+        if let Err(e) = crate::config::set_u8(&min_bri_nvs, crate::config::KEY_MIN_BRIGHTNESS, value) {
+            warn!("Failed to save min brightness: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        if !send_command(&min_bri_producer, LedCommand::SetMinBrightness(value)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"min_brightness\":{}}}", value).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let ambient_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/auto-brightness", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 128];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let enabled = form_value(body_str, "enabled").map(parse_bool);
+        let dark = form_value(body_str, "dark").map(|v| v.parse::<u32>().ok().filter(|v| *v <= 4095));
+        let bright = form_value(body_str, "bright").map(|v| v.parse::<u32>().ok().filter(|v| *v <= 4095));
+        let min = form_value(body_str, "min").map(|v| v.parse::<u8>().ok().filter(|v| *v <= 100));
+        let pin = form_value(body_str, "pin").map(|v| v.parse::<u8>().ok().filter(|v| crate::ambient::is_usable_pin(*v)));
+
+        if [enabled.is_none(), dark.is_none(), bright.is_none(), min.is_none(), pin.is_none()].iter().all(|n| *n) {
+            return send_error(req, 400, "Expected enabled, dark, bright, min or pin");
+        }
+        if matches!(enabled, Some(None)) || matches!(dark, Some(None)) || matches!(bright, Some(None))
+            || matches!(min, Some(None)) || matches!(pin, Some(None))
+        {
+            return send_error(req, 400, "Invalid value (enabled=0|1, dark/bright 0-4095, min 0-100, pin 34-39)");
+        }
+
+        let (old_dark, old_bright, old_min) = ambient.curve();
+        let dark = dark.flatten().unwrap_or(old_dark);
+        let bright = bright.flatten().unwrap_or(old_bright);
+        let min = min.flatten().unwrap_or(old_min);
+        ambient.set_curve(dark, bright, min);
+        if let Some(Some(enabled)) = enabled {
+            ambient.set_enabled(enabled);
+        }
+
+        let saved = crate::config::set_u8(&ambient_nvs, crate::config::KEY_AUTO_BRIGHTNESS, ambient.is_enabled() as u8)
+            .and_then(|_| crate::config::set_u32(&ambient_nvs, crate::config::KEY_AMBIENT_DARK, dark))
+            .and_then(|_| crate::config::set_u32(&ambient_nvs, crate::config::KEY_AMBIENT_BRIGHT, bright))
+            .and_then(|_| crate::config::set_u8(&ambient_nvs, crate::config::KEY_AMBIENT_MIN, min))
+            .and_then(|_| match pin.flatten() {
+                Some(p) => crate::config::set_u8(&ambient_nvs, crate::config::KEY_AMBIENT_PIN, p),
+                None => Ok(()),
+            });
+        if let Err(e) = saved {
+            warn!("Failed to save auto-brightness settings: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        let mut resp_str = heapless::String::<128>::new();
+        write!(
+            resp_str,
+            "{{\"status\":\"ok\",\"enabled\":{},\"dark\":{},\"bright\":{},\"min\":{},\"factor\":{}}}",
+            ambient.is_enabled(), dark, bright, min, ambient.factor()
+        ).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let next_color_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/next-color", esp_idf_svc::http::Method::Post, move |req| {
+        let Some(color) = crate::quick_colors::next() else {
+            return send_error(req, 409, "No quick colors configured");
+        };
+
+        if !send_command(&next_color_producer, LedCommand::SetColor(color.r, color.g, color.b)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<48>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"color\":\"{:02X}{:02X}{:02X}\"}}", color.r, color.g, color.b).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let reseed_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/led/reseed", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 32];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(BodyError::Empty) => "",
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let seed = match form_value(body_str, "seed") {
+            None => unsafe { esp_idf_sys::esp_random() },
+            Some(v) => match v.parse::<u32>() {
+                Ok(seed) => seed,
+                Err(_) => return send_error(req, 400, "seed must be 0-4294967295"),
+            },
+        };
+
+        let Some(state) = crate::controller::state_snapshot() else {
+            return send_error(req, 503, "State unavailable");
+        };
+        if !effect_from_name(state.mode).is_some_and(|(effect, _)| effect.is_randomized()) {
+            return send_error(req, 409, "Current effect has no random pattern");
+        }
+
+        let mut value = ParamValue::new();
+        write!(value, "{}", seed).unwrap();
+        if !send_command(&reseed_producer, LedCommand::SetParam(ParamKey::try_from("seed").unwrap(), value)) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"mode\":\"{}\",\"seed\":{}}}", state.mode, seed).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/config/quick-colors", esp_idf_svc::http::Method::Get, |req| {
+        let mut resp_str = heapless::String::<128>::new();
+        write_quick_colors_json(&mut resp_str, &crate::quick_colors::list()).ok();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let quick_colors_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/quick-colors", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 256];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(list) = form_value(body_str, "colors").and_then(url_decode::<128>) else {
+            return send_error(req, 400, "Expected colors=RRGGBB,RRGGBB,...");
+        };
+        let colors = match crate::quick_colors::parse(&list) {
+            Ok(c) => c,
+            Err(msg) => return send_error(req, 400, msg),
+        };
+
+        if let Err(e) = crate::quick_colors::save(&quick_colors_nvs, colors.clone()) {
+            warn!("Failed to save quick colors: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        let mut resp_str = heapless::String::<128>::new();
+        write_quick_colors_json(&mut resp_str, &colors).ok();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/config/rotation", esp_idf_svc::http::Method::Get, |req| {
+        let mut resp_str = heapless::String::<320>::new();
+        write_rotation_json(&mut resp_str, &crate::rotation::list()).ok();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let rotation_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/rotation", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 512];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(list) = form_value(body_str, "effects").and_then(url_decode::<256>) else {
+            return send_error(req, 400, "Expected effects=name,name,...");
+        };
+        let rotation = match crate::rotation::parse(&list) {
+            Ok(r) => r,
+            Err(msg) => return send_error(req, 400, msg),
+        };
+
+        if let Err(e) = crate::rotation::save(&rotation_nvs, rotation.clone()) {
+            warn!("Failed to save rotation: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        let mut resp_str = heapless::String::<320>::new();
+        write_rotation_json(&mut resp_str, &rotation).ok();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let button_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/button", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 256];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let pin = form_value(body_str, "pin").map(|v| {
+            v.parse::<u8>().ok().filter(|p| *p == crate::button::PIN_DISABLED || crate::button::is_usable_pin(*p))
+        });
+        let actions = form_value(body_str, "actions").map(url_decode::<32>);
+        let favorite = form_value(body_str, "favorite");
+
+        if [pin.is_none(), actions.is_none(), favorite.is_none()].iter().all(|n| *n) {
+            return send_error(req, 400, "Expected pin, actions or favorite");
+        }
+        if matches!(pin, Some(None)) {
+            return send_error(req, 400, "pin must be a free GPIO 0-39 (not 6-11, 18, 25, 32, 33) or 255");
+        }
+        if actions.as_ref().is_some_and(|a| a.as_deref().and_then(crate::button::parse_actions).is_none()) {
+            return send_error(req, 400, "actions must be short,long,double from power|next|favorite|color|none");
+        }
+        if favorite.is_some_and(|f| effect_from_name(f).is_none()) {
+            return send_error(req, 400, "Unknown favorite effect");
+        }
+
+        let saved = match pin.flatten() {
+            Some(p) => crate::config::set_u8(&button_nvs, crate::config::KEY_BUTTON_PIN, p),
+            None => Ok(()),
+        }
+        .and_then(|_| match actions.flatten() {
+            Some(a) => crate::config::set_str(&button_nvs, crate::config::KEY_BUTTON_ACTIONS, &a),
+            None => Ok(()),
+        })
+        .and_then(|_| match favorite {
+            Some(f) => crate::config::set_str(&button_nvs, crate::config::KEY_BUTTON_FAVORITE, f),
+            None => Ok(()),
+        });
+        if let Err(e) = saved {
+            warn!("Failed to save button settings: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(b"{\"status\":\"ok\",\"restart_required\":true}")?;
+        Ok(())
+    })?;
+
+    let scene_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/scene/save", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 384];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let name = match form_value(body_str, "name").map(crate::scenes::parse_name) {
+            Some(Ok(name)) => name,
+            Some(Err(msg)) => return send_error(req, 400, msg),
+            None => return send_error(req, 400, "Expected name=..."),
+        };
+
+        // Trường bỏ trống lấy theo trạng thái hiện tại
+        let Some(state) = crate::controller::state_snapshot() else {
+            return send_error(req, 503, "State unavailable");
+        };
+        let Some((effect, _)) = effect_from_name(form_value(body_str, "mode").unwrap_or(state.mode)) else {
+            return send_error(req, 400, "Unknown mode");
+        };
+        let color = match form_value(body_str, "color").map(parse_hex_color) {
+            None => state.color,
+            Some(Ok((r, g, b))) => smart_leds::RGB8 { r, g, b },
+            Some(Err(_)) => return send_error(req, 400, "color must be RRGGBB"),
+        };
+        let number = |key: &str, default: u8| form_value(body_str, key).map_or(Some(default), |v| v.parse::<u8>().ok());
+        let (Some(brightness), Some(speed), Some(saturation)) =
+            (number("brightness", state.brightness), number("speed", state.speed), number("sat", state.saturation))
+        else {
+            return send_error(req, 400, "brightness, speed and sat must be numbers");
+        };
+        let range = match form_value(body_str, "range").map(crate::scenes::parse_range) {
+            None => state.range,
+            Some(Ok(range)) => range,
+            Some(Err(msg)) => return send_error(req, 400, msg),
+        };
+        let beat_sync = match form_value(body_str, "beatsync").map(parse_bool) {
+            None => state.beat_sync,
+            Some(Some(on)) => on,
+            Some(None) => return send_error(req, 400, "beatsync must be 0 or 1"),
+        };
+        let Some(params) = url_decode::<256>(form_value(body_str, "params").unwrap_or("")) else {
+            return send_error(req, 400, "Invalid params encoding");
+        };
+        let params = match crate::scenes::parse_params(&params) {
+            Ok(params) => params,
+            Err(msg) => return send_error(req, 400, msg),
+        };
+
+        let scene = crate::scenes::Scene { name, effect, brightness, speed, color, range, saturation, beat_sync, params };
+        if let Err(msg) = scene.validate() {
+            return send_error(req, 400, msg);
+        }
+        if !crate::scenes::has_room_for(&scene.name) {
+            return send_error(req, 507, "Scene store full (max 8), delete one first");
+        }
+
+        let name = scene.name.clone();
+        if let Err(e) = crate::scenes::save(&scene_nvs, scene) {
+            warn!("Failed to save scene: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        info!("Scene saved: {}", name);
+        let mut response = req.into_ok_response()?;
+        response.write_all(b"{\"status\":\"ok\"}")?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/scene/list", esp_idf_svc::http::Method::Get, |req| {
+        let mut response = req.into_ok_response()?;
+        let mut out = ChunkWriter::new(&mut response);
+        let _ = write_scene_list_json(&mut out);
+        out.finish()
+    })?;
+
+    let scene_producer = producer.clone();
+    server.fn_handler::<anyhow::Error, _>("/scene/apply", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(name) = form_value(body_str, "name").or_else(|| single_value(body_str)) else {
+            return send_error(req, 400, "Expected name=...");
+        };
+        let Some(scene) = crate::scenes::find(name) else {
+            return send_error(req, 404, "Scene not found");
+        };
+
+        // Cả scene vào queue hoặc không command nào
+        if !send_commands_from(&scene_producer, CommandSource::Http, scene.commands()) {
+            return send_error(req, 503, "Device busy");
+        }
+
+        info!("Scene applied: {}", scene.name);
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"scene\":\"{}\",\"mode\":\"{}\"}}", scene.name, effect_name(&scene.effect)).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let scene_delete_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/scene", esp_idf_svc::http::Method::Delete, move |req| {
+        let Some(name) = query_value(req.uri(), "name").and_then(url_decode::<32>) else {
+            return send_error(req, 400, "Expected ?name=...");
+        };
+
+        match crate::scenes::remove(&scene_delete_nvs, &name) {
+            Ok(true) => {
+                info!("Scene deleted: {}", name);
+                let mut response = req.into_ok_response()?;
+                response.write_all(b"{\"status\":\"ok\"}")?;
+                Ok(())
+            }
+            Ok(false) => send_error(req, 404, "Scene not found"),
+            Err(e) => {
+                warn!("Failed to delete scene: {:#}", e);
+                send_error(req, 500, "NVS write failed")
+            }
+        }
+    })?;
+
+    let palette_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/palette/save", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 256];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let (Some(name), Some(stops)) = (form_value(body_str, "name"), form_value(body_str, "stops")) else {
+            return send_error(req, 400, "Expected name=...&stops=RRGGBB,RRGGBB");
+        };
+        let Some(stops) = url_decode::<128>(stops) else {
+            return send_error(req, 400, "Invalid stops encoding");
+        };
+
+        let palette = match crate::palettes::Palette::parse(name, &stops) {
+            Ok(p) => p,
+            Err(msg) => return send_error(req, 400, msg),
+        };
+
+        if !crate::palettes::has_room_for(&palette.name) {
+            return send_error(req, 507, "Palette store full (max 8), delete one first");
+        }
+
+        if let Err(e) = crate::palettes::save(&palette_nvs, palette) {
+            warn!("Failed to save palette: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+
+        info!("Palette saved: {}", name);
+        let mut response = req.into_ok_response()?;
+        response.write_all(b"{\"status\":\"ok\"}")?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/palette/list", esp_idf_svc::http::Method::Get, |req| {
+        let mut response = req.into_ok_response()?;
+        let mut out = ChunkWriter::new(&mut response);
+        let _ = write_palette_list_json(&mut out);
+        out.finish()
+    })?;
+
+    let palette_delete_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/palette", esp_idf_svc::http::Method::Delete, move |req| {
+        let Some(name) = query_value(req.uri(), "name").and_then(url_decode::<32>) else {
+            return send_error(req, 400, "Expected ?name=...");
+        };
+
+        match crate::palettes::remove(&palette_delete_nvs, &name) {
+            Ok(true) => {
+                info!("Palette deleted: {}", name);
+                let mut response = req.into_ok_response()?;
+                response.write_all(b"{\"status\":\"ok\"}")?;
+                Ok(())
+            }
+            Ok(false) => send_error(req, 404, "Palette not found"),
+            Err(e) => {
+                warn!("Failed to delete palette: {:#}", e);
+                send_error(req, 500, "NVS write failed")
+            }
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/restart", esp_idf_svc::http::Method::Post, move |req| {
+        info!("Restart requested");
+        stop.store(true, Ordering::Relaxed);
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(b"{\"status\":\"ok\",\"message\":\"Restarting\"}")?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/diag/perf", esp_idf_svc::http::Method::Get, |req| {
+        let Some(timing) = crate::controller::frame_timing() else {
+            return send_error(req, 503, "Timing unavailable");
+        };
+
+        let mut resp_str = heapless::String::<256>::new();
+        write!(
+            resp_str,
+            "{{\"effect\":\"{}\",\"frames\":{},\"target_us\":{},\"min_us\":{},\"avg_us\":{},\"max_us\":{},\"blend_us\":{},\"audio_skipped\":{},\"write_errors\":{}}}",
+            timing.effect, timing.frames, timing.target_us, timing.min_us, timing.avg_us(), timing.max_us, timing.blend_us,
+            timing.audio_skipped, crate::controller::write_error_count()
+        ).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/audio", esp_idf_svc::http::Method::Get, move |req| {
+        let snapshot = audio_data.latest();
+
+        let mut resp_str = heapless::String::<256>::new();
+        write!(resp_str, "{{\"audio_available\":{},\"volume\":{:.3},\"bass\":{:.3},\"mid\":{:.3},\"treble\":{:.3},\"beat\":{},\"bins\":[",
+            snapshot.audio_available, snapshot.volume, snapshot.bass, snapshot.mid, snapshot.treble, snapshot.beat).unwrap();
+        for (i, bin) in snapshot.bins.iter().enumerate() {
+            if i > 0 {
+                resp_str.push(',').ok();
+            }
+            write!(resp_str, "{:.3}", bin).unwrap();
+        }
+        write!(resp_str, "],\"soft_clip\":{}}}", crate::audio::soft_clip_enabled()).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    let soft_clip_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/soft-clip", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 64];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let Some(enabled) = form_value(body_str, "enabled").and_then(parse_bool) else {
+            return send_error(req, 400, "Expected enabled=0|1");
+        };
+
+        if let Err(e) = crate::config::set_u8(&soft_clip_nvs, crate::config::KEY_SOFT_CLIP, enabled as u8) {
+            warn!("Failed to save soft clip setting: {:#}", e);
+            return send_error(req, 500, "NVS write failed");
+        }
+        crate::audio::set_soft_clip(enabled);
+
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"soft_clip\":{}}}", enabled).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    // Chỉ đọc - dùng để debug cấu hình đã lưu (không bao giờ xuất password)
+    let export_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/export", esp_idf_svc::http::Method::Get, move |req| {
+        info!("Config export requested");
+
+        let mut response = req.into_ok_response()?;
+        let mut out = ChunkWriter::new(&mut response);
+        let _ = crate::config::export_json(&export_nvs, &mut out);
+        out.finish()
+    })?;
+
+    let import_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/config/import", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 2048];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let written = match crate::config::import_json(&import_nvs, body_str) {
+            Ok(n) => n,
+            Err(msg) => {
+                warn!("Config import rejected: {}", msg);
+                return send_error(req, 400, msg);
+            }
+        };
+
+        info!("Config imported ({} fields)", written);
+        let mut resp_str = heapless::String::<64>::new();
+        write!(resp_str, "{{\"status\":\"ok\",\"written\":{}}}", written).unwrap();
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(resp_str.as_bytes())?;
+        Ok(())
+    })?;
+
+    // Chỉ trả SSID theo thứ tự ưu tiên, không bao giờ trả password
+    let wifi_list_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/wifi/saved", esp_idf_svc::http::Method::Get, move |req| {
+        let networks = match crate::config::load_networks(&wifi_list_nvs) {
+            Ok(list) => list,
+            Err(e) => {
+                warn!("Failed to read saved networks: {:#}", e);
+                return send_error(req, 500, "NVS read failed");
+            }
+        };
+
+        let mut response = req.into_ok_response()?;
+        let mut out = ChunkWriter::new(&mut response);
+        let _ = write_saved_networks_json(&mut out, &networks);
+        out.finish()
+    })?;
+
+    let wifi_save_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/wifi/saved", esp_idf_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 320];
+        let body_str = match read_body(&mut req, &mut buf) {
+            Ok(s) => s,
+            Err(e) => return send_body_error(req, e),
+        };
+
+        let ssid = form_value(body_str, "ssid").and_then(url_decode::<32>);
+        let password = form_value(body_str, "password").and_then(url_decode::<64>);
+        let (Some(ssid), Some(password)) = (ssid, password) else {
+            return send_error(req, 400, "Expected ssid=...&password=...");
+        };
+
+        let result = crate::network::WiFiCredentials::new(ssid.to_string(), password.to_string())
+            .and_then(|creds| crate::network::save_credentials(&wifi_save_nvs, &creds));
+        if let Err(e) = result {
+            warn!("Failed to save network {}: {}", ssid, e);
+            let mut msg = heapless::String::<96>::new();
+            write!(msg, "{}", e).ok();
+            return send_error(req, e.http_status(), &msg);
+        }
+
+        info!("WiFi network saved: {}", ssid);
+        let mut response = req.into_ok_response()?;
+        response.write_all(b"{\"status\":\"ok\",\"restart_required\":true}")?;
+        Ok(())
+    })?;
+
+    let wifi_forget_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/wifi/saved", esp_idf_svc::http::Method::Delete, move |req| {
+        let Some(ssid) = query_value(req.uri(), "ssid").and_then(url_decode::<32>) else {
+            return send_error(req, 400, "Expected ?ssid=...");
+        };
+
+        match crate::config::remove_network(&wifi_forget_nvs, &ssid) {
+            Ok(true) => {
+                info!("WiFi network forgotten: {}", ssid);
+                let mut response = req.into_ok_response()?;
+                response.write_all(b"{\"status\":\"ok\"}")?;
+                Ok(())
+            }
+            Ok(false) => send_error(req, 404, "Network not saved"),
+            Err(e) => {
+                warn!("Failed to forget network: {:#}", e);
+                send_error(req, 500, "NVS write failed")
+            }
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/nvs/dump", esp_idf_svc::http::Method::Get, move |req| {
+        info!("NVS dump requested");
+
+        let mut response = req.into_ok_response()?;
+        let mut out = ChunkWriter::new(&mut response);
+        let _ = crate::config::dump_json(&nvs, &mut out);
+        out.finish()
+    })?;
+
+    let api_nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/api", esp_idf_svc::http::Method::Get, move |req| {
+        info!("API description requested");
+
+        // Proxy báo tiền tố qua header thì dùng header, không thì theo cấu hình
+        let configured = crate::config::get_str::<{ crate::config::MAX_BASE_PATH_LEN }>(&api_nvs, crate::config::KEY_BASE_PATH);
+        let base: heapless::String<{ crate::config::MAX_BASE_PATH_LEN }> = req
+            .header("X-Forwarded-Prefix")
+            .and_then(crate::config::normalize_base_path)
+            .or_else(|| configured.as_deref().and_then(crate::config::normalize_base_path))
+            .and_then(|p| heapless::String::try_from(p).ok())
+            .unwrap_or_default();
+
+        let mut response = req.into_ok_response()?;
+
+        // Gửi từng phần (chunked) để không phải dựng cả JSON trong RAM
+        let mut out = ChunkWriter::new(&mut response);
+        let _ = write_api_json(&mut out, &base);
+        out.finish()
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/effects", esp_idf_svc::http::Method::Get, |req| {
+        let only = match query_value(req.uri(), "name") {
+            None => None,
+            Some(name) => match effect_from_name(name) {
+                Some((_, name)) => Some(name),
+                None => return send_error(req, 404, "Unknown effect"),
+            },
+        };
+
+        let mut response = req.into_ok_response()?;
+        let mut out = ChunkWriter::new(&mut response);
+        let _ = write_effects_json(&mut out, only);
+        out.finish()
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/led", esp_idf_svc::http::Method::Options, |req| {
+        let mut response = req.into_ok_response()?;
+        response.write_all(b"")?;
+        Ok(())
+    })?;
+
+    info!("✅ HTTP server configured successfully");
+    Ok(server)
+}
+
+/// `fmt::Write` ghi thẳng ra response theo từng khối nhỏ, để response lớn
+/// (danh sách, export) không cần dựng cả chuỗi trong RAM.
+/// Lỗi ghi được giữ lại và trả về ở `finish()`.
+pub struct ChunkWriter<'w, W: Write> {
+    out: &'w mut W,
+    buf: heapless::String<256>,
+    failed: bool,
+}
+
+impl<'w, W: Write> ChunkWriter<'w, W> {
+    pub fn new(out: &'w mut W) -> Self {
+        Self { out, buf: heapless::String::new(), failed: false }
+    }
+
+    fn flush_buf(&mut self) -> core::fmt::Result {
+        if !self.buf.is_empty() {
+            if self.out.write_all(self.buf.as_bytes()).is_err() {
+                self.failed = true;
+                return Err(core::fmt::Error);
+            }
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Gửi phần còn lại trong buffer
+    pub fn finish(mut self) -> Result<()> {
+        if self.flush_buf().is_err() || self.failed {
+            anyhow::bail!("Failed to write response");
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> FmtWrite for ChunkWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if self.failed {
+            return Err(core::fmt::Error);
+        }
+        if self.buf.push_str(s).is_ok() {
+            return Ok(());
+        }
+
+        self.flush_buf()?;
+        if self.buf.push_str(s).is_err() {
+            // Lớn hơn cả buffer → ghi thẳng
+            if self.out.write_all(s.as_bytes()).is_err() {
+                self.failed = true;
+                return Err(core::fmt::Error);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ghi mảng JSON `[a,b,...]`, mỗi phần tử do `f` ghi
+pub fn write_json_list<O, T>(
+    out: &mut O,
+    items: impl IntoIterator<Item = T>,
+    mut f: impl FnMut(&mut O, T) -> core::fmt::Result,
+) -> core::fmt::Result
+where
+    O: FmtWrite,
+{
+    out.write_char('[')?;
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        f(out, item)?;
+    }
+    out.write_char(']')
+}
+
+/// `{"routes":[...],"effects":[...]}` cho /api. `base` (tiền tố reverse proxy) được
+/// thêm vào trước mỗi path và trả trong `"base"`; rỗng thì giữ nguyên như truy cập trực tiếp.
+fn write_api_json(out: &mut impl FmtWrite, base: &str) -> core::fmt::Result {
+    out.write_char('{')?;
+    if !base.is_empty() {
+        write!(out, "\"base\":\"{}\",", base)?;
+    }
+    out.write_str("\"routes\":")?;
+    write_json_list(out, ROUTES.iter(), |out, route| {
+        write!(out, "{{\"path\":\"{}{}\",\"method\":\"{}\",\"description\":\"{}\",\"params\":",
+            base, route.path, route.method, route.description)?;
+        write_json_list(out, route.params.iter(), |out, param| {
+            write!(out, "{{\"name\":\"{}\",\"type\":\"{}\",\"description\":\"{}\"",
+                param.name, param.kind, param.description)?;
+            if let Some((min, max)) = param.range {
+                write!(out, ",\"min\":{},\"max\":{}", min, max)?;
+            }
+            out.write_char('}')
+        })?;
+        out.write_char('}')
+    })?;
+
+    out.write_str(",\"effects\":")?;
+    write_json_list(out, EFFECT_REGISTRY.iter(), |out, (name, _)| write!(out, "\"{}\"", name))?;
+    out.write_char('}')
+}
+
+/// `{"effects":[{"name":..,"audio":..,"params":[{"name","type","description","min","max","values"}]}]}`,
+/// `only` = chỉ một hiệu ứng. `sat` áp cho mọi hiệu ứng nên không nằm trong danh sách.
+fn write_effects_json(out: &mut impl FmtWrite, only: Option<&str>) -> core::fmt::Result {
+    let effects = EFFECT_REGISTRY.iter().filter(|(name, _)| only.is_none() || only == Some(*name));
+    out.write_str("{\"effects\":")?;
+    write_json_list(out, effects, |out, (name, effect)| {
+        write!(out, "{{\"name\":\"{}\",\"audio\":{},\"params\":", name, effect.is_audio_reactive())?;
+        write_json_list(out, effect.params().iter(), |out, param| {
+            write!(out, "{{\"name\":\"{}\",\"type\":\"{}\",\"description\":\"{}\"",
+                param.name, param.kind, param.description)?;
+            if let Some((min, max)) = param.range {
+                write!(out, ",\"min\":{},\"max\":{}", min, max)?;
+            }
+            if !param.values.is_empty() {
+                out.write_str(",\"values\":")?;
+                write_json_list(out, param.values.iter(), |out, v| write!(out, "\"{}\"", v))?;
+            }
+            out.write_char('}')
+        })?;
+        out.write_char('}')
+    })?;
+    out.write_char('}')
+}
+
+/// `{"scenes":[{"name","mode","brightness","speed","color","range","sat","beatsync","params"}],"count":N,"max":N}`
+fn write_scene_list_json(out: &mut impl FmtWrite) -> core::fmt::Result {
+    let scenes = crate::scenes::list();
+    out.write_str("{\"scenes\":")?;
+    write_json_list(out, scenes.iter(), |out, scene| {
+        let c = scene.color;
+        write!(
+            out,
+            "{{\"name\":\"{}\",\"mode\":\"{}\",\"brightness\":{},\"speed\":{},\"color\":\"{:02X}{:02X}{:02X}\",\"range\":\"",
+            scene.name, effect_name(&scene.effect), scene.brightness, scene.speed, c.r, c.g, c.b
+        )?;
+        scene.write_range(out)?;
+        write!(out, "\",\"sat\":{},\"beatsync\":{},\"params\":", scene.saturation, scene.beat_sync)?;
+        let mut params = heapless::String::<256>::new();
+        scene.write_params(&mut params)?;
+        crate::config::write_json_str(out, &params)?;
+        out.write_char('}')
+    })?;
+    write!(out, ",\"count\":{},\"max\":{}}}", scenes.len(), crate::scenes::MAX_SCENES)
+}
+
+/// `{"palettes":[...],"builtin":[...],"count":N,"max":N}`
+fn write_palette_list_json(out: &mut impl FmtWrite) -> core::fmt::Result {
+    let palettes = crate::palettes::list();
+    out.write_str("{\"palettes\":")?;
+    write_json_list(out, palettes.iter(), |out, palette| {
+        write!(out, "{{\"name\":\"{}\",\"stops\":", palette.name)?;
+        write_json_list(out, palette.stops.iter(), |out, stop| {
+            write!(out, "\"{:02X}{:02X}{:02X}\"", stop.r, stop.g, stop.b)
+        })?;
+        out.write_char('}')
+    })?;
+
+    out.write_str(",\"builtin\":")?;
+    write_json_list(out, crate::palettes::builtin_names(), |out, name| write!(out, "\"{}\"", name))?;
+    write!(out, ",\"count\":{},\"max\":{}}}", palettes.len(), crate::palettes::MAX_PALETTES)
+}
+
+/// `{"effects":["rainbow",...],"count":N,"max":N}`
+fn write_rotation_json(out: &mut impl FmtWrite, rotation: &crate::rotation::Rotation) -> core::fmt::Result {
+    out.write_str("{\"effects\":")?;
+    write_json_list(out, rotation.iter(), |out, effect| write!(out, "\"{}\"", effect_name(effect)))?;
+    write!(out, ",\"count\":{},\"max\":{}}}", rotation.len(), crate::rotation::MAX_ROTATION)
+}
+
+/// `{"colors":["FF0000",...],"count":N,"max":N}`
+fn write_quick_colors_json(out: &mut impl FmtWrite, colors: &crate::quick_colors::QuickColors) -> core::fmt::Result {
+    out.write_str("{\"colors\":")?;
+    write_json_list(out, colors.iter(), |out, c| write!(out, "\"{:02X}{:02X}{:02X}\"", c.r, c.g, c.b))?;
+    write!(out, ",\"count\":{},\"max\":{}}}", colors.len(), crate::quick_colors::MAX_QUICK_COLORS)
+}
+
+/// `{"networks":["ssid",...],"count":N,"max":N}`
+fn write_saved_networks_json(out: &mut impl FmtWrite, networks: &[crate::config::SavedNetwork]) -> core::fmt::Result {
+    out.write_str("{\"networks\":")?;
+    write_json_list(out, networks.iter(), |out, n| crate::config::write_json_str(out, &n.ssid))?;
+    write!(out, ",\"count\":{},\"max\":{}}}", networks.len(), crate::config::MAX_SAVED_NETWORKS)
+}
+
+/// Lấy giá trị của `key` trong body dạng `key=value&key=value`
+pub fn form_value<'a>(body: &'a str, key: &str) -> Option<&'a str> {
+    body.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+/// Lấy giá trị của `key` trong query string của URI (`/path?key=value`)
+pub fn query_value<'a>(uri: &'a str, key: &str) -> Option<&'a str> {
+    let (_, query) = uri.split_once('?')?;
+    form_value(query, key)
+}
+
+/// Giải mã form-urlencoded (`%XX` và `+`), None nếu sai định dạng hoặc quá dài
+pub fn url_decode<const N: usize>(s: &str) -> Option<heapless::String<N>> {
+    let mut out = heapless::String::<N>::new();
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    let mut decoded = heapless::Vec::<u8, N>::new();
+
+    while i < bytes.len() {
+        let b = match bytes[i] {
+            b'%' => {
+                let hex = s.get(i + 1..i + 3)?;
+                i += 2;
+                u8::from_str_radix(hex, 16).ok()?
+            }
+            b'+' => b' ',
+            b => b,
+        };
+        decoded.push(b).ok()?;
+        i += 1;
+    }
+
+    out.push_str(std::str::from_utf8(&decoded).ok()?).ok()?;
+    Some(out)
+}
+
+/// "1"/"true"/"on" → true, "0"/"false"/"off" → false
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "true" | "on" => Some(true),
+        "0" | "false" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Gửi 1 command từ HTTP handler tới LED task, false nếu queue đầy hoặc đang bận
+pub fn send_command(producer: &Mutex<CommandProducer>, cmd: LedCommand) -> bool {
+    send_command_from(producer, CommandSource::Http, cmd)
+}
+
+/// Như `send_command` nhưng ghi rõ nguồn của command
+pub fn send_command_from(producer: &Mutex<CommandProducer>, source: CommandSource, cmd: LedCommand) -> bool {
+    let Ok(mut guard) = producer.try_lock() else {
+        return false;
+    };
+    if guard.enqueue(QueuedCommand { source, cmd }).is_err() {
+        return false;
+    }
+    record_source(source);
+    true
+}
+
+/// Gửi nhiều command liền nhau: hoặc vào queue hết, hoặc không cái nào
+pub fn send_commands_from<const N: usize>(
+    producer: &Mutex<CommandProducer>,
+    source: CommandSource,
+    cmds: HeaplessVec<LedCommand, N>,
+) -> bool {
+    let Ok(mut guard) = producer.try_lock() else {
+        return false;
+    };
+    if guard.capacity() - guard.len() < cmds.len() {
+        return false;
+    }
+    for cmd in cmds {
+        if guard.enqueue(QueuedCommand { source, cmd }).is_err() {
+            return false;
+        }
+    }
+    record_source(source);
+    true
+}
+
+/// Body dạng `value=N` hoặc chỉ `N`
+fn single_value(body: &str) -> Option<&str> {
+    form_value(body, "value").or_else(|| Some(body.trim()).filter(|b| !b.contains('=')))
+}
+
+/// Trả `{"<key>":<value>}`
+fn send_single_value(req: Request<&mut EspHttpConnection<'_>>, key: &str, value: u32) -> Result<()> {
+    let mut resp_str = heapless::String::<48>::new();
+    write!(resp_str, "{{\"{}\":{}}}", key, value).ok();
+
+    let mut response = req.into_ok_response()?;
+    response.write_all(resp_str.as_bytes())?;
+    Ok(())
+}
+
+/// Trả lỗi dạng `{"status":"error","message":...}`
+pub fn send_error(req: Request<&mut EspHttpConnection<'_>>, status: u16, message: &str) -> Result<()> {
+    let mut resp_str = heapless::String::<128>::new();
+    write!(resp_str, "{{\"status\":\"error\",\"message\":\"{}\"}}", message).ok();
+
+    let mut response = req.into_status_response(status)?;
+    response.write_all(resp_str.as_bytes())?;
+    Ok(())
+}
+
+pub enum BodyError {
+    Empty,
+    /// Content-Length vượt quá giới hạn `max` byte của endpoint
+    TooLarge { max: usize },
+    InvalidUtf8,
+    Io,
+    /// Client ngừng gửi giữa chừng hoặc gửi quá chậm
+    Timeout,
+}
+
+/// Đọc đủ `buf` từ body. Một lần đọc chờ tối đa HTTP_SOCKET_TIMEOUT (timeout socket),
+/// cả body tối đa BODY_READ_DEADLINE; quá hạn → Timeout thay vì treo handler.
+pub fn read_exact_body<R: Read>(req: &mut R, buf: &mut [u8]) -> Result<(), BodyError> {
+    let started = Instant::now();
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        let before = Instant::now();
+        match req.read(&mut buf[filled..]) {
+            Ok(0) => return Err(BodyError::Io), // Client đóng kết nối
+            Ok(n) => filled += n,
+            // Lỗi sau khi chờ gần hết timeout socket = client ngừng gửi
+            Err(_) if before.elapsed() >= HTTP_SOCKET_TIMEOUT / 2 => return Err(BodyError::Timeout),
+            Err(_) => return Err(BodyError::Io),
+        }
+        if filled < buf.len() && started.elapsed() >= BODY_READ_DEADLINE {
+            return Err(BodyError::Timeout);
+        }
+    }
+    Ok(())
+}
+
+/// Đọc toàn bộ body vào `buf`. Body lớn hơn `buf` bị từ chối (không cắt bớt).
+pub fn read_body<'b, R>(req: &mut R, buf: &'b mut [u8]) -> Result<&'b str, BodyError>
+where
+    R: Headers + Read,
+{
+    let len = req.content_len().unwrap_or(0) as usize;
+
+    if len == 0 {
+        return Err(BodyError::Empty);
+    }
+    if len > buf.len() {
+        return Err(BodyError::TooLarge { max: buf.len() });
+    }
+
+    read_exact_body(req, &mut buf[..len])?;
+
+    std::str::from_utf8(&buf[..len]).map_err(|_| BodyError::InvalidUtf8)
+}
+
+/// Trả lỗi tương ứng với BodyError (400 / 408 / 413)
+pub fn send_body_error(req: Request<&mut EspHttpConnection<'_>>, err: BodyError) -> Result<()> {
+    let (status, message) = match err {
+        BodyError::Empty => (400, "Empty body"),
+        BodyError::TooLarge { .. } => (413, "Payload too large"),
+        BodyError::InvalidUtf8 => (400, "Invalid UTF-8"),
+        BodyError::Io => (400, "Failed to read body"),
+        BodyError::Timeout => (408, "Request body timed out"),
+    };
+    warn!("Rejected request body ({})", status);
+
+    // 413 kèm giới hạn của endpoint để client biết body phải ngắn lại bao nhiêu
+    let mut detail = heapless::String::<48>::new();
+    match err {
+        BodyError::TooLarge { max } => write!(detail, "{} (max {} bytes)", message, max),
+        _ => detail.write_str(message),
+    }.ok();
+    send_error(req, status, &detail)
+}
+
+/// "H,S,V" (H 0-360, S/V 0-100) → RGB
+fn parse_hsv(s: &str) -> Option<(u8, u8, u8)> {
+    let mut parts = s.split(',');
+    let h = parts.next()?.trim().parse::<u16>().ok()?;
+    let sat = parts.next()?.trim().parse::<u8>().ok()?;
+    let val = parts.next()?.trim().parse::<u8>().ok()?;
+
+    if parts.next().is_some() || h > 360 || sat > 100 || val > 100 {
+        return None;
+    }
+
+    let rgb = crate::effect::hsv_to_rgb(h as f32, sat as f32 / 100.0, val as f32 / 100.0);
+    Some((rgb.r, rgb.g, rgb.b))
+}
+
+/// Đảo trạng thái bật/tắt theo snapshot hiện tại → (command, độ sáng mới %).
+/// Tắt = brightness 0; bật lại dùng mức controller nhớ trước lần tắt gần nhất,
+/// dù tắt bằng cách nào (mặc định 100%).
+pub(crate) fn power_toggle_command() -> (LedCommand, u8) {
+    let Some(state) = crate::controller::state_snapshot() else {
+        return (LedCommand::SetBrightness(1.0), BRIGHTNESS_MAX);
+    };
+    if state.brightness > 0 {
+        (LedCommand::SetBrightness(0.0), 0)
+    } else {
+        // Mức nhớ làm tròn về 0% thì bật hẳn lên, không thì toggle không có tác dụng
+        let restore = if state.restore_brightness > 0 { state.restore_brightness } else { BRIGHTNESS_MAX };
+        (LedCommand::SetBrightness(restore as f32 / BRIGHTNESS_MAX as f32), restore)
+    }
+}
+
+/// `mode=...` → command. "off" không phải hiệu ứng: tắt đèn (brightness 0), giữ nguyên hiệu ứng.
+pub(crate) fn mode_command(name: &str) -> Option<(LedCommand, &'static str)> {
+    if name == "off" {
+        return Some((LedCommand::SetBrightness(0.0), "off"));
+    }
+    effect_from_name(name).map(|(effect, mode_str)| (LedCommand::SetEffect(effect), mode_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(source: CommandSource, cmd: LedCommand) -> QueuedCommand {
+        QueuedCommand { source, cmd }
+    }
+
+    #[test]
+    fn coalesce_two_clients_interleaved_keeps_last_of_each_kind() {
+        // Client A và B gửi xen kẽ màu/độ sáng trong cùng một lượt đọc queue
+        let mut pending = HeaplessVec::<QueuedCommand, 8>::new();
+        pending.push(queued(CommandSource::Http, LedCommand::SetColor(255, 0, 0))).ok();
+        pending.push(queued(CommandSource::Button, LedCommand::SetColor(0, 255, 0))).ok();
+        pending.push(queued(CommandSource::Http, LedCommand::SetBrightness(0.2))).ok();
+        pending.push(queued(CommandSource::Button, LedCommand::SetBrightness(0.8))).ok();
+        pending.push(queued(CommandSource::Http, LedCommand::SetColor(0, 0, 255))).ok();
+
+        coalesce_commands(&mut pending);
+
+        assert_eq!(pending.len(), 2);
+        assert!(matches!(pending[0].cmd, LedCommand::SetBrightness(v) if v == 0.8));
+        assert_eq!(pending[0].source, CommandSource::Button);
+        assert!(matches!(pending[1].cmd, LedCommand::SetColor(0, 0, 255)));
+        assert_eq!(pending[1].source, CommandSource::Http);
+    }
+
+    #[test]
+    fn coalesce_keeps_every_non_last_write_wins_command() {
+        let mut pending = HeaplessVec::<QueuedCommand, 8>::new();
+        pending.push(queued(CommandSource::Http, LedCommand::Identify { count: 1 })).ok();
+        pending.push(queued(CommandSource::Button, LedCommand::SetSpeed(10))).ok();
+        pending.push(queued(CommandSource::Http, LedCommand::Identify { count: 2 })).ok();
+
+        coalesce_commands(&mut pending);
+
+        assert_eq!(pending.len(), 3);
+        assert!(matches!(pending[0].cmd, LedCommand::Identify { count: 1 }));
+        assert!(matches!(pending[1].cmd, LedCommand::SetSpeed(10)));
+        assert!(matches!(pending[2].cmd, LedCommand::Identify { count: 2 }));
+    }
+}
@@ -1,5 +1,6 @@
 
 use heapless::spsc::{Queue, Consumer};
+use heapless::Vec as HeaplessVec;
 use esp_idf_hal::{
     cpu::Core,
     delay::FreeRtos,
@@ -15,59 +16,232 @@ use esp_idf_svc::{
 use log::info;
 use smart_leds::RGB8;
 use controller::LedController;
-use ws2812_esp32_rmt_driver::Ws2812Esp32RmtDriver;
 
-use std::{sync::{Arc, Mutex, RwLock}, thread};
+use std::{sync::{Arc, Mutex, RwLock, atomic::{AtomicBool, Ordering}}, thread};
 use crate::http::LedCommand;
-use crate::audio::AudioData;
 
 mod wifi;
+mod network;
 mod controller;
 mod http;
 mod audio;
 mod effect;
+mod config;
+mod palettes;
+mod ambient;
+mod output;
+mod button;
+mod color;
+mod rpc;
+mod rotation;
+mod command_log;
+mod quick_colors;
+mod pattern;
+mod scenes;
 
-static mut Q: Queue<LedCommand, 8> = Queue::new();
+// Đủ chỗ cho toàn bộ command cấu hình đẩy vào lúc boot (queue giữ được N-1 phần tử)
+static mut Q: Queue<http::QueuedCommand, { http::COMMAND_QUEUE_SIZE }> = Queue::new();
+
+const NUM_LEDS: usize = 144;
+
+// Boot animation: tổng thời gian và số frame cố định. delay_ms làm tròn theo tick
+// FreeRTOS (10ms) nên không chờ theo từng LED mà canh mỗi frame theo esp_timer.
+const BOOT_ANIMATION_US: u64 = 750_000;
+const BOOT_ANIMATION_FRAMES: usize = 36;
+
+/// Một đoạn xanh lá chạy dọc dải LED (~0.75s) để xác nhận firmware đã boot,
+/// dây data và số LED đúng. Chạy trước WiFi nên không phụ thuộc mạng.
+fn boot_animation(
+    chip: output::ChipType,
+    channel: &mut esp_idf_hal::rmt::CHANNEL0,
+    pin: &mut esp_idf_hal::gpio::Gpio18,
+) -> Result<(), anyhow::Error> {
+    let mut output = output::open(chip, channel, pin)?;
+    let mut frame = vec![0u8; NUM_LEDS * 3];
+
+    let now_us = || unsafe { esp_idf_sys::esp_timer_get_time() }.max(0) as u64;
+    let start = now_us();
+    let mut lit = 0;
+
+    for f in 1..=BOOT_ANIMATION_FRAMES {
+        // Mỗi frame sáng đoạn LED mới, nên LED nào cũng sáng ít nhất một frame
+        let end = f * NUM_LEDS / BOOT_ANIMATION_FRAMES;
+        frame.fill(0);
+        for i in lit..end {
+            frame[i * 3] = 64; // GRB: byte đầu là G
+        }
+        lit = end;
+        output.write(&frame)?;
+
+        // Chờ tới mốc của frame này tính từ lúc bắt đầu: sai số tick không cộng dồn
+        let deadline = start + BOOT_ANIMATION_US * f as u64 / BOOT_ANIMATION_FRAMES as u64;
+        let now = now_us();
+        if deadline > now {
+            FreeRtos::delay_ms(((deadline - now) / 1000) as u32);
+        }
+    }
+
+    frame.fill(0);
+    output.write(&frame)?;
+    Ok(())
+}
+
+fn apply_command(controller: &mut LedController<'_>, cmd: LedCommand) {
+    controller.stop_demo();
+
+    // Command từ user (không phải sync/hẹn giờ) có thể đếm lại hoặc hủy sleep timer
+    if !matches!(cmd, LedCommand::Sync { .. } | LedCommand::SetSleepTimer { .. }) {
+        controller.on_manual_command();
+    }
+
+    match cmd {
+        http::LedCommand::SetEffect(effect) => {
+            info!("Received effect command: {:?}", effect);
+            controller.set_effect(effect);
+        }
+        http::LedCommand::SetBrightness(brightness) => {
+            info!("Received brightness command: {}", brightness);
+            controller.set_brightness(brightness);
+        }
+        http::LedCommand::SetColor(r, g, b) => {
+            info!("Received color command: R:{} G:{} B:{}", r, g, b);
+            controller.set_color(RGB8 { r, g, b });
+        }
+        http::LedCommand::SetSpeed(speed) => {
+            info!("Received speed command: {}", speed);
+            controller.set_speed(speed);
+        }
+        http::LedCommand::SetParam(key, value) => {
+            info!("Received param command: {}={}", key, value);
+            controller.set_param(&key, &value);
+        }
+        http::LedCommand::SetBeatSync(enabled) => {
+            info!("Received beat sync command: {}", enabled);
+            controller.set_beat_sync(enabled);
+        }
+        http::LedCommand::Sync { reference_us, restart } => {
+            controller.apply_sync(reference_us, restart);
+        }
+        http::LedCommand::Identify { count } => {
+            info!("Received identify command");
+            controller.identify(count);
+        }
+        http::LedCommand::SetMinBrightness(level) => {
+            info!("Received min brightness command: {}", level);
+            controller.set_min_brightness(level);
+        }
+        http::LedCommand::SetPowerSave(enabled) => {
+            controller.set_power_save(enabled);
+        }
+        http::LedCommand::SetInterpolation(enabled) => {
+            controller.set_interpolation(enabled);
+        }
+        http::LedCommand::SetHuePreserve(enabled) => {
+            controller.set_hue_preserve(enabled);
+        }
+        http::LedCommand::SetSaturation(percent) => {
+            controller.set_saturation(percent);
+        }
+        http::LedCommand::SetConnectFlash(color) => {
+            controller.set_connect_flash(color.map(|(r, g, b)| RGB8 { r, g, b }));
+        }
+        http::LedCommand::SetPartyMode(settings) => {
+            controller.set_party_mode(settings);
+        }
+        http::LedCommand::SetColorCycle(period_s) => {
+            controller.set_color_cycle(period_s as u64 * 1_000_000);
+        }
+        http::LedCommand::SetSleepTimer { minutes, policy } => {
+            controller.set_sleep_timer(minutes, policy);
+        }
+        http::LedCommand::SetSkipPixels(pixels) => {
+            controller.set_skip_pixels(&pixels);
+        }
+        http::LedCommand::SetRange(range) => {
+            controller.set_range(range);
+        }
+        http::LedCommand::SetOfflineBehavior { behavior, minutes } => {
+            controller.set_offline_behavior(behavior, minutes);
+        }
+        http::LedCommand::SetIdleTimeout(settings) => {
+            controller.set_idle_timeout(settings);
+        }
+        http::LedCommand::SetProgress(fraction) => {
+            controller.set_progress(fraction);
+        }
+        http::LedCommand::SetColorCarryOver(mode) => {
+            controller.set_color_carry_over(mode);
+        }
+        http::LedCommand::SetThermalProtection(minutes) => {
+            controller.set_thermal_protection(minutes);
+        }
+        http::LedCommand::SetAudioMode(enabled) => {
+            info!("Received audio mode command: {}", enabled);
+            controller.set_audio_mode(enabled);
+        }
+    }
+}
 
 fn led_task(
+    chip: output::ChipType,
     channel: esp_idf_hal::rmt::CHANNEL0,
     pin: esp_idf_hal::gpio::Gpio18,
-    mut consumer: Consumer<'static, LedCommand>, 
-    audio_data: Arc<Mutex<audio::AudioData>>,
+    mut consumer: Consumer<'static, http::QueuedCommand>,
+    audio_data: Arc<audio::SharedAudio>,
+    ambient: Arc<ambient::AmbientLight>,
+    mut demo: bool,
+    stop: Arc<AtomicBool>,
 ) -> Result<(), anyhow::Error> {
     // RMT on core 1
-    let ws2812 = Ws2812Esp32RmtDriver::new(channel, pin)?;
-    let mut controller = LedController::new(ws2812, 144);
+    let output = output::open(chip, channel, pin)?;
+    let mut controller = LedController::new(output, NUM_LEDS);
     controller.set_audio_data(audio_data);
-    info!("RMT driver initialized on core {:?}", esp_idf_svc::hal::cpu::core());
-
-
-    loop {
-        // Xử lý commands từ HTTP
-        
-        if let Some(cmd) = consumer.dequeue() {
-            match cmd {
-                http::LedCommand::SetEffect(effect) => {
-                    info!("Received effect command: {:?}", effect);
-                    controller.set_effect(effect);
-                }
-                http::LedCommand::SetBrightness(brightness) => {
-                    info!("Received brightness command: {}", brightness);
-                    controller.set_brightness(brightness);
-                }
-                http::LedCommand::SetColor(r, g, b) => {
-                    info!("Received color command: R:{} G:{} B:{}", r, g, b);
-                    controller.set_color(RGB8 { r, g, b });
-                }
-                http::LedCommand::SetSpeed(speed) => {
-                    info!("Received speed command: {}", speed);
-                    controller.set_speed(speed);
-                }
+    controller.set_ambient_light(ambient);
+    info!("RMT driver ({}) initialized on core {:?}", chip.as_str(), esp_idf_svc::hal::cpu::core());
+
+
+    // Đọc được cả queue (N-1) cộng màu live trong một lượt: scene / RPC batch
+    // được áp cùng một frame, không bị tách làm hai
+    let mut pending = HeaplessVec::<http::QueuedCommand, { http::COMMAND_QUEUE_SIZE }>::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        // Màu live từ /led/color đi ngoài queue, chỉ màu mới nhất. Lấy trước khi
+        // đọc queue: SetColor xếp hàng sau đó mới hơn nên phải thắng khi gộp
+        if let Some((r, g, b)) = http::take_live_color() {
+            let cmd = http::LedCommand::SetColor(r, g, b);
+            pending.push(http::QueuedCommand { source: http::CommandSource::Http, cmd }).ok();
+        }
+        // Đọc hết commands từ HTTP rồi gộp lại (last-write-wins)
+        while !pending.is_full() {
+            let Some(cmd) = consumer.dequeue() else { break; };
+            pending.push(cmd).ok();
+        }
+        http::coalesce_commands(&mut pending);
+
+        if !pending.is_empty() {
+            controller.begin_batch(pending.iter().any(|q| matches!(q.cmd, http::LedCommand::SetColor(..))));
+            for queued in core::mem::take(&mut pending) {
+                command_log::record(queued.source, &queued.cmd);
+                apply_command(&mut controller, queued.cmd);
             }
+            controller.begin_batch(false);
+            controller.publish_state();
+        }
+
+        // Command cấu hình lúc boot có thể nhiều hơn một lượt đọc, và apply_command
+        // dừng demo → chỉ bắt đầu demo khi queue đã trống
+        if demo && !consumer.ready() {
+            demo = false;
+            controller.start_demo();
         }
         controller.update();
         FreeRtos::delay_ms(1);
     }
+
+    // Tắt dải LED để không bị treo ở frame cuối khi restart
+    controller.blank();
+    info!("LED task stopped");
+    Ok(())
 }
 
 fn audio_task(
@@ -75,13 +249,15 @@ fn audio_task(
     sck: esp_idf_hal::gpio::Gpio33,
     ws: esp_idf_hal::gpio::Gpio25,
     sd: esp_idf_hal::gpio::Gpio32,
-    audio_data: Arc<Mutex<audio::AudioData>>,
+    audio_data: Arc<audio::SharedAudio>,
+    stop: Arc<AtomicBool>,
 ) -> Result<(), anyhow::Error> {
     info!("Audio task started on core {:?}", esp_idf_svc::hal::cpu::core());
     
     // Use blocking version for FreeRTOS thread
-    audio::audio_processing_blocking(i2s, sck, ws, sd, audio_data)?;
+    audio::audio_processing_blocking(i2s, sck, ws, sd, &audio_data, &stop)?;
     
+    info!("Audio task stopped");
     Ok(())
 }
 
@@ -94,11 +270,42 @@ fn main() -> anyhow::Result<()> {
     let sysloop = EspSystemEventLoop::take().unwrap();
     let timer_service = EspTaskTimerService::new().unwrap();
     let nvs = EspDefaultNvsPartition::take().unwrap();
-    let _wifi = wifi::wifi(peripherals.modem, sysloop, Some(nvs), timer_service)?;
+    // Nâng layout cũ trước khi bất cứ module nào đọc NVS
+    match config::migrate(&nvs) {
+        Ok(from) if from != config::CONFIG_VERSION => info!("Config upgraded from v{} to v{}", from, config::CONFIG_VERSION),
+        Ok(_) => {}
+        Err(e) => log::warn!("Config migration failed: {:#}", e),
+    }
+    palettes::init(&nvs);
+    rotation::init(&nvs);
+    quick_colors::init(&nvs);
+    pattern::init(&nvs);
+    scenes::init(&nvs);
+    command_log::set_enabled(config::get_u8(&nvs, config::KEY_COMMAND_LOG, 1) != 0);
+    audio::set_soft_clip(config::get_u8(&nvs, config::KEY_SOFT_CLIP, 1) != 0);
+
+    match config::record_boot(&nvs) {
+        Ok(count) => info!("Boot #{}", count),
+        Err(e) => log::warn!("Failed to update boot counter: {:#}", e),
+    }
 
     // Get pins for LED strip
-    let channel = peripherals.rmt.channel0;
-    let led_pin = peripherals.pins.gpio18;
+    let mut channel = peripherals.rmt.channel0;
+    let mut led_pin = peripherals.pins.gpio18;
+
+    // Timing theo loại chip (WS2812 mặc định), đổi qua /config/chip rồi restart
+    let chip = output::ChipType::from_u8(config::get_u8(&nvs, config::KEY_CHIP_TYPE, 0))
+        .unwrap_or(output::ChipType::Ws2812);
+
+    if config::get_u8(&nvs, config::KEY_BOOT_ANIMATION, 1) != 0 {
+        if let Err(e) = boot_animation(chip, &mut channel, &mut led_pin) {
+            log::warn!("Boot animation failed: {:?}", e);
+        }
+    }
+
+    // Thử các mạng đã lưu theo thứ tự ưu tiên, không vào được thì phát AP
+    let mut wifi_manager = network::WiFiManager::new(peripherals.modem, sysloop, nvs.clone(), timer_service)?;
+    futures::executor::block_on(wifi_manager.start())?;
 
     // Get pins for I2S microphone (INMP441)
     let i2s = peripherals.i2s0;
@@ -109,12 +316,94 @@ fn main() -> anyhow::Result<()> {
     let (producer, consumer) = unsafe { Q.split() };
     let producer = Arc::new(Mutex::new(producer));
 
-    let audio_data = Arc::new(Mutex::new(audio::AudioData::default()));
+    // Cấu hình lưu trong NVS được đẩy vào queue trước khi LED task chạy
+    let min_brightness = config::get_u8(&nvs, config::KEY_MIN_BRIGHTNESS, 0);
+    if min_brightness > 0 {
+        http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetMinBrightness(min_brightness));
+    }
+    if config::get_u8(&nvs, config::KEY_POWER_SAVE, 0) != 0 {
+        http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetPowerSave(true));
+    }
+    if config::get_u8(&nvs, config::KEY_INTERPOLATION, 0) != 0 {
+        http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetInterpolation(true));
+    }
+    if config::get_u8(&nvs, config::KEY_HUE_PRESERVE, 0) != 0 {
+        http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetHuePreserve(true));
+    }
+    if let Some(skip) = config::get_str::<96>(&nvs, config::KEY_SKIP_PIXELS) {
+        match http::parse_skip_pixels(&skip, NUM_LEDS) {
+            Some(pixels) if !pixels.is_empty() => {
+                http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetSkipPixels(pixels));
+            }
+            Some(_) => {}
+            None => log::warn!("Ignoring invalid skip list in NVS: {}", skip),
+        }
+    }
+    let carry_over = controller::ColorCarryOver::from_u8(config::get_u8(&nvs, config::KEY_COLOR_CARRY, 0));
+    if carry_over != controller::ColorCarryOver::Preserve {
+        http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetColorCarryOver(carry_over));
+    }
+    if config::get_u8(&nvs, config::KEY_CONNECT_FLASH, 1) != 0 {
+        let color = config::get_str::<8>(&nvs, config::KEY_CONNECT_COLOR)
+            .and_then(|s| color::parse_hex_color(&s).ok())
+            .or(Some(http::CONNECT_FLASH_DEFAULT_COLOR));
+        http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetConnectFlash(color));
+    }
+    if let Some(mode) = config::get_str::<8>(&nvs, config::KEY_OFFLINE_MODE) {
+        let level = config::get_u8(&nvs, config::KEY_OFFLINE_LEVEL, http::OFFLINE_DEFAULT_LEVEL).min(100);
+        // Giá trị từ /config/import chưa qua kiểm tra range của endpoint
+        let minutes = config::get_u32(&nvs, config::KEY_OFFLINE_MIN, http::OFFLINE_DEFAULT_MIN as u32)
+            .clamp(1, http::SLEEP_TIMER_MAX_MIN as u32) as u16;
+        match controller::OfflineBehavior::parse(&mode, level) {
+            Some(controller::OfflineBehavior::KeepRunning) => {}
+            Some(behavior) => {
+                http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetOfflineBehavior { behavior, minutes });
+            }
+            None => log::warn!("Ignoring invalid offline mode in NVS: {}", mode),
+        }
+    }
+    // Giá trị từ /config/import chưa qua kiểm tra range của endpoint
+    let idle_ambient = config::get_u32(&nvs, config::KEY_IDLE_AMBIENT, 0).min(http::SLEEP_TIMER_MAX_MIN as u32) as u16;
+    let idle_off = config::get_u32(&nvs, config::KEY_IDLE_OFF, 0).min(http::SLEEP_TIMER_MAX_MIN as u32) as u16;
+    if idle_ambient > 0 || idle_off > 0 {
+        let effect = config::get_str::<16>(&nvs, config::KEY_IDLE_EFFECT)
+            .and_then(|name| effect::effect_from_name(&name))
+            .or_else(|| effect::effect_from_name(http::IDLE_DEFAULT_EFFECT))
+            .map(|(effect, _)| effect);
+        if let Some(effect) = effect {
+            let level = config::get_u8(&nvs, config::KEY_IDLE_LEVEL, http::IDLE_DEFAULT_LEVEL).clamp(1, 100);
+            let settings = controller::IdleSettings { ambient_minutes: idle_ambient, off_minutes: idle_off, effect, level };
+            http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetIdleTimeout(Some(settings)));
+        }
+    }
+    let saturation = config::get_u8(&nvs, config::KEY_SATURATION, 100);
+    if saturation < 100 {
+        http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetSaturation(saturation));
+    }
+    let thermal_min = config::get_u8(&nvs, config::KEY_THERMAL, 0);
+    if thermal_min > 0 {
+        let minutes = thermal_min.min(http::THERMAL_MAX_MIN) as u16;
+        http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetThermalProtection(Some(minutes)));
+    }
+    let color_cycle = config::get_u32(&nvs, config::KEY_COLOR_CYCLE, 0);
+    if color_cycle > 0 {
+        // Giá trị từ /config/import chưa qua kiểm tra range của endpoint
+        let period = color_cycle.clamp(http::COLOR_CYCLE_MIN_S, http::COLOR_CYCLE_MAX_S);
+        http::send_command_from(&producer, http::CommandSource::Boot, LedCommand::SetColorCycle(period));
+    }
+
+    let audio_data = Arc::new(audio::SharedAudio::new());
      let audio_data_for_led = audio_data.clone();   // Clone cho LED task
     let audio_data_for_audio = audio_data.clone(); // Clone cho audio task
 
+    // Cờ dừng các task trước khi restart (xem /restart)
+    let stop = Arc::new(AtomicBool::new(false));
+
+    // Cảm biến ánh sáng (tùy chọn) - factor = 255 nếu tắt hoặc không có cảm biến
+    let ambient = Arc::new(ambient::AmbientLight::from_nvs(&nvs));
+
     // Start HTTP server
-    let _server = http::start_http_server(producer.clone())?;
+    let _server = http::start_http_server(producer.clone(), nvs.clone(), audio_data.clone(), ambient.clone(), stop.clone())?;
     info!("HTTP server started successfully");
 
     // Thread spawn config for Core 1
@@ -128,8 +417,13 @@ fn main() -> anyhow::Result<()> {
 
     // Spawn LED thread on core 1
 
-    thread::spawn(move || {
-        if let Err(e) = led_task(channel, led_pin, consumer, audio_data_for_led) {
+    // Chưa lưu mạng WiFi nào (đang ở AP provisioning) → chạy demo để dải LED không tối đen
+    let demo = config::load_networks(&nvs).map(|n| n.is_empty()).unwrap_or(false);
+
+    let led_stop = stop.clone();
+    let led_ambient = ambient.clone();
+    let led_handle = thread::spawn(move || {
+        if let Err(e) = led_task(chip, channel, led_pin, consumer, audio_data_for_led, led_ambient, demo, led_stop) {
             log::error!("LED task error: {:?}", e);
         }
     });
@@ -144,14 +438,70 @@ fn main() -> anyhow::Result<()> {
             ..Default::default()
         }.set()?;
 
-    thread::spawn(move || {
-        if let Err(e) = audio_task(i2s, sck_pin, ws_pin, sd_pin, audio_data_for_audio) {
+    let audio_stop = stop.clone();
+    let audio_handle = thread::spawn(move || {
+        if let Err(e) = audio_task(i2s, sck_pin, ws_pin, sd_pin, audio_data_for_audio, audio_stop) {
             log::error!("Audio task error: {:?}", e);
         }
     });
 
+    ThreadSpawnConfiguration {
+            name: Some(b"ambient-task\0"),
+            stack_size: 4096,
+            pin_to_core: Some(Core::Core0),
+            priority: 5,
+            ..Default::default()
+        }.set()?;
+
+    let ambient_stop = stop.clone();
+    let ambient_nvs = nvs.clone();
+    let ambient_handle = thread::spawn(move || {
+        if let Err(e) = ambient::ambient_processing_blocking(&ambient_nvs, &ambient, &ambient_stop) {
+            log::warn!("Ambient light sensor unavailable: {:?}", e);
+        }
+    });
+
+    ThreadSpawnConfiguration {
+            name: Some(b"button-task\0"),
+            stack_size: 4096,
+            pin_to_core: Some(Core::Core0),
+            priority: 5,
+            ..Default::default()
+        }.set()?;
+
+    // Nút bấm (tùy chọn) - không cấu hình chân thì task kết thúc ngay
+    let button_stop = stop.clone();
+    let button_nvs = nvs.clone();
+    let button_producer = producer.clone();
+    let button_handle = thread::spawn(move || {
+        if let Err(e) = button::button_processing_blocking(&button_nvs, &button_producer, &button_stop) {
+            log::warn!("Button unavailable: {:?}", e);
+        }
+    });
+
     // Keep main thread alive
-    loop {
+    let mut seconds_since_persist: u32 = 0;
+    while !stop.load(Ordering::Relaxed) {
         FreeRtos::delay_ms(1000);
+
+        // Cộng dồn thời gian chạy, chỉ ghi flash mỗi ON_TIME_PERSIST_MIN phút
+        seconds_since_persist += 1;
+        if seconds_since_persist >= config::ON_TIME_PERSIST_MIN * 60 {
+            seconds_since_persist = 0;
+            if let Err(e) = config::add_on_time(&nvs, config::ON_TIME_PERSIST_MIN) {
+                log::warn!("Failed to persist on-time: {:#}", e);
+            }
+        }
     }
+
+    // Shutdown có phối hợp: chờ các task xong frame hiện tại rồi mới restart
+    info!("Shutdown requested, waiting for tasks...");
+    let _ = led_handle.join();
+    let _ = audio_handle.join();
+    let _ = ambient_handle.join();
+    let _ = button_handle.join();
+    drop(_server);
+
+    info!("Restarting");
+    unsafe { esp_idf_sys::esp_restart() };
 }
\ No newline at end of file
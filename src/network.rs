@@ -1,25 +1,23 @@
 use esp_idf_hal::peripheral::Peripheral;
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
-    nvs::{EspNvs, EspNvsPartition, NvsDefault},
+    nvs::{EspNvsPartition, NvsDefault},
     timer::EspTimerService,
     wifi::{AsyncWifi, AuthMethod, ClientConfiguration, Configuration, EspWifi},
 };
 
 use esp_idf_svc::timer::Task;
+use esp_idf_sys::EspError;
 use log::{info, warn, error};
-use anyhow::{Result, Context, bail};
+use anyhow::{Result, Context};
+use std::fmt;
 use std::sync::{Arc, Mutex};
 
-// Constants cho NVS storage
-const NVS_NAMESPACE: &str = "wifi_config";
-const NVS_SSID_KEY: &str = "ssid";
-const NVS_PASSWORD_KEY: &str = "password";
-const NVS_CONFIGURED_KEY: &str = "configured";
+use crate::config::{self, SavedNetwork};
 
-// Fallback AP configuration
-const AP_SSID: &str = "ESP32-AP";
-const AP_PASSWORD: &str = "12345678";
+// Fallback AP configuration, giữ nguyên AP cũ để app đã kết nối không phải đổi
+const AP_SSID: &str = "ESP32AP";
+const AP_PASSWORD: &str = "21078481";
 
 // WiFi constraints
 const MIN_SSID_LEN: usize = 1;
@@ -27,6 +25,50 @@ const MAX_SSID_LEN: usize = 32;
 const MIN_PASSWORD_LEN: usize = 8;
 const MAX_PASSWORD_LEN: usize = 64;
 
+/// Lỗi của WiFiManager - HTTP layer match để chọn status code phù hợp
+#[derive(Debug)]
+pub enum WifiError {
+    /// SSID/password không hợp lệ (độ dài, ký tự)
+    InvalidCredentials(&'static str),
+    /// Chưa có cấu hình WiFi trong NVS
+    NotConfigured,
+    /// Lỗi đọc/ghi NVS
+    Nvs(EspError),
+    /// Không thể lock NVS partition
+    NvsLock,
+    /// Không kết nối được AP (sai password, không tìm thấy AP...)
+    ConnectFailed(EspError),
+    /// Lỗi driver/radio tạm thời
+    Driver(EspError),
+}
+
+impl WifiError {
+    /// Status code HTTP tương ứng
+    pub fn http_status(&self) -> u16 {
+        match self {
+            WifiError::InvalidCredentials(_) | WifiError::ConnectFailed(_) => 400,
+            WifiError::NotConfigured => 404,
+            WifiError::Nvs(_) | WifiError::NvsLock => 500,
+            WifiError::Driver(_) => 503,
+        }
+    }
+}
+
+impl fmt::Display for WifiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WifiError::InvalidCredentials(msg) => write!(f, "Credentials không hợp lệ: {}", msg),
+            WifiError::NotConfigured => write!(f, "Chưa có cấu hình WiFi"),
+            WifiError::Nvs(e) => write!(f, "Lỗi NVS: {}", e),
+            WifiError::NvsLock => write!(f, "Không thể lock NVS partition"),
+            WifiError::ConnectFailed(e) => write!(f, "Không thể kết nối, kiểm tra SSID/password: {}", e),
+            WifiError::Driver(e) => write!(f, "Lỗi WiFi driver, thử lại: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WifiError {}
+
 /// Cấu trúc lưu trữ thông tin WiFi
 #[derive(Debug, Clone)]
 pub struct WiFiCredentials {
@@ -36,25 +78,25 @@ pub struct WiFiCredentials {
 
 impl WiFiCredentials {
     /// Tạo credentials mới với validation
-    pub fn new(ssid: String, password: String) -> Result<Self> {
+    pub fn new(ssid: String, password: String) -> Result<Self, WifiError> {
         let creds = Self { ssid, password };
         creds.validate()?;
         Ok(creds)
     }
 
     /// Validate credentials trước khi sử dụng
-    pub fn validate(&self) -> Result<()> {
-        if self.ssid.is_empty() || self.ssid.len() > MAX_SSID_LEN {
-            bail!("SSID phải có từ {}-{} ký tự", MIN_SSID_LEN, MAX_SSID_LEN);
+    pub fn validate(&self) -> Result<(), WifiError> {
+        if self.ssid.len() < MIN_SSID_LEN || self.ssid.len() > MAX_SSID_LEN {
+            return Err(WifiError::InvalidCredentials("SSID phải có từ 1-32 ký tự"));
         }
         
         if self.password.len() < MIN_PASSWORD_LEN || self.password.len() > MAX_PASSWORD_LEN {
-            bail!("Password phải có từ {}-{} ký tự", MIN_PASSWORD_LEN, MAX_PASSWORD_LEN);
+            return Err(WifiError::InvalidCredentials("Password phải có từ 8-64 ký tự"));
         }
         
         // Kiểm tra SSID chỉ chứa các ký tự hợp lệ (UTF-8)
         if !self.ssid.is_ascii() && self.ssid.chars().any(|c| c.is_control()) {
-            bail!("SSID chứa ký tự không hợp lệ");
+            return Err(WifiError::InvalidCredentials("SSID chứa ký tự không hợp lệ"));
         }
         
         Ok(())
@@ -93,113 +135,88 @@ impl WiFiManager {
     pub async fn start(&mut self) -> Result<()> {
         info!("Khởi động WiFi Manager...");
         
-        // Kiểm tra xem đã có cấu hình WiFi chưa
-        match self.load_credentials() {
-            Ok(credentials) => {
-                info!("Tìm thấy cấu hình WiFi đã lưu");
-                info!("SSID: {}", credentials.ssid);
-                
-                match self.connect_to_wifi(&credentials).await {
-                    Ok(_) => {
-                        info!("✓ Kết nối WiFi thành công!");
-                        self.print_ip_info()?;
-                        Ok(())
-                    }
-                    Err(e) => {
-                        error!("✗ Không thể kết nối với WiFi đã lưu: {:#}", e);
-                        warn!("Chuyển sang chế độ provisioning...");
-                        self.start_provisioning_mode().await
-                    }
-                }
+        match self.reconnect_saved().await {
+            Ok(_) => {
+                info!("✓ Kết nối WiFi thành công!");
+                self.print_ip_info()?;
+                Ok(())
             }
-            Err(e) => {
-                info!("Chưa có cấu hình WiFi: {}", e);
+            Err(WifiError::NotConfigured) => {
+                info!("Chưa có cấu hình WiFi");
                 info!("Khởi động chế độ provisioning...");
                 self.start_provisioning_mode().await
             }
+            Err(e) => {
+                error!("✗ Không thể kết nối với WiFi đã lưu: {:#}", e);
+                warn!("Chuyển sang chế độ provisioning...");
+                self.start_provisioning_mode().await
+            }
         }
     }
 
-    /// Lưu thông tin WiFi vào NVS
-    pub fn save_credentials(&self, credentials: &WiFiCredentials) -> Result<()> {
-        // Validate trước khi lưu
-        credentials.validate()
-            .context("Credentials không hợp lệ")?;
-        
-        let nvs_partition = self.nvs.lock()
-            .map_err(|e| anyhow::anyhow!("Không thể lock NVS partition: {}", e))?;
-       
-        let mut nvs_handle = EspNvs::new(nvs_partition.clone(), NVS_NAMESPACE, true)
-            .context("Không thể mở NVS namespace để ghi")?;
-    
-        nvs_handle.set_str(NVS_SSID_KEY, &credentials.ssid)
-            .context("Không thể lưu SSID")?;
-        
-        nvs_handle.set_str(NVS_PASSWORD_KEY, &credentials.password)
-            .context("Không thể lưu password")?;
-        
-        nvs_handle.set_u8(NVS_CONFIGURED_KEY, 1)
-            .context("Không thể đánh dấu đã cấu hình")?;
+    /// Thử lần lượt các mạng đã lưu theo thứ tự ưu tiên.
+    /// Mạng không có trong kết quả scan được bỏ qua (nếu scan thành công).
+    pub async fn reconnect_saved(&mut self) -> Result<(), WifiError> {
+        let saved = self.load_saved_networks()?;
+        if saved.is_empty() {
+            return Err(WifiError::NotConfigured);
+        }
 
-        info!("✓ Đã lưu thông tin WiFi vào flash");
-        Ok(())
-    }
+        // Scan cần driver đang chạy ở chế độ Station
+        let visible = match self.scan_ssids().await {
+            Ok(list) => Some(list),
+            Err(e) => {
+                warn!("Scan thất bại, thử tất cả mạng đã lưu: {}", e);
+                None
+            }
+        };
+
+        let mut last_err = WifiError::NotConfigured;
+        for credentials in saved {
+            if let Some(ref visible) = visible {
+                if !visible.iter().any(|s| *s == credentials.ssid) {
+                    info!("Bỏ qua {}: không tìm thấy", credentials.ssid);
+                    continue;
+                }
+            }
 
-    /// Đọc thông tin WiFi từ NVS
-    pub fn load_credentials(&self) -> Result<WiFiCredentials> {
-        let nvs_partition = self.nvs.lock()
-            .map_err(|e| anyhow::anyhow!("Không thể lock NVS partition: {}", e))?;
-        
-        let nvs_handle = EspNvs::new(nvs_partition.clone(), NVS_NAMESPACE, false)
-            .context("Không thể mở NVS namespace để đọc")?;
+            info!("Thử kết nối: {}", credentials.ssid);
+            match self.connect_to_wifi(&credentials).await {
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    warn!("✗ {}: {}", credentials.ssid, e);
+                    let _ = self.wifi.stop().await;
+                    last_err = e;
+                }
+            }
+        }
 
-        // Kiểm tra xem đã được cấu hình chưa
-        let configured = nvs_handle.get_u8(NVS_CONFIGURED_KEY)
-            .context("Lỗi khi đọc trạng thái cấu hình")?
-            .context("Chưa có cấu hình WiFi")?;
+        Err(last_err)
+    }
 
-        if configured != 1 {
-            bail!("WiFi chưa được cấu hình");
-        }
+    async fn scan_ssids(&mut self) -> Result<Vec<String>, WifiError> {
+        self.wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))
+            .map_err(WifiError::Driver)?;
+        self.wifi.start().await.map_err(WifiError::Driver)?;
 
-        // Đọc SSID
-        let mut ssid_buf = [0u8; MAX_SSID_LEN + 1];
-        let ssid = nvs_handle.get_str(NVS_SSID_KEY, &mut ssid_buf)
-            .context("Lỗi khi đọc SSID")?
-            .context("Không tìm thấy SSID")?
-            .to_string();
-
-        // Đọc Password
-        let mut password_buf = [0u8; MAX_PASSWORD_LEN + 1];
-        let password = nvs_handle.get_str(NVS_PASSWORD_KEY, &mut password_buf)
-            .context("Lỗi khi đọc password")?
-            .context("Không tìm thấy password")?
-            .to_string();
-
-        let credentials = WiFiCredentials { ssid, password };
-        
-        // Validate sau khi đọc
-        credentials.validate()
-            .context("Credentials đã lưu không hợp lệ")?;
+        let result = self.wifi.scan().await.map_err(WifiError::Driver);
+        let _ = self.wifi.stop().await;
 
-        Ok(credentials)
+        Ok(result?.into_iter().map(|ap| ap.ssid.to_string()).collect())
     }
 
-    /// Xóa thông tin WiFi đã lưu
-    pub fn clear_credentials(&self) -> Result<()> {
+    /// Các mạng đã lưu, theo thứ tự ưu tiên
+    pub fn load_saved_networks(&self) -> Result<Vec<WiFiCredentials>, WifiError> {
         let nvs_partition = self.nvs.lock()
-            .map_err(|e| anyhow::anyhow!("Không thể lock NVS partition: {}", e))?;
-        
-        let mut nvs_handle = EspNvs::new(nvs_partition.clone(), NVS_NAMESPACE, true)
-            .context("Không thể mở NVS namespace để xóa")?;
+            .map_err(|_| WifiError::NvsLock)?;
 
-        // Xóa từng key - không cần check return value
-        let _ = nvs_handle.remove(NVS_SSID_KEY);
-        let _ = nvs_handle.remove(NVS_PASSWORD_KEY);
-        let _ = nvs_handle.remove(NVS_CONFIGURED_KEY);
+        let list = config::load_networks(&nvs_partition).map_err(WifiError::Nvs)?;
 
-        info!("✓ Đã xóa thông tin WiFi khỏi flash");
-        Ok(())
+        // Bỏ qua entry hỏng thay vì chặn cả danh sách
+        Ok(list.iter()
+            .map(|n| WiFiCredentials { ssid: n.ssid.to_string(), password: n.password.to_string() })
+            .filter(|c| c.validate().is_ok())
+            .collect())
     }
 
     /// Khởi động chế độ provisioning (Access Point)
@@ -238,75 +255,41 @@ impl WiFiManager {
     }
 
     /// Kết nối đến WiFi với credentials đã cho
-    async fn connect_to_wifi(&mut self, credentials: &WiFiCredentials) -> Result<()> {
+    async fn connect_to_wifi(&mut self, credentials: &WiFiCredentials) -> Result<(), WifiError> {
         info!("Đang kết nối đến WiFi: {}", credentials.ssid);
         
         let sta_config = Configuration::Client(ClientConfiguration {
             ssid: credentials.ssid.as_str().try_into()
-                .map_err(|_| anyhow::anyhow!(
-                    "SSID không hợp lệ hoặc quá dài (tối đa {} ký tự)", 
-                    MAX_SSID_LEN
-                ))?,
+                .map_err(|_| WifiError::InvalidCredentials("SSID quá dài (tối đa 32 ký tự)"))?,
             password: credentials.password.as_str().try_into()
-                .map_err(|_| anyhow::anyhow!(
-                    "Password không hợp lệ hoặc quá dài (tối đa {} ký tự)", 
-                    MAX_PASSWORD_LEN
-                ))?,
+                .map_err(|_| WifiError::InvalidCredentials("Password quá dài (tối đa 64 ký tự)"))?,
             ..Default::default()
         });
 
         self.wifi.set_configuration(&sta_config)
-            .context("Không thể cấu hình WiFi Station mode")?;
+            .map_err(WifiError::Driver)?;
         
         info!("→ Cấu hình WiFi Station mode");
 
         self.wifi.start().await
-            .context("Không thể khởi động WiFi")?;
+            .map_err(WifiError::Driver)?;
         
         info!("→ WiFi driver đã khởi động");
 
+        // Lỗi ở bước này thường do sai password hoặc không thấy AP
         self.wifi.connect().await
-            .context("Không thể kết nối đến WiFi")?;
+            .map_err(WifiError::ConnectFailed)?;
         
         info!("→ Đã kết nối đến: {}", credentials.ssid);
 
         self.wifi.wait_netif_up().await
-            .context("Network interface không sẵn sàng")?;
+            .map_err(WifiError::Driver)?;
         
         info!("→ Network interface đã sẵn sàng");
 
         Ok(())
     }
 
-    /// Cấu hình WiFi mới từ bên ngoài (qua HTTP/BLE)
-    pub async fn provision(&mut self, credentials: WiFiCredentials) -> Result<()> {
-        info!("Bắt đầu provisioning...");
-        
-        // Validate trước
-        credentials.validate()
-            .context("Credentials không hợp lệ")?;
-        
-        info!("Đang thử kết nối với WiFi mới: {}", credentials.ssid);
-        
-        // Thử kết nối
-        self.connect_to_wifi(&credentials).await
-            .context("Không thể kết nối với WiFi mới")?;
-        
-        // Nếu thành công, lưu vào NVS
-        self.save_credentials(&credentials)
-            .context("Không thể lưu credentials")?;
-        
-        info!("✓ Provisioning thành công!");
-        self.print_ip_info()?;
-        
-        Ok(())
-    }
-
-    /// Kiểm tra trạng thái kết nối
-    pub fn is_connected(&self) -> Result<bool> {
-        Ok(self.wifi.is_connected()?)
-    }
-
     /// In thông tin IP khi ở chế độ Station
     fn print_ip_info(&self) -> Result<()> {
         let ip_info = self.wifi.wifi().sta_netif().get_ip_info()
@@ -327,76 +310,26 @@ impl WiFiManager {
         info!("IP: {:23} ", ip_info.ip);
         Ok(())
     }
-
-    /// Lấy reference đến WiFi driver
-    pub fn wifi(&self) -> &AsyncWifi<EspWifi<'static>> {
-        &self.wifi
-    }
-
-    /// Lấy mutable reference đến WiFi driver
-    pub fn wifi_mut(&mut self) -> &mut AsyncWifi<EspWifi<'static>> {
-        &mut self.wifi
-    }
-
-    /// Lấy NVS partition (nếu cần truy cập trực tiếp)
-    pub fn nvs(&self) -> Arc<Mutex<EspNvsPartition<NvsDefault>>> {
-        self.nvs.clone()
-    }
-}
-
-// ==== Ví dụ sử dụng ====
-
-/// Hàm main ví dụ
-pub async fn example_usage(
-    modem: impl Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
-    sysloop: EspSystemEventLoop,
-    nvs: EspNvsPartition<NvsDefault>,
-    timer_service: EspTimerService<Task>,
-) -> Result<()> {
-    // Khởi tạo WiFi Manager
-    let mut wifi_manager = WiFiManager::new(modem, sysloop, nvs, timer_service)?;
-
-    // Khởi động - tự động kiểm tra và kết nối hoặc provisioning
-    wifi_manager.start().await?;
-
-    // Nếu bạn muốn xóa cấu hình cũ (reset)
-    // wifi_manager.clear_credentials()?;
-
-    // Kiểm tra trạng thái kết nối
-    if wifi_manager.is_connected()? {
-        info!("WiFi đang kết nối");
-    }
-
-    Ok(())
 }
 
-/// Hàm để cấu hình WiFi từ web server hoặc BLE
-pub async fn configure_wifi_from_external(
-    wifi_manager: &mut WiFiManager,
-    ssid: String,
-    password: String,
-) -> Result<()> {
-    // Tạo và validate credentials
-    let credentials = WiFiCredentials::new(ssid, password)
-        .context("Không thể tạo credentials")?;
-    
-    // Provision
-    wifi_manager.provision(credentials).await?;
-    
+/// Lưu mạng WiFi vào danh sách (cập nhật nếu SSID đã có).
+/// Mạng mới được thử ở lần khởi động sau.
+pub fn save_credentials(
+    partition: &EspNvsPartition<NvsDefault>,
+    credentials: &WiFiCredentials,
+) -> Result<(), WifiError> {
+    // Validate trước khi lưu
+    credentials.validate()?;
+
+    let network = SavedNetwork {
+        ssid: credentials.ssid.as_str().try_into()
+            .map_err(|_| WifiError::InvalidCredentials("SSID quá dài (tối đa 32 ký tự)"))?,
+        password: credentials.password.as_str().try_into()
+            .map_err(|_| WifiError::InvalidCredentials("Password quá dài (tối đa 64 ký tự)"))?,
+    };
+
+    config::save_network(partition, network).map_err(WifiError::Nvs)?;
+
+    info!("✓ Đã lưu thông tin WiFi vào flash");
     Ok(())
 }
-
-/// Hàm để reset WiFi về factory settings
-pub async fn reset_wifi_to_factory(
-    wifi_manager: &mut WiFiManager,
-) -> Result<()> {
-    info!("Đang reset WiFi về cài đặt ban đầu...");
-    
-    // Xóa credentials
-    wifi_manager.clear_credentials()?;
-    
-    // Khởi động lại provisioning mode
-    wifi_manager.start().await?;
-    
-    Ok(())
-}
\ No newline at end of file
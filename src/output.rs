@@ -0,0 +1,121 @@
+use core::time::Duration;
+use esp_idf_hal::{
+    gpio::OutputPin,
+    peripheral::Peripheral,
+    rmt::{config::TransmitConfig, PinState, Pulse, RmtChannel, TxRmtDriver, VariableLengthSignal},
+};
+use ws2812_esp32_rmt_driver::Ws2812Esp32RmtDriver;
+
+/// Nơi xuất một frame đã mã hóa (GRB, 3 byte mỗi LED).
+/// Controller chỉ biết trait này nên loại chip có thể chọn lúc boot.
+pub trait LedOutput {
+    fn write(&mut self, grb: &[u8]) -> anyhow::Result<()>;
+}
+
+impl LedOutput for Ws2812Esp32RmtDriver<'_> {
+    fn write(&mut self, grb: &[u8]) -> anyhow::Result<()> {
+        self.write_blocking(grb.iter().cloned())?;
+        Ok(())
+    }
+}
+
+/// Loại chip LED, lưu trong NVS (`chip_type`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum ChipType {
+    Ws2812 = 0,
+    Ws2811 = 1,
+    Sk6812 = 2,
+}
+
+impl ChipType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ChipType::Ws2812),
+            1 => Some(ChipType::Ws2811),
+            2 => Some(ChipType::Sk6812),
+            _ => None,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ws2812" | "ws2812b" => Some(ChipType::Ws2812),
+            "ws2811" => Some(ChipType::Ws2811),
+            "sk6812" => Some(ChipType::Sk6812),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChipType::Ws2812 => "ws2812",
+            ChipType::Ws2811 => "ws2811",
+            ChipType::Sk6812 => "sk6812",
+        }
+    }
+
+    /// (T0H, T0L, T1H, T1L) theo ns, lấy từ datasheet
+    fn timing_ns(&self) -> (u64, u64, u64, u64) {
+        match self {
+            ChipType::Ws2812 => (400, 850, 800, 450),
+            ChipType::Ws2811 => (500, 2000, 1200, 1300), // 400kHz
+            ChipType::Sk6812 => (300, 900, 600, 600),
+        }
+    }
+}
+
+/// Driver RMT với timing tùy chỉnh (WS2811, SK6812...).
+/// `ws2812_esp32_rmt_driver` cố định timing WS2812 nên chỉ dùng cho WS2812.
+pub struct RmtOutput<'d> {
+    tx: TxRmtDriver<'d>,
+    bit0: [Pulse; 2],
+    bit1: [Pulse; 2],
+}
+
+impl<'d> RmtOutput<'d> {
+    pub fn new<C: RmtChannel>(
+        chip: ChipType,
+        channel: impl Peripheral<P = C> + 'd,
+        pin: impl Peripheral<P = impl OutputPin> + 'd,
+    ) -> anyhow::Result<Self> {
+        let config = TransmitConfig::new().clock_divider(1);
+        let tx = TxRmtDriver::new(channel, pin, &config)?;
+        let ticks_hz = tx.counter_clock()?;
+
+        let (t0h, t0l, t1h, t1l) = chip.timing_ns();
+        let pulse = |state, ns| Pulse::new_with_duration(ticks_hz, state, &Duration::from_nanos(ns));
+
+        Ok(Self {
+            bit0: [pulse(PinState::High, t0h)?, pulse(PinState::Low, t0l)?],
+            bit1: [pulse(PinState::High, t1h)?, pulse(PinState::Low, t1l)?],
+            tx,
+        })
+    }
+}
+
+impl LedOutput for RmtOutput<'_> {
+    fn write(&mut self, grb: &[u8]) -> anyhow::Result<()> {
+        let mut signal = VariableLengthSignal::with_capacity(grb.len() * 8 * 2);
+        for byte in grb {
+            for bit in (0..8).rev() {
+                let pulses = if byte & (1 << bit) != 0 { &self.bit1 } else { &self.bit0 };
+                signal.push(pulses.iter())?;
+            }
+        }
+        self.tx.start_blocking(&signal)?;
+        Ok(())
+    }
+}
+
+/// Mở output phù hợp với loại chip
+pub fn open<'d, C: RmtChannel>(
+    chip: ChipType,
+    channel: impl Peripheral<P = C> + 'd,
+    pin: impl Peripheral<P = impl OutputPin> + 'd,
+) -> anyhow::Result<Box<dyn LedOutput + 'd>> {
+    Ok(match chip {
+        ChipType::Ws2812 => Box::new(Ws2812Esp32RmtDriver::new(channel, pin)?),
+        _ => Box::new(RmtOutput::new(chip, channel, pin)?),
+    })
+}
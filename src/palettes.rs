@@ -0,0 +1,208 @@
+use anyhow::{bail, Context, Result};
+use core::fmt::Write as FmtWrite;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use heapless::{String, Vec};
+use log::{info, warn};
+use smart_leds::RGB8;
+use std::sync::Mutex;
+
+use crate::effect::blend_color;
+
+pub const MAX_PALETTES: usize = 8;
+pub const MAX_STOPS: usize = 8;
+pub const MAX_NAME_LEN: usize = 15;
+
+const NVS_NAMESPACE: &str = "palettes";
+
+// Record trong NVS: "name;RRGGBB,RRGGBB,..."
+type Record = String<{ MAX_NAME_LEN + 1 + MAX_STOPS * 7 }>;
+
+/// Bảng màu: danh sách các điểm màu, nội suy tuyến tính giữa các điểm
+#[derive(Debug, Clone)]
+pub struct Palette {
+    pub name: String<MAX_NAME_LEN>,
+    pub stops: Vec<RGB8, MAX_STOPS>,
+}
+
+/// Palette có sẵn (không lưu trong NVS)
+const BUILTIN_PALETTES: &[(&str, &[u32])] = &[
+    ("sunset", &[0xFF4000, 0xFF0060, 0x6000A0]),
+    ("ocean", &[0x000040, 0x0060FF, 0x00FFC0]),
+    ("forest", &[0x003000, 0x20A000, 0x80FF20]),
+    ("fire", &[0x200000, 0xFF2000, 0xFFA000, 0xFFFF80]),
+    ("mood", &[0xFF0000, 0xFF6000, 0x8000C0, 0x0030FF]),
+];
+
+// Palette do user lưu - cache trong RAM để các task đọc không cần NVS
+static STORE: Mutex<Vec<Palette, MAX_PALETTES>> = Mutex::new(Vec::new());
+
+impl Palette {
+    /// Tạo palette từ tên và chuỗi "RRGGBB,RRGGBB,..."
+    pub fn parse(name: &str, stops: &str) -> Result<Self, &'static str> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err("Invalid palette name");
+        }
+
+        let name = String::try_from(name).map_err(|_| "Palette name too long")?;
+
+        let mut parsed = Vec::new();
+        for stop in stops.split(',') {
+            let (r, g, b) = crate::color::parse_hex_color(stop.trim()).map_err(|_| "Invalid color stop")?;
+            parsed.push(RGB8 { r, g, b }).map_err(|_| "Too many color stops")?;
+        }
+
+        if parsed.len() < 2 {
+            return Err("At least 2 color stops required");
+        }
+
+        Ok(Self { name, stops: parsed })
+    }
+
+    /// Lấy màu tại vị trí `t` (0 = điểm đầu, 255 = điểm cuối)
+    pub fn sample(&self, t: u8) -> RGB8 {
+        let n = self.stops.len();
+        if n == 0 {
+            return RGB8::default();
+        }
+        if n == 1 {
+            return self.stops[0];
+        }
+
+        let scaled = t as u32 * (n as u32 - 1);
+        let idx = (scaled / 255) as usize;
+        if idx >= n - 1 {
+            return self.stops[n - 1];
+        }
+
+        let frac = (scaled % 255) as u8;
+        blend_color(self.stops[idx], self.stops[idx + 1], frac)
+    }
+
+    pub(crate) fn to_record(&self) -> Record {
+        let mut record = Record::new();
+        record.push_str(&self.name).ok();
+        record.push(';').ok();
+        for (i, stop) in self.stops.iter().enumerate() {
+            if i > 0 {
+                record.push(',').ok();
+            }
+            write!(record, "{:02X}{:02X}{:02X}", stop.r, stop.g, stop.b).ok();
+        }
+        record
+    }
+
+    pub(crate) fn from_record(record: &str) -> Option<Self> {
+        let (name, stops) = record.split_once(';')?;
+        Self::parse(name, stops).ok()
+    }
+}
+
+fn builtin(name: &str) -> Option<Palette> {
+    let (builtin_name, colors) = BUILTIN_PALETTES.iter().find(|(n, _)| *n == name)?;
+
+    let mut stops = Vec::new();
+    for &c in colors.iter() {
+        stops.push(RGB8 { r: (c >> 16) as u8, g: (c >> 8) as u8, b: c as u8 }).ok();
+    }
+
+    Some(Palette { name: String::try_from(*builtin_name).ok()?, stops })
+}
+
+/// Tên các palette có sẵn
+pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+    BUILTIN_PALETTES.iter().map(|(n, _)| *n)
+}
+
+/// Tìm palette theo tên: palette của user trước, sau đó tới palette có sẵn
+pub fn find(name: &str) -> Option<Palette> {
+    if let Ok(store) = STORE.lock() {
+        if let Some(p) = store.iter().find(|p| p.name == name) {
+            return Some(p.clone());
+        }
+    }
+    builtin(name)
+}
+
+/// Bản sao danh sách palette của user
+pub fn list() -> Vec<Palette, MAX_PALETTES> {
+    STORE.lock().map(|store| store.clone()).unwrap_or_default()
+}
+
+fn slot_key(i: usize) -> String<4> {
+    let mut key = String::new();
+    write!(key, "p{}", i).ok();
+    key
+}
+
+/// Nạp palette đã lưu từ NVS vào RAM (gọi 1 lần khi khởi động)
+pub fn init(partition: &EspDefaultNvsPartition) {
+    let Ok(nvs) = EspNvs::new(partition.clone(), NVS_NAMESPACE, false) else {
+        info!("No saved palettes");
+        return;
+    };
+
+    let Ok(mut store) = STORE.lock() else { return; };
+    store.clear();
+
+    for i in 0..MAX_PALETTES {
+        let mut buf = [0u8; 80];
+        if let Ok(Some(record)) = nvs.get_str(&slot_key(i), &mut buf) {
+            match Palette::from_record(record) {
+                Some(p) => { store.push(p).ok(); }
+                None => warn!("Ignoring invalid palette record in slot {}", i),
+            }
+        }
+    }
+
+    info!("Loaded {} palette(s) from NVS", store.len());
+}
+
+fn persist(partition: &EspDefaultNvsPartition, store: &Vec<Palette, MAX_PALETTES>) -> Result<()> {
+    let mut nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)
+        .context("Không thể mở NVS namespace palettes")?;
+
+    for i in 0..MAX_PALETTES {
+        let key = slot_key(i);
+        match store.get(i) {
+            Some(p) => {
+                nvs.set_str(&key, &p.to_record())
+                    .with_context(|| format!("Không thể lưu palette {}", p.name))?;
+            }
+            None => {
+                let _ = nvs.remove(&key);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lưu được palette tên `name`: còn chỗ trống hoặc ghi đè palette cùng tên
+pub fn has_room_for(name: &str) -> bool {
+    STORE.lock().map_or(false, |store| !store.is_full() || store.iter().any(|p| p.name == name))
+}
+
+/// Thêm hoặc ghi đè palette cùng tên, lưu vào NVS
+pub fn save(partition: &EspDefaultNvsPartition, palette: Palette) -> Result<()> {
+    let mut store = STORE.lock().map_err(|_| anyhow::anyhow!("Palette store poisoned"))?;
+
+    if let Some(existing) = store.iter_mut().find(|p| p.name == palette.name) {
+        *existing = palette;
+    } else if store.push(palette).is_err() {
+        bail!("Palette store full (max {})", MAX_PALETTES);
+    }
+
+    persist(partition, &store)
+}
+
+/// Xóa palette theo tên, trả false nếu không tồn tại
+pub fn remove(partition: &EspDefaultNvsPartition, name: &str) -> Result<bool> {
+    let mut store = STORE.lock().map_err(|_| anyhow::anyhow!("Palette store poisoned"))?;
+
+    if !store.iter().any(|p| p.name == name) {
+        return Ok(false);
+    }
+    store.retain(|p| p.name != name);
+
+    persist(partition, &store)?;
+    Ok(true)
+}
@@ -0,0 +1,176 @@
+use anyhow::{Context, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use log::{info, warn};
+use smart_leds::RGB8;
+use std::sync::Mutex;
+
+/// Số màu tối đa của pattern, màu thừa bị bỏ qua
+pub const MAX_PATTERN_LEDS: usize = crate::NUM_LEDS;
+/// Số đoạn tối đa của hiệu ứng `runs`
+pub const MAX_RUNS: usize = 32;
+
+const NVS_NAMESPACE: &str = "pattern";
+// Blob RGB liên tiếp (3 byte mỗi LED) và cờ lặp lại
+const KEY_COLORS: &str = "rgb";
+const KEY_TILE: &str = "tile";
+// Blob 5 byte mỗi đoạn: độ dài (u16 little-endian) + RGB
+const KEY_RUNS: &str = "runs";
+
+/// Ảnh tĩnh cho hiệu ứng `pattern`: màu LED 0, 1, 2...
+/// Ít màu hơn số LED thì phần còn lại tắt, hoặc lặp lại pattern nếu `tile`.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub colors: Vec<RGB8>,
+    pub tile: bool,
+}
+
+// Cache trong RAM, LED task đọc khi tạo hiệu ứng
+static PATTERN: Mutex<Pattern> = Mutex::new(Pattern { colors: Vec::new(), tile: false });
+
+/// Đoạn màu cho hiệu ứng `runs`: (số LED, màu), nối tiếp từ LED 0
+pub type Runs = Vec<(u16, RGB8)>;
+
+static RUNS: Mutex<Runs> = Mutex::new(Vec::new());
+
+/// "FF0000,00FF00,..." (dấu phẩy có thể là %2C) → danh sách màu, tối đa MAX_PATTERN_LEDS
+pub fn parse_hex(s: &str) -> Result<Vec<RGB8>, &'static str> {
+    let mut colors = Vec::new();
+    let parts = s.split(',').flat_map(|p| p.split("%2C")).flat_map(|p| p.split("%2c"));
+    for part in parts.map(str::trim).filter(|p| !p.is_empty()) {
+        if colors.len() == MAX_PATTERN_LEDS {
+            break;
+        }
+        let (r, g, b) = crate::color::parse_hex_color(part).map_err(|_| "Colors must be RRGGBB")?;
+        colors.push(RGB8 { r, g, b });
+    }
+    if colors.is_empty() {
+        return Err("Expected at least one color");
+    }
+    Ok(colors)
+}
+
+/// Byte RGB liên tiếp → danh sách màu, tối đa MAX_PATTERN_LEDS
+pub fn from_bytes(bytes: &[u8]) -> Result<Vec<RGB8>, &'static str> {
+    if bytes.is_empty() || bytes.len() % 3 != 0 {
+        return Err("Binary body must be RGB triplets");
+    }
+    Ok(bytes
+        .chunks_exact(3)
+        .take(MAX_PATTERN_LEDS)
+        .map(|c| RGB8 { r: c[0], g: c[1], b: c[2] })
+        .collect())
+}
+
+/// "5:FF0000,3:000000,..." → danh sách đoạn, tối đa MAX_RUNS.
+/// Độ dài phải dương; tổng dài hơn dải LED thì phần thừa bị cắt khi render.
+pub fn parse_runs(s: &str) -> Result<Runs, &'static str> {
+    let mut runs = Vec::new();
+    for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        if runs.len() == MAX_RUNS {
+            return Err("Too many runs (max 32)");
+        }
+        let (len, hex) = part.split_once(':').ok_or("Runs must be length:RRGGBB")?;
+        let len = match len.parse::<u16>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err("Run length must be a positive number"),
+        };
+        let (r, g, b) = crate::color::parse_hex_color(hex).map_err(|_| "Run colors must be RRGGBB")?;
+        runs.push((len, RGB8 { r, g, b }));
+    }
+    if runs.is_empty() {
+        return Err("Expected at least one run");
+    }
+    Ok(runs)
+}
+
+fn runs_from_bytes(bytes: &[u8]) -> Option<Runs> {
+    if bytes.len() % 5 != 0 {
+        return None;
+    }
+    let runs: Runs = bytes
+        .chunks_exact(5)
+        .take(MAX_RUNS)
+        .map(|c| (u16::from_le_bytes([c[0], c[1]]), RGB8 { r: c[2], g: c[3], b: c[4] }))
+        .collect();
+    runs.iter().all(|(len, _)| *len > 0).then_some(runs)
+}
+
+/// Nạp pattern đã lưu từ NVS (gọi 1 lần khi khởi động)
+pub fn init(partition: &EspDefaultNvsPartition) {
+    let Ok(nvs) = EspNvs::new(partition.clone(), NVS_NAMESPACE, false) else {
+        return;
+    };
+
+    let mut buf = vec![0u8; MAX_PATTERN_LEDS * 3];
+    let colors = match nvs.get_raw(KEY_COLORS, &mut buf) {
+        Ok(Some(bytes)) => from_bytes(bytes).unwrap_or_else(|e| {
+            warn!("Ignoring invalid pattern in NVS: {}", e);
+            Vec::new()
+        }),
+        _ => Vec::new(),
+    };
+    let tile = nvs.get_u8(KEY_TILE).ok().flatten().unwrap_or(0) != 0;
+
+    if !colors.is_empty() {
+        info!("Loaded pattern of {} LEDs{}", colors.len(), if tile { " (tiled)" } else { "" });
+    }
+    if let Ok(mut store) = PATTERN.lock() {
+        *store = Pattern { colors, tile };
+    }
+
+    let mut buf = vec![0u8; MAX_RUNS * 5];
+    if let Ok(Some(bytes)) = nvs.get_raw(KEY_RUNS, &mut buf) {
+        match runs_from_bytes(bytes) {
+            Some(runs) => {
+                info!("Loaded {} run(s)", runs.len());
+                if let Ok(mut store) = RUNS.lock() {
+                    *store = runs;
+                }
+            }
+            None => warn!("Ignoring invalid runs in NVS"),
+        }
+    }
+}
+
+/// Bản sao pattern hiện tại (rỗng nếu chưa upload)
+pub fn get() -> Pattern {
+    PATTERN.lock()
+        .map(|p| p.clone())
+        .unwrap_or(Pattern { colors: Vec::new(), tile: false })
+}
+
+/// Lưu pattern mới vào NVS và cập nhật cache
+pub fn save(partition: &EspDefaultNvsPartition, pattern: Pattern) -> Result<()> {
+    let mut nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)
+        .context("Không thể mở NVS namespace pattern")?;
+
+    let bytes: Vec<u8> = pattern.colors.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+    nvs.set_raw(KEY_COLORS, &bytes).context("Không thể lưu pattern")?;
+    nvs.set_u8(KEY_TILE, pattern.tile as u8).context("Không thể lưu pattern")?;
+
+    let mut store = PATTERN.lock().map_err(|_| anyhow::anyhow!("Pattern store poisoned"))?;
+    *store = pattern;
+    Ok(())
+}
+
+/// Bản sao các đoạn hiện tại (rỗng nếu chưa gửi)
+pub fn get_runs() -> Runs {
+    RUNS.lock().map(|r| r.clone()).unwrap_or_default()
+}
+
+/// Cập nhật các đoạn cho hiệu ứng `runs`, lưu vào NVS nếu `persist`
+/// (không lưu thì mất khi restart, hoặc quay về bản đã lưu trước đó)
+pub fn set_runs(partition: &EspDefaultNvsPartition, runs: Runs, persist: bool) -> Result<()> {
+    if persist {
+        let mut nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)
+            .context("Không thể mở NVS namespace pattern")?;
+        let bytes: Vec<u8> = runs.iter()
+            .flat_map(|(len, c)| { let [lo, hi] = len.to_le_bytes(); [lo, hi, c.r, c.g, c.b] })
+            .collect();
+        nvs.set_raw(KEY_RUNS, &bytes).context("Không thể lưu runs")?;
+    }
+
+    let mut store = RUNS.lock().map_err(|_| anyhow::anyhow!("Runs store poisoned"))?;
+    *store = runs;
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use core::fmt::Write as FmtWrite;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use heapless::{String, Vec};
+use log::{info, warn};
+use smart_leds::RGB8;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::config;
+
+/// Màu lưu sẵn để đổi nhanh bằng `/led/next-color` hoặc nút bấm.
+/// Lưu trong NVS (`quick_colors`) dạng "FF0000,00FF00,...", có thứ tự.
+pub const MAX_QUICK_COLORS: usize = 8;
+
+pub type QuickColors = Vec<RGB8, MAX_QUICK_COLORS>;
+
+// Chuỗi NVS dài nhất: 8 màu × (6 ký tự + dấu phẩy)
+pub type QuickColorsString = String<56>;
+
+const DEFAULT_COLORS: &[RGB8] = &[
+    RGB8 { r: 255, g: 0, b: 0 },
+    RGB8 { r: 255, g: 128, b: 0 },
+    RGB8 { r: 255, g: 255, b: 0 },
+    RGB8 { r: 0, g: 255, b: 0 },
+    RGB8 { r: 0, g: 255, b: 255 },
+    RGB8 { r: 0, g: 0, b: 255 },
+    RGB8 { r: 255, g: 0, b: 255 },
+    RGB8 { r: 255, g: 255, b: 255 },
+];
+
+static COLORS: Mutex<QuickColors> = Mutex::new(Vec::new());
+// Màu vừa dùng; usize::MAX = chưa dùng màu nào → lần đầu lấy màu đầu tiên
+static INDEX: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+fn default_colors() -> QuickColors {
+    DEFAULT_COLORS.iter().copied().collect()
+}
+
+/// "FF0000,00FF00" → QuickColors. Lỗi nếu có màu sai định dạng, rỗng hoặc quá nhiều.
+pub fn parse(s: &str) -> Result<QuickColors, &'static str> {
+    let mut colors = QuickColors::new();
+    for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (r, g, b) = crate::color::parse_hex_color(part).map_err(|_| "Colors must be RRGGBB")?;
+        colors.push(RGB8 { r, g, b }).map_err(|_| "Too many colors (max 8)")?;
+    }
+    if colors.is_empty() {
+        return Err("Expected at least one color");
+    }
+    Ok(colors)
+}
+
+/// QuickColors → "FF0000,00FF00,..."
+pub fn to_string(colors: &QuickColors) -> QuickColorsString {
+    let mut out = QuickColorsString::new();
+    for (i, c) in colors.iter().enumerate() {
+        if i > 0 {
+            out.push(',').ok();
+        }
+        write!(out, "{:02X}{:02X}{:02X}", c.r, c.g, c.b).ok();
+    }
+    out
+}
+
+/// Nạp danh sách từ NVS (gọi 1 lần khi khởi động), mặc định nếu chưa lưu hoặc lỗi
+pub fn init(partition: &EspDefaultNvsPartition) {
+    let colors = match config::get_str::<56>(partition, config::KEY_QUICK_COLORS) {
+        Some(s) => parse(&s).unwrap_or_else(|e| {
+            warn!("Ignoring invalid quick colors in NVS ({}): {}", e, s);
+            default_colors()
+        }),
+        None => default_colors(),
+    };
+
+    info!("Quick colors: {}", to_string(&colors));
+    if let Ok(mut store) = COLORS.lock() {
+        *store = colors;
+    }
+}
+
+/// Bản sao danh sách hiện tại
+pub fn list() -> QuickColors {
+    COLORS.lock().map(|c| c.clone()).unwrap_or_else(|_| default_colors())
+}
+
+/// Chuyển sang màu kế tiếp (quay vòng) và trả về màu đó
+pub fn next() -> Option<RGB8> {
+    let colors = list();
+    if colors.is_empty() {
+        return None;
+    }
+    let previous = INDEX.load(Ordering::Relaxed);
+    let index = if previous == usize::MAX { 0 } else { (previous + 1) % colors.len() };
+    INDEX.store(index, Ordering::Relaxed);
+    Some(colors[index])
+}
+
+/// Lưu danh sách mới vào NVS, cập nhật cache và bắt đầu lại từ màu đầu tiên
+pub fn save(partition: &EspDefaultNvsPartition, colors: QuickColors) -> anyhow::Result<()> {
+    config::set_str(partition, config::KEY_QUICK_COLORS, &to_string(&colors))?;
+    let mut store = COLORS.lock().map_err(|_| anyhow::anyhow!("Quick colors store poisoned"))?;
+    *store = colors;
+    INDEX.store(usize::MAX, Ordering::Relaxed);
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use core::fmt::Write as FmtWrite;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use heapless::{String, Vec};
+use log::{info, warn};
+use std::sync::Mutex;
+
+use crate::config;
+use crate::effect::{effect_from_name, effect_name, EffectType};
+
+/// Danh sách hiệu ứng mà các chế độ tự đổi (demo, party, nút bấm) lấy ra dùng.
+/// Lưu trong NVS (`rotation`) dạng "rainbow,comet,...", có thứ tự.
+pub const MAX_ROTATION: usize = 16;
+
+pub type Rotation = Vec<EffectType, MAX_ROTATION>;
+
+// Chuỗi NVS dài nhất: 16 tên × (tối đa 12 ký tự + dấu phẩy)
+pub type RotationString = String<208>;
+
+const DEFAULT_ROTATION: &[EffectType] = &[
+    EffectType::Rainbow,
+    EffectType::Comet,
+    EffectType::Breathe,
+    EffectType::Scanner,
+    EffectType::Wander,
+    EffectType::TheaterChase,
+    EffectType::Stripes,
+    EffectType::Ripple,
+    EffectType::AudioVolumeBar,
+    EffectType::VuCenter,
+    EffectType::PulseSolid,
+    EffectType::BeatColor,
+    EffectType::TriBand,
+];
+
+// Cache trong RAM để LED task và task nút bấm đọc không cần NVS
+static ROTATION: Mutex<Rotation> = Mutex::new(Vec::new());
+
+fn default_rotation() -> Rotation {
+    DEFAULT_ROTATION.iter().cloned().collect()
+}
+
+/// "rainbow,comet" → Rotation. Bỏ qua tên trùng; lỗi nếu có tên lạ, rỗng hoặc quá dài.
+pub fn parse(s: &str) -> Result<Rotation, &'static str> {
+    let mut rotation = Rotation::new();
+    for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let (effect, _) = effect_from_name(name).ok_or("Unknown effect")?;
+        if rotation.contains(&effect) {
+            continue;
+        }
+        rotation.push(effect).map_err(|_| "Too many effects (max 16)")?;
+    }
+    if rotation.is_empty() {
+        return Err("Expected at least one effect");
+    }
+    Ok(rotation)
+}
+
+/// Rotation → "rainbow,comet,..."
+pub fn to_string(rotation: &Rotation) -> RotationString {
+    let mut out = RotationString::new();
+    for (i, effect) in rotation.iter().enumerate() {
+        if i > 0 {
+            out.push(',').ok();
+        }
+        write!(out, "{}", effect_name(effect)).ok();
+    }
+    out
+}
+
+/// Nạp rotation từ NVS (gọi 1 lần khi khởi động), mặc định nếu chưa lưu hoặc lỗi
+pub fn init(partition: &EspDefaultNvsPartition) {
+    let rotation = match config::get_str::<208>(partition, config::KEY_ROTATION) {
+        Some(s) => parse(&s).unwrap_or_else(|e| {
+            warn!("Ignoring invalid rotation in NVS ({}): {}", e, s);
+            default_rotation()
+        }),
+        None => default_rotation(),
+    };
+
+    info!("Effect rotation: {}", to_string(&rotation));
+    if let Ok(mut store) = ROTATION.lock() {
+        *store = rotation;
+    }
+}
+
+/// Bản sao rotation hiện tại
+pub fn list() -> Rotation {
+    ROTATION.lock().map(|r| r.clone()).unwrap_or_else(|_| default_rotation())
+}
+
+/// Lưu rotation mới vào NVS và cập nhật cache
+pub fn save(partition: &EspDefaultNvsPartition, rotation: Rotation) -> anyhow::Result<()> {
+    config::set_str(partition, config::KEY_ROTATION, &to_string(&rotation))?;
+    let mut store = ROTATION.lock().map_err(|_| anyhow::anyhow!("Rotation store poisoned"))?;
+    *store = rotation;
+    Ok(())
+}
@@ -0,0 +1,305 @@
+use core::fmt::Write as FmtWrite;
+use heapless::Vec as HeaplessVec;
+use std::sync::Mutex;
+
+use crate::color::parse_hex_color;
+use crate::config::{parse_flat_json, JsonPairs, JsonValue};
+use crate::controller::SleepTimerPolicy;
+use crate::effect::IdentifyEffect;
+use crate::http::{
+    mode_command, send_commands_from, CommandProducer, CommandSource, LedCommand, ParamKey, ParamValue,
+    BRIGHTNESS_MAX, COMMAND_QUEUE_SIZE, SLEEP_TIMER_MAX_MIN, SPEED_MAX, SPEED_MIN,
+};
+
+/// Số lời gọi tối đa trong một batch `/rpc`
+pub const MAX_RPC_CALLS: usize = 8;
+
+/// Tổng số command một batch được đẩy vào queue (bằng dung lượng queue).
+/// Một lời gọi `set_led` có thể tạo tới 4 command.
+const MAX_RPC_COMMANDS: usize = COMMAND_QUEUE_SIZE - 1;
+
+/// Method được hỗ trợ, tương ứng với các endpoint /led, /identify, /timer/sleep, /audio-mode...
+const RPC_METHODS: &[&str] = &[
+    "set_led",
+    "set_effect",
+    "set_brightness",
+    "set_color",
+    "set_speed",
+    "set_param",
+    "set_sleep_timer",
+    "set_audio_mode",
+    "identify",
+    "get_state",
+];
+
+type Params<'a> = JsonPairs<'a>;
+type Commands = HeaplessVec<LedCommand, 4>;
+
+struct Call<'a> {
+    method: &'a str,
+    params: &'a str,
+    id: Option<u32>,
+}
+
+/// Tách mảng JSON `[{...},{...}]` thành từng object (không hỗ trợ object lồng quá 2 cấp)
+fn split_calls(body: &str) -> Result<HeaplessVec<&str, MAX_RPC_CALLS>, &'static str> {
+    let inner = body.trim()
+        .strip_prefix('[')
+        .and_then(|r| r.strip_suffix(']'))
+        .ok_or("Expected a JSON array of calls")?;
+
+    let mut calls = HeaplessVec::new();
+    let mut depth = 0u8;
+    let mut in_string = false;
+    let mut start = None;
+
+    for (i, c) in inner.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '{' if !in_string => {
+                if depth == 0 {
+                    start = Some(i);
+                }
+                depth += 1;
+                if depth > 2 {
+                    return Err("Nested params are not supported");
+                }
+            }
+            '}' if !in_string => {
+                depth = depth.checked_sub(1).ok_or("Unbalanced braces")?;
+                if depth == 0 {
+                    let s = start.take().ok_or("Unbalanced braces")?;
+                    calls.push(&inner[s..=i]).map_err(|_| "Too many calls (max 8)")?;
+                }
+            }
+            ',' if depth == 0 && !in_string => {}
+            c if depth == 0 && !in_string && !c.is_whitespace() => return Err("Expected an object"),
+            _ => {}
+        }
+    }
+
+    if depth != 0 || in_string {
+        return Err("Unterminated call");
+    }
+    if calls.is_empty() {
+        return Err("Empty batch");
+    }
+    Ok(calls)
+}
+
+/// `{"method":"...","params":{...},"id":N}` → Call. Thiếu params = `{}`.
+fn parse_call(obj: &str) -> Result<Call<'_>, &'static str> {
+    let mut rest = obj.trim()
+        .strip_prefix('{')
+        .and_then(|r| r.strip_suffix('}'))
+        .ok_or("Expected a call object")?
+        .trim();
+
+    let mut call = Call { method: "", params: "{}", id: None };
+
+    while !rest.is_empty() {
+        let r = rest.strip_prefix('"').ok_or("Expected a key")?;
+        let (key, r) = r.split_once('"').ok_or("Unterminated key")?;
+        let r = r.trim_start().strip_prefix(':').ok_or("Expected ':'")?.trim_start();
+
+        let r = match key {
+            "method" => {
+                let (m, r) = r.strip_prefix('"').and_then(|r| r.split_once('"')).ok_or("method must be a string")?;
+                call.method = m;
+                r
+            }
+            "params" => {
+                if !r.starts_with('{') {
+                    return Err("params must be an object");
+                }
+                let end = r.find('}').ok_or("Unterminated params")?;
+                call.params = &r[..=end];
+                &r[end + 1..]
+            }
+            "id" => {
+                let end = r.find(|c: char| !c.is_ascii_digit()).unwrap_or(r.len());
+                call.id = Some(r[..end].parse::<u32>().map_err(|_| "id must be an unsigned integer")?);
+                &r[end..]
+            }
+            _ => return Err("Unknown call field"),
+        };
+
+        let r = r.trim_start();
+        rest = match r.strip_prefix(',') {
+            Some(r) => r.trim_start(),
+            None if r.is_empty() => r,
+            None => return Err("Expected ',' or '}'"),
+        };
+    }
+
+    if call.method.is_empty() {
+        return Err("Missing method");
+    }
+    Ok(call)
+}
+
+fn int_param(params: &Params, key: &str) -> Option<u32> {
+    params.iter().find(|(k, _)| *k == key).and_then(|(_, v)| match v {
+        JsonValue::Int(n) => Some(*n),
+        JsonValue::Str(s) => s.parse().ok(),
+    })
+}
+
+fn str_param<'a>(params: &Params<'a>, key: &str) -> Option<&'a str> {
+    params.iter().find(|(k, _)| *k == key).and_then(|(_, v)| match v {
+        JsonValue::Str(s) => Some(*s),
+        JsonValue::Int(_) => None,
+    })
+}
+
+fn push(commands: &mut Commands, cmd: LedCommand) -> Result<(), &'static str> {
+    commands.push(cmd).map_err(|_| "Too many changes in one call")
+}
+
+/// Method → command, cùng kiểm tra với endpoint HTTP tương ứng
+fn build_commands(method: &str, params: &Params) -> Result<Commands, &'static str> {
+    let mut commands = Commands::new();
+
+    let effect = |params: &Params| -> Result<Option<LedCommand>, &'static str> {
+        str_param(params, "mode")
+            .map(|m| mode_command(m).map(|(cmd, _)| cmd).ok_or("Unknown mode"))
+            .transpose()
+    };
+    let brightness = |v: u32| -> Result<LedCommand, &'static str> {
+        if v > BRIGHTNESS_MAX as u32 {
+            return Err("brightness must be 0-100");
+        }
+        Ok(LedCommand::SetBrightness(v as f32 / BRIGHTNESS_MAX as f32))
+    };
+    // Như /led và /speed: speed ngoài khoảng được kẹp lại, không báo lỗi
+    let speed = |v: u32| -> LedCommand {
+        LedCommand::SetSpeed(v.clamp(SPEED_MIN as u32, SPEED_MAX as u32) as u8)
+    };
+    let color = |s: &str| -> Result<LedCommand, &'static str> {
+        let (r, g, b) = parse_hex_color(s).map_err(|_| "color must be RRGGBB")?;
+        Ok(LedCommand::SetColor(r, g, b))
+    };
+
+    match method {
+        "set_led" => {
+            if let Some(cmd) = effect(params)? {
+                push(&mut commands, cmd)?;
+            }
+            if let Some(v) = int_param(params, "brightness") {
+                push(&mut commands, brightness(v)?)?;
+            }
+            if let Some(s) = str_param(params, "color") {
+                push(&mut commands, color(s)?)?;
+            }
+            if let Some(v) = int_param(params, "speed") {
+                push(&mut commands, speed(v))?;
+            }
+            if commands.is_empty() {
+                return Err("Expected mode, brightness, color or speed");
+            }
+        }
+        "set_effect" => push(&mut commands, effect(params)?.ok_or("Expected mode")?)?,
+        "set_brightness" => push(&mut commands, brightness(int_param(params, "value").ok_or("Expected value")?)?)?,
+        "set_color" => push(&mut commands, color(str_param(params, "color").ok_or("Expected color")?)?)?,
+        "set_speed" => push(&mut commands, speed(int_param(params, "value").ok_or("Expected value")?))?,
+        "set_param" => {
+            let key = str_param(params, "key").ok_or("Expected key")?;
+            let mut value = ParamValue::new();
+            match params.iter().find(|(k, _)| *k == "value") {
+                Some((_, JsonValue::Str(s))) => value.push_str(s).map_err(|_| "Param too long")?,
+                Some((_, JsonValue::Int(n))) => write!(value, "{}", n).map_err(|_| "Param too long")?,
+                None => return Err("Expected value"),
+            }
+            let key = ParamKey::try_from(key).map_err(|_| "Param too long")?;
+            push(&mut commands, LedCommand::SetParam(key, value))?;
+        }
+        "set_sleep_timer" => {
+            let minutes = int_param(params, "minutes")
+                .filter(|m| *m <= SLEEP_TIMER_MAX_MIN as u32)
+                .ok_or("Expected minutes=0-1440")?;
+            let policy = match str_param(params, "on_command") {
+                None => SleepTimerPolicy::Keep,
+                Some(v) => SleepTimerPolicy::parse(v).ok_or("on_command must be keep|reset|cancel")?,
+            };
+            push(&mut commands, LedCommand::SetSleepTimer { minutes: minutes as u16, policy })?;
+        }
+        "set_audio_mode" => {
+            let enabled = int_param(params, "enabled").filter(|v| *v <= 1).ok_or("Expected enabled=0|1")?;
+            push(&mut commands, LedCommand::SetAudioMode(enabled == 1))?;
+        }
+        "identify" => {
+            let count = match int_param(params, "count") {
+                None => IdentifyEffect::DEFAULT_PULSES,
+                Some(n) if (1..=IdentifyEffect::MAX_PULSES as u32).contains(&n) => n as u8,
+                Some(_) => return Err("count must be 1-10"),
+            };
+            push(&mut commands, LedCommand::Identify { count })?;
+        }
+        _ => return Err("Unknown method"),
+    }
+
+    Ok(commands)
+}
+
+fn write_state(out: &mut impl FmtWrite, state: &crate::controller::StateSnapshot) -> Result<(), &'static str> {
+    write!(
+        out,
+        "\"result\":{{\"version\":{},\"mode\":\"{}\",\"brightness\":{},\"speed\":{},\"color\":\"{:02X}{:02X}{:02X}\"}}",
+        state.version, state.mode, state.brightness, state.speed,
+        state.color.r, state.color.g, state.color.b
+    ).map_err(|_| "Response too long")
+}
+
+/// Chạy một batch `/rpc` và ghi mảng kết quả (cùng thứ tự với lời gọi) vào `out`.
+/// Mỗi lời gọi thành công hoặc lỗi độc lập; Err chỉ khi cả body không hợp lệ.
+/// Command đi vào queue chung nên `get_state` trong cùng batch chưa thấy thay đổi
+/// của các lời gọi trước nó.
+pub fn run_batch(
+    body: &str,
+    producer: &Mutex<CommandProducer>,
+    out: &mut impl FmtWrite,
+) -> Result<(), &'static str> {
+    let calls = split_calls(body)?;
+    let mut queued = 0;
+
+    out.write_char('[').map_err(|_| "Response too long")?;
+    for (i, raw) in calls.iter().enumerate() {
+        if i > 0 {
+            out.write_char(',').map_err(|_| "Response too long")?;
+        }
+
+        let call = parse_call(raw);
+        let id = call.as_ref().ok().and_then(|c| c.id);
+        match id {
+            Some(id) => write!(out, "{{\"id\":{},", id),
+            None => out.write_str("{\"id\":null,"),
+        }.map_err(|_| "Response too long")?;
+
+        let result = call.and_then(|call| {
+            if !RPC_METHODS.contains(&call.method) {
+                return Err("Unknown method");
+            }
+            let params = parse_flat_json(call.params)?;
+            if call.method == "get_state" {
+                let state = crate::controller::state_snapshot().ok_or("State unavailable")?;
+                return write_state(out, &state);
+            }
+            let commands = build_commands(call.method, &params)?;
+            if queued + commands.len() > MAX_RPC_COMMANDS {
+                return Err("Too many changes in one batch");
+            }
+            let count = commands.len();
+            if !send_commands_from(producer, CommandSource::Http, commands) {
+                return Err("Device busy");
+            }
+            queued += count;
+            out.write_str("\"result\":true").map_err(|_| "Response too long")
+        });
+
+        if let Err(msg) = result {
+            write!(out, "\"error\":\"{}\"", msg).map_err(|_| "Response too long")?;
+        }
+        out.write_char('}').map_err(|_| "Response too long")?;
+    }
+    out.write_char(']').map_err(|_| "Response too long")
+}
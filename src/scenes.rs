@@ -0,0 +1,259 @@
+use anyhow::{bail, Context, Result};
+use core::fmt::Write as FmtWrite;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use heapless::{String, Vec};
+use log::{info, warn};
+use smart_leds::RGB8;
+use std::sync::Mutex;
+
+use crate::effect::{effect_from_name, effect_name, EffectType};
+use crate::http::{LedCommand, ParamKey, ParamValue, BRIGHTNESS_MAX, SPEED_MAX, SPEED_MIN};
+
+pub const MAX_SCENES: usize = 8;
+pub const MAX_NAME_LEN: usize = 15;
+/// Số `param=key:value` tối đa lưu kèm scene (band, invert, palette...)
+pub const MAX_SCENE_PARAMS: usize = 4;
+
+/// Effect, màu, speed, brightness, range, saturation, beat sync + params
+pub const SCENE_MAX_COMMANDS: usize = 7 + MAX_SCENE_PARAMS;
+
+// LED task đọc hết queue trong một lượt → scene vừa queue thì được áp trọn một frame
+const _: () = assert!(SCENE_MAX_COMMANDS < crate::http::COMMAND_QUEUE_SIZE);
+
+const NVS_NAMESPACE: &str = "scenes";
+
+// Record trong NVS: "name;mode;brightness;speed;RRGGBB;start-end|full;sat;beatsync;key:value,..."
+const RECORD_LEN: usize = MAX_NAME_LEN + 16 + 4 + 4 + 7 + 12 + 4 + 2 + MAX_SCENE_PARAMS * 50;
+type Record = String<RECORD_LEN>;
+
+pub type SceneParams = Vec<(ParamKey, ParamValue), MAX_SCENE_PARAMS>;
+
+/// Toàn bộ cấu hình hiển thị, áp dụng bằng một lệnh (`/scene/apply`)
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub name: String<MAX_NAME_LEN>,
+    pub effect: EffectType,
+    /// Phần trăm, giống tham số `brightness` của /led
+    pub brightness: u8,
+    pub speed: u8,
+    pub color: RGB8,
+    /// None = cả dải
+    pub range: Option<(u16, u16)>,
+    pub saturation: u8,
+    pub beat_sync: bool,
+    pub params: SceneParams,
+}
+
+// Cache trong RAM giống palette
+static STORE: Mutex<Vec<Scene, MAX_SCENES>> = Mutex::new(Vec::new());
+
+/// Tên hợp lệ cho scene: chữ, số, `_`, `-`
+pub fn parse_name(name: &str) -> Result<String<MAX_NAME_LEN>, &'static str> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err("Invalid scene name");
+    }
+    String::try_from(name).map_err(|_| "Scene name too long (max 15)")
+}
+
+/// "start-end" → range, None nếu là cả dải. Lỗi nếu không thoả start < end <= số LED.
+pub fn parse_range(s: &str) -> Result<Option<(u16, u16)>, &'static str> {
+    if s == "full" {
+        return Ok(None);
+    }
+    let (start, end) = s.split_once('-').ok_or("Range must be start-end or full")?;
+    let (Ok(start), Ok(end)) = (start.parse::<u16>(), end.parse::<u16>()) else {
+        return Err("Range must be start-end or full");
+    };
+    if start >= end || end as usize > crate::NUM_LEDS {
+        return Err("Range must satisfy start < end <= LED count");
+    }
+    Ok((start > 0 || (end as usize) < crate::NUM_LEDS).then_some((start, end)))
+}
+
+/// "band:bass,invert:1" → danh sách param. Chuỗi rỗng = không có param.
+pub fn parse_params(s: &str) -> Result<SceneParams, &'static str> {
+    let mut params = SceneParams::new();
+    for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once(':').ok_or("Params must be key:value,key:value")?;
+        let (Ok(key), Ok(value)) = (ParamKey::try_from(key), ParamValue::try_from(value)) else {
+            return Err("Param too long");
+        };
+        params.push((key, value)).map_err(|_| "Too many params (max 4)")?;
+    }
+    Ok(params)
+}
+
+impl Scene {
+    /// Kiểm tra giá trị, kể cả record đọc từ NVS (có thể từ firmware cũ)
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.brightness > BRIGHTNESS_MAX {
+            return Err("brightness must be 0-100");
+        }
+        if !(SPEED_MIN..=SPEED_MAX).contains(&self.speed) {
+            return Err("speed must be 1-255");
+        }
+        if self.saturation > 100 {
+            return Err("sat must be 0-100");
+        }
+        if let Some((start, end)) = self.range {
+            if start >= end || end as usize > crate::NUM_LEDS {
+                return Err("Range must satisfy start < end <= LED count");
+            }
+        }
+        Ok(())
+    }
+
+    /// Command để dựng lại scene, theo thứ tự LED task cần áp dụng:
+    /// range và effect trước (tạo lại hiệu ứng), params sau cùng
+    pub fn commands(&self) -> Vec<LedCommand, SCENE_MAX_COMMANDS> {
+        let mut cmds = Vec::new();
+        let c = self.color;
+        cmds.push(LedCommand::SetRange(self.range)).ok();
+        cmds.push(LedCommand::SetEffect(self.effect.clone())).ok();
+        cmds.push(LedCommand::SetColor(c.r, c.g, c.b)).ok();
+        cmds.push(LedCommand::SetSpeed(self.speed)).ok();
+        cmds.push(LedCommand::SetBrightness(self.brightness as f32 / BRIGHTNESS_MAX as f32)).ok();
+        cmds.push(LedCommand::SetSaturation(self.saturation)).ok();
+        cmds.push(LedCommand::SetBeatSync(self.beat_sync)).ok();
+        for (key, value) in &self.params {
+            cmds.push(LedCommand::SetParam(key.clone(), value.clone())).ok();
+        }
+        cmds
+    }
+
+    pub fn write_range(&self, out: &mut impl FmtWrite) -> core::fmt::Result {
+        match self.range {
+            Some((start, end)) => write!(out, "{}-{}", start, end),
+            None => out.write_str("full"),
+        }
+    }
+
+    pub fn write_params(&self, out: &mut impl FmtWrite) -> core::fmt::Result {
+        for (i, (key, value)) in self.params.iter().enumerate() {
+            if i > 0 {
+                out.write_char(',')?;
+            }
+            write!(out, "{}:{}", key, value)?;
+        }
+        Ok(())
+    }
+
+    fn to_record(&self) -> Record {
+        let mut record = Record::new();
+        let c = self.color;
+        write!(
+            record,
+            "{};{};{};{};{:02X}{:02X}{:02X};",
+            self.name, effect_name(&self.effect), self.brightness, self.speed, c.r, c.g, c.b
+        ).ok();
+        self.write_range(&mut record).ok();
+        write!(record, ";{};{};", self.saturation, self.beat_sync as u8).ok();
+        self.write_params(&mut record).ok();
+        record
+    }
+
+    fn from_record(record: &str) -> Option<Self> {
+        let mut fields = record.split(';');
+        let name = parse_name(fields.next()?).ok()?;
+        let (effect, _) = effect_from_name(fields.next()?)?;
+        let brightness = fields.next()?.parse::<u8>().ok()?;
+        let speed = fields.next()?.parse::<u8>().ok()?;
+        let (r, g, b) = crate::color::parse_hex_color(fields.next()?).ok()?;
+        let range = parse_range(fields.next()?).ok()?;
+        let saturation = fields.next()?.parse::<u8>().ok()?;
+        let beat_sync = fields.next()? == "1";
+        let params = parse_params(fields.next().unwrap_or("")).ok()?;
+        let scene = Self { name, effect, brightness, speed, color: RGB8 { r, g, b }, range, saturation, beat_sync, params };
+        scene.validate().ok()?;
+        Some(scene)
+    }
+}
+
+/// Bản sao danh sách scene
+pub fn list() -> Vec<Scene, MAX_SCENES> {
+    STORE.lock().map(|store| store.clone()).unwrap_or_default()
+}
+
+pub fn find(name: &str) -> Option<Scene> {
+    STORE.lock().ok()?.iter().find(|s| s.name == name).cloned()
+}
+
+fn slot_key(i: usize) -> String<4> {
+    let mut key = String::new();
+    write!(key, "s{}", i).ok();
+    key
+}
+
+/// Nạp scene đã lưu từ NVS vào RAM (gọi 1 lần khi khởi động).
+/// Scene không hợp lệ (vd hiệu ứng đã bị bỏ) bị bỏ qua.
+pub fn init(partition: &EspDefaultNvsPartition) {
+    let Ok(nvs) = EspNvs::new(partition.clone(), NVS_NAMESPACE, false) else {
+        info!("No saved scenes");
+        return;
+    };
+
+    let Ok(mut store) = STORE.lock() else { return; };
+    store.clear();
+
+    for i in 0..MAX_SCENES {
+        let mut buf = [0u8; RECORD_LEN + 1];
+        if let Ok(Some(record)) = nvs.get_str(&slot_key(i), &mut buf) {
+            match Scene::from_record(record) {
+                Some(scene) => { store.push(scene).ok(); }
+                None => warn!("Ignoring invalid scene record in slot {}", i),
+            }
+        }
+    }
+
+    info!("Loaded {} scene(s) from NVS", store.len());
+}
+
+fn persist(partition: &EspDefaultNvsPartition, store: &Vec<Scene, MAX_SCENES>) -> Result<()> {
+    let mut nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)
+        .context("Không thể mở NVS namespace scenes")?;
+
+    for i in 0..MAX_SCENES {
+        let key = slot_key(i);
+        match store.get(i) {
+            Some(scene) => {
+                nvs.set_str(&key, &scene.to_record())
+                    .with_context(|| format!("Không thể lưu scene {}", scene.name))?;
+            }
+            None => {
+                let _ = nvs.remove(&key);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lưu được scene tên `name`: còn chỗ trống hoặc ghi đè scene cùng tên
+pub fn has_room_for(name: &str) -> bool {
+    STORE.lock().map_or(false, |store| !store.is_full() || store.iter().any(|s| s.name == name))
+}
+
+/// Thêm hoặc ghi đè scene cùng tên, lưu vào NVS
+pub fn save(partition: &EspDefaultNvsPartition, scene: Scene) -> Result<()> {
+    let mut store = STORE.lock().map_err(|_| anyhow::anyhow!("Scene store poisoned"))?;
+
+    if let Some(existing) = store.iter_mut().find(|s| s.name == scene.name) {
+        *existing = scene;
+    } else if store.push(scene).is_err() {
+        bail!("Scene store full (max {})", MAX_SCENES);
+    }
+
+    persist(partition, &store)
+}
+
+/// Xóa scene theo tên, trả false nếu không tồn tại
+pub fn remove(partition: &EspDefaultNvsPartition, name: &str) -> Result<bool> {
+    let mut store = STORE.lock().map_err(|_| anyhow::anyhow!("Scene store poisoned"))?;
+
+    if !store.iter().any(|s| s.name == name) {
+        return Ok(false);
+    }
+    store.retain(|s| s.name != name);
+
+    persist(partition, &store)?;
+    Ok(true)
+}
@@ -1,87 +1,32 @@
-use esp_idf_hal::peripheral::Peripheral;
-use esp_idf_svc::{
-    eventloop::EspSystemEventLoop, nvs::{EspNvsPartition, NvsDefault}, ping::EspPing, timer::EspTimerService, wifi::{AsyncWifi, AuthMethod, ClientConfiguration, Configuration, EspWifi}
-};
-use esp_idf_svc::timer::Task;
-use log::info;
-use anyhow::Result;
-use futures::executor::block_on;
-
-const SSID: &str = "MY CHAU";
-const PASSWORD: &str = "0908814847";
-
-#[allow(dead_code)]
-pub fn wifi(
-    modem: impl Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
-    sysloop: EspSystemEventLoop,
-    nvs: Option<EspNvsPartition<NvsDefault>>,
-    timer_service: EspTimerService<Task>,
-) -> Result<AsyncWifi<EspWifi<'static>>> {
-
-
-    let mut wifi = AsyncWifi::wrap(
-        EspWifi::new(modem, sysloop.clone(), nvs)?,
-        sysloop,
-        timer_service.clone(),
-    )?;
-
-
-
-    block_on(start_access_point(&mut wifi))?;
-
-    let ip_info = wifi.wifi().ap_netif().get_ip_info()?;
-
-    info!("Thông tin Wifi DHCP: {:?}", ip_info);
-    
-    EspPing::default().ping(ip_info.ip, &esp_idf_svc::ping::Configuration::default())?;
-    Ok(wifi)
+/// RSSI hiện tại (dBm): của AP đang kết nối ở chế độ Station,
+/// hoặc của client mạnh nhất khi đang chạy Access Point
+pub fn current_rssi() -> Option<i8> {
+    let mut ap_info = esp_idf_sys::wifi_ap_record_t::default();
+    if unsafe { esp_idf_sys::esp_wifi_sta_get_ap_info(&mut ap_info) } == esp_idf_sys::ESP_OK {
+        return Some(ap_info.rssi);
+    }
+
+    let mut sta_list = esp_idf_sys::wifi_sta_list_t::default();
+    if unsafe { esp_idf_sys::esp_wifi_ap_get_sta_list(&mut sta_list) } == esp_idf_sys::ESP_OK {
+        let count = (sta_list.num.max(0) as usize).min(sta_list.sta.len());
+        return sta_list.sta[..count].iter().map(|sta| sta.rssi).max();
+    }
+
+    None
 }
 
-
-// Hàm để khởi động Access Point
-async fn start_access_point(wifi: &mut AsyncWifi<EspWifi<'static>>) -> anyhow::Result<()> {
-    let ap_configuration: Configuration = Configuration::AccessPoint(esp_idf_svc::wifi::AccessPointConfiguration {
-        ssid: "ESP32AP".try_into().unwrap(),
-        password: "21078481".try_into().unwrap(),
-        channel: 1,
-        auth_method: AuthMethod::WPA2Personal,
-        max_connections: 4,
-        ssid_hidden: false,
-        ..Default::default()
-    });
-
-    wifi.set_configuration(&ap_configuration)?;
-    info!("Wi-Fi configuration set to Access Point mode.");
-
-    wifi.start().await?;
-    info!("Wi-Fi started as an Access Point.");
-
-    wifi.wait_netif_up().await?;
-    info!("Access Point network interface is up.");
-
-    Ok(())
+/// Station đã vào mạng đã lưu (không tính client vào Access Point fallback)
+pub fn sta_connected() -> bool {
+    let mut ap_info = esp_idf_sys::wifi_ap_record_t::default();
+    unsafe { esp_idf_sys::esp_wifi_sta_get_ap_info(&mut ap_info) } == esp_idf_sys::ESP_OK
 }
 
-// Hàm để kết nối với mạng Wi-Fi    
-async fn connect_to_wifi(wifi: &mut AsyncWifi<EspWifi<'static>>) -> anyhow::Result<()> {
-    let sta_configuration: Configuration = Configuration::Client(ClientConfiguration {
-        ssid: SSID.try_into().unwrap(),
-        password: PASSWORD.try_into().unwrap(),
-        ..Default::default()
-    });
-
-    wifi.set_configuration(&sta_configuration)?;
-    info!("Wi-Fi configuration set to Station mode.");
+/// Đã có kết nối thật: Station đã vào mạng, hoặc có client vào Access Point
+pub fn has_connection() -> bool {
+    if sta_connected() {
+        return true;
+    }
 
-    wifi.start().await?;
-    info!("Wi-Fi started as a Station.");
-
-    wifi.connect().await?;
-    info!("Connected to Wi-Fi network: {}", SSID);
-
-    wifi.wait_netif_up().await?;
-    info!("Station network interface is up.");
-
-    Ok(())
+    let mut sta_list = esp_idf_sys::wifi_sta_list_t::default();
+    unsafe { esp_idf_sys::esp_wifi_ap_get_sta_list(&mut sta_list) } == esp_idf_sys::ESP_OK && sta_list.num > 0
 }
-